@@ -0,0 +1,144 @@
+//! This module refreshes the aggregate tables dashboards read.
+//!
+//! After a load, the per-quality table (number of wines and mean of every numeric column per quality)
+//! and the per-run table (number of wines and mean quality per run) are recomputed from all of
+//! `wine_quality`. Both are replaced in one transaction: readers keep seeing the previous aggregates
+//! until it commits, and then see aggregates matching the rows it committed with. When staging is
+//! enabled, the refresh runs in the transaction promoting a run instead, since loads do not change
+//! `wine_quality` before then.
+
+use crate::config::AggregatesConfig;
+use crate::schema::{ColumnSchema, TableSchema};
+use crate::{audit, clustering};
+use anyhow::{Context, Result};
+use sqlx::postgres::{PgConnection, PgPool};
+
+/// The DataFrame column the aggregates are grouped by.
+const QUALITY_COLUMN: &str = "quality";
+
+/// Recomputes the aggregate tables in a transaction of their own.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The schema of the `wine_quality` table.
+/// * `config` - The aggregate settings.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the schema lacks the quality or run ID column, or a statement fails.
+///
+/// # Example
+///
+/// ```
+/// refresh_stage(&pool, &config.schema, &config.aggregates).await?;
+/// ```
+pub async fn refresh_stage(pool: &PgPool, schema: &TableSchema, config: &AggregatesConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let mut tx = pool.begin().await?;
+    refresh(&mut tx, schema, config).await?;
+    tx.commit().await.context("Failed to commit the refreshed aggregates")?;
+    Ok(())
+}
+
+/// Recomputes the aggregate tables on a connection, typically inside the caller's transaction.
+///
+/// # Arguments
+///
+/// * `conn` - The connection, or transaction, the aggregates are refreshed in.
+/// * `schema` - The schema of the `wine_quality` table.
+/// * `config` - The aggregate settings.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the schema lacks the quality or run ID column, or a statement fails.
+pub async fn refresh(conn: &mut PgConnection, schema: &TableSchema, config: &AggregatesConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let quality = find_column(schema, QUALITY_COLUMN)?;
+    let run_id = find_column(schema, audit::RUN_ID_COLUMN)?;
+    let averaged = averaged_columns(schema);
+
+    let statements = [
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (quality INTEGER PRIMARY KEY, wine_count BIGINT NOT NULL, refreshed_at TIMESTAMPTZ NOT NULL DEFAULT now())",
+            config.quality_table
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (run_id TEXT, wine_count BIGINT NOT NULL, mean_quality DOUBLE PRECISION, refreshed_at TIMESTAMPTZ NOT NULL DEFAULT now())",
+            config.run_table
+        ),
+    ]
+    .into_iter()
+    // Columns the schema gained since the table was created
+    .chain(averaged.iter().map(|c| format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS mean_{} DOUBLE PRECISION", config.quality_table, c.column)))
+    // Concurrent refreshes queue here; readers are not blocked
+    .chain([format!("LOCK TABLE {}, {} IN EXCLUSIVE MODE", config.quality_table, config.run_table)]);
+    for sql in statements {
+        sqlx::query(&sql).execute(&mut *conn).await.context(format!("Failed to prepare the aggregate tables: {}", sql))?;
+    }
+
+    let means: Vec<String> = averaged.iter().map(|c| format!("mean_{}", c.column)).collect();
+    let averages: Vec<String> = averaged.iter().map(|c| format!("AVG({})::DOUBLE PRECISION", c.column)).collect();
+    let refresh_quality = format!(
+        "INSERT INTO {} (quality, wine_count{}) SELECT {}, COUNT(*){} FROM wine_quality WHERE {} IS NOT NULL GROUP BY {}",
+        config.quality_table,
+        means.iter().map(|m| format!(", {}", m)).collect::<String>(),
+        quality.column,
+        averages.iter().map(|a| format!(", {}", a)).collect::<String>(),
+        quality.column,
+        quality.column
+    );
+    let refresh_runs = format!(
+        "INSERT INTO {} (run_id, wine_count, mean_quality) SELECT {}, COUNT(*), AVG({})::DOUBLE PRECISION FROM wine_quality GROUP BY {}",
+        config.run_table, run_id.column, quality.column, run_id.column
+    );
+
+    let mut groups = 0;
+    for (table, insert) in [(&config.quality_table, refresh_quality), (&config.run_table, refresh_runs)] {
+        sqlx::query(&format!("DELETE FROM {}", table)).execute(&mut *conn).await.context(format!("Failed to clear {}", table))?;
+        groups += sqlx::query(&insert).execute(&mut *conn).await.context(format!("Failed to refresh {}", table))?.rows_affected();
+    }
+    println!("Refreshed {} aggregate rows in {} and {}", groups, config.quality_table, config.run_table);
+    Ok(())
+}
+
+/// Helper function to select the numeric columns whose mean is aggregated per quality.
+fn averaged_columns(schema: &TableSchema) -> Vec<&ColumnSchema> {
+    schema
+        .columns
+        .iter()
+        .filter(|c| ![QUALITY_COLUMN, clustering::CLUSTER_COLUMN].contains(&c.name.as_str()))
+        .filter(|c| {
+            let pg_type = c.pg_type.to_ascii_uppercase();
+            ["DECIMAL", "NUMERIC", "DOUBLE", "REAL", "FLOAT", "INT", "SMALLINT", "BIGINT"].iter().any(|t| pg_type.starts_with(t))
+        })
+        .collect()
+}
+
+/// Helper function to look up a column the aggregates need.
+fn find_column<'a>(schema: &'a TableSchema, name: &str) -> Result<&'a ColumnSchema> {
+    schema
+        .columns
+        .iter()
+        .find(|c| c.name == name)
+        .context(format!("Aggregates require the {} column in the schema", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_averaged_columns_skip_quality_cluster_and_text() {
+        let averaged: Vec<&str> = averaged_columns(&TableSchema::default()).iter().map(|c| c.column.as_str()).collect();
+
+        assert_eq!(averaged.len(), 12);
+        assert_eq!(averaged[0], "fixed_acidity");
+        assert!(averaged.contains(&"predicted_quality"));
+        assert!(!averaged.iter().any(|c| ["quality", "cluster", "run_id", "labels"].contains(c)));
+    }
+}
@@ -0,0 +1,128 @@
+//! This module deduplicates the failure notifications of runs, so a flaky night pages once.
+//!
+//! With `[alerts] deduplicate`, the runs loading the same input form one logical run: its failures
+//! are an incident, kept in the `alert_state` table from the first failed run until a run succeeds.
+//! Only the run opening an incident fires the `on_failure` hooks and webhooks; further failed runs,
+//! e.g. the retries of a scheduler or the next files of a landing directory while the database keeps
+//! dropping connections, are counted and logged. The run that succeeds again closes the incident and
+//! fires `on_recovery`, whose message says how many runs failed since when. When the state cannot be
+//! read, e.g. because the database is down, the failure is notified anyway: a duplicate alert is
+//! better than a lost one.
+
+use crate::config::PipelineConfig;
+use crate::daemon::PIPELINE_NAME;
+use crate::source;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgPool;
+
+/// DDL of the incidents table, created when it is first written to, since a failing run may not have set up the database.
+const ALERT_STATE_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS alert_state (
+    alert_key TEXT PRIMARY KEY,
+    first_run_id TEXT NOT NULL,
+    last_run_id TEXT NOT NULL,
+    failed_runs INTEGER NOT NULL,
+    failing_since TIMESTAMPTZ NOT NULL DEFAULT now()
+)";
+
+/// The failures of a logical run since it last succeeded.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct Incident {
+    /// The run whose failure opened the incident, and was notified.
+    pub first_run_id: String,
+    pub failed_runs: i32,
+    pub failing_since: DateTime<Utc>,
+}
+
+impl Incident {
+    /// Whether the incident was opened by the failure just recorded, which is then notified.
+    pub fn is_new(&self) -> bool {
+        self.failed_runs == 1
+    }
+
+    /// Describes the incident for the recovery notice.
+    pub fn describe_recovery(&self) -> String {
+        format!(
+            "Recovered after {} failed runs since {} (first failed run {})",
+            self.failed_runs,
+            self.failing_since.format("%Y-%m-%d %H:%M:%S UTC"),
+            self.first_run_id
+        )
+    }
+}
+
+/// Names the logical run of a configuration, the same for every run loading the same input.
+pub fn alert_key(config: &PipelineConfig) -> String {
+    format!("{}:{}", PIPELINE_NAME, source::from_config(config).describe())
+}
+
+/// Records the final failure of a run in the incident of its logical run, opening it if needed.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `key` - The logical run, as returned by [`alert_key`].
+/// * `run_id` - The ID of the failed run.
+///
+/// # Returns
+///
+/// * `Result<Incident>` - The incident including the failure, or an error if the state cannot be written.
+///
+/// # Example
+///
+/// ```
+/// let notify = record_failure(&pool, &alert_key(&config), &run.id).await?.is_new();
+/// ```
+pub async fn record_failure(pool: &PgPool, key: &str, run_id: &str) -> Result<Incident> {
+    sqlx::query(ALERT_STATE_TABLE_SQL).execute(pool).await.context("Failed to create the alert_state table")?;
+    sqlx::query_as(
+        "INSERT INTO alert_state (alert_key, first_run_id, last_run_id, failed_runs) VALUES ($1, $2, $2, 1) \
+         ON CONFLICT (alert_key) DO UPDATE SET last_run_id = EXCLUDED.last_run_id, \
+         failed_runs = alert_state.failed_runs + (alert_state.last_run_id <> EXCLUDED.last_run_id)::INTEGER \
+         RETURNING first_run_id, failed_runs, failing_since",
+    )
+    .bind(key)
+    .bind(run_id)
+    .fetch_one(pool)
+    .await
+    .context(format!("Failed to record the failure of run {} for alerting", run_id))
+}
+
+/// Closes the incident of a logical run after a successful run.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `key` - The logical run, as returned by [`alert_key`].
+///
+/// # Returns
+///
+/// * `Result<Option<Incident>>` - The incident the run recovered from, `None` if the previous run succeeded too.
+pub async fn record_success(pool: &PgPool, key: &str) -> Result<Option<Incident>> {
+    sqlx::query(ALERT_STATE_TABLE_SQL).execute(pool).await.context("Failed to create the alert_state table")?;
+    sqlx::query_as("DELETE FROM alert_state WHERE alert_key = $1 RETURNING first_run_id, failed_runs, failing_since")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to close the alerting incident")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_only_the_first_failure_is_new() {
+        let mut incident = Incident {
+            first_run_id: "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B".to_string(),
+            failed_runs: 1,
+            failing_since: Utc.with_ymd_and_hms(2024, 5, 1, 2, 0, 0).unwrap(),
+        };
+        assert!(incident.is_new());
+        incident.failed_runs = 4;
+        assert!(!incident.is_new());
+        assert_eq!(incident.describe_recovery(), "Recovered after 4 failed runs since 2024-05-01 02:00:00 UTC (first failed run 01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B)");
+        assert_eq!(alert_key(&PipelineConfig::default()), format!("{}:{}", PIPELINE_NAME, source::from_config(&PipelineConfig::default()).describe()));
+    }
+}
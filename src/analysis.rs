@@ -0,0 +1,286 @@
+//! This module runs statistical hypothesis tests between groups of rows.
+//!
+//! It provides Welch's t-test, one-way ANOVA, and the chi-square test of independence. The tests are
+//! declared in the `[analysis]` section of the configuration and their results are stored with the run.
+
+use crate::config::AnalysisConfig;
+use crate::run::RunContext;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use prettytable::{row, Table};
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use statrs::distribution::{ChiSquared, ContinuousCDF, FisherSnedecor, StudentsT};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A declared hypothesis test.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "test", rename_all = "snake_case", deny_unknown_fields)]
+pub enum HypothesisTest {
+    /// Welch's t-test of `column` between rows where `group_by` is below `threshold` and rows at or above it.
+    TTest { column: String, group_by: String, threshold: f64 },
+    /// One-way ANOVA of `column` across the distinct values of `group_by`.
+    Anova { column: String, group_by: String },
+    /// Chi-square test of independence between the values of `column` and `group_by`.
+    ChiSquare { column: String, group_by: String },
+}
+
+impl fmt::Display for HypothesisTest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HypothesisTest::TTest { column, group_by, threshold } => write!(f, "t-test of {} by {} < {}", column, group_by, threshold),
+            HypothesisTest::Anova { column, group_by } => write!(f, "ANOVA of {} by {}", column, group_by),
+            HypothesisTest::ChiSquare { column, group_by } => write!(f, "chi-square of {} by {}", column, group_by),
+        }
+    }
+}
+
+/// Outcome of one hypothesis test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub test: String,
+    pub statistic: f64,
+    pub degrees_of_freedom: f64,
+    pub p_value: f64,
+}
+
+/// Runs the configured tests, prints the results table, and stores the results for the run.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the current run.
+/// * `df` - A reference to the DataFrame to analyse.
+/// * `config` - The analysis settings.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of the analysis.
+///
+/// # Example
+///
+/// ```
+/// run_tests(&pool, &run, &transformed_df, &config.analysis).await.expect("Analysis failed");
+/// ```
+pub async fn run_tests(pool: &PgPool, run: &RunContext, df: &DataFrame, config: &AnalysisConfig) -> Result<()> {
+    if config.tests.is_empty() {
+        return Ok(());
+    }
+
+    let results = config.tests.iter().map(|test| evaluate(df, test)).collect::<Result<Vec<_>>>()?;
+
+    let mut table = Table::new();
+    table.add_row(row!["Test", "Statistic", "DoF", "p-value"]);
+    for result in &results {
+        table.add_row(row![
+            result.test,
+            format!("{:.4}", result.statistic),
+            format!("{:.2}", result.degrees_of_freedom),
+            format!("{:.6}", result.p_value)
+        ]);
+    }
+    table.printstd();
+
+    for result in &results {
+        sqlx::query("INSERT INTO analysis_results (run_id, test, statistic, degrees_of_freedom, p_value) VALUES ($1, $2, $3, $4, $5)")
+            .bind(&run.id)
+            .bind(&result.test)
+            .bind(result.statistic)
+            .bind(result.degrees_of_freedom)
+            .bind(result.p_value)
+            .execute(pool)
+            .await
+            .context("Failed to store analysis results")?;
+    }
+
+    Ok(())
+}
+
+/// Evaluates a single hypothesis test against a DataFrame.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to analyse.
+/// * `test` - The test to run.
+///
+/// # Returns
+///
+/// * `Result<TestResult>` - The test statistic and p-value, or an error if columns are missing or the groups are too small.
+pub fn evaluate(df: &DataFrame, test: &HypothesisTest) -> Result<TestResult> {
+    let (statistic, degrees_of_freedom, p_value) = match test {
+        HypothesisTest::TTest { column, group_by, threshold } => {
+            let values = float_values(df, column)?;
+            let groups = float_values(df, group_by)?;
+            let (mut below, mut above) = (vec![], vec![]);
+            for (value, group) in values.iter().zip(&groups) {
+                if let (Some(value), Some(group)) = (value, group) {
+                    if group < threshold { below.push(*value) } else { above.push(*value) }
+                }
+            }
+            welch_t_test(&below, &above)?
+        }
+        HypothesisTest::Anova { column, group_by } => {
+            let values = float_values(df, column)?;
+            let mut groups: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+            for (value, group) in values.iter().zip(string_values(df, group_by)?) {
+                if let (Some(value), Some(group)) = (value, group) {
+                    groups.entry(group).or_default().push(*value);
+                }
+            }
+            one_way_anova(&groups.into_values().collect::<Vec<_>>())?
+        }
+        HypothesisTest::ChiSquare { column, group_by } => {
+            let pairs: Vec<(String, String)> = string_values(df, column)?
+                .into_iter()
+                .zip(string_values(df, group_by)?)
+                .filter_map(|(a, b)| Some((a?, b?)))
+                .collect();
+            chi_square_independence(&pairs)?
+        }
+    };
+
+    Ok(TestResult {
+        test: test.to_string(),
+        statistic,
+        degrees_of_freedom,
+        p_value,
+    })
+}
+
+/// Welch's two-sample t-test. Returns the t statistic, the Welch–Satterthwaite degrees of freedom and the two-sided p-value.
+fn welch_t_test(a: &[f64], b: &[f64]) -> Result<(f64, f64, f64)> {
+    if a.len() < 2 || b.len() < 2 {
+        bail!("t-test needs at least two values per group, found {} and {}", a.len(), b.len());
+    }
+
+    let (mean_a, var_a) = mean_variance(a);
+    let (mean_b, var_b) = mean_variance(b);
+    let (se_a, se_b) = (var_a / a.len() as f64, var_b / b.len() as f64);
+    if se_a + se_b == 0.0 {
+        bail!("t-test is undefined when both groups have zero variance");
+    }
+
+    let t = (mean_a - mean_b) / (se_a + se_b).sqrt();
+    let dof = (se_a + se_b).powi(2) / (se_a.powi(2) / (a.len() - 1) as f64 + se_b.powi(2) / (b.len() - 1) as f64);
+    let distribution = StudentsT::new(0.0, 1.0, dof)?;
+    let p = 2.0 * (1.0 - distribution.cdf(t.abs()));
+    Ok((t, dof, p))
+}
+
+/// One-way ANOVA. Returns the F statistic, the between-groups degrees of freedom and the p-value.
+fn one_way_anova(groups: &[Vec<f64>]) -> Result<(f64, f64, f64)> {
+    let groups: Vec<&Vec<f64>> = groups.iter().filter(|g| !g.is_empty()).collect();
+    let n: usize = groups.iter().map(|g| g.len()).sum();
+    let k = groups.len();
+    if k < 2 || n <= k {
+        bail!("ANOVA needs at least two groups and more values than groups");
+    }
+
+    let grand_mean = groups.iter().flat_map(|g| g.iter()).sum::<f64>() / n as f64;
+    let mut between = 0.0;
+    let mut within = 0.0;
+    for group in &groups {
+        let mean = group.iter().sum::<f64>() / group.len() as f64;
+        between += group.len() as f64 * (mean - grand_mean).powi(2);
+        within += group.iter().map(|v| (v - mean).powi(2)).sum::<f64>();
+    }
+
+    let (df_between, df_within) = ((k - 1) as f64, (n - k) as f64);
+    if within == 0.0 {
+        bail!("ANOVA is undefined when every group has zero variance");
+    }
+    let f = (between / df_between) / (within / df_within);
+    let distribution = FisherSnedecor::new(df_between, df_within)?;
+    Ok((f, df_between, 1.0 - distribution.cdf(f)))
+}
+
+/// Chi-square test of independence over observed category pairs. Returns the statistic, degrees of freedom and p-value.
+fn chi_square_independence(pairs: &[(String, String)]) -> Result<(f64, f64, f64)> {
+    let mut observed: BTreeMap<(&str, &str), f64> = BTreeMap::new();
+    let mut row_totals: BTreeMap<&str, f64> = BTreeMap::new();
+    let mut column_totals: BTreeMap<&str, f64> = BTreeMap::new();
+    for (a, b) in pairs {
+        *observed.entry((a.as_str(), b.as_str())).or_default() += 1.0;
+        *row_totals.entry(a.as_str()).or_default() += 1.0;
+        *column_totals.entry(b.as_str()).or_default() += 1.0;
+    }
+    if row_totals.len() < 2 || column_totals.len() < 2 {
+        bail!("chi-square test needs at least two categories in each column");
+    }
+
+    let n = pairs.len() as f64;
+    let mut statistic = 0.0;
+    for (row, row_total) in &row_totals {
+        for (column, column_total) in &column_totals {
+            let expected = row_total * column_total / n;
+            let count = observed.get(&(*row, *column)).copied().unwrap_or(0.0);
+            statistic += (count - expected).powi(2) / expected;
+        }
+    }
+
+    let dof = ((row_totals.len() - 1) * (column_totals.len() - 1)) as f64;
+    let distribution = ChiSquared::new(dof)?;
+    Ok((statistic, dof, 1.0 - distribution.cdf(statistic)))
+}
+
+fn mean_variance(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance)
+}
+
+/// Helper function to fetch a column as f64 values, keeping nulls.
+fn float_values(df: &DataFrame, column: &str) -> Result<Vec<Option<f64>>> {
+    let series = df
+        .column(column)
+        .context(format!("Error fetching column {}", column))?
+        .cast(&DataType::Float64)
+        .context(format!("Error converting {} column to f64", column))?;
+    let values = series.f64()?.into_iter().collect();
+    Ok(values)
+}
+
+/// Helper function to fetch a column as category labels, keeping nulls.
+fn string_values(df: &DataFrame, column: &str) -> Result<Vec<Option<String>>> {
+    let series = df
+        .column(column)
+        .context(format!("Error fetching column {}", column))?
+        .cast(&DataType::String)
+        .context(format!("Error converting {} column to strings", column))?;
+    let values = series.str()?.into_iter().map(|v| v.map(str::to_string)).collect();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_welch_t_test() {
+        let (t, dof, p) = welch_t_test(&[1.0, 2.0, 3.0, 4.0, 5.0], &[6.0, 7.0, 8.0, 9.0, 10.0]).unwrap();
+        assert!((t + 5.0).abs() < 1e-9);
+        assert!((dof - 8.0).abs() < 1e-9);
+        assert!(p > 0.0009 && p < 0.0012);
+    }
+
+    #[test]
+    fn test_anova_and_chi_square_without_effect() {
+        let df = df!(
+            "alcohol" => &[9.0, 10.0, 11.0, 9.0, 10.0, 11.0],
+            "quality" => &[5i64, 5, 5, 6, 6, 6],
+            "style" => &["red", "white", "red", "white", "red", "white"]
+        )
+        .unwrap();
+
+        let anova = evaluate(&df, &HypothesisTest::Anova { column: "alcohol".to_string(), group_by: "quality".to_string() }).unwrap();
+        assert!(anova.statistic.abs() < 1e-12);
+        assert!((anova.p_value - 1.0).abs() < 1e-9);
+
+        let chi = evaluate(&df, &HypothesisTest::ChiSquare { column: "style".to_string(), group_by: "quality".to_string() }).unwrap();
+        assert_eq!(chi.degrees_of_freedom, 1.0);
+        assert!(chi.p_value > 0.05);
+    }
+}
@@ -0,0 +1,284 @@
+//! This module serves the HTTP control API of daemon mode.
+//!
+//! Every endpoint requires a static API key, sent as `Authorization: Bearer <key>`, and each key only
+//! grants the permissions listed for it in the `[[daemon.api.keys]]` configuration. Keys are read from
+//! environment variables so they never appear in the configuration file. Mutual TLS, where required,
+//! is expected to be terminated in front of the daemon (ingress or service mesh). The pages of the
+//! web UI, which hold no data, are the only routes served without a key; see [`crate::ui`].
+
+use crate::config::{ApiConfig, PipelineConfig};
+use crate::daemon::{RunCoordinator, RunRequest, Trigger, PIPELINE_NAME};
+use crate::encryption::ColumnCipher;
+use crate::live::{self, LiveFeed};
+use crate::records::{self, WineQualityRecord};
+use crate::status::{self, StatusDump};
+use crate::{reference, staging, storage, ui};
+use anyhow::{bail, Context, Result};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// An action an API key may be allowed to perform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// `POST /runs`: queue a run, and `POST /cache/invalidate`: drop cached reference tables before it.
+    TriggerRun,
+    /// `GET /runs/queue` and `GET /runs/live`: read the run queue and follow running runs, and
+    /// `GET /runs/history` and `GET /runs/<run id>/report`: read past runs and their quality reports, and
+    /// `GET /status`: dump the state of the runs in progress, as `SIGUSR1` does.
+    ReadStatus,
+    /// `POST /staging/<run id>/promote`: move a staged run into its production table.
+    PromoteRun,
+    /// `GET /records?min_quality=<n>&as_of=<run id>`: read stored wines, optionally as of a run, and
+    /// `GET /records/encrypted?limit=<n>`: read the first stored values of the encrypted columns in plaintext, and
+    /// `GET /runs/<run id>/rejects`: read the rows a run could not store.
+    ReadRecords,
+}
+
+/// An API key and what it grants.
+#[derive(Clone)]
+pub struct ApiKey {
+    pub name: String,
+    key: String,
+    pub permissions: Vec<Permission>,
+}
+
+/// Reads the configured keys from their environment variables.
+///
+/// # Arguments
+///
+/// * `config` - The API settings.
+///
+/// # Returns
+///
+/// * `Result<Vec<ApiKey>>` - The keys, or an error if a variable is unset or empty.
+pub fn resolve_keys(config: &ApiConfig) -> Result<Vec<ApiKey>> {
+    config
+        .keys
+        .iter()
+        .map(|k| {
+            let key = std::env::var(&k.key_env).context(format!("API key {} expects the environment variable {}", k.name, k.key_env))?;
+            if key.is_empty() {
+                bail!("API key {} is empty ({})", k.name, k.key_env);
+            }
+            Ok(ApiKey {
+                name: k.name.clone(),
+                key,
+                permissions: k.permissions.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Checks that the request carries a key granting `permission`.
+///
+/// # Returns
+///
+/// * `Result<&ApiKey, StatusCode>` - The matching key, `401` without a known key, or `403` if the key lacks the permission.
+pub fn authorize<'a>(keys: &'a [ApiKey], headers: &HeaderMap, permission: Permission) -> Result<&'a ApiKey, StatusCode> {
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let key = keys.iter().find(|k| constant_time_eq(k.key.as_bytes(), presented.as_bytes())).ok_or(StatusCode::UNAUTHORIZED)?;
+    if !key.permissions.contains(&permission) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(key)
+}
+
+/// Compares two byte strings in time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Shared state of the API handlers.
+#[derive(Clone)]
+pub struct ApiState {
+    pub keys: Arc<Vec<ApiKey>>,
+    pub requests: mpsc::UnboundedSender<RunRequest>,
+    pub coordinator: Arc<RunCoordinator>,
+    pub config: Arc<PipelineConfig>,
+    pub live: Arc<LiveFeed>,
+    /// Connection pool of the handlers reading or changing the database, created once when the API starts.
+    pub pool: PgPool,
+}
+
+#[derive(Debug, Serialize)]
+struct QueueStatus {
+    waiting: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Promotion {
+    run_id: String,
+    rows: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Invalidation {
+    invalidated: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct CacheFilter {
+    table: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordFilter {
+    #[serde(default)]
+    min_quality: i32,
+    /// ID of a run to read the wines as they were stored when it completed.
+    as_of: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EncryptedFilter {
+    /// Maximum number of rows to read.
+    limit: Option<i64>,
+}
+
+/// Rows read by `GET /records/encrypted` without a `limit`.
+const DEFAULT_ENCRYPTED_ROWS: i64 = 50;
+
+/// Builds the API routes.
+pub fn router(state: ApiState) -> Router {
+    let routes = if state.config.daemon.api.ui { ui::router() } else { Router::new() };
+    routes
+        .route("/runs", post(trigger_run))
+        .route("/runs/queue", get(queue_status))
+        .route("/runs/live", get(live_progress))
+        .route("/status", get(dump_status))
+        .route("/cache/invalidate", post(invalidate_cache))
+        .route("/staging/:run_id/promote", post(promote_run))
+        .route("/records", get(read_records))
+        .route("/records/encrypted", get(read_encrypted))
+        .with_state(state)
+}
+
+/// Serves the API until the daemon stops.
+///
+/// # Arguments
+///
+/// * `config` - The API settings.
+/// * `state` - The state shared with the daemon.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the listen address cannot be bound.
+pub async fn serve(config: &ApiConfig, state: ApiState) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(&config.listen)
+        .await
+        .context(format!("Failed to bind the control API to {}", config.listen))?;
+    println!("Control API listening on {}", config.listen);
+    axum::serve(listener, router(state)).await.context("Control API failed")
+}
+
+async fn trigger_run(State(state): State<ApiState>, headers: HeaderMap) -> Result<StatusCode, StatusCode> {
+    let key = authorize(&state.keys, &headers, Permission::TriggerRun)?;
+    println!("Run requested through the control API by {}", key.name);
+    state
+        .requests
+        .send(RunRequest { pipeline: PIPELINE_NAME.to_string(), trigger: Trigger::Api, input: None })
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn queue_status(State(state): State<ApiState>, headers: HeaderMap) -> Result<Json<QueueStatus>, StatusCode> {
+    authorize(&state.keys, &headers, Permission::ReadStatus)?;
+    Ok(Json(QueueStatus { waiting: state.coordinator.waiting() }))
+}
+
+async fn live_progress(State(state): State<ApiState>, headers: HeaderMap, upgrade: WebSocketUpgrade) -> Result<Response, StatusCode> {
+    let key = authorize(&state.keys, &headers, Permission::ReadStatus)?;
+    println!("Live progress subscribed through the control API by {}", key.name);
+    let events = state.live.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| live::forward(socket, events)))
+}
+
+async fn dump_status(State(state): State<ApiState>, headers: HeaderMap) -> Result<Json<StatusDump>, StatusCode> {
+    let key = authorize(&state.keys, &headers, Permission::ReadStatus)?;
+    let dump = status::snapshot();
+    println!("Status dumped through the control API by {}\n{}", key.name, dump.describe());
+    Ok(Json(dump))
+}
+
+async fn invalidate_cache(State(state): State<ApiState>, Query(filter): Query<CacheFilter>, headers: HeaderMap) -> Result<Json<Invalidation>, StatusCode> {
+    let key = authorize(&state.keys, &headers, Permission::TriggerRun)?;
+    let invalidated = reference::invalidate(filter.table.as_deref());
+    println!("{} cached reference tables invalidated through the control API by {}", invalidated, key.name);
+    Ok(Json(Invalidation { invalidated }))
+}
+
+async fn promote_run(State(state): State<ApiState>, Path(run_id): Path<String>, headers: HeaderMap) -> Result<Json<Promotion>, StatusCode> {
+    let key = authorize(&state.keys, &headers, Permission::PromoteRun)?;
+    println!("Promotion of run {} requested through the control API by {}", run_id, key.name);
+    // The run is missing, already decided, or does not fit the production table
+    let rows = staging::promote(&state.pool, &state.config.schema, &state.config.staging, &state.config.storage.table, &state.config.aggregates, &run_id).await.map_err(|e| {
+        eprintln!("{:#}", e);
+        StatusCode::CONFLICT
+    })?;
+    Ok(Json(Promotion { run_id, rows }))
+}
+
+async fn read_records(State(state): State<ApiState>, Query(filter): Query<RecordFilter>, headers: HeaderMap) -> Result<Json<Vec<WineQualityRecord>>, StatusCode> {
+    authorize(&state.keys, &headers, Permission::ReadRecords)?;
+    let records = records::fetch_by_quality(&state.pool, &state.config.schema, filter.min_quality, filter.as_of.as_deref()).await.map_err(|e| {
+        eprintln!("{:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(records))
+}
+
+async fn read_encrypted(State(state): State<ApiState>, Query(filter): Query<EncryptedFilter>, headers: HeaderMap) -> Result<Json<Vec<BTreeMap<String, Option<String>>>>, StatusCode> {
+    let key = authorize(&state.keys, &headers, Permission::ReadRecords)?;
+    let cipher = ColumnCipher::from_config(&state.config.storage.encryption).map_err(|e| {
+        eprintln!("{:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    // Without encrypted columns there is nothing to decrypt
+    let Some(cipher) = cipher else { return Err(StatusCode::NOT_FOUND) };
+    println!("Encrypted columns read in plaintext through the control API by {}", key.name);
+    let limit = filter.limit.unwrap_or(DEFAULT_ENCRYPTED_ROWS).max(0);
+    let rows = storage::fetch_decrypted(&state.pool, &state.config.schema, &state.config.storage.table, &cipher, limit).await.map_err(|e| {
+        eprintln!("{:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(rows.into_iter().map(|row| row.into_iter().collect()).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_authorize() {
+        let keys = vec![ApiKey {
+            name: "orchestrator".to_string(),
+            key: "secret".to_string(),
+            permissions: vec![Permission::TriggerRun],
+        }];
+
+        assert_eq!(authorize(&keys, &headers("Bearer secret"), Permission::TriggerRun).unwrap().name, "orchestrator");
+        assert_eq!(authorize(&keys, &headers("Bearer secret"), Permission::ReadStatus).err(), Some(StatusCode::FORBIDDEN));
+        assert_eq!(authorize(&keys, &headers("Bearer wrong"), Permission::TriggerRun).err(), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(authorize(&keys, &HeaderMap::new(), Permission::TriggerRun).err(), Some(StatusCode::UNAUTHORIZED));
+    }
+}
@@ -0,0 +1,228 @@
+//! This module persists intermediate DataFrames of a run as named Parquet artifacts.
+//!
+//! Artifacts are written below `<path>/<run id>/<name>.parquet` in a local directory or, with the `s3`
+//! feature, an S3 bucket. Keeping the raw, cleaned, normalized, and rejected rows of each run makes a
+//! failed load debuggable after the fact. Old runs are removed according to the retention settings.
+//! Artifacts are compressed with zstd unless `compression` names another codec or level. Runs encrypt
+//! the columns of `[storage.encryption]` before writing them, as for PostgreSQL.
+
+use crate::config::{ArtifactBackend, ArtifactsConfig};
+use crate::run::RunContext;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use polars::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+/// A location where runs persist their intermediate DataFrames.
+pub struct ArtifactStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    config: ArtifactsConfig,
+}
+
+impl ArtifactStore {
+    /// Opens the configured store.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The artifact settings.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<ArtifactStore>>` - The store, `None` if artifacts are disabled, or an error if the store cannot be opened.
+    pub fn from_config(config: &ArtifactsConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let (store, prefix): (Arc<dyn ObjectStore>, Path) = match config.backend {
+            ArtifactBackend::Local => {
+                std::fs::create_dir_all(&config.path).context(format!("Failed to create artifact directory {}", config.path))?;
+                let store = LocalFileSystem::new_with_prefix(&config.path).context(format!("Failed to open artifact directory {}", config.path))?;
+                (Arc::new(store), Path::default())
+            }
+            ArtifactBackend::S3 => (s3_store(config)?, Path::from(config.path.as_str())),
+        };
+
+        Ok(Some(Self {
+            store,
+            prefix,
+            config: config.clone(),
+        }))
+    }
+
+    /// Returns whether the artifact `name` is configured to be persisted.
+    pub fn wants(&self, name: &str) -> bool {
+        self.config.persist.iter().any(|p| p == name)
+    }
+
+    fn location(&self, run_id: &str, name: &str) -> Path {
+        self.prefix.child(run_id).child(format!("{}.parquet", name))
+    }
+
+    /// Writes a DataFrame as the run's artifact `name`, if that artifact is configured to be persisted.
+    ///
+    /// # Arguments
+    ///
+    /// * `run` - The context of the current run.
+    /// * `name` - The artifact name, e.g. `raw` or `rejects`.
+    /// * `df` - A reference to the DataFrame to persist.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - A result indicating success or failure of the write.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// store.put(&run, "raw", &df).await.expect("Failed to persist artifact");
+    /// ```
+    pub async fn put(&self, run: &RunContext, name: &str, df: &DataFrame) -> Result<()> {
+        if !self.wants(name) {
+            return Ok(());
+        }
+
+        let mut buffer = Vec::new();
+        ParquetWriter::new(&mut buffer)
+            .with_compression(self.config.compression.parquet()?)
+            .finish(&mut df.clone())
+            .context(format!("Failed to encode artifact {}", name))?;
+        let location = self.location(&run.id, name);
+        self.store
+            .put(&location, buffer.into())
+            .await
+            .context(format!("Failed to write artifact {}", location))?;
+        println!("Artifact {} written to {}", name, location);
+        Ok(())
+    }
+
+    /// Reads the artifact `name` of a run.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_id` - The ID of the run that wrote the artifact.
+    /// * `name` - The artifact name.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DataFrame>` - The persisted DataFrame, or an error if it does not exist.
+    pub async fn get(&self, run_id: &str, name: &str) -> Result<DataFrame> {
+        let location = self.location(run_id, name);
+        let bytes = self
+            .store
+            .get(&location)
+            .await
+            .context(format!("Failed to read artifact {}", location))?
+            .bytes()
+            .await?;
+        ParquetReader::new(Cursor::new(bytes.to_vec()))
+            .finish()
+            .context(format!("Failed to decode artifact {}", location))
+    }
+
+    /// Deletes the artifacts of runs outside the retention policy.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>>` - The IDs of the runs whose artifacts were deleted.
+    pub async fn apply_retention(&self) -> Result<Vec<String>> {
+        let objects: Vec<_> = self.store.list(Some(&self.prefix)).try_collect().await.context("Failed to list artifacts")?;
+
+        let prefix_depth = self.prefix.parts().count();
+        let mut runs: BTreeMap<String, (DateTime<Utc>, Vec<Path>)> = BTreeMap::new();
+        for object in objects {
+            let Some(run_id) = object.location.parts().nth(prefix_depth) else { continue };
+            let entry = runs.entry(run_id.as_ref().to_string()).or_insert((object.last_modified, vec![]));
+            entry.0 = entry.0.max(object.last_modified);
+            entry.1.push(object.location);
+        }
+
+        let written: Vec<(String, DateTime<Utc>)> = runs.iter().map(|(id, (modified, _))| (id.clone(), *modified)).collect();
+        let expired = expired_runs(&written, self.config.retention_runs, self.config.retention_days, Utc::now());
+        for run_id in &expired {
+            for location in &runs[run_id].1 {
+                self.store.delete(location).await.context(format!("Failed to delete artifact {}", location))?;
+            }
+        }
+        if !expired.is_empty() {
+            println!("Deleted artifacts of {} expired runs", expired.len());
+        }
+        Ok(expired)
+    }
+}
+
+/// Helper function to pick the runs to delete: all but the newest `keep_runs`, and all last written more than `max_age_days` ago.
+fn expired_runs(runs: &[(String, DateTime<Utc>)], keep_runs: Option<usize>, max_age_days: Option<u64>, now: DateTime<Utc>) -> Vec<String> {
+    let mut newest_first: Vec<&(String, DateTime<Utc>)> = runs.iter().collect();
+    newest_first.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+
+    newest_first
+        .into_iter()
+        .enumerate()
+        .filter(|(position, (_, modified))| {
+            keep_runs.is_some_and(|keep| *position >= keep)
+                || max_age_days.is_some_and(|days| now - *modified > Duration::days(days as i64))
+        })
+        .map(|(_, (id, _))| id.clone())
+        .collect()
+}
+
+#[cfg(feature = "s3")]
+fn s3_store(config: &ArtifactsConfig) -> Result<Arc<dyn ObjectStore>> {
+    let bucket = config.bucket.as_deref().context("artifacts.bucket is required for the s3 backend")?;
+    let store = object_store::aws::AmazonS3Builder::from_env()
+        .with_bucket_name(bucket)
+        .build()
+        .context(format!("Failed to open S3 bucket {}", bucket))?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_store(_config: &ArtifactsConfig) -> Result<Arc<dyn ObjectStore>> {
+    anyhow::bail!("The s3 artifact backend requires building with the `s3` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_expired_runs() {
+        let now = Utc::now();
+        let runs = vec![
+            ("run-a".to_string(), now - Duration::days(40)),
+            ("run-b".to_string(), now - Duration::days(2)),
+            ("run-c".to_string(), now - Duration::days(1)),
+            ("run-d".to_string(), now),
+        ];
+
+        assert_eq!(expired_runs(&runs, Some(2), None, now), vec!["run-b".to_string(), "run-a".to_string()]);
+        assert_eq!(expired_runs(&runs, None, Some(30), now), vec!["run-a".to_string()]);
+        assert!(expired_runs(&runs, None, None, now).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get_roundtrip() {
+        let config = ArtifactsConfig {
+            enabled: true,
+            path: "temp_artifacts_test".to_string(),
+            ..ArtifactsConfig::default()
+        };
+        let store = ArtifactStore::from_config(&config).unwrap().expect("Store is enabled");
+        let run = RunContext::new();
+        let df = df!("alcohol" => &[9.4, 9.8], "quality" => &[5i64, 6]).unwrap();
+
+        store.put(&run, "raw", &df).await.expect("Failed to write artifact");
+        let read = store.get(&run.id, "raw").await.expect("Failed to read artifact");
+        std::fs::remove_dir_all("temp_artifacts_test").ok();
+
+        assert!(read.equals(&df));
+    }
+}
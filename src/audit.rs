@@ -0,0 +1,73 @@
+//! This module adds the audit columns that tie every stored row to the run that loaded it.
+//!
+//! Each row gets the run ID and the run's labels, serialized as a JSON object and stored as JSONB, so
+//! batches can be sliced by operational metadata later, e.g.
+//! `SELECT avg(quality) FROM wine_quality WHERE labels->>'vintage' = '2023'`.
+
+use crate::run::RunContext;
+use anyhow::{Context, Result};
+use polars::prelude::*;
+
+/// Column holding the ID of the run that stored the row.
+pub const RUN_ID_COLUMN: &str = "run_id";
+
+/// Column holding the labels of the run that stored the row.
+pub const LABELS_COLUMN: &str = "labels";
+
+/// Label of runs that processed only the first rows of their input, holding the row limit.
+pub const ROW_LIMIT_LABEL: &str = "row_limit";
+
+/// Label of runs that processed a random sample of their input, describing the sample.
+pub const SAMPLE_LABEL: &str = "sample";
+
+/// Adds the `run_id` and `labels` columns to a DataFrame about to be stored.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame to store.
+/// * `run` - The context of the current run.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with the audit columns, or an error if they cannot be added.
+///
+/// # Example
+///
+/// ```
+/// let df = add_audit_columns(df, &run).expect("Failed to add audit columns");
+/// ```
+pub fn add_audit_columns(mut df: DataFrame, run: &RunContext) -> Result<DataFrame> {
+    let labels = serde_json::to_string(&run.labels).context("Failed to serialize run labels")?;
+    let height = df.height();
+    df.with_column(Series::new(RUN_ID_COLUMN, vec![run.id.clone(); height]))
+        .context(format!("Failed to add the {} column", RUN_ID_COLUMN))?;
+    df.with_column(Series::new(LABELS_COLUMN, vec![labels; height]))
+        .context(format!("Failed to add the {} column", LABELS_COLUMN))?;
+    Ok(df)
+}
+
+/// Removes the audit columns, e.g. before rows of an earlier run are stored again by another run.
+pub fn drop_audit_columns(df: DataFrame) -> DataFrame {
+    df.drop_many(&[RUN_ID_COLUMN, LABELS_COLUMN])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_add_audit_columns() {
+        let run = RunContext::new().with_labels(BTreeMap::from([
+            ("vintage".to_string(), "2023".to_string()),
+            ("lab".to_string(), "bordeaux".to_string()),
+        ]));
+        let df = polars::df!("alcohol" => &[9.4, 9.8]).unwrap();
+
+        let df = add_audit_columns(df, &run).unwrap();
+
+        assert_eq!(df.column(RUN_ID_COLUMN).unwrap().str().unwrap().get(1), Some(run.id.as_str()));
+        assert_eq!(df.column(LABELS_COLUMN).unwrap().str().unwrap().get(0), Some(r#"{"lab":"bordeaux","vintage":"2023"}"#));
+        assert_eq!(drop_audit_columns(df).get_column_names(), vec!["alcohol"]);
+    }
+}
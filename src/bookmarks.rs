@@ -0,0 +1,317 @@
+//! This module reads only the rows appended to CSV files since the last run, for append-only feeds such as logs.
+//!
+//! With `[deduplication] incremental`, a run reading a CSV file, or the files a glob pattern matches,
+//! looks up the byte offset up to which earlier runs read each file in the `file_bookmarks` table and
+//! parses only the rows after it: the new bytes are copied to a temporary file behind the file's
+//! preamble (its skipped lines and header row), which is read like the file itself. A row still being
+//! written, with no line ending yet, is left for the next run. Once the run has stored the new rows,
+//! the bookmarks are moved past them. A file shorter than its bookmark, or whose first bytes changed,
+//! was rotated or rewritten, and is read from the start again. A run with no new rows in any file is
+//! skipped. Compressed and UTF-16 files, whose byte offsets do not fall between rows, cannot be read
+//! incrementally.
+
+use crate::ingestion::{self, Compression, Encoding, IngestOptions};
+use crate::seed;
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use ulid::Ulid;
+
+/// DDL of the table of bookmarks, one row per input file.
+pub const FILE_BOOKMARKS_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS file_bookmarks (
+    path TEXT PRIMARY KEY,
+    byte_offset BIGINT NOT NULL,
+    head_checksum TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+)";
+
+/// Bytes at the start of a file whose checksum tells a file that grew from one that was replaced.
+const HEAD_BYTES: u64 = 4096;
+
+/// Bytes read at a time when looking back for the end of the last complete row.
+const BLOCK_BYTES: u64 = 64 * 1024;
+
+/// The part of an input file a run reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tail {
+    pub path: String,
+    /// Offset of the first byte read: the bookmark, or 0 for a new, rotated, or rewritten file.
+    pub start: u64,
+    /// Offset past the last complete row, which becomes the bookmark once the run stored the rows.
+    pub end: u64,
+    /// SHA-256 checksum of the first `HEAD_BYTES` bytes before `end`.
+    head_checksum: String,
+}
+
+impl Tail {
+    /// Tells whether no rows were appended since the bookmark.
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Describes the part read, for the log.
+    pub fn describe(&self) -> String {
+        if self.is_empty() {
+            format!("No rows appended to input file {} since byte {}", self.path, self.start)
+        } else {
+            format!("Reading input file {} from byte {} to {} ({} new bytes)", self.path, self.start, self.end, self.end - self.start)
+        }
+    }
+}
+
+/// The parts of a run's input files it reads, and the files they are read from.
+///
+/// The temporary files holding the new rows are removed when it is dropped.
+#[derive(Debug, Default)]
+pub struct Tails {
+    pub tails: Vec<Tail>,
+    /// Files read by the run in order: a file itself when it is read whole, a temporary file otherwise.
+    pub files: Vec<String>,
+    temporary: Vec<PathBuf>,
+}
+
+impl Drop for Tails {
+    fn drop(&mut self) {
+        for path in &self.temporary {
+            if let Err(e) = std::fs::remove_file(path) {
+                eprintln!("Failed to remove the temporary file {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Looks up the bookmarks of the files of an input and prepares the rows appended after them for reading.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `path` - The input file, or a glob pattern matching several.
+/// * `options` - How the files are delimited, which tells their preamble.
+///
+/// # Returns
+///
+/// * `Result<Tails>` - The parts to read, or an error if a file is compressed, in UTF-16, or cannot be read, or the lookup fails.
+///
+/// # Example
+///
+/// ```
+/// let tails = plan(&pool, "logs/tastings-*.csv", &IngestOptions::default()).await?;
+/// ```
+pub async fn plan(pool: &PgPool, path: &str, options: &IngestOptions) -> Result<Tails> {
+    let paths = ingestion::expand_paths(path)?;
+    // Looked up before the run sets up the database, so on a fresh one no file was read yet
+    let bookmarks: Vec<(String, i64, String)> = if seed::table_exists(pool, "file_bookmarks").await? {
+        sqlx::query_as("SELECT path, byte_offset, head_checksum FROM file_bookmarks WHERE path = ANY($1)")
+            .bind(&paths)
+            .fetch_all(pool)
+            .await
+            .context("Failed to look up the file bookmarks")?
+    } else {
+        vec![]
+    };
+    let bookmarks: HashMap<String, (u64, String)> = bookmarks.into_iter().map(|(path, offset, checksum)| (path, (offset.max(0) as u64, checksum))).collect();
+
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut tails = Tails::default();
+        for path in paths {
+            let tail = locate(&path, bookmarks.get(&path), &options)?;
+            let len = File::open(&path).and_then(|file| file.metadata()).context(format!("Failed to read {}", path))?.len();
+            if tail.start == 0 && tail.end == len {
+                tails.files.push(path);
+            } else if !tail.is_empty() {
+                let temporary = temporary_path(&path);
+                // Listed first, so a failed copy is removed too
+                tails.temporary.push(temporary.clone());
+                extract(&tail, &options, &temporary)?;
+                tails.files.push(temporary.display().to_string());
+            }
+            tails.tails.push(tail);
+        }
+        Ok(tails)
+    })
+    .await
+    .context("Bookmark lookup panicked")?
+}
+
+/// Moves the bookmarks of the files a run read past the rows it stored.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run_id` - The ID of the run that read the files.
+/// * `tails` - The parts of the files it read.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the bookmarks cannot be recorded.
+pub async fn record(pool: &PgPool, run_id: &str, tails: &[Tail]) -> Result<()> {
+    let paths: Vec<&str> = tails.iter().map(|t| t.path.as_str()).collect();
+    let offsets: Vec<i64> = tails.iter().map(|t| t.end as i64).collect();
+    let checksums: Vec<&str> = tails.iter().map(|t| t.head_checksum.as_str()).collect();
+    sqlx::query(
+        "INSERT INTO file_bookmarks (path, byte_offset, head_checksum, run_id) \
+         SELECT path, byte_offset, head_checksum, $4 FROM UNNEST($1::TEXT[], $2::BIGINT[], $3::TEXT[]) AS b (path, byte_offset, head_checksum) \
+         ON CONFLICT (path) DO UPDATE SET byte_offset = EXCLUDED.byte_offset, head_checksum = EXCLUDED.head_checksum, run_id = EXCLUDED.run_id, updated_at = now()",
+    )
+    .bind(&paths)
+    .bind(&offsets)
+    .bind(&checksums)
+    .bind(run_id)
+    .execute(pool)
+    .await
+    .context(format!("Failed to record the file bookmarks of run {}", run_id))?;
+    Ok(())
+}
+
+/// Helper function to find the part of a file after its bookmark, up to the end of its last complete row.
+fn locate(path: &str, bookmark: Option<&(u64, String)>, options: &IngestOptions) -> Result<Tail> {
+    if Compression::detect(path)? != Compression::None {
+        bail!("Input file {} is compressed, so it cannot be read incrementally; disable deduplication.incremental", path);
+    }
+    if matches!(options.encoding.detect(&mut ingestion::open_input(path)?)?, Encoding::Utf16le | Encoding::Utf16be) {
+        bail!("Input file {} is in UTF-16, so it cannot be read incrementally; disable deduplication.incremental", path);
+    }
+
+    let mut file = File::open(path).context(format!("Failed to open {}", path))?;
+    let len = file.metadata().context(format!("Failed to read {}", path))?.len();
+    let start = match bookmark {
+        Some((offset, checksum)) if *offset <= len && head_checksum(&mut file, *offset)? == *checksum => *offset,
+        Some(_) => {
+            eprintln!("Reading input file {} from the start: it is shorter than its bookmark or its first bytes changed, so it was rotated or rewritten", path);
+            0
+        }
+        None => 0,
+    };
+    let end = row_end(&mut file, start, len).context(format!("Failed to read {}", path))?;
+    Ok(Tail { path: path.to_string(), start, end, head_checksum: head_checksum(&mut file, end)? })
+}
+
+/// Helper function to checksum the first `HEAD_BYTES` bytes of a file before an offset.
+fn head_checksum(file: &mut File, offset: u64) -> Result<String> {
+    let mut head = vec![];
+    file.seek(SeekFrom::Start(0))?;
+    file.take(offset.min(HEAD_BYTES)).read_to_end(&mut head)?;
+    Ok(Sha256::digest(&head).iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Helper function to find the offset past the last line ending after `start`, or `start` if there is none.
+fn row_end(file: &mut File, start: u64, len: u64) -> std::io::Result<u64> {
+    let mut block_end = len;
+    while block_end > start {
+        let block_start = block_end.saturating_sub(BLOCK_BYTES).max(start);
+        let mut block = vec![0; (block_end - block_start) as usize];
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block)?;
+        if let Some(i) = block.iter().rposition(|&b| b == b'\n') {
+            return Ok(block_start + i as u64 + 1);
+        }
+        block_end = block_start;
+    }
+    Ok(start)
+}
+
+/// Helper function to read the preamble of a CSV file: its skipped and comment lines, and its header row if it has one.
+fn preamble(path: &str, options: &IngestOptions) -> Result<Vec<u8>> {
+    let mut reader = BufReader::new(File::open(path).context(format!("Failed to open {}", path))?);
+    let mut preamble = vec![];
+    let mut line = vec![];
+    let mut skipped = 0;
+    while reader.read_until(b'\n', &mut line).context(format!("Failed to read {}", path))? > 0 {
+        let text = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+        if skipped < options.skip_rows {
+            skipped += 1;
+        } else if !options.is_comment(&text) {
+            if options.is_header(&text) {
+                preamble.append(&mut line);
+            }
+            break;
+        }
+        preamble.append(&mut line);
+    }
+    Ok(preamble)
+}
+
+/// Helper function to name the temporary file holding the new rows of a file after it.
+fn temporary_path(path: &str) -> PathBuf {
+    let name = Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    std::env::temp_dir().join(format!("wine_quality_tail_{}_{}", Ulid::new(), name))
+}
+
+/// Helper function to write the preamble of a file and the bytes of its tail to a temporary file.
+fn extract(tail: &Tail, options: &IngestOptions, temporary: &Path) -> Result<()> {
+    let write = || -> Result<()> {
+        let mut out = File::create(temporary)?;
+        if tail.start > 0 {
+            out.write_all(&preamble(&tail.path, options)?)?;
+        }
+        let mut file = File::open(&tail.path)?;
+        file.seek(SeekFrom::Start(tail.start))?;
+        std::io::copy(&mut file.take(tail.end - tail.start), &mut out)?;
+        Ok(())
+    };
+    write().context(format!("Failed to copy the new rows of {} to {}", tail.path, temporary.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_rows_appended_after_bookmark() {
+        let path = "temp_bookmarks_test.csv";
+        let options = IngestOptions { skip_rows: 1, ..IngestOptions::default() };
+        std::fs::write(path, "# tasting log\nalcohol,quality\n9.4,5\n9.8,5\n").expect("Failed to write temp CSV file");
+        let first = locate(path, None, &options).unwrap();
+        assert_eq!((first.start, first.end), (0, 42));
+
+        // A row still being written is left for the next run
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(b"10.1,6\n10.4,").unwrap();
+        let bookmark = (first.end, first.head_checksum.clone());
+        let second = locate(path, Some(&bookmark), &options).unwrap();
+        assert_eq!((second.start, second.end), (42, 49));
+        let temporary = temporary_path(path);
+        extract(&second, &options, &temporary).unwrap();
+        let extracted = std::fs::read_to_string(&temporary).unwrap();
+        std::fs::remove_file(&temporary).ok();
+        assert_eq!(extracted, "# tasting log\nalcohol,quality\n10.1,6\n");
+
+        std::fs::write(path, "alcohol,quality\n11.2,7\n").expect("Failed to write temp CSV file");
+        let rotated = locate(path, Some(&bookmark), &options).unwrap();
+        std::fs::remove_file(path).ok();
+        assert_eq!((rotated.start, rotated.end), (0, 23));
+    }
+
+    #[tokio::test]
+    async fn test_second_run_stores_only_appended_rows() -> Result<()> {
+        use crate::hooks::Hooks;
+        use crate::testing::{Scratch, ROWS};
+        use crate::{pipeline, storage};
+
+        dotenv::dotenv().ok();
+        let pool = storage::create_connection_pool().await?;
+        let mut scratch = Scratch::default();
+        let table = scratch.table("temp_bookmarks_runs");
+        // A path of its own, so no bookmark of an earlier test run applies
+        let path = scratch.csv("temp_bookmarks", &ROWS[..2])?;
+        let mut config = Scratch::config(&table, &path);
+        config.deduplication.incremental = true;
+        pipeline::run(&config, &Hooks::from_commands(&[])).await?;
+
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path)?;
+        file.write_all(ROWS[2..].concat().as_bytes())?;
+        drop(file);
+        pipeline::run(&config, &Hooks::from_commands(&[])).await?;
+
+        // The rows of both runs, each stored once
+        let stored: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {}", table)).fetch_one(&pool).await?;
+        assert_eq!(stored, 5);
+        Ok(())
+    }
+}
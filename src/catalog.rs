@@ -0,0 +1,177 @@
+//! This module publishes dataset metadata to a data catalog after each run.
+//!
+//! Metadata (schema, row counts, owner, lineage, freshness) is upserted into the local `data_catalog`
+//! table and, when an endpoint is configured, posted as JSON to an external catalog API.
+
+use crate::config::CatalogConfig;
+use crate::run::RunContext;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use polars::prelude::*;
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+
+/// A column of the published schema.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnMetadata {
+    pub name: String,
+    pub dtype: String,
+}
+
+/// Where the data came from and how it was produced.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Lineage {
+    pub source: String,
+    pub run_id: String,
+    pub stages: Vec<String>,
+}
+
+/// The metadata document published for the dataset.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DatasetMetadata {
+    pub dataset: String,
+    pub table: String,
+    pub owner: String,
+    pub description: String,
+    pub schema: Vec<ColumnMetadata>,
+    pub rows_loaded: usize,
+    pub table_rows: i64,
+    pub lineage: Lineage,
+    /// RFC 3339 timestamp of the load.
+    pub loaded_at: String,
+}
+
+/// Builds the metadata document for a completed load.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the stored DataFrame.
+/// * `config` - The catalog settings.
+/// * `table` - The table the data was stored in.
+/// * `lineage` - The source and stages that produced the data.
+/// * `table_rows` - The total number of rows in the target table after the load.
+///
+/// # Returns
+///
+/// * `DatasetMetadata` - The metadata document.
+pub fn build_metadata(df: &DataFrame, config: &CatalogConfig, table: &str, lineage: Lineage, table_rows: i64) -> DatasetMetadata {
+    DatasetMetadata {
+        dataset: config.dataset.clone(),
+        table: table.to_string(),
+        owner: config.owner.clone(),
+        description: config.description.clone(),
+        schema: df
+            .get_columns()
+            .iter()
+            .map(|s| ColumnMetadata { name: s.name().to_string(), dtype: s.dtype().to_string() })
+            .collect(),
+        rows_loaded: df.height(),
+        table_rows,
+        lineage,
+        loaded_at: Utc::now().to_rfc3339(),
+    }
+}
+
+/// Publishes metadata for the run's load, if the catalog is enabled.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the current run.
+/// * `df` - A reference to the stored DataFrame.
+/// * `config` - The catalog settings.
+/// * `table` - The table the data was stored in.
+/// * `source` - The input the data was read from.
+/// * `stages` - The pipeline stages applied to the data.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of publication.
+///
+/// # Example
+///
+/// ```
+/// publish(&pool, &run, &transformed_df, &config.catalog, "wine_quality", "data/dataset.csv", &stages).await.expect("Catalog publication failed");
+/// ```
+pub async fn publish(pool: &PgPool, run: &RunContext, df: &DataFrame, config: &CatalogConfig, table: &str, source: &str, stages: &[String]) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let table_rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(pool)
+        .await
+        .context("Failed to count rows for the catalog")?;
+    let lineage = Lineage {
+        source: source.to_string(),
+        run_id: run.id.clone(),
+        stages: stages.to_vec(),
+    };
+    let metadata = build_metadata(df, config, table, lineage, table_rows);
+
+    sqlx::query(
+        r#"
+        INSERT INTO data_catalog (dataset, table_name, owner, description, schema, row_count, lineage, last_run_id, last_loaded_at)
+        VALUES ($1, $2, $3, $4, $5::jsonb, $6, $7::jsonb, $8, now())
+        ON CONFLICT (dataset) DO UPDATE SET
+            table_name = EXCLUDED.table_name,
+            owner = EXCLUDED.owner,
+            description = EXCLUDED.description,
+            schema = EXCLUDED.schema,
+            row_count = EXCLUDED.row_count,
+            lineage = EXCLUDED.lineage,
+            last_run_id = EXCLUDED.last_run_id,
+            last_loaded_at = EXCLUDED.last_loaded_at
+        "#,
+    )
+    .bind(&metadata.dataset)
+    .bind(&metadata.table)
+    .bind(&metadata.owner)
+    .bind(&metadata.description)
+    .bind(serde_json::to_string(&metadata.schema)?)
+    .bind(metadata.table_rows)
+    .bind(serde_json::to_string(&metadata.lineage)?)
+    .bind(&run.id)
+    .execute(pool)
+    .await
+    .context("Failed to update the data_catalog table")?;
+    println!("Published catalog metadata for dataset {}", metadata.dataset);
+
+    if let Some(endpoint) = &config.endpoint {
+        let mut request = reqwest::Client::new().post(endpoint).json(&metadata);
+        if let Some(token) = config.token_env.as_deref().and_then(|name| std::env::var(name).ok()) {
+            request = request.bearer_auth(token);
+        }
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context(format!("Failed to publish catalog metadata to {}", endpoint))?;
+        println!("Posted catalog metadata to {}", endpoint);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_build_metadata() {
+        let df = df!("alcohol" => &[9.4, 9.8], "quality" => &[5i64, 6]).unwrap();
+        let lineage = Lineage {
+            source: "data/dataset.csv".to_string(),
+            run_id: "test-run".to_string(),
+            stages: vec!["ingest".to_string(), "transform".to_string()],
+        };
+
+        let metadata = build_metadata(&df, &CatalogConfig::default(), "wine_quality", lineage, 10);
+
+        assert_eq!(metadata.dataset, "wine_quality");
+        assert_eq!(metadata.rows_loaded, 2);
+        assert_eq!(metadata.table_rows, 10);
+        assert_eq!(metadata.schema[1], ColumnMetadata { name: "quality".to_string(), dtype: "i64".to_string() });
+    }
+}
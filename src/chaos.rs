@@ -0,0 +1,156 @@
+//! This module injects faults for resilience testing.
+//!
+//! With `[chaos] enabled` in a binary built with the `chaos` feature, runs fail on purpose at the
+//! configured rates: connecting to the database or acquiring a connection fails with a transient
+//! error, reads of the source are delayed, and ingested rows get values no column can store. That
+//! exercises retries from checkpoints, the rejects path, replay, and alerting hooks before production
+//! relies on them. Builds without the feature refuse to enable it, so production binaries never inject faults.
+
+use crate::config::ChaosConfig;
+use crate::generate::Rng;
+use anyhow::{anyhow, bail, Result};
+use polars::prelude::*;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Value written into malformed rows; it overflows every `DECIMAL` and `INTEGER` column of the schema.
+const MALFORMED_VALUE: f64 = 1e12;
+
+static CHAOS: OnceLock<Chaos> = OnceLock::new();
+
+struct Chaos {
+    config: ChaosConfig,
+    rng: Mutex<Rng>,
+}
+
+impl Chaos {
+    fn roll(&self, rate: f64) -> bool {
+        self.rng.lock().expect("Chaos generator poisoned").chance(rate)
+    }
+}
+
+/// Enables fault injection for this invocation, if configured.
+///
+/// # Arguments
+///
+/// * `config` - The fault injection settings.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if fault injection is enabled in a build without the `chaos` feature.
+pub fn install(config: &ChaosConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    if !cfg!(feature = "chaos") {
+        bail!("Fault injection requires building with the `chaos` feature");
+    }
+    eprintln!(
+        "Chaos mode: injecting database errors at {}, slow reads at {}, and malformed rows at {}",
+        config.db_error_rate, config.slow_read_rate, config.malformed_row_rate
+    );
+    CHAOS
+        .set(Chaos { config: config.clone(), rng: Mutex::new(Rng::new(config.seed)) })
+        .map_err(|_| anyhow!("Fault injection was already enabled"))
+}
+
+/// Fails with a transient database error at the configured rate.
+///
+/// # Arguments
+///
+/// * `operation` - The database operation that fails, for the error message.
+pub fn inject_db_error(operation: &str) -> Result<()> {
+    match CHAOS.get() {
+        Some(chaos) if chaos.roll(chaos.config.db_error_rate) => bail!("Injected transient database error: failed to {}", operation),
+        _ => Ok(()),
+    }
+}
+
+/// Delays a read of the source at the configured rate.
+pub async fn slow_read() {
+    if let Some(chaos) = CHAOS.get().filter(|chaos| chaos.roll(chaos.config.slow_read_rate)) {
+        eprintln!("Chaos mode: delaying read by {} ms", chaos.config.slow_read_ms);
+        tokio::time::sleep(Duration::from_millis(chaos.config.slow_read_ms)).await;
+    }
+}
+
+/// Overwrites a numeric value in rows of the DataFrame at the configured rate, so they cannot be stored.
+///
+/// # Arguments
+///
+/// * `df` - The ingested DataFrame.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with malformed rows, or an error if a column cannot be replaced.
+pub fn corrupt_rows(df: DataFrame) -> Result<DataFrame> {
+    match CHAOS.get() {
+        Some(chaos) if chaos.config.malformed_row_rate > 0.0 => {
+            let mut rng = chaos.rng.lock().expect("Chaos generator poisoned");
+            corrupt_with(df, chaos.config.malformed_row_rate, &mut rng)
+        }
+        _ => Ok(df),
+    }
+}
+
+/// Helper function to overwrite one numeric value of each chosen row.
+fn corrupt_with(mut df: DataFrame, rate: f64, rng: &mut Rng) -> Result<DataFrame> {
+    let numeric: Vec<String> = df
+        .get_columns()
+        .iter()
+        .filter(|s| s.dtype().is_numeric())
+        .map(|s| s.name().to_string())
+        .collect();
+    if numeric.is_empty() {
+        return Ok(df);
+    }
+
+    let mut malformed: Vec<Vec<bool>> = vec![vec![false; df.height()]; numeric.len()];
+    for row in 0..df.height() {
+        if rng.chance(rate) {
+            malformed[rng.below(numeric.len() as u64) as usize][row] = true;
+        }
+    }
+    let total: usize = malformed.iter().map(|rows| rows.iter().filter(|&&m| m).count()).sum();
+
+    for (name, rows) in numeric.iter().zip(malformed).filter(|(_, rows)| rows.contains(&true)) {
+        let series = df.column(name)?;
+        let dtype = series.dtype().clone();
+        let values: Float64Chunked = series
+            .cast(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .zip(rows)
+            .map(|(value, malformed)| if malformed { Some(MALFORMED_VALUE) } else { value })
+            .collect();
+        df.with_column(values.with_name(name).into_series().cast(&dtype)?)?;
+    }
+    eprintln!("Chaos mode: malformed {} of {} rows", total, df.height());
+    Ok(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corrupt_with_overwrites_one_value_per_chosen_row() {
+        let df = polars::df!(
+            "alcohol" => &[9.4, 9.8, 10.1, 10.5],
+            "quality" => &[5i64, 5, 6, 7],
+            "taster" => &["a", "b", "c", "d"]
+        )
+        .unwrap();
+
+        let corrupted = corrupt_with(df.clone(), 1.0, &mut Rng::new(3)).unwrap();
+        let alcohol = corrupted.column("alcohol").unwrap().f64().unwrap();
+        let quality = corrupted.column("quality").unwrap().i64().unwrap();
+        for row in 0..4 {
+            let malformed = [alcohol.get(row), quality.get(row).map(|q| q as f64)].iter().filter(|v| **v == Some(MALFORMED_VALUE)).count();
+            assert_eq!(malformed, 1);
+        }
+        assert!(corrupted.column("taster").unwrap().equals(df.column("taster").unwrap()));
+
+        assert!(corrupt_with(df.clone(), 0.0, &mut Rng::new(3)).unwrap().column("alcohol").unwrap().equals(df.column("alcohol").unwrap()));
+    }
+}
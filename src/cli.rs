@@ -0,0 +1,162 @@
+//! This module defines the command-line interface of the pipeline binary.
+//!
+//! Running the binary without a subcommand executes the full pipeline.
+
+use crate::bugreport::BugReportRequest;
+use crate::compare::CompareRequest;
+use crate::config::DEFAULT_CONFIG_PATH;
+use crate::export::ExportRequest;
+use crate::generate::GenerateRequest;
+use crate::profile::SuggestRulesRequest;
+use clap::{Parser, Subcommand};
+
+/// Wine quality data pipeline: ingests, transforms, and stores the dataset.
+#[derive(Debug, Parser)]
+#[command(name = "pipeline", version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Path to the pipeline configuration file.
+    #[arg(long, global = true, env = "PIPELINE_CONFIG", default_value = DEFAULT_CONFIG_PATH)]
+    pub config: String,
+
+    /// Label attached to the run as `KEY=VALUE`, e.g. `--label vintage=2023`; may be repeated.
+    #[arg(long = "label", global = true, value_name = "KEY=VALUE", value_parser = parse_label)]
+    pub labels: Vec<(String, String)>,
+
+    /// Retry a failed run up to N times, resuming after its last completed stage.
+    #[arg(long, global = true, value_name = "N")]
+    pub auto_retry: Option<u32>,
+
+    /// Process at most N rows of the input, e.g. to debug a production file; the run is labelled `row_limit=N`.
+    #[arg(long, global = true, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Process a random sample of the input rows, a share such as `1%` or a number of rows such as `1000`, e.g. for a quick smoke run; the run is labelled `sample=<size>`.
+    #[arg(long, global = true, value_name = "PERCENT|ROWS", value_parser = parse_sample)]
+    pub sample: Option<SampleSize>,
+
+    /// Seed of the random sample; the same seed keeps the same rows of the same input.
+    #[arg(long, global = true, value_name = "SEED")]
+    pub sample_seed: Option<u64>,
+
+    /// Evaluate the expectations on a random sample of the rows first, e.g. `5%`, and on all rows only if the sample fails.
+    #[arg(long, global = true, value_name = "PERCENT", value_parser = parse_percentage)]
+    pub validate_sample: Option<f64>,
+
+    /// Route the data and run history to the tenant's own PostgreSQL schema, `tenant_<TENANT>`, created on demand.
+    #[arg(long, global = true, env = "PIPELINE_TENANT")]
+    pub tenant: Option<String>,
+
+    /// Load each dataset listed in a YAML or JSON manifest, one run per dataset, instead of the configured source.
+    #[arg(long, value_name = "PATH", conflicts_with = "stdin")]
+    pub manifest: Option<String>,
+
+    /// Read CSV rows from standard input instead of the configured source, e.g. `cat wines.csv | pipeline --stdin`.
+    #[arg(long, global = true)]
+    pub stdin: bool,
+
+    /// Start an ephemeral PostgreSQL server for this invocation instead of using DATABASE_URL (requires the `embedded-postgres` feature).
+    #[arg(long, global = true)]
+    pub embedded_db: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands of the pipeline binary.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Profiles an input file: dtypes, missingness, distributions, outliers, correlations and suggested validation rules.
+    Profile {
+        /// Path to the file to profile.
+        file: String,
+    },
+    /// Profiles a trusted historical file and writes a draft `[expectations]` suite its values satisfy.
+    SuggestRules(SuggestRulesRequest),
+    /// Re-transforms and re-inserts the rows a run rejected, reporting which rows now succeed.
+    ReplayDlq {
+        /// ID of the run whose `rejects` artifact is replayed.
+        #[arg(long)]
+        run: String,
+    },
+    /// Runs the default pipeline over a built-in sample and checks that every row was stored.
+    Selftest,
+    /// Benchmarks insert batch sizes and concurrency against the configured database with synthetic rows.
+    Tune {
+        /// Number of synthetic rows loaded by each trial.
+        #[arg(long, default_value_t = 20_000)]
+        rows: usize,
+        /// Write the recommended settings into the `[storage]` section of the configuration file.
+        #[arg(long)]
+        write: bool,
+    },
+    /// Exports stored rows matching a condition to Parquet or CSV files, locally or on S3.
+    Export(ExportRequest),
+    /// Moves the rows of a staged run into `storage.table` after review, or discards them.
+    Promote {
+        /// ID of the staged run.
+        #[arg(long)]
+        run: String,
+        /// Delete the staged rows instead of promoting them.
+        #[arg(long)]
+        discard: bool,
+    },
+    /// Diffs staged rows against `wine_quality` (row counts, keys added and removed, column aggregates) before they are promoted.
+    CompareTables(CompareRequest),
+    /// Writes a synthetic CSV for the configured schema, with optional missing values and outliers, for load tests and demos.
+    Generate(GenerateRequest),
+    /// Bundles an anonymized sample of the input, the redacted configuration and the error into a directory to attach to an issue.
+    BugReport(BugReportRequest),
+    /// Checks that everything a run needs is in place (configuration, database, tables, input, disk, credentials) and prints fixes.
+    Doctor,
+    /// Runs the pipeline as a daemon, whenever a trigger from the `[daemon]` configuration section fires.
+    Daemon,
+    /// Drops the table rows are stored in and creates it again empty, with the per-run result tables.
+    ResetDb {
+        /// Confirm that every stored row of the table is to be deleted.
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+/// Size of the random sample of the input given with `--sample`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleSize {
+    /// A share of the rows, as a fraction.
+    Fraction(f64),
+    /// A number of rows.
+    Rows(usize),
+}
+
+/// Parses a sample size such as `1%` or `1000`.
+fn parse_sample(value: &str) -> Result<SampleSize, String> {
+    if value.trim().ends_with('%') {
+        return parse_percentage(value).map(SampleSize::Fraction);
+    }
+    match value.trim().parse::<usize>() {
+        Ok(rows) if rows > 0 => Ok(SampleSize::Rows(rows)),
+        _ => Err(format!("expected a percentage such as 1% or a number of rows such as 1000, got `{}`", value)),
+    }
+}
+
+/// Parses a `KEY=VALUE` label.
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("expected KEY=VALUE, got `{}`", label)),
+    }
+}
+
+/// Parses a percentage such as `5%` or `2.5%` into a fraction.
+fn parse_percentage(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .trim()
+        .strip_suffix('%')
+        .unwrap_or(value)
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a percentage such as 5%, got `{}`", value))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(format!("expected a percentage between 0% and 100%, got `{}`", value));
+    }
+    Ok(percent / 100.0)
+}
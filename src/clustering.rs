@@ -0,0 +1,270 @@
+//! This module assigns each row to a k-means cluster of wine styles.
+//!
+//! It standardizes the features, fits centroids with Lloyd's algorithm, and appends the label as a
+//! `cluster` column. The centroids are persisted so later runs label rows against the same clusters.
+
+use crate::config::ClusteringConfig;
+use crate::run::RunContext;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File name of the centroids artifact inside the run's artifact directory.
+pub const CENTROIDS_FILE_NAME: &str = "kmeans_centroids.json";
+
+/// Name of the label column appended by [`clustering_stage`].
+pub const CLUSTER_COLUMN: &str = "cluster";
+
+/// Fitted k-means clusters. Centroids are expressed in standardized feature units.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterModel {
+    pub features: Vec<String>,
+    pub means: Vec<f64>,
+    pub stds: Vec<f64>,
+    pub centroids: Vec<Vec<f64>>,
+}
+
+impl ClusterModel {
+    /// Labels each row with the index of its nearest centroid.
+    ///
+    /// Rows with a null in any feature column get a null label.
+    ///
+    /// # Arguments
+    ///
+    /// * `df` - A reference to the DataFrame to label. It must contain all feature columns.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Series>` - The labels as a `cluster` series, or an error if a feature column is missing.
+    pub fn assign(&self, df: &DataFrame) -> Result<Series> {
+        let rows = standardized_rows(df, &self.features, &self.means, &self.stds)?;
+        let labels: Vec<Option<i32>> = rows
+            .iter()
+            .map(|row| row.as_ref().map(|point| nearest(&self.centroids, point) as i32))
+            .collect();
+        Ok(Series::new(CLUSTER_COLUMN, labels))
+    }
+}
+
+/// Appends the `cluster` column, if clustering is enabled.
+///
+/// Centroids are read from `centroids_path` when that file exists; otherwise they are fitted on the
+/// DataFrame and written to the run's artifact directory.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame to label.
+/// * `config` - The clustering settings.
+/// * `run` - The context of the current run.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with the label column appended.
+///
+/// # Example
+///
+/// ```
+/// let df = clustering_stage(transformed_df, &config.clustering, &run).expect("Clustering failed");
+/// ```
+pub fn clustering_stage(mut df: DataFrame, config: &ClusteringConfig, run: &RunContext) -> Result<DataFrame> {
+    if !config.enabled {
+        return Ok(df);
+    }
+
+    let model = match config.centroids_path.as_deref().map(Path::new).filter(|p| p.exists()) {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).context(format!("Failed to read centroids {}", path.display()))?;
+            let model: ClusterModel = serde_json::from_str(&json).context(format!("Failed to parse centroids {}", path.display()))?;
+            println!("Loaded {} centroids from {}", model.centroids.len(), path.display());
+            model
+        }
+        None => {
+            let features = feature_columns(&df, config);
+            let model = fit_kmeans(&df, &features, config.k, config.max_iterations)?;
+            let dir = run.artifact_dir(&config.artifact_dir);
+            std::fs::create_dir_all(&dir).context(format!("Failed to create clustering directory {}", dir.display()))?;
+            let path = dir.join(CENTROIDS_FILE_NAME);
+            std::fs::write(&path, serde_json::to_string_pretty(&model)?).context(format!("Failed to write centroids {}", path.display()))?;
+            println!("Centroids written to {}", path.display());
+            model
+        }
+    };
+
+    let labels = model.assign(&df)?;
+    df.with_column(labels).context("Failed to append cluster labels")?;
+    Ok(df)
+}
+
+/// Fits `k` clusters on the standardized features.
+///
+/// Initial centroids are chosen deterministically: the first complete row, then repeatedly the row
+/// farthest from all centroids chosen so far.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to fit on. Rows with a null in any feature are skipped.
+/// * `features` - The feature columns.
+/// * `k` - The number of clusters.
+/// * `max_iterations` - The maximum number of assignment/update rounds.
+///
+/// # Returns
+///
+/// * `Result<ClusterModel>` - The fitted model, or an error if there are fewer complete rows than clusters.
+pub fn fit_kmeans(df: &DataFrame, features: &[String], k: usize, max_iterations: usize) -> Result<ClusterModel> {
+    if k == 0 || features.is_empty() {
+        bail!("k-means needs at least one cluster and one feature");
+    }
+
+    let (means, stds) = feature_moments(df, features)?;
+    let points: Vec<Vec<f64>> = standardized_rows(df, features, &means, &stds)?.into_iter().flatten().collect();
+    if points.len() < k {
+        bail!("k-means with {} clusters needs at least as many complete rows, found {}", k, points.len());
+    }
+
+    let mut centroids = vec![points[0].clone()];
+    while centroids.len() < k {
+        let farthest = points
+            .iter()
+            .max_by(|a, b| {
+                let da = squared_distance(&centroids[nearest(&centroids, a)], a);
+                let db = squared_distance(&centroids[nearest(&centroids, b)], b);
+                da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .expect("points is not empty");
+        centroids.push(farthest.clone());
+    }
+
+    let mut labels = vec![usize::MAX; points.len()];
+    for _ in 0..max_iterations {
+        let mut changed = false;
+        for (label, point) in labels.iter_mut().zip(&points) {
+            let closest = nearest(&centroids, point);
+            if *label != closest {
+                *label = closest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![vec![0.0; features.len()]; k];
+        let mut counts = vec![0usize; k];
+        for (&label, point) in labels.iter().zip(&points) {
+            counts[label] += 1;
+            sums[label].iter_mut().zip(point).for_each(|(s, v)| *s += v);
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            // An empty cluster keeps its previous centroid
+            if count > 0 {
+                *centroid = sum.into_iter().map(|s| s / count as f64).collect();
+            }
+        }
+    }
+
+    Ok(ClusterModel {
+        features: features.to_vec(),
+        means,
+        stds,
+        centroids,
+    })
+}
+
+/// Helper function to pick the configured features, or every numeric column not excluded.
+fn feature_columns(df: &DataFrame, config: &ClusteringConfig) -> Vec<String> {
+    if !config.features.is_empty() {
+        return config.features.clone();
+    }
+
+    df.get_columns()
+        .iter()
+        .filter(|s| s.dtype().is_numeric() && !config.exclude.iter().any(|e| e == s.name()))
+        .map(|s| s.name().to_string())
+        .collect()
+}
+
+/// Helper function to compute the mean and standard deviation of each feature.
+fn feature_moments(df: &DataFrame, features: &[String]) -> Result<(Vec<f64>, Vec<f64>)> {
+    let mut means = vec![];
+    let mut stds = vec![];
+    for name in features {
+        let series = df
+            .column(name)
+            .context(format!("Error fetching column {}", name))?
+            .cast(&DataType::Float64)
+            .context(format!("Error converting {} column to f64", name))?;
+        let ca = series.f64()?;
+        means.push(ca.mean().unwrap_or(0.0));
+        stds.push(ca.std(1).filter(|s| *s > 0.0).unwrap_or(1.0));
+    }
+    Ok((means, stds))
+}
+
+/// Helper function to build standardized rows, `None` where any feature is null.
+fn standardized_rows(df: &DataFrame, features: &[String], means: &[f64], stds: &[f64]) -> Result<Vec<Option<Vec<f64>>>> {
+    let mut columns = vec![];
+    for name in features {
+        let series = df
+            .column(name)
+            .context(format!("Error fetching column {}", name))?
+            .cast(&DataType::Float64)
+            .context(format!("Error converting {} column to f64", name))?;
+        columns.push(series.f64()?.into_iter().collect::<Vec<Option<f64>>>());
+    }
+
+    let rows = (0..df.height())
+        .map(|row| {
+            columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| column[row].map(|v| (v - means[i]) / stds[i]))
+                .collect::<Option<Vec<f64>>>()
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn nearest(centroids: &[Vec<f64>], point: &[f64]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| squared_distance(a, point).partial_cmp(&squared_distance(b, point)).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_fit_kmeans_separates_groups() {
+        let df = df!(
+            "alcohol" => &[9.0, 9.1, 9.2, 13.0, 13.1, 13.2],
+            "pH" => &[3.0, 3.1, 3.0, 3.6, 3.5, 3.6]
+        )
+        .unwrap();
+        let features = vec!["alcohol".to_string(), "pH".to_string()];
+
+        let model = fit_kmeans(&df, &features, 2, 100).expect("k-means failed");
+        let labels = model.assign(&df).unwrap();
+        let labels: Vec<Option<i32>> = labels.i32().unwrap().into_iter().collect();
+
+        assert_eq!(labels[0], labels[1]);
+        assert_eq!(labels[1], labels[2]);
+        assert_eq!(labels[3], labels[4]);
+        assert_ne!(labels[0], labels[3]);
+    }
+
+    #[test]
+    fn test_fit_kmeans_too_few_rows() {
+        let df = df!("alcohol" => &[9.0]).unwrap();
+        assert!(fit_kmeans(&df, &["alcohol".to_string()], 2, 10).is_err());
+    }
+}
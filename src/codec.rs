@@ -0,0 +1,128 @@
+//! This module compresses the files the pipeline writes: artifacts, exports, archives, and the Parquet dataset.
+//!
+//! Parquet files are compressed page by page with the configured codec, so any Parquet reader reads
+//! them as they are. CSV files are compressed as a whole with gzip or zstd and named with a `.gz` or
+//! `.zst` suffix, the compressions ingestion decompresses when it reads them back. Outputs use zstd at
+//! its default level unless configured otherwise; higher levels trade CPU time for smaller files.
+
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use serde::Deserialize;
+use std::io::Write;
+
+/// Compression codec of an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    /// Files are written uncompressed.
+    None,
+    /// Fast, with a lower ratio; Parquet only.
+    Snappy,
+    Gzip,
+    /// Fast, with a lower ratio; Parquet only.
+    Lz4,
+    #[default]
+    Zstd,
+}
+
+/// Compression of the files an output writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub codec: Codec,
+    /// Compression level, 1 to 22 for zstd and 0 to 9 for gzip; the codec's default when unset.
+    pub level: Option<u32>,
+}
+
+impl CompressionConfig {
+    /// Returns the compression of Parquet files written with this configuration.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ParquetCompression>` - The compression, or an error if the level is out of the codec's range.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ParquetWriter::new(&mut buffer).with_compression(config.artifacts.compression.parquet()?).finish(&mut df)?;
+    /// ```
+    pub fn parquet(&self) -> Result<ParquetCompression> {
+        let level = |range: std::ops::RangeInclusive<u32>| -> Result<Option<u32>> {
+            match self.level {
+                Some(level) if !range.contains(&level) => bail!("{:?} compression levels range from {} to {}, got {}", self.codec, range.start(), range.end(), level),
+                level => Ok(level),
+            }
+        };
+        Ok(match self.codec {
+            Codec::Zstd => ParquetCompression::Zstd(level(1..=22)?.map(|l| ZstdLevel::try_new(l as i32)).transpose()?),
+            Codec::Gzip => ParquetCompression::Gzip(level(0..=9)?.map(|l| GzipLevel::try_new(l as u8)).transpose()?),
+            codec if self.level.is_some() => bail!("{:?} compression takes no level", codec),
+            Codec::Snappy => ParquetCompression::Snappy,
+            Codec::Lz4 => ParquetCompression::Lz4Raw,
+            Codec::None => ParquetCompression::Uncompressed,
+        })
+    }
+
+    /// Compresses the content of a text file, such as a CSV file, as a whole.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The uncompressed content.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>>` - The compressed content, or an error if the codec compresses Parquet pages only
+    ///   or the level is out of its range.
+    pub fn compress(&self, content: Vec<u8>) -> Result<Vec<u8>> {
+        // The levels of gzip and zstd range the same for both kinds of files
+        self.parquet()?;
+        match self.codec {
+            Codec::None => Ok(content),
+            Codec::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), self.level.map_or(flate2::Compression::default(), flate2::Compression::new));
+                encoder.write_all(&content).context("Failed to compress with gzip")?;
+                encoder.finish().context("Failed to compress with gzip")
+            }
+            Codec::Zstd => zstd::encode_all(content.as_slice(), self.level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| l as i32)).context("Failed to compress with zstd"),
+            codec => bail!("{:?} compresses Parquet files only; use gzip or zstd for CSV files", codec),
+        }
+    }
+
+    /// Returns the file name suffix of text files compressed with this configuration, e.g. `.gz`.
+    pub fn suffix(&self) -> &'static str {
+        match self.codec {
+            Codec::Gzip => ".gz",
+            Codec::Zstd => ".zst",
+            _ => "",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_parquet_compression_checks_levels() {
+        let zstd = CompressionConfig { codec: Codec::Zstd, level: Some(9) };
+        assert_eq!(zstd.parquet().unwrap(), ParquetCompression::Zstd(Some(ZstdLevel::try_new(9).unwrap())));
+        assert!(CompressionConfig { level: Some(23), ..zstd }.parquet().is_err());
+        assert!(CompressionConfig { codec: Codec::Snappy, level: Some(1) }.parquet().is_err());
+        assert_eq!(CompressionConfig { codec: Codec::None, level: None }.parquet().unwrap(), ParquetCompression::Uncompressed);
+    }
+
+    #[test]
+    fn test_compress_text_round_trips() {
+        let content = b"alcohol,quality\n9.4,5\n".repeat(100);
+        let gzip = CompressionConfig { codec: Codec::Gzip, level: Some(9) };
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(gzip.compress(content.clone()).unwrap().as_slice()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded.as_bytes(), content.as_slice());
+
+        let zstd = CompressionConfig::default();
+        assert_eq!(zstd::decode_all(zstd.compress(content.clone()).unwrap().as_slice()).unwrap(), content);
+        assert_eq!((gzip.suffix(), zstd.suffix()), (".gz", ".zst"));
+        assert!(CompressionConfig { codec: Codec::Lz4, level: None }.compress(content).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! This module records per-column statistics of every load for monitoring.
+//!
+//! After the data is stored, the mean, standard deviation, minimum, maximum, and null rate of each
+//! column are written to the `column_stats` table, keyed by run. Dashboards can chart them over time
+//! and drift alerts can compare a run against the ones before it.
+
+use crate::config::ColumnStatsConfig;
+use crate::run::RunContext;
+use anyhow::{Context, Result};
+use polars::prelude::*;
+use sqlx::postgres::PgPool;
+
+/// Statistics of one column of a load. The numeric statistics are `None` for non-numeric or empty columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub column: String,
+    pub rows: usize,
+    pub null_rate: f64,
+    pub mean: Option<f64>,
+    pub std: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// Computes the statistics of every column.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the loaded DataFrame.
+///
+/// # Returns
+///
+/// * `Result<Vec<ColumnStats>>` - The statistics per column, or an error if a numeric column cannot be cast.
+pub fn compute(df: &DataFrame) -> Result<Vec<ColumnStats>> {
+    df.get_columns()
+        .iter()
+        .map(|series| {
+            let rows = series.len();
+            let null_rate = if rows == 0 { 0.0 } else { series.null_count() as f64 / rows as f64 };
+            let (mean, std, min, max) = if series.dtype().is_numeric() {
+                let values = series.cast(&DataType::Float64).context(format!("Error converting {} column to f64", series.name()))?;
+                let values = values.f64()?;
+                (values.mean(), values.std(1), values.min(), values.max())
+            } else {
+                (None, None, None, None)
+            };
+
+            Ok(ColumnStats {
+                column: series.name().to_string(),
+                rows,
+                null_rate,
+                mean,
+                std,
+                min,
+                max,
+            })
+        })
+        .collect()
+}
+
+/// Computes the column statistics of a load and stores them in the `column_stats` table, if enabled.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the current run.
+/// * `df` - A reference to the loaded DataFrame.
+/// * `config` - The column statistics settings.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of computing and storing the statistics.
+///
+/// # Example
+///
+/// ```
+/// record(&pool, &run, &transformed_df, &config.column_stats).await.expect("Failed to record column statistics");
+/// ```
+pub async fn record(pool: &PgPool, run: &RunContext, df: &DataFrame, config: &ColumnStatsConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let stats = compute(df)?;
+    for column in &stats {
+        sqlx::query(
+            "INSERT INTO column_stats (run_id, column_name, row_count, null_rate, mean, std, min, max) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(&run.id)
+        .bind(&column.column)
+        .bind(column.rows as i64)
+        .bind(column.null_rate)
+        .bind(column.mean)
+        .bind(column.std)
+        .bind(column.min)
+        .bind(column.max)
+        .execute(pool)
+        .await
+        .context("Failed to store column statistics")?;
+    }
+    println!("Recorded statistics of {} columns", stats.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_column_stats() {
+        let df = polars::df!(
+            "alcohol" => &[Some(9.0), None, Some(11.0), Some(10.0)],
+            "run_id" => &["a", "a", "a", "a"]
+        )
+        .unwrap();
+
+        let stats = compute(&df).unwrap();
+
+        assert_eq!(stats[0].null_rate, 0.25);
+        assert_eq!(stats[0].mean, Some(10.0));
+        assert_eq!(stats[0].std, Some(1.0));
+        assert_eq!((stats[0].min, stats[0].max), (Some(9.0), Some(11.0)));
+        assert_eq!(stats[1].null_rate, 0.0);
+        assert_eq!(stats[1].mean, None);
+    }
+}
@@ -0,0 +1,318 @@
+//! This module implements `pipeline compare-tables`, which diffs a staging load against production.
+//!
+//! Before a staged run is promoted, the diff shows what promoting it would change: the row counts of
+//! the staged rows and of `wine_quality`, how many distinct keys the staged rows add and how many
+//! production keys they lack, and per column the null and distinct counts and, for numeric columns,
+//! the range and mean on both sides with their deltas. A key is a set of columns identifying a row,
+//! every input column by default, so with no natural key the rows themselves are compared. The diff
+//! is rendered as Markdown, to attach to a review, or as JSON.
+
+use crate::audit;
+use crate::config::PipelineConfig;
+use crate::locale::ReportFormat;
+use crate::schema::TableSchema;
+use crate::staging::{self, PRODUCTION_TABLE};
+use crate::storage;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Rendering of a table diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffFormat {
+    Markdown,
+    Json,
+}
+
+/// What `pipeline compare-tables` compares and how it renders the diff.
+#[derive(Debug, Clone, clap::Args)]
+pub struct CompareRequest {
+    /// ID of the staged run compared with production; every row of the staging table by default.
+    #[arg(long)]
+    pub run: Option<String>,
+    /// Columns identifying a row, comma-separated, e.g. `--key quality,alcohol`; every input column by default.
+    #[arg(long, value_delimiter = ',')]
+    pub key: Vec<String>,
+    /// Rendering of the diff.
+    #[arg(long, value_enum, default_value_t = DiffFormat::Markdown)]
+    pub format: DiffFormat,
+    /// File the diff is written to; printed when omitted.
+    #[arg(long)]
+    pub output: Option<String>,
+}
+
+/// Aggregates of a column over the rows of one side of the diff.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnAggregates {
+    pub nulls: i64,
+    pub distinct: i64,
+    /// Range and mean, for numeric columns.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+}
+
+/// A column of the diff; `production` is `None` if the production table lacks the column.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnDiff {
+    pub column: String,
+    pub staged: ColumnAggregates,
+    pub production: Option<ColumnAggregates>,
+}
+
+impl ColumnDiff {
+    /// Returns the change of the mean the staged rows would bring, for numeric columns both sides have.
+    pub fn mean_delta(&self) -> Option<f64> {
+        Some(self.staged.mean? - self.production.as_ref()?.mean?)
+    }
+}
+
+/// The diff of a staging load against the production table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableDiff {
+    pub staging_table: String,
+    pub run_id: Option<String>,
+    pub staged_rows: i64,
+    pub production_rows: i64,
+    pub key: Vec<String>,
+    /// Distinct keys of the staged rows missing from production.
+    pub keys_added: i64,
+    /// Distinct keys of production missing from the staged rows.
+    pub keys_removed: i64,
+    /// Distinct keys on both sides.
+    pub keys_shared: i64,
+    pub columns: Vec<ColumnDiff>,
+}
+
+/// Runs `pipeline compare-tables`, diffing the staged rows against `wine_quality` and rendering the diff.
+///
+/// # Arguments
+///
+/// * `request` - What to compare, and how to render it.
+/// * `config` - The pipeline configuration, naming the staging table and the schema.
+///
+/// # Returns
+///
+/// * `Result<TableDiff>` - The diff, or an error if a key column is unknown or a query fails.
+///
+/// # Example
+///
+/// ```
+/// let diff = run_compare(&request, &config).await.expect("Comparison failed");
+/// ```
+pub async fn run_compare(request: &CompareRequest, config: &PipelineConfig) -> Result<TableDiff> {
+    let pool = storage::create_connection_pool().await?;
+    let diff = compare(&pool, &config.schema, &config.staging.table, request.run.as_deref(), &request.key).await?;
+    let rendered = match request.format {
+        DiffFormat::Markdown => render_markdown(&diff, &ReportFormat::from_config(&config.reports)),
+        DiffFormat::Json => serde_json::to_string_pretty(&diff).context("Failed to encode the diff")?,
+    };
+    match &request.output {
+        Some(path) => {
+            std::fs::write(path, &rendered).context(format!("Failed to write {}", path))?;
+            println!("Wrote the diff of {} against {} to {}", diff.staging_table, PRODUCTION_TABLE, path);
+        }
+        None => println!("{}", rendered),
+    }
+    Ok(diff)
+}
+
+/// Diffs the rows of a staging table, or of one staged run, against `wine_quality`.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The declared schema of both tables.
+/// * `staging_table` - The staging table.
+/// * `run_id` - The staged run compared, or `None` for every staged row.
+/// * `key` - The columns identifying a row, by DataFrame or table column name; empty for every input column.
+///
+/// # Returns
+///
+/// * `Result<TableDiff>` - The diff, or an error if a key column is unknown or missing from a table.
+pub async fn compare(pool: &PgPool, schema: &TableSchema, staging_table: &str, run_id: Option<&str>, key: &[String]) -> Result<TableDiff> {
+    let run_column = staging::run_column(schema)?;
+    let key = key_columns(schema, key)?;
+    let staged_columns = table_columns(pool, staging_table).await?;
+    let production_columns = table_columns(pool, PRODUCTION_TABLE).await?;
+    for column in &key {
+        if !staged_columns.contains(column) || !production_columns.contains(column) {
+            bail!("Key column {} must be in both {} and {}", column, staging_table, PRODUCTION_TABLE);
+        }
+    }
+    // Every row of the staging table is compared when no run is given
+    let source = format!("(SELECT * FROM {} WHERE $1::TEXT IS NULL OR {} = $1) AS staged", staging_table, run_column);
+
+    let staged_rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {}", source))
+        .bind(run_id)
+        .fetch_one(pool)
+        .await
+        .context(format!("Failed to count the rows of {}", staging_table))?;
+    let production_rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {}", PRODUCTION_TABLE)).fetch_one(pool).await?;
+    let (keys_added, keys_removed, keys_shared): (i64, i64, i64) = sqlx::query_as(&key_sql(&source, &key.join(", ")))
+        .bind(run_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to compare the keys of the staged and production rows")?;
+
+    let mut columns = vec![];
+    for spec in schema.columns.iter().filter(|c| c.name != audit::RUN_ID_COLUMN && c.name != audit::LABELS_COLUMN && staged_columns.contains(&c.column)) {
+        let numeric = staging::is_numeric(&spec.pg_type);
+        let (nulls, distinct, min, max, mean) = sqlx::query_as(&aggregates_sql(&spec.column, &source, numeric))
+            .bind(run_id)
+            .fetch_one(pool)
+            .await
+            .context(format!("Failed to aggregate staged column {}", spec.column))?;
+        let staged = ColumnAggregates { nulls, distinct, min, max, mean };
+        let production = if production_columns.contains(&spec.column) {
+            let (nulls, distinct, min, max, mean) = sqlx::query_as(&aggregates_sql(&spec.column, PRODUCTION_TABLE, numeric))
+                .fetch_one(pool)
+                .await
+                .context(format!("Failed to aggregate production column {}", spec.column))?;
+            Some(ColumnAggregates { nulls, distinct, min, max, mean })
+        } else {
+            None
+        };
+        columns.push(ColumnDiff { column: spec.column.clone(), staged, production });
+    }
+
+    Ok(TableDiff {
+        staging_table: staging_table.to_string(),
+        run_id: run_id.map(str::to_string),
+        staged_rows,
+        production_rows,
+        key,
+        keys_added,
+        keys_removed,
+        keys_shared,
+        columns,
+    })
+}
+
+/// Helper function to resolve the key columns to table column names, defaulting to every input column.
+fn key_columns(schema: &TableSchema, key: &[String]) -> Result<Vec<String>> {
+    if key.is_empty() {
+        return Ok(schema.input_columns().map(|c| c.column.clone()).collect());
+    }
+    key.iter()
+        .map(|name| {
+            schema
+                .columns
+                .iter()
+                .find(|c| &c.name == name || &c.column == name)
+                .map(|c| c.column.clone())
+                .context(format!("Key column {} is not in the schema", name))
+        })
+        .collect()
+}
+
+/// Helper function to list the columns of a table.
+async fn table_columns(pool: &PgPool, table: &str) -> Result<HashSet<String>> {
+    let columns: Vec<String> = sqlx::query_scalar("SELECT column_name::TEXT FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1")
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .context(format!("Failed to look up the columns of {}", table))?;
+    Ok(columns.into_iter().collect())
+}
+
+/// Helper function to generate the query counting the distinct keys added, removed, and shared; `EXCEPT` matches NULL keys.
+fn key_sql(staged: &str, key: &str) -> String {
+    format!(
+        "WITH s AS (SELECT DISTINCT {1} FROM {0}), p AS (SELECT DISTINCT {1} FROM {2}) \
+         SELECT (SELECT count(*) FROM (SELECT * FROM s EXCEPT SELECT * FROM p) AS added), \
+         (SELECT count(*) FROM (SELECT * FROM p EXCEPT SELECT * FROM s) AS removed), \
+         (SELECT count(*) FROM (SELECT * FROM s INTERSECT SELECT * FROM p) AS shared)",
+        staged, key, PRODUCTION_TABLE
+    )
+}
+
+/// Helper function to generate the query aggregating a column, with its range and mean if it is numeric.
+fn aggregates_sql(column: &str, source: &str, numeric: bool) -> String {
+    if numeric {
+        format!(
+            "SELECT count(*) - count({0}), count(DISTINCT {0}), min({0})::DOUBLE PRECISION, max({0})::DOUBLE PRECISION, avg({0})::DOUBLE PRECISION FROM {1}",
+            column, source
+        )
+    } else {
+        format!(
+            "SELECT count(*) - count({0}), count(DISTINCT {0}), NULL::DOUBLE PRECISION, NULL::DOUBLE PRECISION, NULL::DOUBLE PRECISION FROM {1}",
+            column, source
+        )
+    }
+}
+
+/// Renders the Markdown report of a diff, with numbers and units written as `format` says.
+fn render_markdown(diff: &TableDiff, format: &ReportFormat) -> String {
+    let number = |value: Option<f64>| format.optional(value, 4);
+    let staged = match &diff.run_id {
+        Some(run_id) => format!("run {} in `{}`", run_id, diff.staging_table),
+        None => format!("`{}`", diff.staging_table),
+    };
+    let mut report = format!("# {} against `{}`\n\n", staged, PRODUCTION_TABLE);
+    let _ = writeln!(report, "{} staged rows, {} production rows.\n", format.count(diff.staged_rows), format.count(diff.production_rows));
+
+    let _ = writeln!(report, "## Keys\n\nKeyed by {}.\n", diff.key.join(", "));
+    report.push_str("| Added | Removed | Shared |\n|---|---|---|\n");
+    let _ = writeln!(report, "| {} | {} | {} |\n", format.count(diff.keys_added), format.count(diff.keys_removed), format.count(diff.keys_shared));
+
+    report.push_str("## Columns\n\n| Column | Nulls | Distinct | Min | Max | Mean | Production mean | Mean delta |\n|---|---|---|---|---|---|---|---|\n");
+    for c in &diff.columns {
+        let counts = |staged: i64, production: Option<i64>| match production {
+            Some(production) => format!("{} ({})", format.count(staged), format.signed(Some((staged - production) as f64), 0)),
+            None => format.count(staged),
+        };
+        let production = c.production.as_ref();
+        let _ = writeln!(
+            report,
+            "| {} | {} | {} | {} | {} | {} | {} | {} |",
+            format.column(&c.column),
+            counts(c.staged.nulls, production.map(|p| p.nulls)),
+            counts(c.staged.distinct, production.map(|p| p.distinct)),
+            number(c.staged.min),
+            number(c.staged.max),
+            number(c.staged.mean),
+            production.map_or_else(|| "not in production".to_string(), |p| number(p.mean)),
+            format.signed(c.mean_delta(), 4)
+        );
+    }
+    if let Some(run_id) = &diff.run_id {
+        let _ = writeln!(report, "\nPromote with `pipeline promote --run {0}`, or discard with `pipeline promote --run {0} --discard`.", run_id);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let aggregates = |nulls, mean| ColumnAggregates { nulls, distinct: 12, min: Some(8.4), max: Some(14.9), mean: Some(mean) };
+        let diff = TableDiff {
+            staging_table: "wine_quality_staging".to_string(),
+            run_id: Some("01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B".to_string()),
+            staged_rows: 1599,
+            production_rows: 4898,
+            key: vec!["alcohol".to_string(), "quality".to_string()],
+            keys_added: 40,
+            keys_removed: 310,
+            keys_shared: 120,
+            columns: vec![
+                ColumnDiff { column: "alcohol".to_string(), staged: aggregates(2, 10.42), production: Some(aggregates(0, 10.5)) },
+                ColumnDiff { column: "cluster".to_string(), staged: aggregates(0, 1.0), production: None },
+            ],
+        };
+
+        let report = render_markdown(&diff, &ReportFormat::default());
+
+        assert!(report.starts_with("# run 01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B in `wine_quality_staging` against `wine_quality`\n\n1599 staged rows, 4898 production rows."));
+        assert!(report.contains("Keyed by alcohol, quality.\n\n| Added | Removed | Shared |\n|---|---|---|\n| 40 | 310 | 120 |"));
+        assert!(report.contains("| alcohol | 2 (+2) | 12 (+0) | 8.4000 | 14.9000 | 10.4200 | 10.5000 | -0.0800 |"));
+        assert!(report.contains("| cluster | 0 | 12 | 8.4000 | 14.9000 | 1.0000 | not in production | - |"));
+        assert_eq!(key_columns(&TableSchema::default(), &["free sulfur dioxide".to_string()]).unwrap(), vec!["free_sulfur_dioxide"]);
+        assert!(key_columns(&TableSchema::default(), &["vintage".to_string()]).is_err());
+    }
+}
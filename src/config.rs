@@ -0,0 +1,1151 @@
+//! This module handles loading the pipeline configuration.
+//!
+//! The configuration is read from a TOML file. Every section is optional and falls back to its defaults,
+//! so the pipeline runs unchanged when no configuration file is present.
+
+use crate::analysis::HypothesisTest;
+use crate::api::Permission;
+use crate::codec::CompressionConfig;
+use crate::evolution::NewColumnPolicy;
+use crate::overflow::OverflowPolicy;
+use crate::expectations::Rule;
+use crate::fingerprint::DuplicatePolicy;
+use crate::hooks::HookCommand;
+use crate::mapping::{self, NamingConvention};
+use crate::retry::RetryPolicy;
+use crate::rounding::RoundingMode;
+use crate::schema::TableSchema;
+use crate::source::SourceConfig;
+use crate::staging;
+use crate::storage;
+use crate::typemap::TypeMapping;
+use crate::validation;
+use crate::webhooks::WebhookConfig;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The configuration file used when neither `--config` nor `PIPELINE_CONFIG` is given.
+pub const DEFAULT_CONFIG_PATH: &str = "pipeline.toml";
+
+/// Top-level pipeline configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Where the input is read from.
+    pub source: SourceConfig,
+    /// Settings of the cleaning and normalization stages.
+    pub transform: TransformConfig,
+    /// Settings for the optional distribution chart step.
+    pub visualization: VisualizationConfig,
+    /// Declarative expectations evaluated after transformation.
+    pub expectations: ExpectationsConfig,
+    /// In-memory caching of the reference tables expectations read, shared by the runs of a daemon.
+    pub reference_cache: ReferenceCacheConfig,
+    /// Settings for the optional quality model training step.
+    pub model: ModelConfig,
+    /// Settings for the optional principal component step.
+    pub pca: PcaConfig,
+    /// Settings for the optional k-means labeling step.
+    pub clustering: ClusteringConfig,
+    /// Hypothesis tests run on the transformed data.
+    pub analysis: AnalysisConfig,
+    /// Settings for publishing dataset metadata after each run.
+    pub catalog: CatalogConfig,
+    /// Settings for recording per-column statistics of each load.
+    pub column_stats: ColumnStatsConfig,
+    /// Aggregate tables recomputed after every load.
+    pub aggregates: AggregatesConfig,
+    /// Columns of the stored table, their types and null handling.
+    pub schema: TableSchema,
+    /// How source headers map to table columns not named in the schema.
+    pub column_mapping: ColumnMappingConfig,
+    /// Renaming of the input's headers to the schema's column names.
+    pub headers: HeadersConfig,
+    /// Rounding of numeric columns to their stored scale.
+    pub rounding: RoundingConfig,
+    /// Narrowing of columns to smaller dtypes before artifacts, exports, and new table columns are written.
+    pub downcast: DowncastConfig,
+    /// Settings for writing the data to PostgreSQL.
+    pub storage: StorageConfig,
+    /// Appending of the stored rows to an Apache Iceberg table, in builds with the `iceberg` feature.
+    pub iceberg: IcebergConfig,
+    /// Appending of the stored rows to a Hive-partitioned Parquet dataset.
+    pub dataset: DatasetConfig,
+    /// Removal of the stored rows of old runs.
+    pub retention: RetentionConfig,
+    /// Review of loads in a staging table before they reach `storage.table`.
+    pub staging: StagingConfig,
+    /// Settings for processing large inputs chunk by chunk.
+    pub streaming: StreamingConfig,
+    /// Memory budget of transformation, beyond which partitions are spilled to disk.
+    pub spill: SpillConfig,
+    /// Triggers and concurrency limits of daemon mode.
+    pub daemon: DaemonConfig,
+    /// Settings for persisting intermediate DataFrames of each run.
+    pub artifacts: ArtifactsConfig,
+    /// Detection of runs repeating an earlier successful load.
+    pub deduplication: DeduplicationConfig,
+    /// Automatic retries of failed runs.
+    pub retry: RetryConfig,
+    /// Comparison of each run's duration and throughput with the runs before it.
+    pub performance: PerformanceConfig,
+    /// Tracking of the memory and CPU the process uses during a run, and its memory ceiling.
+    pub resources: ResourcesConfig,
+    /// Deduplication of failure notifications across the runs of a failing input.
+    pub alerts: AlertsConfig,
+    /// Number formatting and units of the staging reports, table diffs, and run summaries.
+    pub reports: ReportsConfig,
+    /// Shell commands run at hook points of each run.
+    pub hooks: Vec<HookCommand>,
+    /// HTTP endpoints notified at hook points of each run.
+    pub webhooks: Vec<WebhookConfig>,
+    /// Key/value labels attached to every run; `--label` adds to and overrides them.
+    pub labels: BTreeMap<String, String>,
+    /// Processes at most this many input rows, to debug a large file quickly; `--limit` sets it.
+    pub row_limit: Option<usize>,
+    /// Processes a random sample of the input rows, for quick smoke runs over a large dataset; `--sample` sets it.
+    pub sampling: SamplingConfig,
+    /// Fault injection for resilience testing, in builds with the `chaos` feature.
+    pub chaos: ChaosConfig,
+}
+
+impl PipelineConfig {
+    /// Returns the names of the stages a run with this configuration applies, in order.
+    pub fn enabled_stages(&self) -> Vec<String> {
+        let optional = [
+            ("expectations", !self.expectations.suite.is_empty()),
+            ("model_training", self.model.train),
+            ("model_scoring", self.model.score_with.is_some()),
+            ("pca", self.pca.enabled),
+            ("clustering", self.clustering.enabled),
+            ("analysis", !self.analysis.tests.is_empty()),
+            ("rounding", self.rounding.enabled),
+        ];
+
+        ["ingest", "transform"]
+            .into_iter()
+            .chain(optional.into_iter().filter(|(_, enabled)| *enabled).map(|(name, _)| name))
+            .chain(std::iter::once("store"))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Settings of the transformation stages.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformConfig {
+    /// Columns no transformation stage changes, e.g. the `quality` label and identifiers.
+    pub passthrough: Vec<String>,
+    /// Share of a column's values cleaning and imputation may fill in before the run warns about it.
+    pub max_filled_rate: f64,
+}
+
+impl Default for TransformConfig {
+    fn default() -> Self {
+        Self {
+            passthrough: vec![],
+            max_filled_rate: 0.05,
+        }
+    }
+}
+
+/// Settings for rendering distribution charts before and after transformation.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VisualizationConfig {
+    /// Whether charts are rendered at all.
+    pub enabled: bool,
+    /// Base directory; each run writes its charts into a subdirectory named after the run ID.
+    pub output_dir: String,
+    /// Columns to chart.
+    pub columns: Vec<String>,
+    /// Output image format.
+    pub format: ChartFormat,
+    /// Number of histogram bins.
+    pub bins: usize,
+}
+
+impl Default for VisualizationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output_dir: "visualizations/runs".to_string(),
+            columns: vec!["alcohol".to_string(), "pH".to_string(), "quality".to_string()],
+            format: ChartFormat::Png,
+            bins: 20,
+        }
+    }
+}
+
+/// Image format for rendered charts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartFormat {
+    Png,
+    Svg,
+}
+
+impl ChartFormat {
+    /// Returns the file extension for the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChartFormat::Png => "png",
+            ChartFormat::Svg => "svg",
+        }
+    }
+}
+
+/// Settings for the expectation suite.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpectationsConfig {
+    /// What to do when at least one expectation fails.
+    pub on_failure: FailurePolicy,
+    /// The expectations to evaluate.
+    pub suite: Vec<Rule>,
+    /// Fraction of rows the suite is first evaluated on, escalating to all rows if the sample fails; `--validate-sample` overrides it.
+    pub sample_fraction: Option<f64>,
+}
+
+/// Settings for caching reference tables in memory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReferenceCacheConfig {
+    /// Whether reference tables read by one run are reused by the following runs.
+    pub enabled: bool,
+    /// Seconds a cached table is reused before it is read again.
+    pub ttl_secs: u64,
+}
+
+impl Default for ReferenceCacheConfig {
+    fn default() -> Self {
+        Self { enabled: false, ttl_secs: 300 }
+    }
+}
+
+/// How failed expectations affect the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Report failures and continue loading.
+    #[default]
+    Warn,
+    /// Abort the run before the data is stored.
+    Fail,
+}
+
+/// Settings for training the quality prediction model.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelConfig {
+    /// Whether a model is trained during the run.
+    pub train: bool,
+    /// Base directory; each run writes its model into a subdirectory named after the run ID.
+    pub artifact_dir: String,
+    /// Column to predict.
+    pub target: String,
+    /// Feature columns. Empty means every numeric column except the target.
+    pub features: Vec<String>,
+    /// Share of rows held out to compute the metrics.
+    pub test_fraction: f64,
+    /// Model artifact used to append a `predicted_quality` column before storage.
+    pub score_with: Option<String>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            train: false,
+            artifact_dir: "artifacts/models".to_string(),
+            target: "quality".to_string(),
+            features: vec![],
+            test_fraction: 0.2,
+            score_with: None,
+        }
+    }
+}
+
+/// Settings for appending principal components of the numeric features.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PcaConfig {
+    /// Whether the `pc1..pcK` columns are appended.
+    pub enabled: bool,
+    /// Number of components to keep.
+    pub components: usize,
+    /// Feature columns. Empty means every numeric column not listed in `exclude`.
+    pub features: Vec<String>,
+    /// Columns never used as features when `features` is empty.
+    pub exclude: Vec<String>,
+    /// Previously fitted loadings to reuse. When unset or missing, loadings are fitted on the run's data.
+    pub loadings_path: Option<String>,
+    /// Base directory; each run writes fitted loadings into a subdirectory named after the run ID.
+    pub artifact_dir: String,
+}
+
+impl Default for PcaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            components: 2,
+            features: vec![],
+            exclude: vec!["quality".to_string(), "predicted_quality".to_string()],
+            loadings_path: None,
+            artifact_dir: "artifacts/pca".to_string(),
+        }
+    }
+}
+
+/// Settings for labeling rows with k-means clusters.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClusteringConfig {
+    /// Whether the `cluster` column is appended.
+    pub enabled: bool,
+    /// Number of clusters.
+    pub k: usize,
+    /// Feature columns. Empty means every numeric column not listed in `exclude`.
+    pub features: Vec<String>,
+    /// Columns never used as features when `features` is empty.
+    pub exclude: Vec<String>,
+    /// Maximum number of k-means iterations.
+    pub max_iterations: usize,
+    /// Previously fitted centroids to reuse. When unset or missing, centroids are fitted on the run's data.
+    pub centroids_path: Option<String>,
+    /// Base directory; each run writes fitted centroids into a subdirectory named after the run ID.
+    pub artifact_dir: String,
+}
+
+impl Default for ClusteringConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            k: 3,
+            features: vec![],
+            exclude: vec!["quality".to_string(), "predicted_quality".to_string()],
+            max_iterations: 100,
+            centroids_path: None,
+            artifact_dir: "artifacts/clustering".to_string(),
+        }
+    }
+}
+
+/// Settings for the hypothesis tests stored with each run.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisConfig {
+    /// The tests to run.
+    pub tests: Vec<HypothesisTest>,
+}
+
+/// Settings for recording per-column statistics of each load.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnStatsConfig {
+    /// Whether mean, standard deviation, range, and null rate of each column are stored in `column_stats` after the load.
+    pub enabled: bool,
+}
+
+/// Settings for refreshing the aggregate tables dashboards read.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AggregatesConfig {
+    /// Whether the aggregates are recomputed after each load, or after promotion of a staged run.
+    pub enabled: bool,
+    /// Table of the number of wines and the mean of every numeric column per quality.
+    pub quality_table: String,
+    /// Table of the number of wines and their mean quality per run.
+    pub run_table: String,
+}
+
+impl Default for AggregatesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            quality_table: "wine_quality_by_quality".to_string(),
+            run_table: "wine_quality_by_run".to_string(),
+        }
+    }
+}
+
+/// Settings for publishing dataset metadata to a catalog.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogConfig {
+    /// Whether metadata is published after each run.
+    pub enabled: bool,
+    /// Name under which the dataset is registered.
+    pub dataset: String,
+    /// Owning team or person.
+    pub owner: String,
+    pub description: String,
+    /// Optional catalog API (e.g. an OpenMetadata or DataHub ingestion endpoint) receiving the metadata as JSON.
+    pub endpoint: Option<String>,
+    /// Environment variable holding a bearer token for the endpoint.
+    pub token_env: Option<String>,
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dataset: "wine_quality".to_string(),
+            owner: String::new(),
+            description: "Physicochemical measurements and quality scores of red wines".to_string(),
+            endpoint: None,
+            token_env: None,
+        }
+    }
+}
+
+/// Renaming of source headers to the column names of the schema, right after the input is read.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadersConfig {
+    /// Whether headers are trimmed, lowercased, and their runs of whitespace collapsed, and then read
+    /// under the schema column they match once it is normalized the same way.
+    pub normalize: bool,
+    /// Schema column name of each source header, e.g. `fixed_acidity = "fixed acidity"`.
+    pub rename: HashMap<String, String>,
+}
+
+impl HeadersConfig {
+    /// Whether any header is renamed.
+    pub fn is_active(&self) -> bool {
+        self.normalize || !self.rename.is_empty()
+    }
+}
+
+/// Mapping of source headers to table columns.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnMappingConfig {
+    /// Table column of each source header, overriding the convention.
+    pub rename: HashMap<String, String>,
+    /// How the table column is derived from a header without an explicit mapping.
+    pub convention: NamingConvention,
+}
+
+/// Rounding of numeric columns to their stored scale.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoundingConfig {
+    /// Whether float columns are rounded before storage.
+    pub enabled: bool,
+    pub mode: RoundingMode,
+    /// Decimal places keyed by DataFrame column name, overriding the scale of the column's type in the schema.
+    pub decimals: HashMap<String, u32>,
+}
+
+/// Settings for downcasting columns to the smallest dtype holding their values.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DowncastConfig {
+    /// Whether artifacts, exports, and columns the schema does not declare are narrowed.
+    pub enabled: bool,
+}
+
+impl Default for RoundingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: RoundingMode::HalfUp,
+            decimals: HashMap::new(),
+        }
+    }
+}
+
+/// Settings for writing the data to PostgreSQL.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Table the rows are stored in, created by the first run; a manifest gives each dataset its own.
+    pub table: String,
+    /// Type mappings overriding the defaults for the Polars dtype, keyed by DataFrame column name.
+    pub column_types: HashMap<String, TypeMapping>,
+    /// Columns encrypted before they are stored.
+    pub encryption: EncryptionConfig,
+    /// Maximum number of rows per insert statement; `pipeline tune` measures the best value for a database.
+    pub batch_rows: usize,
+    /// Maximum number of insert statements running at once, at most the connection pool size of 5.
+    pub concurrency: usize,
+    /// What happens to columns of the transformed data the table lacks.
+    pub on_new_columns: NewColumnPolicy,
+    /// Writing the rows of each value of a key column in a transaction of their own.
+    pub partitioning: PartitioningConfig,
+    /// Committing long loads in chunks, so an interrupted load resumes after the last committed one.
+    pub resumable: ResumableConfig,
+    /// What happens to values out of the range of their `NUMERIC(p, s)` column.
+    pub on_numeric_overflow: OverflowPolicy,
+    /// Waits between the retries of a failed partition and the reconnects of a resumable load, whose
+    /// numbers are `partitioning.max_retries` and `resumable.max_reconnects`.
+    pub retry: RetryPolicy,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            table: staging::PRODUCTION_TABLE.to_string(),
+            column_types: HashMap::new(),
+            encryption: EncryptionConfig::default(),
+            batch_rows: 1_000,
+            concurrency: storage::POOL_SIZE,
+            on_new_columns: NewColumnPolicy::default(),
+            partitioning: PartitioningConfig::default(),
+            resumable: ResumableConfig::default(),
+            on_numeric_overflow: OverflowPolicy::default(),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+/// Settings for committing long loads in chunks that survive a dropped connection.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResumableConfig {
+    /// Whether loads are committed chunk by chunk with a progress marker per chunk.
+    pub enabled: bool,
+    /// Rows per committed chunk.
+    pub commit_rows: usize,
+    /// Number of times a chunk whose transaction failed is written again on a new connection.
+    pub max_reconnects: usize,
+}
+
+impl Default for ResumableConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            commit_rows: 50_000,
+            max_reconnects: 5,
+        }
+    }
+}
+
+/// Settings for writing the rows of each key value in a transaction of its own.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PartitioningConfig {
+    /// The DataFrame column whose values partition the writes, e.g. `vintage`; `None` writes in plain batches.
+    pub column: Option<String>,
+    /// Whether each partition first deletes the stored rows with its key, so writing it again replaces them.
+    pub overwrite: bool,
+    /// Number of times a partition whose transaction failed is written again before the load fails.
+    pub max_retries: usize,
+}
+
+impl Default for PartitioningConfig {
+    fn default() -> Self {
+        Self {
+            column: None,
+            overwrite: false,
+            max_retries: 2,
+        }
+    }
+}
+
+/// Settings for appending the stored rows to an Apache Iceberg table through a REST catalog.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IcebergConfig {
+    /// Whether the rows stored in PostgreSQL are appended to the Iceberg table too.
+    pub enabled: bool,
+    /// Base URL of the REST catalog.
+    pub catalog_uri: String,
+    /// Warehouse the catalog serves the table from.
+    pub warehouse: String,
+    /// Namespace of the table, with levels separated by dots; created if missing.
+    pub namespace: String,
+    /// The table, created from the columns of the first appended rows if missing.
+    pub table: String,
+    /// Environment variable holding a bearer token for the catalog, if it requires one.
+    pub token_env: Option<String>,
+    /// Endpoint of an S3-compatible store such as MinIO; AWS S3 when unset.
+    pub s3_endpoint: Option<String>,
+    pub s3_region: String,
+    /// Whether buckets are addressed by path rather than by host name, as MinIO requires.
+    pub s3_path_style: bool,
+}
+
+impl Default for IcebergConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            catalog_uri: "http://localhost:8181".to_string(),
+            warehouse: "warehouse".to_string(),
+            namespace: "wine".to_string(),
+            table: "wine_quality".to_string(),
+            token_env: None,
+            s3_endpoint: None,
+            s3_region: "us-east-1".to_string(),
+            s3_path_style: false,
+        }
+    }
+}
+
+/// Settings for appending the stored rows to a Hive-partitioned Parquet dataset.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatasetConfig {
+    /// Whether the rows stored in PostgreSQL are appended to the dataset too.
+    pub enabled: bool,
+    /// Local directory, or `s3://bucket/prefix` URL in builds with the `s3` feature, of the dataset.
+    pub path: String,
+    /// Columns whose values name the partition directories, outermost first; `load_date` is the date of
+    /// the run unless the rows have a column of that name.
+    pub partition_by: Vec<String>,
+    /// Compression of the Parquet files.
+    pub compression: CompressionConfig,
+}
+
+impl Default for DatasetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "lake/wine_quality".to_string(),
+            partition_by: vec![],
+            compression: CompressionConfig::default(),
+        }
+    }
+}
+
+/// Settings for removing the stored rows of old runs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Whether the rows of expired runs are deleted after each run.
+    pub enabled: bool,
+    /// Age, since its last successful attempt, after which a run's rows expire.
+    pub max_age_days: u64,
+    /// Directory the rows of each expired run are exported to as Parquet before deletion; `None` deletes them without an export.
+    pub archive_dir: Option<String>,
+    /// Compression of the archived Parquet files.
+    pub compression: CompressionConfig,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age_days: 90,
+            archive_dir: None,
+            compression: CompressionConfig::default(),
+        }
+    }
+}
+
+/// Settings for staging loads for review before they reach `storage.table`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StagingConfig {
+    /// Whether runs store their rows in the staging table, to be promoted by `pipeline promote`.
+    pub enabled: bool,
+    /// The staging table, created from the schema on first use.
+    pub table: String,
+    /// Directory the verification report of each staged run is written to, as `<run id>.md`.
+    pub report_dir: String,
+}
+
+impl Default for StagingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table: "wine_quality_staging".to_string(),
+            report_dir: "reports/staging".to_string(),
+        }
+    }
+}
+
+/// Settings for encrypting sensitive columns.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionConfig {
+    /// DataFrame columns whose values are encrypted; each must be declared with `pg_type = "TEXT"` in the schema.
+    pub columns: Vec<String>,
+    /// Environment variable holding the base64-encoded 32-byte AES key.
+    pub key_env: String,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            columns: vec![],
+            key_env: "PIPELINE_ENCRYPTION_KEY".to_string(),
+        }
+    }
+}
+
+/// Settings for processing large inputs chunk by chunk.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamingConfig {
+    /// Whether ingestion, transformation, and storage overlap on chunks of the input.
+    pub enabled: bool,
+    /// Maximum number of rows per chunk.
+    pub chunk_rows: usize,
+    /// Number of chunks that may wait between two stages before the faster stage is held back.
+    pub channel_capacity: usize,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            chunk_rows: 10_000,
+            channel_capacity: 2,
+        }
+    }
+}
+
+/// Settings for spilling oversized transformation inputs to disk.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpillConfig {
+    /// Estimated in-memory size of the input, in MB, beyond which it is transformed in spilled partitions; `None` never spills.
+    pub memory_budget_mb: Option<u64>,
+    /// Directory the spill files are written to; defaults to the system temporary directory.
+    pub dir: Option<String>,
+}
+
+/// Settings for running the pipeline as a daemon.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Maximum number of runs executing at the same time. Runs of the same pipeline never overlap.
+    pub max_concurrent_runs: usize,
+    /// Maximum number of runs waiting for their turn; further requests are dropped.
+    pub max_queued_runs: usize,
+    /// Requests a run at this interval, starting when the daemon starts.
+    pub schedule_every_secs: Option<u64>,
+    /// Requests a run whenever this file is modified.
+    pub watch_path: Option<String>,
+    /// How often the watched file is checked.
+    pub watch_poll_secs: u64,
+    /// The HTTP control API.
+    pub api: ApiConfig,
+    /// The liveness and readiness endpoints.
+    pub health: HealthConfig,
+    /// The landing directory whose new files are loaded one by one.
+    pub landing: LandingConfig,
+    /// Restarting of the triggers and endpoints that panic or fail.
+    pub supervisor: SupervisorConfig,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_runs: 1,
+            max_queued_runs: 10,
+            schedule_every_secs: None,
+            watch_path: None,
+            watch_poll_secs: 5,
+            api: ApiConfig::default(),
+            health: HealthConfig::default(),
+            landing: LandingConfig::default(),
+            supervisor: SupervisorConfig::default(),
+        }
+    }
+}
+
+/// Settings for processing a random sample of the input rows.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplingConfig {
+    /// Share of the rows kept, each row drawn on its own, e.g. `0.01`.
+    pub fraction: Option<f64>,
+    /// Number of rows kept, drawn uniformly from the whole input, which is read to its end first.
+    pub rows: Option<usize>,
+    /// Seed of the draws; the same seed keeps the same rows of the same input.
+    pub seed: u64,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            fraction: None,
+            rows: None,
+            seed: 42,
+        }
+    }
+}
+
+impl SamplingConfig {
+    /// Describes the sample, e.g. `1%` or `1000 rows`, or returns `None` if every row is processed.
+    pub fn describe(&self) -> Option<String> {
+        match (self.fraction, self.rows) {
+            (_, Some(rows)) => Some(format!("{} rows", rows)),
+            (Some(fraction), None) => Some(format!("{}%", fraction * 100.0)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Settings for restarting the daemon's components after a panic or an error.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    /// Delay before the first restart of a failed component, doubled with each consecutive failure.
+    pub restart_backoff_secs: u64,
+    /// Longest delay between restarts; a component running this long is no longer counted as failing in a loop.
+    pub max_restart_backoff_secs: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            restart_backoff_secs: 1,
+            max_restart_backoff_secs: 60,
+        }
+    }
+}
+
+/// Settings for loading the files that land in a directory, in daemon mode.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LandingConfig {
+    /// Directory watched for new files; each is loaded in a run of its own, read like the configured source.
+    pub dir: Option<String>,
+    /// Glob pattern of the file names loaded, e.g. `*.csv`; files starting with a dot are always ignored.
+    pub pattern: String,
+    /// Directory the files of successful runs are moved to.
+    pub archive_dir: String,
+    /// Directory the files of failed runs are moved to, so they are not loaded again.
+    pub failed_dir: String,
+    /// Time a file must stay unchanged before it is loaded, so files still being written are not read.
+    pub settle_secs: u64,
+}
+
+impl Default for LandingConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            pattern: "*".to_string(),
+            archive_dir: "data/archive".to_string(),
+            failed_dir: "data/failed".to_string(),
+            settle_secs: 2,
+        }
+    }
+}
+
+/// Settings for the HTTP control API of daemon mode.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Whether the API is served.
+    pub enabled: bool,
+    /// Address the API listens on.
+    pub listen: String,
+    /// The accepted API keys.
+    pub keys: Vec<ApiKeyConfig>,
+    /// Whether the web UI showing run history, quality reports, and rejected rows is served at `/ui`.
+    pub ui: bool,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "0.0.0.0:8080".to_string(),
+            keys: vec![],
+            ui: false,
+        }
+    }
+}
+
+/// Settings for the `/healthz` and `/readyz` endpoints of daemon mode.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Whether the endpoints are served.
+    pub enabled: bool,
+    /// Address the endpoints listen on, separate from the authenticated control API.
+    pub listen: String,
+    /// Free disk space below which the daemon reports itself not ready.
+    pub min_free_disk_mb: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: "0.0.0.0:8081".to_string(),
+            min_free_disk_mb: 512,
+        }
+    }
+}
+
+/// An API key accepted by the control API.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// Name of the client, used in logs.
+    pub name: String,
+    /// Environment variable holding the key.
+    pub key_env: String,
+    /// Endpoints the key may call.
+    pub permissions: Vec<Permission>,
+}
+
+/// Settings for persisting intermediate DataFrames as Parquet artifacts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArtifactsConfig {
+    /// Whether artifacts are written at all.
+    pub enabled: bool,
+    /// Where artifacts are stored.
+    pub backend: ArtifactBackend,
+    /// Base directory for the local backend, or key prefix for the S3 backend.
+    pub path: String,
+    /// Bucket for the S3 backend.
+    pub bucket: Option<String>,
+    /// Artifacts to write, out of `raw`, `cleaned`, `normalized` and `rejects`.
+    pub persist: Vec<String>,
+    /// Number of most recent runs whose artifacts are kept.
+    pub retention_runs: Option<usize>,
+    /// Age after which a run's artifacts are deleted.
+    pub retention_days: Option<u64>,
+    /// Compression of the Parquet artifacts.
+    pub compression: CompressionConfig,
+}
+
+impl Default for ArtifactsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: ArtifactBackend::Local,
+            path: "artifacts/intermediates".to_string(),
+            bucket: None,
+            persist: ["raw", "cleaned", "normalized", "rejects"].map(str::to_string).to_vec(),
+            retention_runs: Some(10),
+            retention_days: None,
+            compression: CompressionConfig::default(),
+        }
+    }
+}
+
+/// Automatic retries of failed runs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Number of times a failed run is retried from its last checkpoint; `--auto-retry` overrides it.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each further retry.
+    pub backoff_secs: u64,
+    /// Attempts of reading an input file, and the waits between them, before the run fails.
+    pub ingestion: RetryPolicy,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff_secs: 5,
+            ingestion: RetryPolicy::default(),
+        }
+    }
+}
+
+/// Comparison of each run's duration and throughput with previous runs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PerformanceConfig {
+    /// Number of most recent successful runs averaged into the baseline.
+    pub baseline_runs: usize,
+    /// Share by which a run may be slower than the baseline before it is flagged, e.g. 0.5 for 50%.
+    pub regression_threshold: f64,
+}
+
+impl Default for PerformanceConfig {
+    fn default() -> Self {
+        Self {
+            baseline_runs: 10,
+            regression_threshold: 0.5,
+        }
+    }
+}
+
+/// Self-monitoring of the memory and CPU the process uses during a run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourcesConfig {
+    /// Whether the resident memory and CPU time of the process are sampled while a run is in progress,
+    /// and their peak is reported at its end.
+    pub monitor: bool,
+    /// Milliseconds between two samples.
+    pub interval_ms: u64,
+    /// Resident memory, in megabytes, beyond which a run is aborted at the end of its current stage.
+    pub max_rss_mb: Option<u64>,
+}
+
+impl Default for ResourcesConfig {
+    fn default() -> Self {
+        Self {
+            monitor: false,
+            interval_ms: 500,
+            max_rss_mb: None,
+        }
+    }
+}
+
+/// Deduplication of failure notifications across runs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// Whether only the first of consecutive failed runs of an input fires `on_failure`, and the run
+    /// succeeding after them fires `on_recovery`.
+    pub deduplicate: bool,
+}
+
+/// How the numbers and units of reports are written, for readers in other countries.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportsConfig {
+    /// Locale whose decimal and thousands separators numbers are written with, e.g. `de` or `fr-FR`;
+    /// numbers are written plainly when unset.
+    pub locale: Option<String>,
+    /// Unit labels of table columns, e.g. `chlorides = "g/dm³"`.
+    pub units: BTreeMap<String, String>,
+}
+
+/// Detection of runs repeating an earlier successful load.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeduplicationConfig {
+    /// What happens when a run with the same input, configuration, and target already succeeded.
+    pub on_duplicate: DuplicatePolicy,
+    /// Whether the input files earlier runs loaded, known by the SHA-256 checksum of their content, are left out.
+    pub skip_processed_files: bool,
+    /// Whether only the rows appended to CSV files since earlier runs are read, from the byte offset recorded for each file.
+    pub incremental: bool,
+}
+
+impl Default for DeduplicationConfig {
+    fn default() -> Self {
+        Self {
+            on_duplicate: DuplicatePolicy::Warn,
+            skip_processed_files: false,
+            incremental: false,
+        }
+    }
+}
+
+/// Settings for injecting faults to test resilience.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Whether faults are injected; requires a build with the `chaos` feature.
+    pub enabled: bool,
+    /// Seed of the fault schedule, so a failing scenario can be repeated.
+    pub seed: u64,
+    /// Share of connection attempts that fail with a transient database error.
+    pub db_error_rate: f64,
+    /// Share of source reads delayed by `slow_read_ms`.
+    pub slow_read_rate: f64,
+    pub slow_read_ms: u64,
+    /// Share of ingested rows given a value no column can store, so they are rejected.
+    pub malformed_row_rate: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            seed: 1,
+            db_error_rate: 0.0,
+            slow_read_rate: 0.0,
+            slow_read_ms: 2_000,
+            malformed_row_rate: 0.0,
+        }
+    }
+}
+
+/// Storage backend of the artifact store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactBackend {
+    Local,
+    /// Requires the `s3` feature. Credentials and region are read from the standard AWS environment variables.
+    S3,
+}
+
+/// Loads the pipeline configuration from a TOML file.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the configuration file.
+///
+/// # Returns
+///
+/// * `Result<PipelineConfig>` - The parsed configuration, the defaults if the file does not exist, or an error if the file cannot be parsed.
+///
+/// # Example
+///
+/// ```
+/// let config = load_config("pipeline.toml").expect("Failed to load configuration");
+/// ```
+pub fn load_config(path: &str) -> Result<PipelineConfig> {
+    if !Path::new(path).exists() {
+        println!("No configuration file found at {}, using defaults", path);
+        return Ok(PipelineConfig::default());
+    }
+
+    let contents = std::fs::read_to_string(path).context(format!("Failed to read configuration file {}", path))?;
+    let mut config: PipelineConfig = toml::from_str(&contents).context(format!("Failed to parse configuration file {}", path))?;
+    mapping::resolve(&mut config.schema, &config.column_mapping);
+
+    let issues = validation::validate(&config);
+    if !issues.is_empty() {
+        let issues: Vec<String> = issues.iter().map(|issue| format!("  {}", issue)).collect();
+        bail!("Invalid configuration file {}:\n{}", path, issues.join("\n"));
+    }
+
+    println!("Loaded configuration from {}", path);
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config_missing_file_uses_defaults() {
+        let config = load_config("non_existent_config.toml").expect("Loading defaults failed");
+        assert!(!config.visualization.enabled);
+        assert_eq!(config.visualization.bins, 20);
+    }
+
+    #[test]
+    fn test_parse_visualization_section() {
+        let config: PipelineConfig = toml::from_str(
+            r#"
+            [visualization]
+            enabled = true
+            format = "svg"
+            columns = ["alcohol"]
+            "#,
+        )
+        .expect("Failed to parse configuration");
+
+        assert!(config.visualization.enabled);
+        assert_eq!(config.visualization.format, ChartFormat::Svg);
+        assert_eq!(config.visualization.columns, vec!["alcohol".to_string()]);
+        assert_eq!(config.visualization.output_dir, "visualizations/runs");
+    }
+
+    #[test]
+    fn test_parse_storage_column_types() {
+        let config: PipelineConfig = toml::from_str(
+            r#"
+            [storage.column_types]
+            "free sulfur dioxide" = { pg_type = "INTEGER", bind = "int4" }
+            "#,
+        )
+        .expect("Failed to parse configuration");
+
+        let mapping = &config.storage.column_types["free sulfur dioxide"];
+        assert_eq!(mapping.pg_type, "INTEGER");
+        assert_eq!(mapping.bind, crate::typemap::BindStrategy::Int4);
+    }
+}
@@ -0,0 +1,359 @@
+//! This module runs the pipeline as a long-lived daemon.
+//!
+//! Runs are requested by triggers (a fixed schedule, changes to a watched input file, files landing in a
+//! watched directory, or the control API) and queued in arrival order. The [`RunCoordinator`] caps the number of concurrent runs and never lets two runs of
+//! the same pipeline overlap, so two loads of the same table never interleave. Triggers and endpoints
+//! run under a [`Supervisor`], which restarts them when they panic or fail.
+
+use crate::config::PipelineConfig;
+use crate::hooks::Hooks;
+use crate::live::LiveFeed;
+use crate::supervisor::Supervisor;
+use crate::{api, health, landing, pipeline, status, storage};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, Semaphore};
+
+/// Name under which runs of the configured pipeline are serialized: the table it loads.
+pub const PIPELINE_NAME: &str = "wine_quality";
+
+/// What requested a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Schedule,
+    FileWatch,
+    Landing,
+    Api,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trigger::Schedule => write!(f, "schedule"),
+            Trigger::FileWatch => write!(f, "file watch"),
+            Trigger::Landing => write!(f, "landing directory"),
+            Trigger::Api => write!(f, "control API"),
+        }
+    }
+}
+
+/// A queued request to run a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunRequest {
+    pub pipeline: String,
+    pub trigger: Trigger,
+    /// A file the run reads instead of the configured source's path, for files in the landing directory.
+    pub input: Option<String>,
+}
+
+/// Admits runs in FIFO order, at most `max_concurrent_runs` at a time and one at a time per pipeline.
+pub struct RunCoordinator {
+    permits: Semaphore,
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    waiting: AtomicUsize,
+    max_queued_runs: usize,
+}
+
+impl RunCoordinator {
+    /// Creates a coordinator.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_concurrent_runs` - The number of runs that may execute at the same time.
+    /// * `max_queued_runs` - The number of runs that may wait for their turn before new requests are rejected.
+    pub fn new(max_concurrent_runs: usize, max_queued_runs: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_concurrent_runs.max(1)),
+            locks: Mutex::new(HashMap::new()),
+            waiting: AtomicUsize::new(0),
+            max_queued_runs,
+        }
+    }
+
+    /// Returns the number of runs waiting for their turn.
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the pipeline's turn, then runs `run`.
+    ///
+    /// Both the per-pipeline lock and the concurrency permits are fair, so waiting runs start in the
+    /// order they were submitted. The pipeline lock is taken first, so a run waiting behind another
+    /// run of the same pipeline does not hold a permit that other pipelines could use.
+    ///
+    /// # Arguments
+    ///
+    /// * `pipeline` - The name of the pipeline; runs with the same name never overlap.
+    /// * `run` - Produces the run's future once it is admitted.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T>` - The result of the run, or an error if the run would have to wait and the queue is full.
+    pub async fn run<T, F, Fut>(&self, pipeline: &str, run: F) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let lock = self.locks.lock().expect("run locks poisoned").entry(pipeline.to_string()).or_default().clone();
+
+        // Runs that can start right away skip the queue
+        let mut queued = false;
+        let _guard = match lock.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                self.enqueue(pipeline)?;
+                queued = true;
+                lock.lock().await
+            }
+        };
+        let _permit = match self.permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                if !queued {
+                    self.enqueue(pipeline)?;
+                    queued = true;
+                }
+                self.permits.acquire().await?
+            }
+        };
+        if queued {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        run().await
+    }
+
+    fn enqueue(&self, pipeline: &str) -> Result<()> {
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.max_queued_runs {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            bail!("Run queue is full ({} waiting), dropping run of {}", self.max_queued_runs, pipeline);
+        }
+        println!("Run of {} queued ({} waiting)", pipeline, self.waiting());
+        Ok(())
+    }
+}
+
+/// Runs the pipeline whenever a configured trigger fires, until interrupted with Ctrl-C.
+///
+/// # Arguments
+///
+/// * `config` - The pipeline configuration; its `[daemon]` section selects the triggers.
+/// * `config_path` - The file the configuration was loaded from, re-validated by the readiness endpoint.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if no trigger is configured or an interval is zero.
+///
+/// # Example
+///
+/// ```
+/// run_daemon(config, "pipeline.toml").await.expect("Daemon failed");
+/// ```
+pub async fn run_daemon(config: PipelineConfig, config_path: &str) -> Result<()> {
+    let daemon = config.daemon.clone();
+    if daemon.schedule_every_secs.is_none() && daemon.watch_path.is_none() && daemon.landing.dir.is_none() && !daemon.api.enabled {
+        bail!("Daemon mode needs a trigger: set daemon.schedule_every_secs, daemon.watch_path or daemon.landing.dir, or enable daemon.api");
+    }
+    if daemon.schedule_every_secs == Some(0) || daemon.watch_poll_secs == 0 {
+        bail!("daemon.schedule_every_secs and daemon.watch_poll_secs must be at least 1");
+    }
+
+    let config = Arc::new(config);
+    let live = Arc::new(LiveFeed::new());
+    let mut hooks = Hooks::from_config(&config);
+    if daemon.api.enabled {
+        live.register(&mut hooks);
+    }
+    let hooks = Arc::new(hooks);
+    let coordinator = Arc::new(RunCoordinator::new(daemon.max_concurrent_runs, daemon.max_queued_runs));
+    status::watch_queue(coordinator.clone());
+    let (tx, mut rx) = mpsc::unbounded_channel::<RunRequest>();
+    let supervisor = Arc::new(Supervisor::new(daemon.supervisor.clone()));
+
+    if let Some(every) = daemon.schedule_every_secs {
+        let tx = tx.clone();
+        supervisor.spawn("schedule", move || {
+            let tx = tx.clone();
+            async move {
+                schedule_trigger(tx, Duration::from_secs(every)).await;
+                Ok(())
+            }
+        });
+    }
+    if let Some(path) = daemon.watch_path.clone() {
+        let (tx, poll) = (tx.clone(), Duration::from_secs(daemon.watch_poll_secs));
+        supervisor.spawn("file_watch", move || {
+            let (tx, path) = (tx.clone(), path.clone());
+            async move {
+                file_watch_trigger(tx, path, poll).await;
+                Ok(())
+            }
+        });
+    }
+    if daemon.landing.dir.is_some() {
+        let (landing_config, tx) = (daemon.landing.clone(), tx.clone());
+        supervisor.spawn("landing", move || landing::watch(landing_config.clone(), tx.clone()));
+    }
+    if daemon.health.enabled {
+        let (health_config, config_path, components) = (daemon.health.clone(), config_path.to_string(), supervisor.clone());
+        supervisor.spawn("health", move || {
+            let (health_config, config_path, components) = (health_config.clone(), config_path.clone(), components.clone());
+            async move { health::serve(&health_config, &config_path, components).await }
+        });
+    }
+    if daemon.api.enabled {
+        let keys = api::resolve_keys(&daemon.api)?;
+        if keys.is_empty() {
+            eprintln!("Control API has no keys configured; every request will be rejected");
+        }
+        // Shared by the handlers, so requests reuse its connections instead of each connecting anew
+        let pool = storage::create_connection_pool().await.context("Failed to connect the control API to the database")?;
+        let state = api::ApiState {
+            keys: Arc::new(keys),
+            requests: tx.clone(),
+            coordinator: coordinator.clone(),
+            config: config.clone(),
+            live,
+            pool,
+        };
+        let api_config = daemon.api.clone();
+        supervisor.spawn("api", move || {
+            let (api_config, state) = (api_config.clone(), state.clone());
+            async move { api::serve(&api_config, state).await }
+        });
+    }
+    drop(tx);
+    println!("Daemon started with at most {} concurrent runs", daemon.max_concurrent_runs);
+
+    loop {
+        tokio::select! {
+            request = rx.recv() => {
+                let Some(request) = request else { break };
+                println!("Run of {} requested by {} ({} waiting)", request.pipeline, request.trigger, coordinator.waiting());
+
+                let (config, hooks, coordinator) = (config.clone(), hooks.clone(), coordinator.clone());
+                tokio::spawn(async move {
+                    let result = coordinator.run(&request.pipeline, || run_request(&config, &hooks, request.input.as_deref())).await;
+                    if let Err(e) = result {
+                        eprintln!("Run of {} requested by {} failed: {:#}", request.pipeline, request.trigger, e);
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => {
+                println!("Daemon stopping");
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Helper function to run the pipeline for a request, over its landed file if it names one, which is moved away afterwards.
+async fn run_request(config: &PipelineConfig, hooks: &Hooks, input: Option<&str>) -> Result<()> {
+    let Some(input) = input else { return pipeline::run(config, hooks).await };
+    let mut file_config = config.clone();
+    file_config.source = config.source.with_path(input).context("Landed files need a source reading a local file")?;
+    let result = pipeline::run(&file_config, hooks).await;
+    match landing::finish(&config.daemon.landing, Path::new(input), result.is_ok()) {
+        Ok(moved) => println!("Moved {} to {}", input, moved.display()),
+        Err(e) => eprintln!("{:#}", e),
+    }
+    result
+}
+
+/// Requests a run at a fixed interval, starting immediately.
+async fn schedule_trigger(tx: mpsc::UnboundedSender<RunRequest>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        if tx.send(RunRequest { pipeline: PIPELINE_NAME.to_string(), trigger: Trigger::Schedule, input: None }).is_err() {
+            return;
+        }
+    }
+}
+
+/// Requests a run whenever the modification time of `path` changes.
+async fn file_watch_trigger(tx: mpsc::UnboundedSender<RunRequest>, path: String, poll: Duration) {
+    let modified = |path: &str| -> Option<SystemTime> { std::fs::metadata(Path::new(path)).and_then(|m| m.modified()).ok() };
+    let mut last_seen = modified(&path);
+    let mut interval = tokio::time::interval(poll);
+    loop {
+        interval.tick().await;
+        let current = modified(&path);
+        if current.is_some() && current != last_seen {
+            last_seen = current;
+            if tx.send(RunRequest { pipeline: PIPELINE_NAME.to_string(), trigger: Trigger::FileWatch, input: None }).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_runs_of_same_pipeline_do_not_overlap() {
+        let coordinator = Arc::new(RunCoordinator::new(4, 10));
+        let active = Arc::new(AtomicUsize::new(0));
+        let overlapped = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let (coordinator, active, overlapped) = (coordinator.clone(), active.clone(), overlapped.clone());
+                tokio::spawn(async move {
+                    coordinator
+                        .run("wine_quality", || async {
+                            if active.fetch_add(1, Ordering::SeqCst) > 0 {
+                                overlapped.fetch_add(1, Ordering::SeqCst);
+                            }
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                            active.fetch_sub(1, Ordering::SeqCst);
+                            Ok(())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(overlapped.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects_runs() {
+        let coordinator = Arc::new(RunCoordinator::new(1, 0));
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+
+        let running = coordinator.clone();
+        let first = tokio::spawn(async move {
+            running
+                .run("wine_quality", || async move {
+                    started_tx.send(()).ok();
+                    release_rx.await.ok();
+                    Ok(())
+                })
+                .await
+        });
+        started_rx.await.unwrap();
+
+        // Neither a run of the same pipeline nor of another one may wait
+        assert!(coordinator.run("wine_quality", || async { Ok(()) }).await.is_err());
+        assert!(coordinator.run("other", || async { Ok(()) }).await.is_err());
+
+        release_tx.send(()).unwrap();
+        first.await.unwrap().unwrap();
+        assert!(coordinator.run("wine_quality", || async { Ok(()) }).await.is_ok());
+    }
+}
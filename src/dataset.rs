@@ -0,0 +1,190 @@
+//! This module appends the stored rows to a Hive-partitioned Parquet dataset, alongside PostgreSQL.
+//!
+//! With `[dataset] enabled`, the rows of each load that PostgreSQL accepted (of each chunk, in chunked
+//! mode) are written below the dataset path as new Parquet files, one per partition, in nested
+//! `<column>=<value>/` directories such as `wine_type=red/load_date=2024-05-01/`. Engines such as
+//! Athena, Trino, and Spark read the directory as a partitioned table. Files are only ever added,
+//! never rewritten, and the partition columns are left out of them, as Hive expects. Columns are named
+//! and encrypted as in PostgreSQL. The `_manifest.json` file at the root, which engines skip like every
+//! file starting with an underscore, lists each file with its run, partition values, and row count.
+//! It is updated once a load's files are written, so a file it does not list was left by a failed run.
+
+use crate::config::DatasetConfig;
+use crate::encryption::ColumnCipher;
+use crate::run::RunContext;
+use crate::schema::TableSchema;
+use crate::{export, lakehouse};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ulid::Ulid;
+
+/// Name of the manifest at the root of the dataset.
+pub const MANIFEST_FILE: &str = "_manifest.json";
+
+/// Partition column holding the date the run started, unless the rows have a column of that name.
+pub const LOAD_DATE_PARTITION: &str = "load_date";
+
+/// The files of a dataset, as its manifest lists them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The partition columns, outermost first.
+    pub partition_by: Vec<String>,
+    pub files: Vec<ManifestFile>,
+}
+
+/// A data file of the dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Location of the file, relative to the dataset root.
+    pub path: String,
+    pub run_id: String,
+    /// The value of each partition column.
+    pub partition: BTreeMap<String, String>,
+    pub rows: usize,
+    pub written_at: String,
+}
+
+/// Appends rows to the configured Parquet dataset.
+///
+/// # Arguments
+///
+/// * `config` - The dataset settings.
+/// * `schema` - The declared schema, naming the dataset columns.
+/// * `cipher` - The cipher of the encrypted columns, if any.
+/// * `run` - The context of the current run.
+/// * `df` - The rows stored in PostgreSQL.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the dataset is partitioned by other columns, or a file or the manifest
+///   cannot be written.
+///
+/// # Example
+///
+/// ```
+/// dataset::append(&config.dataset, &config.schema, cipher.as_ref(), &run, &accepted).await?;
+/// ```
+pub async fn append(config: &DatasetConfig, schema: &TableSchema, cipher: Option<&ColumnCipher>, run: &RunContext, df: &DataFrame) -> Result<()> {
+    if !config.enabled || df.height() == 0 {
+        return Ok(());
+    }
+    // Partition columns are named like the table columns, as every other column of the files
+    let columns: Vec<String> = config
+        .partition_by
+        .iter()
+        .map(|name| schema.columns.iter().find(|c| &c.name == name).map_or(name.clone(), |c| c.column.clone()))
+        .collect();
+    let mut df = lakehouse::lake_frame(df, schema, cipher)?;
+    if columns.iter().any(|c| c == LOAD_DATE_PARTITION) && df.column(LOAD_DATE_PARTITION).is_err() {
+        let date = run.started_at.format("%Y-%m-%d").to_string();
+        df.with_column(Series::new(LOAD_DATE_PARTITION, vec![date; df.height()]))?;
+    }
+
+    let (store, prefix) = export::open_destination(&config.path)?;
+    let manifest_location = prefix.child(MANIFEST_FILE);
+    let mut manifest = read_manifest(store.as_ref(), &manifest_location).await?;
+    if manifest.files.is_empty() {
+        manifest.partition_by = columns.clone();
+    } else if manifest.partition_by != columns {
+        bail!(
+            "The dataset at {} is partitioned by [{}], not [{}]; write to a new path to change its partitioning",
+            config.path,
+            manifest.partition_by.join(", "),
+            columns.join(", ")
+        );
+    }
+
+    let mut written = 0;
+    for (partition, mut part) in partitions(&df, &columns)? {
+        let relative = file_location(&partition);
+        let location: Path = prefix.parts().chain(relative.parts()).collect();
+        let mut buffer = Vec::new();
+        ParquetWriter::new(&mut buffer).with_compression(config.compression.parquet()?).finish(&mut part).context("Failed to encode Parquet")?;
+        store.put(&location, buffer.into()).await.context(format!("Failed to write {}", location))?;
+        manifest.files.push(ManifestFile {
+            path: relative.to_string(),
+            run_id: run.id.clone(),
+            partition: partition.into_iter().collect(),
+            rows: part.height(),
+            written_at: Utc::now().to_rfc3339(),
+        });
+        written += 1;
+    }
+
+    let json = serde_json::to_vec_pretty(&manifest).context("Failed to serialize the dataset manifest")?;
+    store.put(&manifest_location, json.into()).await.context(format!("Failed to write the manifest {}", manifest_location))?;
+    run.log(format_args!("Appended {} rows to Parquet dataset {} in {} files", df.height(), config.path, written));
+    Ok(())
+}
+
+/// Helper function to read the manifest of a dataset, empty if the dataset has none yet.
+async fn read_manifest(store: &dyn ObjectStore, location: &Path) -> Result<Manifest> {
+    match store.get(location).await {
+        Ok(result) => {
+            let bytes = result.bytes().await.context(format!("Failed to read the manifest {}", location))?;
+            serde_json::from_slice(&bytes).context(format!("The manifest {} is not valid", location))
+        }
+        Err(object_store::Error::NotFound { .. }) => Ok(Manifest::default()),
+        Err(e) => Err(e).context(format!("Failed to read the manifest {}", location)),
+    }
+}
+
+/// Helper function to split rows by the values of the partition columns, which are dropped from the parts.
+fn partitions(df: &DataFrame, columns: &[String]) -> Result<Vec<(Vec<(String, String)>, DataFrame)>> {
+    if columns.is_empty() {
+        return Ok(vec![(vec![], df.clone())]);
+    }
+    let mut parts = vec![];
+    for part in df.partition_by_stable(columns, true)? {
+        let partition = columns.iter().map(|c| Ok((c.clone(), export::partition_value(&part, c)?))).collect::<Result<Vec<_>>>()?;
+        parts.push((partition, part.drop_many(columns)));
+    }
+    Ok(parts)
+}
+
+/// Helper function to name a new file of a partition, relative to the dataset root.
+///
+/// Values are percent-encoded where they hold characters that files cannot be named with, such as `/`.
+fn file_location(partition: &[(String, String)]) -> Path {
+    let directory = partition.iter().fold(Path::default(), |path, (column, value)| path.child(format!("{}={}", column, value)));
+    directory.child(format!("part-{}.parquet", Ulid::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_append_writes_partitions_and_manifest() {
+        let root = "temp_dataset_test";
+        let mut config = DatasetConfig {
+            enabled: true,
+            path: root.to_string(),
+            partition_by: vec!["wine_type".to_string(), LOAD_DATE_PARTITION.to_string()],
+            ..DatasetConfig::default()
+        };
+        let df = polars::df!("wine_type" => &["red", "white", "red"], "alcohol" => &[9.4, 10.0, 9.8]).unwrap();
+        let run = RunContext::new();
+        let date = run.started_at.format("%Y-%m-%d").to_string();
+
+        append(&config, &TableSchema::default(), None, &run, &df).await.unwrap();
+        append(&config, &TableSchema::default(), None, &run, &df.head(Some(1))).await.unwrap();
+        let manifest: Manifest = serde_json::from_slice(&std::fs::read(format!("{}/{}", root, MANIFEST_FILE)).unwrap()).unwrap();
+        let red = &manifest.files[0];
+        let part = ParquetReader::new(std::fs::File::open(format!("{}/{}", root, red.path)).unwrap()).finish().unwrap();
+        config.partition_by.pop();
+        let repartitioned = append(&config, &TableSchema::default(), None, &run, &df).await;
+        std::fs::remove_dir_all(root).ok();
+
+        assert_eq!(manifest.files.iter().map(|f| f.rows).collect::<Vec<_>>(), vec![2, 1, 1]);
+        assert!(red.path.starts_with(&format!("wine_type=red/load_date={}/part-", date)));
+        assert_eq!(red.partition.get("wine_type").map(String::as_str), Some("red"));
+        assert_eq!(part.get_column_names(), vec!["alcohol"]);
+        assert!(repartitioned.is_err());
+    }
+}
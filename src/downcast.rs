@@ -0,0 +1,174 @@
+//! This module downcasts columns to the smallest dtype that holds their values exactly.
+//!
+//! Transformation produces `Float64` and `Int64` columns even where `Float32` or `Int8`/`Int16`/`Int32`
+//! would do, which makes Parquet artifacts and exports, and the table columns added for undeclared
+//! columns, larger than needed. With `[downcast] enabled`, an integer column is narrowed to the smallest
+//! type its range fits, and a float column to `Float32` when every value has the same decimal form as an
+//! `f32`, so nothing is lost. Exports, whose pages are written as separate files, narrow by the declared
+//! column types instead, so every file of an export has the same schema.
+
+use crate::config::DowncastConfig;
+use crate::schema::TableSchema;
+use anyhow::{Context, Result};
+use polars::prelude::*;
+
+/// Narrows the columns of a DataFrame the schema does not declare, before storage.
+///
+/// Declared columns keep their dtype, since the table type they are stored as is fixed by the schema;
+/// undeclared ones are added to the table with the type of their narrowed dtype.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame about to be stored.
+/// * `config` - The downcast settings.
+/// * `schema` - The declared schema.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with narrowed undeclared columns, or an error if a cast fails.
+///
+/// # Example
+///
+/// ```
+/// let transformed_df = downcast_stage(transformed_df, &config.downcast, &config.schema)?;
+/// ```
+pub fn downcast_stage(df: DataFrame, config: &DowncastConfig, schema: &TableSchema) -> Result<DataFrame> {
+    if !config.enabled {
+        return Ok(df);
+    }
+    narrow(df, |name| !schema.columns.iter().any(|c| c.name == name))
+}
+
+/// Narrows every column of a DataFrame to the smallest dtype holding its values.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame to narrow.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The narrowed DataFrame, or an error if a cast fails.
+pub fn narrow_all(df: DataFrame) -> Result<DataFrame> {
+    narrow(df, |_| true)
+}
+
+/// Narrows the columns of a DataFrame to the dtypes their declared PostgreSQL types always fit.
+///
+/// `SMALLINT` and `INTEGER` columns become `Int16` and `Int32`, and `REAL` columns and `DECIMAL(p, s)`
+/// columns of at most six digits become `Float32`, which keeps every such value's decimal digits.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame read from the table.
+/// * `schema` - The schema describing the table columns.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The narrowed DataFrame, or an error if a cast fails.
+pub fn narrow_to_schema(mut df: DataFrame, schema: &TableSchema) -> Result<DataFrame> {
+    for spec in &schema.columns {
+        let pg_type = spec.pg_type.to_ascii_uppercase();
+        let dtype = if pg_type.starts_with("SMALLINT") {
+            DataType::Int16
+        } else if pg_type == "INTEGER" || pg_type == "INT" || pg_type == "INT4" {
+            DataType::Int32
+        } else if pg_type.starts_with("REAL") || decimal_precision(&pg_type).is_some_and(|p| p <= 6) {
+            DataType::Float32
+        } else {
+            continue;
+        };
+        let Ok(series) = df.column(&spec.name) else { continue };
+        let narrowed = series.cast(&dtype).context(format!("Failed to downcast column {} to {}", spec.name, dtype))?;
+        df.with_column(narrowed)?;
+    }
+    Ok(df)
+}
+
+/// Helper function to narrow the selected columns and report the saving.
+fn narrow(mut df: DataFrame, selected: impl Fn(&str) -> bool) -> Result<DataFrame> {
+    let before = df.estimated_size();
+    let mut narrowed = 0;
+    let names: Vec<String> = df.get_column_names().iter().map(|s| s.to_string()).collect();
+    for name in names {
+        if !selected(&name) {
+            continue;
+        }
+        let series = df.column(&name)?;
+        if let Some(dtype) = narrowest_dtype(series)? {
+            let cast = series.cast(&dtype).context(format!("Failed to downcast column {} to {}", name, dtype))?;
+            df.with_column(cast)?;
+            narrowed += 1;
+        }
+    }
+    if narrowed > 0 {
+        println!("Downcast {} columns, from {} KB to {} KB", narrowed, before >> 10, df.estimated_size() >> 10);
+    }
+    Ok(df)
+}
+
+/// Helper function to find a smaller dtype holding every value of a column, if there is one.
+fn narrowest_dtype(series: &Series) -> Result<Option<DataType>> {
+    if series.null_count() == series.len() {
+        return Ok(None);
+    }
+    match series.dtype() {
+        DataType::Float64 => {
+            let exact = series.f64()?.into_iter().flatten().all(|v| v.is_nan() || (v as f32).to_string().parse::<f64>().is_ok_and(|w| w == v));
+            Ok(exact.then_some(DataType::Float32))
+        }
+        DataType::Int64 | DataType::Int32 | DataType::Int16 => {
+            let values = series.cast(&DataType::Int64)?;
+            let values = values.i64()?;
+            let (min, max) = (values.min().unwrap_or(0), values.max().unwrap_or(0));
+            let dtype = [
+                (DataType::Int8, i8::MIN as i64, i8::MAX as i64),
+                (DataType::Int16, i16::MIN as i64, i16::MAX as i64),
+                (DataType::Int32, i32::MIN as i64, i32::MAX as i64),
+            ]
+            .into_iter()
+            .find(|(_, low, high)| min >= *low && max <= *high)
+            .map(|(dtype, ..)| dtype)
+            .filter(|dtype| dtype != series.dtype());
+            Ok(dtype)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Helper function to read the precision of a `DECIMAL(p, s)` or `NUMERIC(p, s)` type.
+fn decimal_precision(pg_type: &str) -> Option<u32> {
+    let arguments = pg_type.strip_prefix("DECIMAL").or_else(|| pg_type.strip_prefix("NUMERIC"))?;
+    arguments.trim().strip_prefix('(')?.split([',', ')']).next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_narrow_all_keeps_values_exact() {
+        let df = df!(
+            "alcohol" => &[Some(9.4), None, Some(10.5)],
+            "ratio" => &[1.0 / 3.0, 0.5, 0.25],
+            "quality" => &[3i64, 5, 8],
+            "total sulfur dioxide" => &[6i64, 289, 40_000],
+            "taster" => &["a", "b", "c"]
+        )
+        .unwrap();
+
+        let narrowed = narrow_all(df).unwrap();
+        assert_eq!(narrowed.dtypes(), vec![DataType::Float32, DataType::Float64, DataType::Int8, DataType::Int32, DataType::String]);
+        assert_eq!(narrowed.column("alcohol").unwrap().f32().unwrap().get(0).unwrap().to_string(), "9.4");
+    }
+
+    #[test]
+    fn test_narrow_to_schema() {
+        let df = df!("density" => &[0.99676], "alcohol" => &[9.4], "quality" => &[5i64]).unwrap();
+
+        let narrowed = narrow_to_schema(df, &TableSchema::default()).unwrap();
+        // DECIMAL(6, 5) fits an f32 exactly, DECIMAL(4, 1) too; INTEGER becomes Int32
+        assert_eq!(narrowed.dtypes(), vec![DataType::Float32, DataType::Float32, DataType::Int32]);
+        assert_eq!(decimal_precision("NUMERIC(10, 2)"), Some(10));
+    }
+}
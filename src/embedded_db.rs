@@ -0,0 +1,53 @@
+//! This module starts an ephemeral PostgreSQL server inside the pipeline process.
+//!
+//! With the `embedded-postgres` feature, `--embedded-db` downloads (on first use) and starts a
+//! throwaway PostgreSQL server in a temporary directory and points `DATABASE_URL` at it, so the
+//! self-test and local demos run without an external database. The server and its data are removed
+//! when the process exits.
+
+use anyhow::Result;
+
+/// Name of the database created on the embedded server.
+#[cfg(feature = "embedded-postgres")]
+const DATABASE_NAME: &str = "wine_quality_pipeline";
+
+/// A running embedded server. Dropping it stops the server.
+#[cfg_attr(not(feature = "embedded-postgres"), allow(dead_code))]
+pub struct EmbeddedDatabase {
+    #[cfg(feature = "embedded-postgres")]
+    _server: postgresql_embedded::PostgreSQL,
+}
+
+/// Starts the embedded server and sets `DATABASE_URL` to it.
+///
+/// # Returns
+///
+/// * `Result<EmbeddedDatabase>` - The running server, or an error if it cannot be started or the binary was built without the `embedded-postgres` feature.
+///
+/// # Example
+///
+/// ```
+/// let _database = start().await.expect("Failed to start the embedded database");
+/// ```
+#[cfg(feature = "embedded-postgres")]
+pub async fn start() -> Result<EmbeddedDatabase> {
+    use anyhow::Context;
+
+    let mut server = postgresql_embedded::PostgreSQL::default();
+    server.setup().await.context("Failed to install the embedded PostgreSQL server")?;
+    server.start().await.context("Failed to start the embedded PostgreSQL server")?;
+    server
+        .create_database(DATABASE_NAME)
+        .await
+        .context(format!("Failed to create the {} database", DATABASE_NAME))?;
+
+    let url = server.settings().url(DATABASE_NAME);
+    std::env::set_var("DATABASE_URL", &url);
+    println!("Embedded PostgreSQL listening on port {}", server.settings().port);
+    Ok(EmbeddedDatabase { _server: server })
+}
+
+#[cfg(not(feature = "embedded-postgres"))]
+pub async fn start() -> Result<EmbeddedDatabase> {
+    anyhow::bail!("--embedded-db requires building with the `embedded-postgres` feature")
+}
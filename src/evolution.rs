@@ -0,0 +1,211 @@
+//! This module reconciles the stored table with the columns of the transformed data.
+//!
+//! Before rows are stored, the columns of the table are compared with the columns the DataFrame
+//! brings: declared schema columns the table lacks, and, unless they are ignored, DataFrame columns
+//! the schema does not declare at all, such as a new measurement added to the dataset. Depending on
+//! `[storage] on_new_columns`, the missing columns are added with `ALTER TABLE ... ADD COLUMN` or the
+//! run fails with the list of columns that differ, so no manual DDL is needed to load a wider dataset.
+
+use crate::config::{ColumnMappingConfig, PipelineConfig};
+use crate::mapping;
+use crate::schema::{ColumnSchema, TableSchema};
+use crate::typemap::TypeRegistry;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+
+/// What happens to DataFrame columns the table does not have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewColumnPolicy {
+    /// Columns the schema does not declare are not stored; declared columns the table lacks fail the run.
+    #[default]
+    Ignore,
+    /// Any column the table lacks, declared or not, fails the run.
+    Fail,
+    /// Columns the table lacks are added to it, undeclared ones with the type mapped from their dtype.
+    Add,
+}
+
+/// The columns of the transformed data that the table lacks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SchemaDiff {
+    /// Declared schema columns missing from the table.
+    pub missing: Vec<ColumnSchema>,
+    /// DataFrame columns the schema does not declare, with their derived table column and type.
+    pub undeclared: Vec<ColumnSchema>,
+}
+
+impl SchemaDiff {
+    /// Helper function to describe the missing columns, one `+ column type` line each.
+    fn describe(&self, table: &str) -> String {
+        let lines: Vec<String> = self
+            .missing
+            .iter()
+            .map(|c| format!("  + {} {}", c.column, c.pg_type))
+            .chain(self.undeclared.iter().map(|c| format!("  + {} {} (column {:?} is not in [[schema.columns]])", c.column, c.pg_type, c.name)))
+            .collect();
+        format!("Table {} lacks {} columns of the transformed data:\n{}", table, lines.len(), lines.join("\n"))
+    }
+}
+
+/// Makes sure the table can store the transformed data, adding missing columns if configured to.
+///
+/// Added columns are nullable, since the rows already stored have no value for them.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `table` - The table the data is stored in.
+/// * `df` - A reference to the transformed DataFrame.
+/// * `config` - The pipeline configuration, for the schema, column mapping, and policy.
+/// * `registry` - The type registry mapping dtypes of undeclared columns to PostgreSQL types.
+///
+/// # Returns
+///
+/// * `Result<TableSchema>` - The schema to store the data with, including added undeclared columns,
+///   or an error listing the missing columns if the policy does not add them.
+///
+/// # Example
+///
+/// ```
+/// let schema = evolve(&pool, "wine_quality", &transformed_df, &config, &registry).await?;
+/// ```
+pub async fn evolve(pool: &PgPool, table: &str, df: &DataFrame, config: &PipelineConfig, registry: &TypeRegistry) -> Result<TableSchema> {
+    let existing: HashSet<String> = sqlx::query_scalar(
+        "SELECT column_name::TEXT FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+    )
+    .bind(table)
+    .fetch_all(pool)
+    .await
+    .context(format!("Failed to look up the columns of {}", table))?
+    .into_iter()
+    .collect();
+    if existing.is_empty() {
+        bail!("Table {} does not exist", table);
+    }
+
+    let policy = config.storage.on_new_columns;
+    let mut diff = diff(df, &config.schema, &existing, registry, &config.column_mapping)?;
+    if policy == NewColumnPolicy::Ignore {
+        diff.undeclared.clear();
+    }
+    if diff.missing.is_empty() && diff.undeclared.is_empty() {
+        return Ok(config.schema.clone());
+    }
+    if policy != NewColumnPolicy::Add {
+        bail!(
+            "{}\nAdd them to the table, or set on_new_columns = \"add\" in [storage] to add them automatically",
+            diff.describe(table)
+        );
+    }
+
+    let mut tx = pool.begin().await?;
+    for column in diff.missing.iter().chain(&diff.undeclared) {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}", table, column.column, column.pg_type))
+            .execute(&mut *tx)
+            .await
+            .context(format!("Failed to add column {} to {}", column.column, table))?;
+    }
+    tx.commit().await?;
+    println!("{}\nAdded them to the table.", diff.describe(table));
+
+    let mut schema = config.schema.clone();
+    schema.columns.extend(diff.undeclared);
+    Ok(schema)
+}
+
+/// Compares the columns of a DataFrame and its schema with the columns of the table.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the transformed DataFrame.
+/// * `schema` - The declared schema.
+/// * `existing` - The columns of the table.
+/// * `registry` - The type registry mapping dtypes of undeclared columns to PostgreSQL types.
+/// * `mapping` - The column mapping deriving table columns of undeclared columns.
+///
+/// # Returns
+///
+/// * `Result<SchemaDiff>` - The columns the table lacks, or an error if a dtype has no mapping.
+pub fn diff(
+    df: &DataFrame,
+    schema: &TableSchema,
+    existing: &HashSet<String>,
+    registry: &TypeRegistry,
+    mapping: &ColumnMappingConfig,
+) -> Result<SchemaDiff> {
+    let names: HashSet<&str> = df.get_column_names().into_iter().collect();
+    let missing = schema
+        .columns
+        .iter()
+        // Absent nullable columns are not stored, so the table does not need them
+        .filter(|c| !existing.contains(&c.column) && (names.contains(c.name.as_str()) || !c.nullable))
+        .cloned()
+        .collect();
+
+    let mut undeclared = vec![];
+    for series in df.get_columns() {
+        if schema.columns.iter().any(|c| c.name == series.name()) {
+            continue;
+        }
+        let column = mapping::target_column(series.name(), mapping);
+        if existing.contains(&column) {
+            continue;
+        }
+        undeclared.push(ColumnSchema {
+            name: series.name().to_string(),
+            column,
+            pg_type: registry.mapping(series.name(), series.dtype())?.pg_type,
+            nullable: true,
+            impute: None,
+        });
+    }
+    Ok(SchemaDiff { missing, undeclared })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lists_missing_and_undeclared_columns() {
+        let df = polars::df!(
+            "alcohol" => &[9.4],
+            "quality" => &[5i64],
+            "Tannin Level" => &[0.3]
+        )
+        .unwrap();
+        let schema: TableSchema = toml::from_str(
+            r#"
+            [[columns]]
+            name = "alcohol"
+            column = "alcohol"
+            pg_type = "DECIMAL(4, 1)"
+
+            [[columns]]
+            name = "quality"
+            column = "quality"
+            pg_type = "INTEGER"
+
+            [[columns]]
+            name = "cluster"
+            column = "cluster"
+            pg_type = "INTEGER"
+            nullable = true
+            "#,
+        )
+        .unwrap();
+        let existing = HashSet::from(["id".to_string(), "alcohol".to_string()]);
+
+        let diff = diff(&df, &schema, &existing, &TypeRegistry::default(), &ColumnMappingConfig::default()).unwrap();
+
+        let missing: Vec<&str> = diff.missing.iter().map(|c| c.column.as_str()).collect();
+        assert_eq!(missing, vec!["quality"]);
+        assert_eq!(diff.undeclared.len(), 1);
+        assert_eq!(diff.undeclared[0].column, "tannin_level");
+        assert!(diff.describe("wine_quality").contains("+ tannin_level DOUBLE PRECISION"));
+    }
+}
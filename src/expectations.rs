@@ -0,0 +1,516 @@
+//! This module evaluates declarative expectations against transformed data.
+//!
+//! Expectations are declared in the `[expectations]` section of the configuration, evaluated after
+//! transformation, and their results are stored per run. Failures either only get reported or fail the run,
+//! depending on the configured policy. Expectations of severity `warning` never fail the run: they are
+//! reported and counted in the staging report, so minor issues stay visible without blocking a load. Expectations checking values against a dictionary table, such as
+//! valid region codes, read the table once when the run starts, or take it from the reference cache.
+
+use crate::config::{ExpectationsConfig, FailurePolicy, ReferenceCacheConfig};
+use crate::reference;
+use crate::run::RunContext;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// An expectation of the suite and how severe its failure is.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub expectation: Expectation,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// How severe the failure of an expectation is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The failure is handled by the failure policy, and fails the run when it is `fail`.
+    #[default]
+    Error,
+    /// The failure is only reported.
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A single declarative expectation. Bounds are inclusive and either may be omitted.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "expect", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Expectation {
+    /// The mean of the column lies within the bounds.
+    ColumnMeanBetween { column: String, min: Option<f64>, max: Option<f64> },
+    /// Every non-null value of the column lies within the bounds.
+    ColumnValuesBetween { column: String, min: Option<f64>, max: Option<f64> },
+    /// Every non-null value of the column is one of the listed values.
+    ColumnValuesInSet { column: String, values: Vec<f64> },
+    /// Every non-null value of the column, compared as text, is a value of `key` in the dictionary table `table`.
+    ColumnValuesInTable { column: String, table: String, key: String },
+    /// The column contains no nulls.
+    ColumnValuesNotNull { column: String },
+    /// The number of rows lies within the bounds.
+    RowCountBetween { min: Option<usize>, max: Option<usize> },
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expectation::ColumnMeanBetween { column, min, max } => write!(f, "mean of {} {}", column, describe_bounds(min, max)),
+            Expectation::ColumnValuesBetween { column, min, max } => write!(f, "values of {} {}", column, describe_bounds(min, max)),
+            Expectation::ColumnValuesInSet { column, values } => write!(f, "values of {} in {:?}", column, values),
+            Expectation::ColumnValuesInTable { column, table, key } => write!(f, "values of {} in {}.{}", column, table, key),
+            Expectation::ColumnValuesNotNull { column } => write!(f, "values of {} not null", column),
+            Expectation::RowCountBetween { min, max } => write!(f, "row count {}", describe_bounds(min, max)),
+        }
+    }
+}
+
+/// The values of the dictionary tables referenced by a suite, keyed by table and key column.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionaries {
+    values: HashMap<(String, String), Arc<HashSet<String>>>,
+}
+
+impl Dictionaries {
+    /// Reads the dictionary tables referenced by the suite, each once, unless the reference cache holds them.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - A reference to the PostgreSQL connection pool.
+    /// * `suite` - The expectations to evaluate.
+    /// * `cache` - The reference cache settings.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Dictionaries>` - The values of every referenced key column, or an error if a table cannot be read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let dictionaries = Dictionaries::load(&pool, &config.expectations.suite, &config.reference_cache).await?;
+    /// ```
+    pub async fn load(pool: &PgPool, suite: &[Rule], cache: &ReferenceCacheConfig) -> Result<Self> {
+        let mut dictionaries = Self::default();
+        for rule in suite {
+            let Expectation::ColumnValuesInTable { table, key, .. } = &rule.expectation else {
+                continue;
+            };
+            if dictionaries.values.contains_key(&(table.clone(), key.clone())) {
+                continue;
+            }
+            let values = reference::get_or_load(table, key, cache, || async {
+                let values: Vec<String> = sqlx::query_scalar(&format!("SELECT DISTINCT {}::TEXT FROM {} WHERE {} IS NOT NULL", key, table, key))
+                    .fetch_all(pool)
+                    .await
+                    .context(format!("Failed to read the dictionary {}.{}", table, key))?;
+                println!("Loaded {} values of the dictionary {}.{}", values.len(), table, key);
+                Ok(values)
+            })
+            .await?;
+            dictionaries.insert(table, key, values);
+        }
+        Ok(dictionaries)
+    }
+
+    /// Helper function to add the values of a dictionary.
+    fn insert(&mut self, table: &str, key: &str, values: Arc<HashSet<String>>) {
+        self.values.insert((table.to_string(), key.to_string()), values);
+    }
+
+    /// Helper function to look up the values of a dictionary.
+    fn get(&self, table: &str, key: &str) -> Result<&HashSet<String>> {
+        self.values
+            .get(&(table.to_string(), key.to_string()))
+            .map(|values| values.as_ref())
+            .context(format!("The dictionary {}.{} was not loaded", table, key))
+    }
+}
+
+/// Outcome of evaluating one expectation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectationResult {
+    pub expectation: String,
+    pub passed: bool,
+    /// Human-readable description of the observed value.
+    pub observed: String,
+    pub severity: Severity,
+}
+
+impl ExpectationResult {
+    /// Whether the expectation failed with severity `error`.
+    pub fn is_error(&self) -> bool {
+        !self.passed && self.severity == Severity::Error
+    }
+}
+
+/// Evaluates the configured suite and stores the results for the run.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the current run.
+/// * `df` - A reference to the transformed DataFrame.
+/// * `config` - The expectation suite settings.
+/// * `dictionaries` - The dictionary tables read at the start of the run.
+///
+/// # Returns
+///
+/// * `Result<Vec<ExpectationResult>>` - One result per expectation, or an error if the suite cannot be evaluated or stored.
+///
+/// # Example
+///
+/// ```
+/// let results = run_suite(&pool, &run, &transformed_df, &config.expectations, &dictionaries).await.expect("Failed to evaluate expectations");
+/// enforce(&results, &config.expectations)?;
+/// ```
+pub async fn run_suite(pool: &PgPool, run: &RunContext, df: &DataFrame, config: &ExpectationsConfig, dictionaries: &Dictionaries) -> Result<Vec<ExpectationResult>> {
+    if config.suite.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let suite: Vec<Expectation> = config.suite.iter().map(|rule| rule.expectation.clone()).collect();
+    let mut results = match config.sample_fraction {
+        Some(fraction) if fraction < 1.0 => evaluate_sampled(df, &suite, dictionaries, fraction, &run.id)?,
+        _ => evaluate(df, &suite, dictionaries)?,
+    };
+    for (result, rule) in results.iter_mut().zip(&config.suite) {
+        result.severity = rule.severity;
+        let status = match (result.passed, result.severity) {
+            (true, _) => "PASS",
+            (false, Severity::Error) => "FAIL",
+            (false, Severity::Warning) => "WARN",
+        };
+        println!("[{}] {} (observed: {})", status, result.expectation, result.observed);
+        if !result.passed && (result.severity == Severity::Warning || config.on_failure == FailurePolicy::Warn) {
+            run.add_warning("expectations", format_args!("{} failed (observed: {})", result.expectation, result.observed));
+        }
+    }
+    store_results(pool, run, &results).await?;
+    Ok(results)
+}
+
+/// Describes the failed expectations of a suite, if any failed.
+pub fn describe_failures(results: &[ExpectationResult]) -> Option<String> {
+    let failed: Vec<String> = results
+        .iter()
+        .filter(|r| !r.passed)
+        .map(|r| match r.severity {
+            Severity::Error => format!("{} (observed: {})", r.expectation, r.observed),
+            Severity::Warning => format!("{} (observed: {}, warning)", r.expectation, r.observed),
+        })
+        .collect();
+    (!failed.is_empty()).then(|| format!("{} of {} expectations failed: {}", failed.len(), results.len(), failed.join("; ")))
+}
+
+/// Applies the failure policy to the results of a suite.
+///
+/// # Returns
+///
+/// * `Result<()>` - Ok if no expectation of severity `error` failed or the policy is `warn`, or an error if the policy is `fail` and one failed.
+pub fn enforce(results: &[ExpectationResult], config: &ExpectationsConfig) -> Result<()> {
+    let failed = results.iter().filter(|r| r.is_error()).count();
+    if failed > 0 && config.on_failure == FailurePolicy::Fail {
+        bail!("{} of {} expectations failed", failed, results.len());
+    }
+    let warnings = results.iter().filter(|r| !r.passed && r.severity == Severity::Warning).count();
+    if warnings > 0 {
+        println!("{} expectations of severity warning failed; continuing", warnings);
+    }
+    Ok(())
+}
+
+/// Evaluates each expectation against a DataFrame.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to check.
+/// * `suite` - The expectations to evaluate.
+/// * `dictionaries` - The dictionary tables the suite refers to.
+///
+/// # Returns
+///
+/// * `Result<Vec<ExpectationResult>>` - One result per expectation, or an error if a referenced column is missing.
+pub fn evaluate(df: &DataFrame, suite: &[Expectation], dictionaries: &Dictionaries) -> Result<Vec<ExpectationResult>> {
+    suite
+        .iter()
+        .map(|expectation| {
+            let (passed, observed) = match expectation {
+                Expectation::ColumnMeanBetween { column, min, max } => match float_column(df, column)?.mean() {
+                    Some(mean) => (within(mean, *min, *max), format!("{:.4}", mean)),
+                    None => (false, "no values".to_string()),
+                },
+                Expectation::ColumnValuesBetween { column, min, max } => {
+                    let values = float_column(df, column)?;
+                    let outside = values.into_iter().flatten().filter(|&v| !within(v, *min, *max)).count();
+                    (outside == 0, format!("{} values out of range", outside))
+                }
+                Expectation::ColumnValuesInSet { column, values: allowed } => {
+                    let values = float_column(df, column)?;
+                    let unexpected = values.into_iter().flatten().filter(|v| !allowed.contains(v)).count();
+                    (unexpected == 0, format!("{} unexpected values", unexpected))
+                }
+                Expectation::ColumnValuesInTable { column, table, key } => {
+                    let allowed = dictionaries.get(table, key)?;
+                    let values = df
+                        .column(column)
+                        .context(format!("Error fetching column {}", column))?
+                        .cast(&DataType::String)
+                        .context(format!("Error converting {} column to text", column))?;
+                    let unexpected = values.str()?.into_iter().flatten().filter(|v| !allowed.contains(*v)).count();
+                    (unexpected == 0, format!("{} values not in {}.{}", unexpected, table, key))
+                }
+                Expectation::ColumnValuesNotNull { column } => {
+                    let nulls = df.column(column).context(format!("Error fetching column {}", column))?.null_count();
+                    (nulls == 0, format!("{} nulls", nulls))
+                }
+                Expectation::RowCountBetween { min, max } => {
+                    let rows = df.height();
+                    let passed = min.is_none_or(|m| rows >= m) && max.is_none_or(|m| rows <= m);
+                    (passed, format!("{} rows", rows))
+                }
+            };
+
+            Ok(ExpectationResult {
+                expectation: expectation.to_string(),
+                passed,
+                observed,
+                severity: Severity::Error,
+            })
+        })
+        .collect()
+}
+
+/// Evaluates the expectations against a random sample of the rows, and against all rows if the sample fails.
+///
+/// Row count expectations are always evaluated against all rows. When every expectation holds for the
+/// sample, its results are returned with the sample size noted in the observed values; otherwise the
+/// suite is evaluated again on the full DataFrame, so a failure always reports the complete picture.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to check.
+/// * `suite` - The expectations to evaluate.
+/// * `dictionaries` - The dictionary tables the suite refers to.
+/// * `fraction` - The fraction of rows sampled, e.g. `0.05`.
+/// * `seed` - Seed of the sample, e.g. the run ID, so a run always checks the same rows.
+///
+/// # Returns
+///
+/// * `Result<Vec<ExpectationResult>>` - One result per expectation, or an error if a referenced column is missing.
+pub fn evaluate_sampled(df: &DataFrame, suite: &[Expectation], dictionaries: &Dictionaries, fraction: f64, seed: &str) -> Result<Vec<ExpectationResult>> {
+    let sample = sample_rows(df, fraction, seed)?;
+    let mut results = vec![];
+    for expectation in suite {
+        let frame = if matches!(expectation, Expectation::RowCountBetween { .. }) { df } else { &sample };
+        results.extend(evaluate(frame, std::slice::from_ref(expectation), dictionaries)?);
+    }
+
+    if results.iter().all(|r| r.passed) {
+        println!("Sample of {} of {} rows passed all expectations", sample.height(), df.height());
+        for (result, expectation) in results.iter_mut().zip(suite) {
+            if !matches!(expectation, Expectation::RowCountBetween { .. }) {
+                result.observed = format!("{} in a sample of {} rows", result.observed, sample.height());
+            }
+        }
+        return Ok(results);
+    }
+    println!("Sample of {} rows failed; validating all {} rows", sample.height(), df.height());
+    evaluate(df, suite, dictionaries)
+}
+
+/// Helper function to select a seeded random sample of about `fraction` of the rows, at least one if there are any.
+fn sample_rows(df: &DataFrame, fraction: f64, seed: &str) -> Result<DataFrame> {
+    let seed = seed.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+    let threshold = (fraction.clamp(0.0, 1.0) * u64::MAX as f64) as u64;
+    let mut indices: Vec<IdxSize> = (0..df.height() as u64)
+        .filter(|&i| splitmix64(seed ^ i) < threshold)
+        .map(|i| i as IdxSize)
+        .collect();
+    if indices.is_empty() && df.height() > 0 {
+        indices.push((splitmix64(seed) % df.height() as u64) as IdxSize);
+    }
+    df.take(&IdxCa::from_vec("sample", indices)).context("Failed to sample rows")
+}
+
+/// Helper function to scramble a 64-bit value (SplitMix64).
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Stores the evaluated results in the `expectation_results` table.
+async fn store_results(pool: &PgPool, run: &RunContext, results: &[ExpectationResult]) -> Result<()> {
+    for result in results {
+        sqlx::query("INSERT INTO expectation_results (run_id, expectation, passed, observed, severity) VALUES ($1, $2, $3, $4, $5)")
+            .bind(&run.id)
+            .bind(&result.expectation)
+            .bind(result.passed)
+            .bind(&result.observed)
+            .bind(result.severity.as_str())
+            .execute(pool)
+            .await
+            .context("Failed to store expectation results")?;
+    }
+    Ok(())
+}
+
+/// Helper function to fetch a column as f64 values.
+fn float_column(df: &DataFrame, column: &str) -> Result<Float64Chunked> {
+    let series = df
+        .column(column)
+        .context(format!("Error fetching column {}", column))?
+        .cast(&DataType::Float64)
+        .context(format!("Error converting {} column to f64", column))?;
+    Ok(series.f64()?.clone())
+}
+
+fn within(value: f64, min: Option<f64>, max: Option<f64>) -> bool {
+    min.is_none_or(|m| value >= m) && max.is_none_or(|m| value <= m)
+}
+
+fn describe_bounds<T: fmt::Display>(min: &Option<T>, max: &Option<T>) -> String {
+    match (min, max) {
+        (Some(min), Some(max)) => format!("between {} and {}", min, max),
+        (Some(min), None) => format!("at least {}", min),
+        (None, Some(max)) => format!("at most {}", max),
+        (None, None) => "unbounded".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_parse_suite() {
+        let config: ExpectationsConfig = toml::from_str(
+            r#"
+            on_failure = "fail"
+
+            [[suite]]
+            expect = "column_mean_between"
+            column = "alcohol"
+            min = 9.0
+            max = 12.0
+
+            [[suite]]
+            expect = "row_count_between"
+            min = 1
+            "#,
+        )
+        .expect("Failed to parse expectations");
+
+        assert_eq!(config.on_failure, FailurePolicy::Fail);
+        assert_eq!(config.suite.len(), 2);
+        assert_eq!(config.suite[1].expectation, Expectation::RowCountBetween { min: Some(1), max: None });
+    }
+
+    #[test]
+    fn test_warnings_do_not_fail_the_run() {
+        let config: ExpectationsConfig = toml::from_str(
+            r#"
+            on_failure = "fail"
+
+            [[suite]]
+            expect = "column_values_between"
+            column = "sulphates"
+            max = 1.0
+            severity = "warning"
+            "#,
+        )
+        .expect("Failed to parse expectations");
+        assert_eq!(config.suite[0].severity, Severity::Warning);
+
+        let df = df!("sulphates" => &[0.56, 1.2]).unwrap();
+        let mut results = evaluate(&df, &[config.suite[0].expectation.clone()], &Dictionaries::default()).unwrap();
+        results[0].severity = Severity::Warning;
+        assert!(!results[0].passed && !results[0].is_error());
+        assert!(enforce(&results, &config).is_ok());
+        assert!(describe_failures(&results).unwrap().ends_with("(observed: 1 values out of range, warning)"));
+
+        results[0].severity = Severity::Error;
+        assert!(enforce(&results, &config).is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let df = df!(
+            "alcohol" => &[9.4, 9.8, 10.5],
+            "quality" => &[Some(5i64), Some(11), None]
+        )
+        .unwrap();
+        let suite = vec![
+            Expectation::ColumnMeanBetween { column: "alcohol".to_string(), min: Some(9.0), max: Some(12.0) },
+            Expectation::ColumnValuesBetween { column: "quality".to_string(), min: Some(0.0), max: Some(10.0) },
+            Expectation::ColumnValuesNotNull { column: "quality".to_string() },
+            Expectation::RowCountBetween { min: Some(1), max: Some(3) },
+        ];
+
+        let results = evaluate(&df, &suite, &Dictionaries::default()).expect("Evaluation failed");
+        let passed: Vec<bool> = results.iter().map(|r| r.passed).collect();
+        assert_eq!(passed, vec![true, false, false, true]);
+        assert_eq!(results[1].observed, "1 values out of range");
+
+        let failures = describe_failures(&results).unwrap();
+        assert!(failures.starts_with("2 of 4 expectations failed: "));
+        assert!(enforce(&results, &ExpectationsConfig { on_failure: FailurePolicy::Fail, ..ExpectationsConfig::default() }).is_err());
+        assert!(describe_failures(&results[..1]).is_none());
+    }
+
+    #[test]
+    fn test_evaluate_sampled_escalates_on_failure() {
+        let df = df!("alcohol" => (0..1000).map(|i| 9.0 + (i % 10) as f64 * 0.1).collect::<Vec<_>>()).unwrap();
+        let passing = vec![Expectation::ColumnValuesBetween { column: "alcohol".to_string(), min: Some(8.0), max: Some(11.0) }];
+        let failing = vec![Expectation::ColumnValuesBetween { column: "alcohol".to_string(), min: Some(12.0), max: None }];
+
+        let sample = sample_rows(&df, 0.05, "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B").unwrap();
+        assert!((20..=80).contains(&sample.height()));
+
+        let results = evaluate_sampled(&df, &passing, &Dictionaries::default(), 0.05, "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B").unwrap();
+        assert!(results[0].passed);
+        assert_eq!(results[0].observed, format!("0 values out of range in a sample of {} rows", sample.height()));
+
+        let results = evaluate_sampled(&df, &failing, &Dictionaries::default(), 0.05, "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B").unwrap();
+        assert_eq!(results[0].observed, "1000 values out of range");
+    }
+
+    #[test]
+    fn test_evaluate_missing_column() {
+        let df = df!("alcohol" => &[9.4]).unwrap();
+        let suite = vec![Expectation::ColumnValuesNotNull { column: "pH".to_string() }];
+        assert!(evaluate(&df, &suite, &Dictionaries::default()).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_values_in_table() {
+        let df = df!("region" => &[Some("BDX"), Some("RHN"), None, Some("XXX")], "grade" => &[1i64, 2, 2, 3]).unwrap();
+        let mut dictionaries = Dictionaries::default();
+        dictionaries.insert("regions", "code", Arc::new(HashSet::from(["BDX".to_string(), "RHN".to_string()])));
+        dictionaries.insert("grades", "grade", Arc::new(["1", "2", "3"].map(String::from).into()));
+        let suite = vec![
+            Expectation::ColumnValuesInTable { column: "region".to_string(), table: "regions".to_string(), key: "code".to_string() },
+            Expectation::ColumnValuesInTable { column: "grade".to_string(), table: "grades".to_string(), key: "grade".to_string() },
+        ];
+
+        let results = evaluate(&df, &suite, &dictionaries).unwrap();
+        assert_eq!(results[0].expectation, "values of region in regions.code");
+        assert_eq!((results[0].passed, results[0].observed.as_str()), (false, "1 values not in regions.code"));
+        assert!(results[1].passed);
+        assert!(evaluate(&df, &suite, &Dictionaries::default()).is_err());
+    }
+}
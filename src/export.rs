@@ -0,0 +1,291 @@
+//! This module implements `pipeline export`, which extracts stored rows to files.
+//!
+//! The rows of a table matching an optional SQL condition are read page by page, in the order they
+//! were inserted, and each page is written as a Parquet or CSV file below a local directory or, with
+//! the `s3` feature, an `s3://bucket/prefix` URL, so extracts of any size run in bounded memory. With
+//! a partition column, files are grouped in Hive-style `<column>=<value>/` directories. Parquet files
+//! are compressed with zstd unless `--compression` names another codec; CSV files are compressed as a
+//! whole, and named `.csv.gz` or `.csv.zst`, only when it does. With `--as-of <run id>`, the table is
+//! exported as it was when that run completed, to reproduce a report made at the time.
+
+use crate::codec::{Codec, CompressionConfig};
+use crate::config::DowncastConfig;
+use crate::schema::{ColumnSchema, TableSchema};
+use crate::{downcast, storage, timetravel};
+use anyhow::{Context, Result};
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use polars::prelude::*;
+use sqlx::postgres::PgPool;
+use sqlx::Row;
+use std::sync::Arc;
+
+/// Directory name of the partition holding rows whose partition column is NULL.
+pub const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// File format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Parquet,
+    Csv,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// What `pipeline export` extracts and where it writes it.
+#[derive(Debug, Clone, clap::Args)]
+pub struct ExportRequest {
+    /// The table to export; it needs an `id` column to page through it.
+    #[arg(long, default_value = "wine_quality")]
+    pub table: String,
+    /// File format of the exported files.
+    #[arg(long, value_enum, default_value_t = ExportFormat::Parquet)]
+    pub format: ExportFormat,
+    /// SQL condition on the table columns selecting the exported rows, e.g. "quality >= 7".
+    #[arg(long = "where", value_name = "CONDITION")]
+    pub filter: Option<String>,
+    /// Local directory, or `s3://bucket/prefix` URL, the files are written to.
+    #[arg(long, default_value = "exports")]
+    pub output: String,
+    /// Column whose values the files are partitioned by, in `<column>=<value>/` directories.
+    #[arg(long)]
+    pub partition_by: Option<String>,
+    /// Maximum number of rows read and written per file.
+    #[arg(long, default_value_t = 100_000)]
+    pub batch_rows: usize,
+    /// Compression codec of the files; zstd for Parquet and none for CSV by default.
+    #[arg(long, value_enum)]
+    pub compression: Option<Codec>,
+    /// Compression level, 1 to 22 for zstd and 0 to 9 for gzip.
+    #[arg(long, requires = "compression")]
+    pub compression_level: Option<u32>,
+    /// ID of a run to export the table as it was when that run completed, rather than as it is now.
+    #[arg(long, value_name = "RUN_ID")]
+    pub as_of: Option<String>,
+}
+
+impl ExportRequest {
+    /// Returns the compression of the exported files.
+    pub fn compression(&self) -> CompressionConfig {
+        let default = match self.format {
+            ExportFormat::Parquet => Codec::Zstd,
+            ExportFormat::Csv => Codec::None,
+        };
+        CompressionConfig { codec: self.compression.unwrap_or(default), level: self.compression_level }
+    }
+}
+
+/// Exports the rows of a table to files.
+///
+/// Rows of `wine_quality` are named and typed after the configured schema, so an export reads the
+/// same as the transformed DataFrame; other tables are described by `information_schema`.
+///
+/// # Arguments
+///
+/// * `request` - What to export, and where.
+/// * `schema` - The schema of the `wine_quality` table.
+/// * `downcast` - Whether columns are narrowed to the smallest dtype their table type fits.
+///
+/// # Returns
+///
+/// * `Result<usize>` - The number of rows exported, or an error if the query or a write fails.
+///
+/// # Example
+///
+/// ```
+/// let rows = run_export(&request, &config.schema, &config.downcast).await.expect("Export failed");
+/// ```
+pub async fn run_export(request: &ExportRequest, schema: &TableSchema, downcast: &DowncastConfig) -> Result<usize> {
+    let pool = storage::create_connection_pool().await?;
+    let schema = if request.table == "wine_quality" { schema.clone() } else { table_schema(&pool, &request.table).await? };
+    if let Some(column) = &request.partition_by {
+        schema
+            .columns
+            .iter()
+            .find(|c| &c.name == column)
+            .context(format!("Partition column {} is not a column of {}", column, request.table))?;
+    }
+    let compression = request.compression();
+    // An unusable codec or level fails before any file is written
+    match request.format {
+        ExportFormat::Parquet => compression.parquet().map(|_| ())?,
+        ExportFormat::Csv => compression.compress(vec![]).map(|_| ())?,
+    }
+    let (store, prefix) = open_destination(&request.output)?;
+
+    // As of a run, the rows are read from the table as it was then, superseded rows included
+    let (source, as_of) = match &request.as_of {
+        Some(run_id) => (timetravel::snapshot_sql(&request.table, timetravel::run_column(&schema)?, "$3"), Some(timetravel::load_time(&pool, run_id).await?)),
+        None => (request.table.clone(), None),
+    };
+    let columns: Vec<String> = schema.columns.iter().map(|c| format!("{}::TEXT", c.column)).collect();
+    let sql = format!(
+        "SELECT id::BIGINT, {} FROM {} WHERE ({}) AND id > $1 ORDER BY id LIMIT $2",
+        columns.join(", "),
+        source,
+        request.filter.as_deref().unwrap_or("TRUE")
+    );
+
+    let (mut last_id, mut page, mut exported, mut files) = (0i64, 0usize, 0usize, 0usize);
+    loop {
+        let mut query = sqlx::query(&sql).bind(last_id).bind(request.batch_rows.max(1) as i64);
+        if let Some(at) = as_of {
+            query = query.bind(at);
+        }
+        let rows = query
+            .fetch_all(&pool)
+            .await
+            .context(format!("Failed to read {}", request.table))?;
+        let Some(last) = rows.last() else { break };
+        last_id = last.try_get(0)?;
+
+        let mut df = storage::frame_from_rows(&rows, &schema, 1)?;
+        if downcast.enabled {
+            df = downcast::narrow_to_schema(df, &schema)?;
+        }
+        let parts = match &request.partition_by {
+            Some(column) => df.partition_by_stable([column.as_str()], true)?,
+            None => vec![df],
+        };
+        for mut part in parts {
+            let partition = match &request.partition_by {
+                Some(column) => Some((column.as_str(), partition_value(&part, column)?)),
+                None => None,
+            };
+            let location = file_location(&prefix, partition, page, request.format, &compression);
+            store
+                .put(&location, encode(&mut part, request.format, &compression)?.into())
+                .await
+                .context(format!("Failed to write {}", location))?;
+            exported += part.height();
+            files += 1;
+        }
+        page += 1;
+    }
+
+    match &request.as_of {
+        Some(run_id) => println!("Exported {} rows of {} as of run {} to {} files in {}", exported, request.table, run_id, files, request.output),
+        None => println!("Exported {} rows of {} to {} files in {}", exported, request.table, files, request.output),
+    }
+    Ok(exported)
+}
+
+/// Helper function to describe a table other than `wine_quality` from `information_schema`.
+async fn table_schema(pool: &PgPool, table: &str) -> Result<TableSchema> {
+    let columns: Vec<(String, String)> = sqlx::query_as(
+        "SELECT column_name::TEXT, data_type::TEXT FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1 AND column_name <> 'id' ORDER BY ordinal_position",
+    )
+    .bind(table)
+    .fetch_all(pool)
+    .await
+    .context(format!("Failed to look up the columns of {}", table))?;
+    if columns.is_empty() {
+        anyhow::bail!("Table {} does not exist", table);
+    }
+
+    Ok(TableSchema {
+        columns: columns
+            .into_iter()
+            .map(|(name, pg_type)| ColumnSchema {
+                column: name.clone(),
+                name,
+                pg_type,
+                nullable: true,
+                impute: None,
+            })
+            .collect(),
+    })
+}
+
+/// Opens the local directory, created if missing, or S3 prefix files are written to.
+///
+/// # Arguments
+///
+/// * `output` - A local directory, or an `s3://bucket/prefix` URL.
+///
+/// # Returns
+///
+/// * `Result<(Arc<dyn ObjectStore>, Path)>` - The store and the prefix within it, or an error if the
+///   directory cannot be created or the build lacks the `s3` feature.
+pub fn open_destination(output: &str) -> Result<(Arc<dyn ObjectStore>, Path)> {
+    if let Some(url) = output.strip_prefix("s3://") {
+        let (bucket, prefix) = url.split_once('/').unwrap_or((url, ""));
+        return Ok((s3_store(bucket)?, Path::from(prefix)));
+    }
+
+    std::fs::create_dir_all(output).context(format!("Failed to create directory {}", output))?;
+    let store = LocalFileSystem::new_with_prefix(output).context(format!("Failed to open directory {}", output))?;
+    Ok((Arc::new(store), Path::default()))
+}
+
+#[cfg(feature = "s3")]
+fn s3_store(bucket: &str) -> Result<Arc<dyn ObjectStore>> {
+    let store = object_store::aws::AmazonS3Builder::from_env()
+        .with_bucket_name(bucket)
+        .build()
+        .context(format!("Failed to open S3 bucket {}", bucket))?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_store(_bucket: &str) -> Result<Arc<dyn ObjectStore>> {
+    anyhow::bail!("Writing to S3 requires building with the `s3` feature")
+}
+
+/// Renders the partition column value shared by all rows of a partition, [`NULL_PARTITION`] for NULL.
+pub fn partition_value(part: &DataFrame, column: &str) -> Result<String> {
+    let values = part.column(column)?.cast(&DataType::String)?;
+    Ok(values.str()?.get(0).unwrap_or(NULL_PARTITION).to_string())
+}
+
+/// Helper function to name the file of a page, inside its partition directory if partitioned.
+fn file_location(prefix: &Path, partition: Option<(&str, String)>, page: usize, format: ExportFormat, compression: &CompressionConfig) -> Path {
+    let directory = match partition {
+        Some((column, value)) => prefix.child(format!("{}={}", column, value)),
+        None => prefix.clone(),
+    };
+    // Parquet files compress their pages, so only CSV files are named after their compression
+    let suffix = if format == ExportFormat::Csv { compression.suffix() } else { "" };
+    directory.child(format!("part-{:05}.{}{}", page, format.extension(), suffix))
+}
+
+/// Helper function to encode a page in the export format.
+fn encode(df: &mut DataFrame, format: ExportFormat, compression: &CompressionConfig) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    match format {
+        ExportFormat::Parquet => {
+            ParquetWriter::new(&mut buffer).with_compression(compression.parquet()?).finish(df).context("Failed to encode Parquet")?;
+            Ok(buffer)
+        }
+        ExportFormat::Csv => {
+            CsvWriter::new(&mut buffer).finish(df).context("Failed to encode CSV")?;
+            compression.compress(buffer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_locations_and_partition_values() {
+        let prefix = Path::from("exports/wine");
+        let df = polars::df!("quality" => &[Some(7i64), None]).unwrap();
+
+        let uncompressed = CompressionConfig { codec: Codec::None, level: None };
+        assert_eq!(file_location(&prefix, None, 3, ExportFormat::Csv, &uncompressed).as_ref(), "exports/wine/part-00003.csv");
+        assert_eq!(file_location(&prefix, None, 3, ExportFormat::Csv, &CompressionConfig::default()).as_ref(), "exports/wine/part-00003.csv.zst");
+        let value = partition_value(&df.slice(0, 1), "quality").unwrap();
+        assert_eq!(file_location(&prefix, Some(("quality", value)), 0, ExportFormat::Parquet, &CompressionConfig::default()).as_ref(), "exports/wine/quality=7/part-00000.parquet");
+        assert_eq!(partition_value(&df.slice(1, 1), "quality").unwrap(), NULL_PARTITION);
+    }
+}
@@ -0,0 +1,198 @@
+//! This module detects runs that would load the same input into the same table twice.
+//!
+//! A run's fingerprint combines the checksum of the input's content, a digest of the configuration
+//! that shapes the stored rows (schema, type mappings, and enabled stages), and the target table.
+//! Successful runs record their fingerprint in the `pipeline_runs` table; a later run with the same
+//! fingerprint, e.g. from a retried CI job, is then warned about or skipped. A run also claims its
+//! fingerprint while it loads, so two processes started at the same time cannot both load the input.
+
+use crate::config::PipelineConfig;
+use crate::history;
+use crate::run::RunContext;
+use crate::seed;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::{PgConnection, PgPool};
+
+/// Version of the transformation logic; bump it when a code change alters the stored rows, so
+/// earlier runs no longer count as duplicates.
+pub const TRANSFORM_VERSION: u32 = 1;
+
+/// What happens when an identical run already succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Run without checking.
+    Run,
+    /// Print a warning and run anyway.
+    Warn,
+    /// Skip the run.
+    Skip,
+}
+
+/// Computes the fingerprint of a run.
+///
+/// # Arguments
+///
+/// * `input_checksum` - The checksum of the input, as returned by [`Source::checksum`](crate::source::Source::checksum).
+/// * `config` - The pipeline configuration.
+/// * `target` - The table the run loads.
+///
+/// # Returns
+///
+/// * `String` - The hex-encoded fingerprint.
+///
+/// # Example
+///
+/// ```
+/// let fingerprint = compute(&source.checksum().await?, &config, "wine_quality");
+/// ```
+pub fn compute(input_checksum: &str, config: &PipelineConfig, target: &str) -> String {
+    combine(input_checksum, &transform_digest(config), target)
+}
+
+/// Helper function to digest the configuration that determines the stored rows.
+fn transform_digest(config: &PipelineConfig) -> String {
+    // Sorted, since the iteration order of the map is not stable
+    let mut column_types: Vec<_> = config.storage.column_types.iter().collect();
+    column_types.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut description = format!(
+        "v{}|{:?}|{:?}|{:?}|{:?}",
+        TRANSFORM_VERSION,
+        config.schema,
+        column_types,
+        config.enabled_stages(),
+        config.model.score_with
+    );
+    // A limited run stores only part of the input, so it must not count as a load of all of it
+    if let Some(limit) = config.row_limit {
+        description.push_str(&format!("|limit {}", limit));
+    }
+    if let Some(sample) = config.sampling.describe() {
+        description.push_str(&format!("|sample {} seed {}", sample, config.sampling.seed));
+    }
+    hex(&Sha256::digest(description.as_bytes()))
+}
+
+fn combine(input_checksum: &str, transform_digest: &str, target: &str) -> String {
+    hex(&Sha256::digest(format!("{}|{}|{}", input_checksum, transform_digest, target).as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decides whether the run goes ahead, looking up an earlier successful run with the same fingerprint.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The current run, whose log the decision is written to.
+/// * `fingerprint` - The fingerprint of the current run.
+/// * `policy` - What to do when a duplicate is found.
+///
+/// # Returns
+///
+/// * `Result<bool>` - `false` if the run is to be skipped, or an error if the lookup fails.
+pub async fn admit(pool: &PgPool, run: &RunContext, fingerprint: &str, policy: DuplicatePolicy) -> Result<bool> {
+    // Admitted before the run sets up the database, so on a fresh one no run loaded anything yet
+    if policy == DuplicatePolicy::Run || !seed::table_exists(pool, "pipeline_runs").await? {
+        return Ok(true);
+    }
+
+    let previous: Option<(String, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT run_id, finished_at FROM pipeline_runs WHERE fingerprint = $1 AND status = $2 ORDER BY finished_at DESC LIMIT 1",
+    )
+    .bind(fingerprint)
+    .bind(history::SUCCEEDED)
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up earlier runs")?;
+
+    let Some((run_id, finished_at)) = previous else { return Ok(true) };
+    match policy {
+        DuplicatePolicy::Skip => {
+            run.log(format_args!("Skipping run: run {} already loaded this input with this configuration at {}", run_id, finished_at));
+            Ok(false)
+        }
+        _ => {
+            run.warn(format_args!("Run {} already loaded this input with this configuration at {}", run_id, finished_at));
+            Ok(true)
+        }
+    }
+}
+
+/// A run's claim on its fingerprint, held until it is dropped.
+///
+/// The claim is a PostgreSQL advisory lock held by a connection of its own, which is closed when the
+/// claim is dropped, so the database releases the lock even when the process holding it dies.
+pub struct Claim {
+    _connection: PgConnection,
+}
+
+/// Claims a fingerprint for the current run, before it is admitted.
+///
+/// A second process loading the same input with the same configuration fails to claim it until the
+/// first run ends; retried, it is then admitted or not as the first run's outcome says.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `fingerprint` - The fingerprint of the current run.
+/// * `policy` - What to do when a duplicate is found; with [`DuplicatePolicy::Run`] nothing is claimed.
+///
+/// # Returns
+///
+/// * `Result<Option<Claim>>` - The claim, or an error if another process holds it or the lock cannot be taken.
+///
+/// # Example
+///
+/// ```
+/// let claim = claim(&pool, &fingerprint, DuplicatePolicy::Skip).await?;
+/// ```
+pub async fn claim(pool: &PgPool, fingerprint: &str, policy: DuplicatePolicy) -> Result<Option<Claim>> {
+    if policy == DuplicatePolicy::Run {
+        return Ok(None);
+    }
+
+    // Detached from the pool, so the lock is not left behind on a connection another query reuses
+    let mut connection = pool.acquire().await.context("Failed to connect to claim the input")?.detach();
+    let claimed: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtextextended($1, 0))")
+        .bind(fingerprint)
+        .fetch_one(&mut connection)
+        .await
+        .context("Failed to claim the input")?;
+    if !claimed {
+        bail!("Another pipeline process is loading this input with this configuration (fingerprint {})", fingerprint);
+    }
+    Ok(Some(Claim { _connection: connection }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_changes_with_each_part() {
+        let config = PipelineConfig::default();
+        let base = combine("abc", &transform_digest(&config), "wine_quality");
+
+        assert_eq!(base, combine("abc", &transform_digest(&PipelineConfig::default()), "wine_quality"));
+        assert_ne!(base, combine("abd", &transform_digest(&config), "wine_quality"));
+        assert_ne!(base, combine("abc", &transform_digest(&config), "wine_quality_staging"));
+
+        let mut changed = PipelineConfig::default();
+        changed.schema.columns[0].pg_type = "DECIMAL(5, 2)".to_string();
+        assert_ne!(base, combine("abc", &transform_digest(&changed), "wine_quality"));
+
+        let limited = PipelineConfig { row_limit: Some(1000), ..PipelineConfig::default() };
+        assert_ne!(base, combine("abc", &transform_digest(&limited), "wine_quality"));
+
+        let mut sampled = PipelineConfig::default();
+        sampled.sampling.fraction = Some(0.01);
+        assert_ne!(base, combine("abc", &transform_digest(&sampled), "wine_quality"));
+    }
+}
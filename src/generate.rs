@@ -0,0 +1,254 @@
+//! This module implements `pipeline generate`, which writes synthetic input files.
+//!
+//! The generated CSV has one column per input column of the configured schema, named by its source
+//! header, so it feeds straight into the pipeline. Columns of the default wine schema follow the
+//! distributions of the red wine dataset; other numeric columns are spread over the range their type
+//! can store. Configurable shares of missing values and outliers exercise imputation, expectations,
+//! and the reject path, which makes the files useful for load tests, demos, and fuzzing.
+
+use crate::schema::{ColumnSchema, TableSchema};
+use crate::mapping;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use std::fs::File;
+use std::path::Path;
+
+/// Mean, standard deviation, minimum and maximum of the columns of the red wine dataset.
+const WINE_DISTRIBUTIONS: [(&str, f64, f64, f64, f64); 12] = [
+    ("fixed acidity", 8.32, 1.74, 4.6, 15.9),
+    ("volatile acidity", 0.53, 0.18, 0.12, 1.58),
+    ("citric acid", 0.27, 0.19, 0.0, 1.0),
+    ("residual sugar", 2.54, 1.41, 0.9, 15.5),
+    ("chlorides", 0.087, 0.047, 0.012, 0.611),
+    ("free sulfur dioxide", 15.9, 10.5, 1.0, 72.0),
+    ("total sulfur dioxide", 46.5, 32.9, 6.0, 289.0),
+    ("density", 0.9967, 0.0019, 0.990, 1.004),
+    ("pH", 3.31, 0.15, 2.74, 4.01),
+    ("sulphates", 0.66, 0.17, 0.33, 2.0),
+    ("alcohol", 10.42, 1.07, 8.4, 14.9),
+    ("quality", 5.64, 0.81, 3.0, 8.0),
+];
+
+/// Distance of an injected outlier from the mean, in standard deviations.
+const OUTLIER_DEVIATIONS: f64 = 6.0;
+
+/// What `pipeline generate` writes.
+#[derive(Debug, Clone, clap::Args)]
+pub struct GenerateRequest {
+    /// Number of rows to generate.
+    #[arg(long, default_value_t = 10_000)]
+    pub rows: usize,
+    /// Path of the CSV file to write.
+    #[arg(long, default_value = "data/generated.csv")]
+    pub output: String,
+    /// Share of values left empty, e.g. 0.01.
+    #[arg(long, default_value_t = 0.0)]
+    pub null_rate: f64,
+    /// Share of numeric values replaced by an outlier far outside the usual range, e.g. 0.001.
+    #[arg(long, default_value_t = 0.0)]
+    pub outlier_rate: f64,
+    /// Seed of the generator; the same seed and options produce the same file.
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+}
+
+/// Writes a synthetic input file for the configured schema.
+///
+/// # Arguments
+///
+/// * `request` - The number of rows, null and outlier rates, seed, and output path.
+/// * `schema` - The declared schema; columns added by the pipeline itself are left out.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The generated rows, or an error if a rate is out of range or the file cannot be written.
+///
+/// # Example
+///
+/// ```
+/// let df = run_generate(&request, &config.schema).expect("Failed to generate data");
+/// ```
+pub fn run_generate(request: &GenerateRequest, schema: &TableSchema) -> Result<DataFrame> {
+    for (name, rate) in [("--null-rate", request.null_rate), ("--outlier-rate", request.outlier_rate)] {
+        if !(0.0..=1.0).contains(&rate) {
+            bail!("{} must be between 0 and 1, got {}", name, rate);
+        }
+    }
+
+    let mut df = generate_frame(request, schema)?;
+    if let Some(dir) = Path::new(&request.output).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).context(format!("Failed to create directory {}", dir.display()))?;
+    }
+    let file = File::create(&request.output).context(format!("Failed to create {}", request.output))?;
+    CsvWriter::new(file).finish(&mut df).context(format!("Failed to write {}", request.output))?;
+
+    println!("Generated {} rows of {} columns in {}", df.height(), df.width(), request.output);
+    Ok(df)
+}
+
+/// Generates the synthetic rows.
+fn generate_frame(request: &GenerateRequest, schema: &TableSchema) -> Result<DataFrame> {
+    let mut rng = Rng::new(request.seed);
+    let columns = schema
+        .input_columns()
+        .map(|spec| generate_column(spec, request, &mut rng))
+        .collect::<Result<Vec<Series>>>()?;
+    DataFrame::new(columns).context("Failed to assemble the generated columns")
+}
+
+/// Helper function to generate the values of one column.
+fn generate_column(spec: &ColumnSchema, request: &GenerateRequest, rng: &mut Rng) -> Result<Series> {
+    let pg_type = spec.pg_type.to_ascii_uppercase();
+    let name = spec.name.as_str();
+
+    if pg_type.starts_with("TEXT") || pg_type.starts_with("VARCHAR") || pg_type.starts_with("CHAR") {
+        let prefix = mapping::snake_case(name);
+        return Ok(Series::new(name, sample(request, rng, |rng| format!("{}_{}", prefix, rng.below(100)))));
+    }
+    if pg_type.starts_with("BOOL") {
+        return Ok(Series::new(name, sample(request, rng, |rng| rng.chance(0.5))));
+    }
+    if pg_type.starts_with("DATE") {
+        let start = chrono::NaiveDate::from_ymd_opt(2020, 1, 1).expect("Valid date");
+        return Ok(Series::new(name, sample(request, rng, |rng| (start + chrono::Duration::days(rng.below(1826) as i64)).to_string())));
+    }
+
+    let integer = pg_type.starts_with("INT") || pg_type.starts_with("SMALLINT") || pg_type.starts_with("BIGINT");
+    let numeric = pg_type.starts_with("DECIMAL") || pg_type.starts_with("NUMERIC");
+    if !integer && !numeric && !pg_type.starts_with("REAL") && !pg_type.starts_with("DOUBLE") && !pg_type.starts_with("FLOAT") {
+        bail!("Cannot generate values of column {} with type {}", name, spec.pg_type);
+    }
+
+    // Outliers stay storable, so they reach expectations rather than fail the insert
+    let limit = if numeric { decimal_limit(spec) } else { f64::MAX };
+    let (mean, std, min, max) = distribution(name).unwrap_or_else(|| {
+        let max = limit.min(100.0);
+        (max / 2.0, max / 6.0, 0.0, max)
+    });
+    let scale = spec.scale().map_or(6, |s| s as i32);
+    let values = sample(request, rng, |rng| {
+        let value = if rng.chance(request.outlier_rate) {
+            let sign = if rng.chance(0.5) { 1.0 } else { -1.0 };
+            (mean + sign * OUTLIER_DEVIATIONS * std).clamp(-limit, limit)
+        } else {
+            rng.normal(mean, std).clamp(min, max)
+        };
+        if integer { value.round() } else { round_to(value, scale) }
+    });
+    let series = Series::new(name, values);
+    if integer {
+        return series.cast(&DataType::Int64).context(format!("Failed to convert {} to integers", name));
+    }
+    Ok(series)
+}
+
+/// Helper function to draw the values of a column, leaving each empty with the configured null rate.
+fn sample<T>(request: &GenerateRequest, rng: &mut Rng, mut value: impl FnMut(&mut Rng) -> T) -> Vec<Option<T>> {
+    (0..request.rows)
+        .map(|_| {
+            let present = rng.chance(1.0 - request.null_rate);
+            let value = value(rng);
+            present.then_some(value)
+        })
+        .collect()
+}
+
+/// Helper function to look up the distribution of a column of the wine dataset.
+fn distribution(name: &str) -> Option<(f64, f64, f64, f64)> {
+    WINE_DISTRIBUTIONS
+        .iter()
+        .find(|(column, ..)| *column == name)
+        .map(|&(_, mean, std, min, max)| (mean, std, min, max))
+}
+
+/// Helper function to compute the largest magnitude a `DECIMAL(p, s)` column stores.
+fn decimal_limit(spec: &ColumnSchema) -> f64 {
+    let precision = spec
+        .pg_type
+        .split_once('(')
+        .and_then(|(_, arguments)| arguments.trim_end_matches(')').split(',').next()?.trim().parse::<i32>().ok());
+    match (precision, spec.scale()) {
+        (Some(precision), Some(scale)) => 10f64.powi(precision - scale as i32) - 10f64.powi(-(scale as i32)),
+        _ => f64::MAX,
+    }
+}
+
+/// Helper function to round a value to a number of decimal places.
+fn round_to(value: f64, scale: i32) -> f64 {
+    let factor = 10f64.powi(scale);
+    (value * factor).round() / factor
+}
+
+/// A small seeded generator (xorshift64*), so generated files are reproducible without extra dependencies.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed.max(1) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A uniform value in `[0, 1)`.
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.uniform() < probability
+    }
+
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// A normally distributed value, by the Box-Muller transform.
+    pub fn normal(&mut self, mean: f64, std: f64) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        mean + std * (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(null_rate: f64, outlier_rate: f64) -> GenerateRequest {
+        GenerateRequest { rows: 500, output: String::new(), null_rate, outlier_rate, seed: 7 }
+    }
+
+    #[test]
+    fn test_generate_frame_follows_schema() {
+        let schema = TableSchema::default();
+        let df = generate_frame(&request(0.0, 0.0), &schema).unwrap();
+
+        assert_eq!(df.height(), 500);
+        assert_eq!(df.get_column_names()[0], "fixed acidity");
+        assert!(df.column("run_id").is_err() && df.column("cluster").is_err());
+        let quality = df.column("quality").unwrap().i64().unwrap();
+        assert!(quality.min().unwrap() >= 3 && quality.max().unwrap() <= 8);
+        let density = df.column("density").unwrap().f64().unwrap();
+        assert!(density.into_iter().flatten().all(|d| (0.990..=1.004).contains(&d)));
+
+        // Same seed, same rows
+        assert!(df.equals(&generate_frame(&request(0.0, 0.0), &schema).unwrap()));
+    }
+
+    #[test]
+    fn test_generate_frame_injects_nulls_and_outliers() {
+        let df = generate_frame(&request(1.0, 0.0), &TableSchema::default()).unwrap();
+        assert_eq!(df.column("alcohol").unwrap().null_count(), 500);
+
+        let df = generate_frame(&request(0.0, 1.0), &TableSchema::default()).unwrap();
+        let alcohol = df.column("alcohol").unwrap().f64().unwrap();
+        assert!(alcohol.into_iter().flatten().all(|a| !(8.4..=14.9).contains(&a)));
+    }
+}
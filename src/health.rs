@@ -0,0 +1,154 @@
+//! This module serves the liveness and readiness endpoints of daemon mode.
+//!
+//! `/healthz` answers as long as the process is running. `/readyz` checks that the database is
+//! reachable, that the configuration file still parses, that enough disk space is left for
+//! artifacts, and that no daemon component is waiting to restart, so an orchestrator such as
+//! Kubernetes only routes work to a pod that can complete a run. `/metrics` exposes the state of the
+//! components to Prometheus. The endpoints are served without authentication on their own address.
+
+use crate::config::{self, HealthConfig};
+use crate::supervisor::Supervisor;
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Outcome of one readiness check.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Body of the readiness response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Readiness {
+    fn from_checks(checks: Vec<Check>) -> Self {
+        Self {
+            ready: checks.iter().all(|c| c.ok),
+            checks,
+        }
+    }
+}
+
+struct HealthState {
+    pool: Option<PgPool>,
+    config_path: String,
+    min_free_disk_mb: u64,
+    supervisor: Arc<Supervisor>,
+}
+
+/// Serves `/healthz`, `/readyz` and `/metrics` until the daemon stops.
+///
+/// # Arguments
+///
+/// * `config` - The health endpoint settings.
+/// * `config_path` - The configuration file the daemon was started with.
+/// * `supervisor` - The supervisor of the daemon's components.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the listen address cannot be bound.
+pub async fn serve(config: &HealthConfig, config_path: &str, supervisor: Arc<Supervisor>) -> Result<()> {
+    // A lazy pool, so readiness reports a missing database instead of the server failing to start
+    let pool = std::env::var("DATABASE_URL").ok().and_then(|url| {
+        PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_secs(2))
+            .connect_lazy(&url)
+            .ok()
+    });
+    let state = Arc::new(HealthState {
+        pool,
+        config_path: config_path.to_string(),
+        min_free_disk_mb: config.min_free_disk_mb,
+        supervisor,
+    });
+
+    let app = Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(|State(state): State<Arc<HealthState>>| async move { state.supervisor.metrics() }))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(&config.listen)
+        .await
+        .context(format!("Failed to bind the health endpoints to {}", config.listen))?;
+    println!("Health endpoints listening on {}", config.listen);
+    axum::serve(listener, app).await.context("Health endpoints failed")
+}
+
+async fn readyz(State(state): State<Arc<HealthState>>) -> (StatusCode, Json<Readiness>) {
+    let database = match &state.pool {
+        Some(pool) => match sqlx::query("SELECT 1").execute(pool).await {
+            Ok(_) => check("database", true, "reachable".to_string()),
+            Err(e) => check("database", false, e.to_string()),
+        },
+        None => check("database", false, "DATABASE_URL is unset or invalid".to_string()),
+    };
+    let configuration = match config::load_config(&state.config_path) {
+        Ok(_) => check("config", true, format!("{} is valid", state.config_path)),
+        Err(e) => check("config", false, format!("{:#}", e)),
+    };
+    let disk = disk_check(".", state.min_free_disk_mb);
+    let components = components_check(&state.supervisor);
+
+    let readiness = Readiness::from_checks(vec![database, configuration, disk, components]);
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(readiness))
+}
+
+/// Checks that the file system holding `path` has at least `min_free_mb` megabytes available.
+pub fn disk_check(path: &str, min_free_mb: u64) -> Check {
+    match fs2::available_space(path) {
+        Ok(bytes) => {
+            let free_mb = bytes / (1024 * 1024);
+            check("disk", free_mb >= min_free_mb, format!("{} MB free, {} MB required", free_mb, min_free_mb))
+        }
+        Err(e) => check("disk", false, format!("Failed to read free space: {}", e)),
+    }
+}
+
+/// Checks that every daemon component is running, rather than waiting to restart after a failure.
+pub fn components_check(supervisor: &Supervisor) -> Check {
+    let failing: Vec<String> = supervisor
+        .components()
+        .into_iter()
+        .filter(|(_, status)| !status.running)
+        .map(|(name, status)| format!("{} ({} restarts, last {})", name, status.restarts, status.last_error.as_deref().unwrap_or("stopped")))
+        .collect();
+    if failing.is_empty() {
+        check("components", true, "all running".to_string())
+    } else {
+        check("components", false, format!("not running: {}", failing.join(", ")))
+    }
+}
+
+fn check(name: &'static str, ok: bool, detail: String) -> Check {
+    Check { name, ok, detail }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_requires_every_check() {
+        let ready = Readiness::from_checks(vec![disk_check(".", 0), check("config", true, String::new())]);
+        assert!(ready.ready);
+
+        let not_ready = Readiness::from_checks(vec![disk_check(".", u64::MAX), check("config", true, String::new())]);
+        assert!(!not_ready.ready);
+        assert_eq!(not_ready.checks[0].name, "disk");
+    }
+}
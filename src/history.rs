@@ -0,0 +1,216 @@
+//! This module records every attempt of a run in the `pipeline_runs` table.
+//!
+//! Successful attempts carry the run's fingerprint, which duplicate detection looks up, and the number
+//! of stored rows; failed attempts carry the error, so retried runs leave a trail of what went wrong
+//! before they succeeded. Every attempt also stores the warnings of the run so far as a JSON array, and
+//! with `[resources] monitor` its peak memory and CPU time. At the end of a run its duration and throughput are compared with the average
+//! of the previous successful runs, so a run that got markedly slower is flagged right away.
+
+use crate::config::PerformanceConfig;
+use crate::locale::ReportFormat;
+use crate::resources::PeakUsage;
+use crate::run::RunContext;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::postgres::PgPool;
+
+/// Status of an attempt that completed the run.
+pub const SUCCEEDED: &str = "succeeded";
+
+/// Status of an attempt that failed.
+pub const FAILED: &str = "failed";
+
+/// Records one attempt of a run.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the run.
+/// * `fingerprint` - The fingerprint of the run, if it was computed before the attempt ended.
+/// * `attempt` - The attempt number, starting at 1.
+/// * `rows` - The number of rows the attempt stored, if it succeeded.
+/// * `error` - The error the attempt failed with, or `None` if it succeeded.
+/// * `usage` - The peak memory and CPU time of the attempt, if resources were monitored.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of the insert.
+///
+/// # Example
+///
+/// ```
+/// record_attempt(&pool, &run, Some(&fingerprint), 1, Some(1599), None, None).await.expect("Failed to record the run");
+/// ```
+pub async fn record_attempt(
+    pool: &PgPool,
+    run: &RunContext,
+    fingerprint: Option<&str>,
+    attempt: u32,
+    rows: Option<usize>,
+    error: Option<&anyhow::Error>,
+    usage: Option<&PeakUsage>,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO pipeline_runs (run_id, fingerprint, attempt, status, error, row_count, warnings, peak_rss_bytes, cpu_secs, started_at, finished_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7::JSONB, $8, $9, $10, now())",
+    )
+    .bind(&run.id)
+    .bind(fingerprint)
+    .bind(attempt as i32)
+    .bind(if error.is_some() { FAILED } else { SUCCEEDED })
+    .bind(error.map(|e| format!("{:#}", e)))
+    .bind(rows.map(|r| r as i64))
+    .bind(run.warnings.to_json())
+    .bind(usage.map(|u| u.peak_rss_bytes as i64))
+    .bind(usage.map(|u| u.cpu_secs))
+    .bind(run.started_at)
+    .execute(pool)
+    .await
+    .context("Failed to record the run")?;
+    Ok(())
+}
+
+/// Duration and throughput of a successful run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunMetrics {
+    pub duration_secs: f64,
+    pub rows: f64,
+}
+
+impl RunMetrics {
+    /// Stored rows per second.
+    pub fn throughput(&self) -> f64 {
+        if self.duration_secs > 0.0 { self.rows / self.duration_secs } else { 0.0 }
+    }
+}
+
+/// A run compared with the average of the runs before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerformanceSummary {
+    pub current: RunMetrics,
+    /// The average duration and row count of the previous runs, if there were any.
+    pub baseline: Option<RunMetrics>,
+    pub compared_runs: usize,
+    /// Descriptions of the metrics that regressed beyond the threshold.
+    pub regressions: Vec<String>,
+}
+
+impl PerformanceSummary {
+    /// Describes the run and how it compares with its baseline, with numbers written as `format` says.
+    pub fn describe(&self, format: &ReportFormat) -> String {
+        let current = format!(
+            "Run took {} s for {} rows ({} rows/s)",
+            format.decimal(self.current.duration_secs, 1),
+            format.decimal(self.current.rows, 0),
+            format.decimal(self.current.throughput(), 0)
+        );
+        match &self.baseline {
+            Some(baseline) => format!(
+                "{}; the previous {} runs averaged {} s and {} rows/s",
+                current,
+                self.compared_runs,
+                format.decimal(baseline.duration_secs, 1),
+                format.decimal(baseline.throughput(), 0)
+            ),
+            None => format!("{}; no previous runs to compare with", current),
+        }
+    }
+
+    /// Describes the regressions, if any metric regressed.
+    pub fn describe_regressions(&self) -> Option<String> {
+        (!self.regressions.is_empty()).then(|| format!("Performance regression: {}", self.regressions.join("; ")))
+    }
+}
+
+/// Compares the duration and throughput of a run with the average of the previous successful runs.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the run, which finishes now.
+/// * `rows` - The number of rows the run stored.
+/// * `config` - The number of previous runs and the regression threshold.
+///
+/// # Returns
+///
+/// * `Result<PerformanceSummary>` - The comparison, or an error if the run history cannot be read.
+///
+/// # Example
+///
+/// ```
+/// let summary = compare_with_history(&pool, &run, 1599, &config.performance).await?;
+/// println!("{}", summary.describe(&ReportFormat::from_config(&config.reports)));
+/// ```
+pub async fn compare_with_history(pool: &PgPool, run: &RunContext, rows: usize, config: &PerformanceConfig) -> Result<PerformanceSummary> {
+    let previous: Vec<(f64, i64)> = sqlx::query_as(
+        "SELECT EXTRACT(EPOCH FROM finished_at - started_at)::DOUBLE PRECISION, row_count FROM pipeline_runs \
+         WHERE status = $1 AND run_id <> $2 AND row_count IS NOT NULL ORDER BY finished_at DESC LIMIT $3",
+    )
+    .bind(SUCCEEDED)
+    .bind(&run.id)
+    .bind(config.baseline_runs as i64)
+    .fetch_all(pool)
+    .await
+    .context("Failed to read the run history")?;
+
+    let current = RunMetrics {
+        duration_secs: (Utc::now() - run.started_at).num_milliseconds() as f64 / 1000.0,
+        rows: rows as f64,
+    };
+    let previous: Vec<RunMetrics> = previous.into_iter().map(|(duration_secs, rows)| RunMetrics { duration_secs, rows: rows as f64 }).collect();
+    Ok(compare(current, &previous, config.regression_threshold))
+}
+
+/// Helper function to compare a run with previous runs, flagging metrics worse than the average by more than `threshold`.
+fn compare(current: RunMetrics, previous: &[RunMetrics], threshold: f64) -> PerformanceSummary {
+    let mut summary = PerformanceSummary { current, baseline: None, compared_runs: previous.len(), regressions: vec![] };
+    if previous.is_empty() {
+        return summary;
+    }
+
+    let count = previous.len() as f64;
+    let baseline = RunMetrics {
+        duration_secs: previous.iter().map(|m| m.duration_secs).sum::<f64>() / count,
+        rows: previous.iter().map(|m| m.rows).sum::<f64>() / count,
+    };
+    // Average of the throughputs, so one large run does not dominate
+    let throughput = previous.iter().map(RunMetrics::throughput).sum::<f64>() / count;
+    if current.duration_secs > baseline.duration_secs * (1.0 + threshold) {
+        summary.regressions.push(format!(
+            "took {:.1} s, {:.0}% longer than the average of {:.1} s",
+            current.duration_secs,
+            (current.duration_secs / baseline.duration_secs - 1.0) * 100.0,
+            baseline.duration_secs
+        ));
+    }
+    if current.throughput() < throughput * (1.0 - threshold).max(0.0) {
+        summary.regressions.push(format!(
+            "stored {:.0} rows/s, {:.0}% fewer than the average of {:.0} rows/s",
+            current.throughput(),
+            (1.0 - current.throughput() / throughput) * 100.0,
+            throughput
+        ));
+    }
+    summary.baseline = Some(baseline);
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_flags_regressions() {
+        let previous = [RunMetrics { duration_secs: 10.0, rows: 1000.0 }, RunMetrics { duration_secs: 12.0, rows: 1200.0 }];
+
+        let steady = compare(RunMetrics { duration_secs: 11.0, rows: 1100.0 }, &previous, 0.5);
+        assert!(steady.describe_regressions().is_none());
+        assert!(steady.describe(&ReportFormat::default()).ends_with("the previous 2 runs averaged 11.0 s and 100 rows/s"));
+
+        let slow = compare(RunMetrics { duration_secs: 30.0, rows: 1100.0 }, &previous, 0.5);
+        assert_eq!(slow.regressions.len(), 2);
+        assert!(slow.regressions[0].starts_with("took 30.0 s, 173% longer"));
+
+        assert!(compare(RunMetrics { duration_secs: 30.0, rows: 1.0 }, &[], 0.5).baseline.is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! This module provides hook points around the pipeline stages.
+//!
+//! Hooks are either async callbacks registered in code or shell commands declared in the `[[hooks]]`
+//! section of the configuration. They make custom notifications and side effects possible without
+//! changing the pipeline itself.
+
+use crate::config::PipelineConfig;
+use crate::webhooks;
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+
+/// The points in a run at which hooks fire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookPoint {
+    OnRunStart,
+    AfterIngest,
+    AfterTransform,
+    BeforeStore,
+    /// A DataFrame, or in chunked mode a chunk, was stored; the event counts its stored and rejected rows.
+    AfterStore,
+    /// The run stored its rows and finished.
+    OnSuccess,
+    OnFailure,
+    /// Expectations failed, whether or not the failure policy aborts the run.
+    OnQualityViolation,
+    /// The run finished markedly slower than the average of the runs before it.
+    OnPerformanceRegression,
+    /// The run succeeded after runs of the same input failed; only fired with `[alerts] deduplicate`.
+    OnRecovery,
+}
+
+impl HookPoint {
+    /// Every hook point, in the order they fire in a run.
+    pub const ALL: [HookPoint; 10] = [
+        HookPoint::OnRunStart,
+        HookPoint::AfterIngest,
+        HookPoint::AfterTransform,
+        HookPoint::BeforeStore,
+        HookPoint::AfterStore,
+        HookPoint::OnSuccess,
+        HookPoint::OnFailure,
+        HookPoint::OnQualityViolation,
+        HookPoint::OnPerformanceRegression,
+        HookPoint::OnRecovery,
+    ];
+
+    /// Returns the configuration name of the hook point.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookPoint::OnRunStart => "on_run_start",
+            HookPoint::AfterIngest => "after_ingest",
+            HookPoint::AfterTransform => "after_transform",
+            HookPoint::BeforeStore => "before_store",
+            HookPoint::AfterStore => "after_store",
+            HookPoint::OnSuccess => "on_success",
+            HookPoint::OnFailure => "on_failure",
+            HookPoint::OnQualityViolation => "on_quality_violation",
+            HookPoint::OnPerformanceRegression => "on_performance_regression",
+            HookPoint::OnRecovery => "on_recovery",
+        }
+    }
+}
+
+/// A shell command run at a hook point.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookCommand {
+    pub point: HookPoint,
+    /// Command line passed to `sh -c`.
+    pub command: String,
+    /// Whether a failing command fails the run. Otherwise the failure is only reported.
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Information passed to hooks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookEvent {
+    pub point: HookPoint,
+    pub run_id: String,
+    /// Number of rows in the current DataFrame, where one exists.
+    pub rows: Option<usize>,
+    /// Number of rows sent to the rejects table, for `after_store`.
+    pub rejected: Option<usize>,
+    /// The error message, for `on_failure`, the failed expectations, for `on_quality_violation`, or the
+    /// regressed metrics, for `on_performance_regression`, or the failed runs recovered from, for `on_recovery`.
+    pub error: Option<String>,
+}
+
+impl HookEvent {
+    /// Creates an event for a hook point.
+    pub fn new(point: HookPoint, run_id: &str) -> Self {
+        Self {
+            point,
+            run_id: run_id.to_string(),
+            rows: None,
+            rejected: None,
+            error: None,
+        }
+    }
+
+    /// Attaches the current row count.
+    pub fn with_rows(mut self, rows: usize) -> Self {
+        self.rows = Some(rows);
+        self
+    }
+
+    /// Attaches the number of rejected rows.
+    pub fn with_rejected(mut self, rejected: usize) -> Self {
+        self.rejected = Some(rejected);
+        self
+    }
+
+    /// Attaches an error message.
+    pub fn with_error(mut self, error: &anyhow::Error) -> Self {
+        self.error = Some(format!("{:#}", error));
+        self
+    }
+
+    /// Attaches a description of what went wrong, where there is no error.
+    pub fn with_message(mut self, message: String) -> Self {
+        self.error = Some(message);
+        self
+    }
+}
+
+type HookCallback = Box<dyn Fn(HookEvent) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// The registered hooks of a pipeline.
+#[derive(Default)]
+pub struct Hooks {
+    callbacks: HashMap<HookPoint, Vec<HookCallback>>,
+    commands: Vec<HookCommand>,
+}
+
+impl Hooks {
+    /// Creates hooks running the given shell commands.
+    pub fn from_commands(commands: &[HookCommand]) -> Self {
+        Self {
+            callbacks: HashMap::new(),
+            commands: commands.to_vec(),
+        }
+    }
+
+    /// Creates the hooks declared in the configuration: shell commands and webhooks.
+    pub fn from_config(config: &PipelineConfig) -> Self {
+        let mut hooks = Self::from_commands(&config.hooks);
+        webhooks::register(&mut hooks, &config.webhooks, &config.labels);
+        hooks
+    }
+
+    /// Registers an async callback for a hook point. An error returned by the callback fails the run.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// hooks.register(HookPoint::AfterIngest, |event| async move {
+    ///     println!("Ingested {:?} rows", event.rows);
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn register<F, Fut>(&mut self, point: HookPoint, callback: F)
+    where
+        F: Fn(HookEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.callbacks
+            .entry(point)
+            .or_default()
+            .push(Box::new(move |event| Box::pin(callback(event))));
+    }
+
+    /// Runs every callback and command registered for the event's hook point, in registration order.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event describing the hook point.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<()>` - An error if a callback or a required command failed.
+    pub async fn fire(&self, event: HookEvent) -> Result<()> {
+        for callback in self.callbacks.get(&event.point).into_iter().flatten() {
+            callback(event.clone())
+                .await
+                .context(format!("Hook callback for {} failed", event.point.as_str()))?;
+        }
+
+        for hook in self.commands.iter().filter(|h| h.point == event.point) {
+            if let Err(e) = run_command(&hook.command, &event).await {
+                if hook.required {
+                    return Err(e);
+                }
+                eprintln!("Hook command for {} failed: {:#}", event.point.as_str(), e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a hook command, exposing the event through `PIPELINE_*` environment variables.
+async fn run_command(command: &str, event: &HookEvent) -> Result<()> {
+    let mut process = tokio::process::Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .env("PIPELINE_HOOK", event.point.as_str())
+        .env("PIPELINE_RUN_ID", &event.run_id);
+    if let Some(rows) = event.rows {
+        process.env("PIPELINE_ROWS", rows.to_string());
+    }
+    if let Some(rejected) = event.rejected {
+        process.env("PIPELINE_REJECTED", rejected.to_string());
+    }
+    if let Some(error) = &event.error {
+        process.env("PIPELINE_ERROR", error);
+    }
+
+    let status = process
+        .status()
+        .await
+        .context(format!("Failed to start hook command `{}`", command))?;
+    if !status.success() {
+        bail!("Hook command `{}` exited with {}", command, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_fire_runs_callbacks_for_point() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut hooks = Hooks::default();
+        let counter = calls.clone();
+        hooks.register(HookPoint::AfterIngest, move |event| {
+            let counter = counter.clone();
+            async move {
+                assert_eq!(event.rows, Some(3));
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+
+        hooks.fire(HookEvent::new(HookPoint::AfterIngest, "test-run").with_rows(3)).await.unwrap();
+        hooks.fire(HookEvent::new(HookPoint::BeforeStore, "test-run")).await.unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_required_command_failure_fails() {
+        let hooks = Hooks::from_commands(&[
+            HookCommand { point: HookPoint::OnRunStart, command: "exit 1".to_string(), required: false },
+            HookCommand { point: HookPoint::BeforeStore, command: "exit 1".to_string(), required: true },
+        ]);
+
+        assert!(hooks.fire(HookEvent::new(HookPoint::OnRunStart, "test-run")).await.is_ok());
+        assert!(hooks.fire(HookEvent::new(HookPoint::BeforeStore, "test-run")).await.is_err());
+    }
+}
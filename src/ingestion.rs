@@ -1,44 +1,1531 @@
-//! This module handles the ingestion of CSV data files into DataFrames.
+//! This module handles the ingestion of CSV, JSON, newline-delimited JSON, Excel, and Avro data files into DataFrames.
 //!
-//! It provides functions for reading CSV files and retrying the ingestion process.
+//! It provides functions for reading each format, whole or (for CSV and NDJSON) in chunks, and for
+//! retrying the ingestion process. The result of a SQL query can be ingested too, so data already in
+//! the warehouse goes through the same transformations as files, and so can a CSV object in S3, read
+//! through the object store source. A path may be a glob pattern such as `data/*.csv`, in which case
+//! every matching file is read and the files are concatenated. Gzip and zstd-compressed CSV files,
+//! such as archived `.csv.gz` and `.csv.zst` datasets, are decompressed while they are read. A CSV file whose first row is data rather
+//! than a header is recognized, and its columns are named after the configured ones. The path `-` reads
+//! CSV from standard input, so the binary composes with shell pipelines. CSV columns may be given
+//! explicit types, so a column is read the same way whatever values a file happens to start with.
+//! Latin-1 and UTF-16 CSV files, such as the exports of legacy lab software, are transcoded to UTF-8
+//! while they are read; their encoding is detected from a byte order mark or invalid UTF-8, or configured.
+//! Malformed CSV rows, with the wrong number of fields or a value that is not of its column's configured
+//! type, fail the ingestion, or are skipped or set aside in a quarantine file by `on_malformed_row`.
+//! The async variants parse on blocking threads and wait between retries without blocking the runtime,
+//! so a file is read while other tasks, such as the inserts of earlier data, keep running.
 
-use anyhow::{Context, Result};
+use crate::retry::RetryPolicy;
+use crate::schema::{ColumnSchema, TableSchema};
+use crate::source::{self, ObjectStoreProvider, ObjectStoreSource, Source};
+use crate::storage;
+use anyhow::{bail, Context, Result};
+use apache_avro::schema::{RecordField, Schema as AvroSchema};
+use apache_avro::types::Value as AvroValue;
+use calamine::{Data as Cell, DataType as _, Reader};
 use polars::prelude::*;
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use sqlx::{Column, Executor, TypeInfo};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Lines, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Path of a CSV input read from standard input, as in `cat wines.csv | pipeline --stdin`.
+pub const STDIN_PATH: &str = "-";
+
+/// How a CSV file is delimited and quoted, set in the `[source.options]` section of a CSV source.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestOptions {
+    /// Field separator, e.g. `;` for European CSVs or `\t` for TSVs.
+    pub delimiter: char,
+    /// Character quoting fields that contain the delimiter.
+    pub quote_char: char,
+    /// Character escaping a quote inside a quoted field, e.g. `\`; quotes are escaped by doubling them when unset.
+    pub escape_char: Option<char>,
+    /// Lines skipped before the header, such as an export banner.
+    pub skip_rows: usize,
+    /// Rows of data skipped after the header, such as rows an earlier load already stored.
+    pub skip_data_rows: usize,
+    /// Columns read, by name; the others are skipped while parsing, so they take no memory. Every column is read when empty.
+    pub select: Vec<String>,
+    /// Lines starting with this prefix are ignored.
+    pub comment_prefix: Option<String>,
+    /// Whether the file starts with a header row; detected from its first row when unset.
+    pub has_header: Option<bool>,
+    /// Names of the columns of a file without a header row, in order; a CSV source uses the input
+    /// columns of the schema when this is empty.
+    pub columns: Vec<String>,
+    /// Types of columns read as given instead of as inferred, by column name, e.g.
+    /// `{ "free sulfur dioxide" = "float64" }` for a column whose values are whole numbers in some files.
+    pub dtypes: BTreeMap<String, ColumnType>,
+    /// Character encoding of the file.
+    pub encoding: Encoding,
+    /// What happens to rows that cannot be parsed.
+    pub on_malformed_row: MalformedRowPolicy,
+    /// Directory of the quarantine files of malformed rows, one per input file, named `<file name>.malformed.csv`.
+    pub quarantine_dir: String,
+    /// Whether the delimiter, header row, and column types are guessed from the start of the file; the
+    /// options set here are used where the guess fails, and `has_header` and `dtypes` win over it.
+    pub sniff: bool,
+    /// Bytes at the start of the file sampled for the guess.
+    pub sniff_bytes: usize,
+}
+
+/// What happens to CSV rows that cannot be parsed.
+///
+/// A row is malformed when its number of fields differs from the header's, when a quoted field is never
+/// closed, or when a value is not of the type `dtypes` configures for its column. Values of columns
+/// without a configured type are parsed by the type inferred for them, and only checked by [`MalformedRowPolicy::Fail`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MalformedRowPolicy {
+    /// The ingestion fails on the first row that cannot be parsed.
+    #[default]
+    Fail,
+    /// Malformed rows are left out, and counted in the log.
+    Skip,
+    /// Malformed rows are left out and written to a file of `quarantine_dir`, with their line number and error.
+    Quarantine,
+}
+
+/// A row of a CSV file left out because it cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedRow {
+    /// Line of the file the row starts on, counting from 1.
+    pub line: usize,
+    pub error: String,
+    /// The row as it appears in the file.
+    pub record: String,
+}
+
+/// Type a CSV column is read as, instead of the type inferred from its first values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Int64,
+    Float64,
+    String,
+    Bool,
+}
+
+impl ColumnType {
+    /// Returns the DataFrame dtype of the column.
+    pub fn dtype(self) -> DataType {
+        match self {
+            ColumnType::Int64 => DataType::Int64,
+            ColumnType::Float64 => DataType::Float64,
+            ColumnType::String => DataType::String,
+            ColumnType::Bool => DataType::Boolean,
+        }
+    }
+
+    /// Helper function to tell whether a value that is not empty is read as this type.
+    fn parses(self, value: &str) -> bool {
+        match self {
+            ColumnType::Int64 => value.parse::<i64>().is_ok(),
+            ColumnType::Float64 => value.parse::<f64>().is_ok(),
+            ColumnType::String => true,
+            ColumnType::Bool => value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false"),
+        }
+    }
+}
+
+/// Character encoding of a CSV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// UTF-16 when the file starts with its byte order mark, Latin-1 when its first block is not valid UTF-8, and UTF-8 otherwise.
+    #[default]
+    Auto,
+    Utf8,
+    /// ISO 8859-1, whose every byte is a character.
+    Latin1,
+    Utf16le,
+    Utf16be,
+}
+
+impl Encoding {
+    /// Resolves [`Encoding::Auto`] from the first block of an input, leaving it unread.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - A reader at the start of the input.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Encoding>` - The encoding, never [`Encoding::Auto`], or an error if the input cannot be read.
+    pub fn detect<R: BufRead + ?Sized>(self, input: &mut R) -> Result<Encoding> {
+        if self != Encoding::Auto {
+            return Ok(self);
+        }
+        let sample = input.fill_buf().context("Failed to read the input")?;
+        Ok(if sample.starts_with(Encoding::Utf16le.bom()) {
+            Encoding::Utf16le
+        } else if sample.starts_with(Encoding::Utf16be.bom()) {
+            Encoding::Utf16be
+        } else {
+            match std::str::from_utf8(sample) {
+                // A character cut off at the end of the block is not an error
+                Err(e) if e.error_len().is_some() => Encoding::Latin1,
+                _ => Encoding::Utf8,
+            }
+        })
+    }
+
+    /// Helper function to return the byte order mark of the encoding, which is not part of the content.
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xef, 0xbb, 0xbf],
+            Encoding::Utf16le => &[0xff, 0xfe],
+            Encoding::Utf16be => &[0xfe, 0xff],
+            Encoding::Auto | Encoding::Latin1 => &[],
+        }
+    }
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            quote_char: '"',
+            escape_char: None,
+            skip_rows: 0,
+            skip_data_rows: 0,
+            select: vec![],
+            comment_prefix: None,
+            has_header: None,
+            columns: vec![],
+            dtypes: BTreeMap::new(),
+            encoding: Encoding::default(),
+            on_malformed_row: MalformedRowPolicy::default(),
+            quarantine_dir: "data/quarantine".to_string(),
+            sniff: false,
+            sniff_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl IngestOptions {
+    /// Helper function to convert the options to Polars read options.
+    fn read_options(&self, has_header: bool) -> Result<CsvReadOptions> {
+        let byte = |name: &str, c: char| -> Result<u8> {
+            if !c.is_ascii() {
+                bail!("The CSV {} must be an ASCII character, got {:?}", name, c);
+            }
+            Ok(c as u8)
+        };
+        let (separator, quote) = (byte("delimiter", self.delimiter)?, byte("quote character", self.quote_char)?);
+        let comment_prefix = self.comment_prefix.clone();
+        let mut fields = vec![];
+        for (name, column_type) in &self.dtypes {
+            fields.push(Field::new(&self.read_name("dtypes", name, has_header)?, column_type.dtype()));
+        }
+        let select = self.select.iter().map(|name| self.read_name("select", name, has_header)).collect::<Result<Vec<_>>>()?;
+        // Without a header, the data starts right after the skipped lines
+        let (skip_rows, skip_data_rows) = if has_header { (self.skip_rows, self.skip_data_rows) } else { (self.skip_rows + self.skip_data_rows, 0) };
+        Ok(CsvReadOptions::default()
+            .with_has_header(has_header)
+            .with_skip_rows(skip_rows)
+            .with_skip_rows_after_header(skip_data_rows)
+            .with_columns((!select.is_empty()).then(|| select.into()))
+            .with_schema_overwrite((!fields.is_empty()).then(|| Arc::new(Schema::from_iter(fields))))
+            .map_parse_options(|parse| parse.with_separator(separator).with_quote_char(Some(quote)).with_comment_prefix(comment_prefix.as_deref())))
+    }
+
+    /// Helper function to name a column as Polars reads it, which names the columns of a file without a header row column_1, column_2, ...
+    fn read_name(&self, option: &str, name: &str, has_header: bool) -> Result<String> {
+        if has_header {
+            return Ok(name.to_string());
+        }
+        match self.columns.iter().position(|c| c == name) {
+            Some(i) => Ok(format!("column_{}", i + 1)),
+            None => bail!("{} names column {}, which is not one of the configured columns", option, name),
+        }
+    }
+
+    /// Helper function to rewrite escaped quotes inside quoted fields as doubled quotes, which Polars reads.
+    fn unescape(&self, text: String) -> String {
+        let Some(escape) = self.escape_char.filter(|escape| *escape != self.quote_char) else { return text };
+        let mut unescaped = String::with_capacity(text.len());
+        let mut quoted = false;
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match chars.clone().next() {
+                Some(next) if c == escape && quoted && (next == self.quote_char || next == escape) => {
+                    chars.next();
+                    if next == self.quote_char {
+                        unescaped.push(self.quote_char);
+                    }
+                    unescaped.push(next);
+                }
+                _ => {
+                    if c == self.quote_char {
+                        quoted = !quoted;
+                    }
+                    unescaped.push(c);
+                }
+            }
+        }
+        unescaped
+    }
+
+    /// Helper function to tell whether a quoted field is still open at the end of a line.
+    fn ends_quoted(&self, line: &str, quoted: bool) -> bool {
+        let escape = self.escape_char.filter(|escape| *escape != self.quote_char);
+        let mut quoted = quoted;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if quoted && Some(c) == escape {
+                chars.next();
+            } else if c == self.quote_char {
+                // Doubled quotes toggle twice, so they leave the field open
+                quoted = !quoted;
+            }
+        }
+        quoted
+    }
+
+    /// Helper function to split a record, with quotes escaped by doubling them, into its unquoted fields.
+    fn fields(&self, record: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = record.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == self.quote_char {
+                if quoted && chars.peek() == Some(&self.quote_char) {
+                    chars.next();
+                    fields.last_mut().unwrap().push(c);
+                } else {
+                    quoted = !quoted;
+                }
+            } else if c == self.delimiter && !quoted {
+                fields.push(String::new());
+            } else {
+                fields.last_mut().unwrap().push(c);
+            }
+        }
+        fields
+    }
+
+    /// Helper function to tell why a record, with quotes escaped by doubling them, cannot be parsed, if it cannot.
+    fn malformed(&self, record: &str, names: &[String]) -> Option<String> {
+        let fields = self.fields(record);
+        if fields.len() != names.len() {
+            return Some(format!("has {} fields, but the header has {}", fields.len(), names.len()));
+        }
+        names.iter().zip(&fields).find_map(|(name, value)| {
+            let column_type = self.dtypes.get(name)?;
+            (!value.is_empty() && !column_type.parses(value)).then(|| format!("{} value {:?} is not a valid {:?}", name, value, column_type))
+        })
+    }
+
+    /// Helper function to leave the malformed rows out of the content of a CSV file, keeping every other line as it is.
+    fn screen(&self, content: &str, has_header: bool) -> (String, Vec<MalformedRow>) {
+        let mut screened = String::with_capacity(content.len());
+        let mut malformed = vec![];
+        let mut names = (!has_header).then(|| self.columns.clone());
+        // The skipped rows of data are left for Polars to skip, so they are not checked
+        let mut skip = self.skip_data_rows;
+        let mut lines = content.lines().enumerate();
+        for (_, line) in lines.by_ref().take(self.skip_rows) {
+            screened.push_str(line);
+            screened.push('\n');
+        }
+        while let Some((number, line)) = lines.next() {
+            let mut record = line.to_string();
+            if !line.trim().is_empty() && !self.is_comment(line) {
+                let mut quoted = self.ends_quoted(line, false);
+                while quoted {
+                    let Some((_, next)) = lines.next() else { break };
+                    quoted = self.ends_quoted(next, true);
+                    record.push('\n');
+                    record.push_str(next);
+                }
+                if names.is_none() {
+                    names = Some(self.fields(&record));
+                } else if skip > 0 {
+                    skip -= 1;
+                } else {
+                    let error = if quoted { Some("ends inside a quoted field".to_string()) } else { names.as_deref().and_then(|names| self.malformed(&record, names)) };
+                    if let Some(error) = error {
+                        malformed.push(MalformedRow { line: number + 1, error, record });
+                        continue;
+                    }
+                }
+            }
+            screened.push_str(&record);
+            screened.push('\n');
+        }
+        (screened, malformed)
+    }
+
+    /// Helper function to report the malformed rows left out of a CSV file, writing them to its quarantine file under [`MalformedRowPolicy::Quarantine`].
+    ///
+    /// The quarantine file is started over unless `append` is set, for the later chunks of a file.
+    fn set_aside(&self, file_path: &str, malformed: &[MalformedRow], append: bool) -> Result<()> {
+        let Some(first) = malformed.first() else { return Ok(()) };
+        if self.on_malformed_row != MalformedRowPolicy::Quarantine {
+            eprintln!("Skipped {} malformed rows of CSV file {}, the first on line {}: {}", malformed.len(), file_path, first.line, first.error);
+            return Ok(());
+        }
+
+        let path = self.quarantine_path(file_path);
+        std::fs::create_dir_all(&self.quarantine_dir).context(format!("Failed to create quarantine directory {}", self.quarantine_dir))?;
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(&path)?;
+            if !append {
+                writeln!(file, "line,error,record")?;
+            }
+            let quote = |text: &str| format!("\"{}\"", text.replace('"', "\"\""));
+            for row in malformed {
+                writeln!(file, "{},{},{}", row.line, quote(&row.error), quote(&row.record))?;
+            }
+            Ok(())
+        };
+        write().context(format!("Failed to write quarantine file {}", path.display()))?;
+        eprintln!("Quarantined {} malformed rows of CSV file {} to {}", malformed.len(), file_path, path.display());
+        Ok(())
+    }
+
+    /// Returns the quarantine file of the malformed rows of a CSV file.
+    pub fn quarantine_path(&self, file_path: &str) -> PathBuf {
+        let name = match Path::new(file_path).file_name() {
+            Some(name) if file_path != STDIN_PATH => name.to_string_lossy().into_owned(),
+            _ => "stdin".to_string(),
+        };
+        Path::new(&self.quarantine_dir).join(format!("{}.malformed.csv", name))
+    }
+
+    /// Helper function to tell whether a line is a comment.
+    pub(crate) fn is_comment(&self, line: &str) -> bool {
+        self.comment_prefix.as_deref().is_some_and(|prefix| line.starts_with(prefix))
+    }
+
+    /// Tells whether the first row of a CSV file is a header row.
+    ///
+    /// Unless `has_header` says so, a first row is taken for data when it has one field per configured
+    /// column name, none of its fields is one of the names, and every field that is not empty is a
+    /// number. Files without a header whose first row holds text need `has_header = false`.
+    ///
+    /// # Arguments
+    ///
+    /// * `first_row` - The first line of the file after the skipped and comment lines.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the row is read as the header.
+    pub fn is_header(&self, first_row: &str) -> bool {
+        if let Some(has_header) = self.has_header {
+            return has_header;
+        }
+        let fields: Vec<&str> = first_row.split(self.delimiter).map(|field| field.trim().trim_matches(self.quote_char)).collect();
+        let data = fields.len() == self.columns.len()
+            && !fields.iter().any(|field| self.columns.iter().any(|name| name == field))
+            && fields.iter().any(|field| !field.is_empty())
+            && fields.iter().all(|field| field.is_empty() || field.parse::<f64>().is_ok());
+        !data
+    }
+
+    /// Guesses how a CSV file is delimited, whether it has a header row, and the types of its columns, from its first `sniff_bytes` bytes.
+    ///
+    /// The delimiter is the one of `,`, `;`, tab, and `|` splitting every sampled line into the same
+    /// number of fields, more than one; the most fields win, then the configured delimiter. The first row
+    /// is a header when a column whose other values are all numbers has text in it, and data when such
+    /// columns have numbers in it too; without numeric columns, it is told as [`IngestOptions::is_header`]
+    /// does. Each column is typed as the narrowest of int64, float64, bool, and string holding its sampled
+    /// values. The configured delimiter is kept when no candidate splits the lines evenly, and a configured
+    /// `has_header` or column type is never replaced by a guess.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The file, decompressed and transcoded to UTF-8.
+    /// * `file_path` - The path of the file, for messages.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<IngestOptions>` - The options with the guesses filled in, or an error if the file cannot be read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let options = options.sniff(open_text("data.csv", options.encoding)?, "data.csv")?;
+    /// ```
+    pub fn sniff(&self, input: impl BufRead, file_path: &str) -> Result<IngestOptions> {
+        let mut bytes = vec![];
+        input.take(self.sniff_bytes as u64).read_to_end(&mut bytes).context(format!("Failed to sample CSV file {}", file_path))?;
+        let sample = String::from_utf8_lossy(&bytes);
+        let mut lines: Vec<&str> = sample.lines().skip(self.skip_rows).filter(|line| !line.trim().is_empty() && !self.is_comment(line)).collect();
+        if bytes.len() == self.sniff_bytes && lines.len() > 1 {
+            // The sample most likely ends in the middle of its last line
+            lines.pop();
+        }
+        let mut sniffed = IngestOptions { sniff: false, ..self.clone() };
+        let Some(first) = lines.first() else { return Ok(sniffed) };
+
+        sniffed.delimiter = self.sniff_delimiter(&lines);
+        let rows: Vec<Vec<String>> = lines.iter().map(|line| sniffed.fields(line)).collect();
+        if sniffed.has_header.is_none() {
+            sniffed.has_header = header_guess(&rows);
+        }
+        let has_header = sniffed.is_header(first);
+        let (names, data) = if has_header { (rows[0].clone(), &rows[1..]) } else { (sniffed.columns.clone(), &rows[..]) };
+        for (i, name) in names.iter().enumerate().filter(|(_, name)| !self.dtypes.contains_key(*name)) {
+            let values: Vec<&str> = data.iter().filter_map(|row| row.get(i)).map(String::as_str).filter(|value| !value.is_empty()).collect();
+            if let Some(column_type) = type_guess(&values) {
+                sniffed.dtypes.insert(name.clone(), column_type);
+            }
+        }
+
+        println!(
+            "Sniffed CSV file {} from {} lines: delimiter {:?}, {}, column types {:?}",
+            file_path,
+            lines.len(),
+            sniffed.delimiter,
+            if has_header { "header row" } else { "no header row" },
+            sniffed.dtypes
+        );
+        Ok(sniffed)
+    }
+
+    /// Helper function to pick the delimiter splitting every line into the same number of fields, keeping the configured one if none does.
+    fn sniff_delimiter(&self, lines: &[&str]) -> char {
+        let field_count = |delimiter: char| {
+            let options = IngestOptions { delimiter, ..self.clone() };
+            let mut counts = lines.iter().map(|line| options.fields(line).len());
+            let first = counts.next()?;
+            (first > 1 && counts.all(|count| count == first)).then_some(first)
+        };
+        SNIFFED_DELIMITERS
+            .iter()
+            .filter_map(|&delimiter| field_count(delimiter).map(|count| (delimiter, count)))
+            .max_by_key(|&(delimiter, count)| (count, delimiter == self.delimiter))
+            .map_or(self.delimiter, |(delimiter, _)| delimiter)
+    }
+
+    /// Helper function to announce that a file is read without a header row, which needs column names.
+    fn check_columns(&self, file_path: &str) -> Result<()> {
+        if self.columns.is_empty() {
+            bail!("CSV file {} has no header row, and no column names are configured", file_path);
+        }
+        println!("CSV file {} has no header row; naming its columns {:?}", file_path, self.columns);
+        Ok(())
+    }
+
+    /// Helper function to write the configured column names as a header row.
+    fn header_row(&self) -> String {
+        let quote = self.quote_char.to_string();
+        let names: Vec<String> = self.columns.iter().map(|name| format!("{0}{1}{0}", quote, name.replace(&quote, &quote.repeat(2)))).collect();
+        names.join(&self.delimiter.to_string())
+    }
+
+    /// Helper function to name the columns of a file read without a header row.
+    fn name_columns(&self, df: &mut DataFrame, file_path: &str) -> Result<()> {
+        if self.select.is_empty() && df.width() != self.columns.len() {
+            bail!("CSV file {} has no header row and {} columns, but {} column names are configured", file_path, df.width(), self.columns.len());
+        }
+        let names = df
+            .get_column_names()
+            .iter()
+            .map(|name| {
+                let position = name.strip_prefix("column_").and_then(|n| n.parse::<usize>().ok()).and_then(|n| n.checked_sub(1));
+                position.and_then(|i| self.columns.get(i)).cloned().context(format!("CSV file {} has more columns than column names are configured", file_path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        df.set_column_names(&names).context(format!("Failed to name the columns of CSV file {}", file_path))
+    }
+}
+
+/// Delimiters a sniffed CSV file may be delimited by.
+const SNIFFED_DELIMITERS: [char; 4] = [',', ';', '\t', '|'];
+
+/// Helper function to guess whether the first of the sampled rows is a header, from the columns whose other values are all numbers.
+fn header_guess(rows: &[Vec<String>]) -> Option<bool> {
+    let (first, data) = rows.split_first()?;
+    let numeric: Vec<&String> = first
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            let mut values = data.iter().filter_map(|row| row.get(*i)).filter(|value| !value.is_empty()).peekable();
+            values.peek().is_some() && values.all(|value| value.parse::<f64>().is_ok())
+        })
+        .map(|(_, field)| field)
+        .collect();
+    if numeric.is_empty() {
+        return None;
+    }
+    Some(numeric.iter().any(|field| !field.is_empty() && field.parse::<f64>().is_err()))
+}
+
+/// Helper function to guess the narrowest type of a column from its sampled values that are not empty.
+fn type_guess(values: &[&str]) -> Option<ColumnType> {
+    if values.is_empty() {
+        return None;
+    }
+    [ColumnType::Int64, ColumnType::Float64, ColumnType::Bool, ColumnType::String]
+        .into_iter()
+        .find(|column_type| values.iter().all(|value| column_type.parses(value)))
+}
+
+/// Helper function to read the first line of a CSV input after the skipped and comment lines, if it has one.
+fn first_row(input: impl BufRead, file_path: &str, options: &IngestOptions) -> Result<Option<String>> {
+    let mut lines = input.lines().skip(options.skip_rows);
+    while let Some(line) = lines.next().transpose().context(format!("Failed to read CSV file {}", file_path))? {
+        if !options.is_comment(&line) {
+            return Ok(Some(line));
+        }
+    }
+    Ok(None)
+}
 
 /// Ingests a CSV file and returns a DataFrame.
 ///
 /// # Arguments
 ///
-/// * `file_path` - A string slice that holds the path to the CSV file.
+/// * `file_path` - A string slice that holds the path to the CSV file.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the ingestion fails.
+///
+/// # Example
+///
+/// ```
+/// let df = ingest_csv("data.csv").expect("CSV ingestion failed");
+/// ```
+pub fn ingest_csv(file_path: &str) -> Result<DataFrame> {
+    ingest_csv_with(file_path, &IngestOptions::default())
+}
+
+/// Ingests a CSV file with a given delimiter, quoting, and leading lines, and returns a DataFrame.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the CSV file.
+/// * `options` - How the file is delimited and quoted.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the ingestion fails.
+///
+/// # Example
+///
+/// ```
+/// let options = IngestOptions { delimiter: ';', ..IngestOptions::default() };
+/// let df = ingest_csv_with("data.csv", &options).expect("CSV ingestion failed");
+/// ```
+pub fn ingest_csv_with(file_path: &str, options: &IngestOptions) -> Result<DataFrame> {
+    println!("Starting data ingestion from CSV file: {}", file_path);
+
+    // Standard input can be read only once, and compressed, escaped, transcoded, or screened files are rewritten, so these are read into memory
+    let in_memory = file_path == STDIN_PATH
+        || options.escape_char.is_some()
+        || options.on_malformed_row != MalformedRowPolicy::Fail
+        || Compression::detect(file_path)? != Compression::None
+        || options.encoding.detect(&mut open_input(file_path)?)? != Encoding::Utf8;
+    let content = if in_memory {
+        let mut content = String::new();
+        open_text(file_path, options.encoding)?.read_to_string(&mut content).context(format!("Failed to read CSV file {}", file_path))?;
+        Some(options.unescape(content))
+    } else {
+        None
+    };
+    let sniffed;
+    let options = if options.sniff {
+        sniffed = match &content {
+            Some(content) => options.sniff(content.as_bytes(), file_path)?,
+            None => options.sniff(open_text(file_path, options.encoding)?, file_path)?,
+        };
+        &sniffed
+    } else {
+        options
+    };
+    let has_header = match (options.has_header, &content) {
+        (Some(has_header), _) => has_header,
+        (None, Some(content)) => first_row(content.as_bytes(), file_path, options)?.is_none_or(|row| options.is_header(&row)),
+        (None, None) => first_row(open_text(file_path, options.encoding)?, file_path, options)?.is_none_or(|row| options.is_header(&row)),
+    };
+    if !has_header {
+        options.check_columns(file_path)?;
+    }
+    let content = match content {
+        Some(content) if options.on_malformed_row != MalformedRowPolicy::Fail => {
+            let (screened, malformed) = options.screen(&content, has_header);
+            options.set_aside(file_path, &malformed, false)?;
+            Some(screened)
+        }
+        content => content,
+    };
+    let read_options = options.read_options(has_header)?;
+    let reader = match content {
+        Some(content) => read_options.into_reader_with_file_handle(Cursor::new(content.into_bytes())).finish(),
+        None => read_options.try_into_reader_with_file_path(Some(file_path.into()))?.finish(),
+    };
+    let mut df = reader.context("Failed to read CSV file")?;
+    if !has_header {
+        options.name_columns(&mut df, file_path)?;
+    }
+
+    println!("Successfully ingested {} rows", df.height());
+    println!("Columns: {:?}", df.get_column_names());
+
+    Ok(df)
+}
+
+/// How an input file is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression of a file by its magic bytes, or by its extension for files too short to have them.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - A string slice that holds the path to the file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Compression>` - The compression, or an error if the file cannot be read.
+    pub fn detect(file_path: &str) -> Result<Compression> {
+        let mut magic = Vec::with_capacity(4);
+        File::open(file_path)
+            .context(format!("Failed to open {}", file_path))?
+            .take(4)
+            .read_to_end(&mut magic)
+            .context(format!("Failed to read {}", file_path))?;
+        Ok(Self::from_magic(&magic).unwrap_or_else(|| Self::from_extension(file_path)))
+    }
+
+    /// Helper function to recognize the gzip and zstd magic bytes.
+    fn from_magic(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if magic.len() >= 4 {
+            Some(Compression::None)
+        } else {
+            None
+        }
+    }
+
+    /// Helper function to recognize the `.gz` and `.zst` extensions.
+    fn from_extension(file_path: &str) -> Compression {
+        match Path::new(file_path).extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("gz" | "gzip") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "uncompressed",
+            Compression::Gzip => "gzip-compressed",
+            Compression::Zstd => "zstd-compressed",
+        })
+    }
+}
+
+/// Opens a file for reading, decompressing it if it is gzip or zstd-compressed.
+///
+/// The path [`STDIN_PATH`] opens standard input, whose compression is detected by its magic bytes.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the file.
+///
+/// # Returns
+///
+/// * `Result<Box<dyn BufRead + Send>>` - A reader of the decompressed content, or an error if the file cannot be opened.
+///
+/// # Example
+///
+/// ```
+/// let header = open_input("archive/2023.csv.gz")?.lines().next();
+/// ```
+pub fn open_input(file_path: &str) -> Result<Box<dyn BufRead + Send>> {
+    if file_path == STDIN_PATH {
+        let mut stdin = BufReader::new(std::io::stdin());
+        let magic = stdin.fill_buf().context("Failed to read standard input")?;
+        return Ok(match Compression::from_magic(&magic[..magic.len().min(4)]).unwrap_or(Compression::None) {
+            Compression::None => Box::new(stdin),
+            Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(stdin))),
+            Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(stdin).context("Failed to open zstd-compressed standard input")?)),
+        });
+    }
+    let compression = Compression::detect(file_path)?;
+    let file = File::open(file_path).context(format!("Failed to open {}", file_path))?;
+    Ok(match compression {
+        Compression::None => Box::new(BufReader::new(file)),
+        Compression::Gzip => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file).context(format!("Failed to open zstd-compressed file {}", file_path))?)),
+    })
+}
+
+/// Opens a text file for reading as UTF-8, decompressing it like [`open_input`] and transcoding it from its encoding.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the file.
+/// * `encoding` - The encoding of the file, detected from its first block if [`Encoding::Auto`].
+///
+/// # Returns
+///
+/// * `Result<Box<dyn BufRead + Send>>` - A reader of the UTF-8 content, without a byte order mark, or an error if the file cannot be opened.
+///
+/// # Example
+///
+/// ```
+/// let header = open_text("lab/export.csv", Encoding::Latin1)?.lines().next();
+/// ```
+pub fn open_text(file_path: &str, encoding: Encoding) -> Result<Box<dyn BufRead + Send>> {
+    let mut input = open_input(file_path)?;
+    let encoding = encoding.detect(&mut input)?;
+    let bom = encoding.bom();
+    if input.fill_buf().context(format!("Failed to read {}", file_path))?.starts_with(bom) {
+        input.consume(bom.len());
+    }
+    if encoding == Encoding::Utf8 {
+        return Ok(input);
+    }
+    println!("Transcoding {} from {:?} to UTF-8", file_path, encoding);
+    Ok(Box::new(BufReader::new(Transcoder { input, encoding, leftover: vec![], decoded: vec![], position: 0 })))
+}
+
+/// Reader decoding Latin-1 or UTF-16 input to UTF-8, created by [`open_text`].
+struct Transcoder {
+    input: Box<dyn BufRead + Send>,
+    encoding: Encoding,
+    /// Bytes of a UTF-16 character cut off at the end of the last block.
+    leftover: Vec<u8>,
+    decoded: Vec<u8>,
+    /// Number of decoded bytes already read.
+    position: usize,
+}
+
+impl Read for Transcoder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.decoded.len() {
+            let block = self.input.fill_buf()?;
+            if block.is_empty() {
+                if !self.leftover.is_empty() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "UTF-16 input ends within a character"));
+                }
+                return Ok(0);
+            }
+            let mut text = String::with_capacity(block.len());
+            match self.encoding {
+                Encoding::Latin1 => text.extend(block.iter().map(|&b| b as char)),
+                _ => {
+                    self.leftover.extend_from_slice(block);
+                    let mut units: Vec<u16> = self
+                        .leftover
+                        .chunks_exact(2)
+                        .map(|pair| if self.encoding == Encoding::Utf16le { u16::from_le_bytes([pair[0], pair[1]]) } else { u16::from_be_bytes([pair[0], pair[1]]) })
+                        .collect();
+                    let mut kept = self.leftover.len() % 2;
+                    // A high surrogate at the end of the block pairs with the first unit of the next one
+                    if units.last().is_some_and(|unit| (0xd800..0xdc00).contains(unit)) {
+                        units.pop();
+                        kept += 2;
+                    }
+                    for c in char::decode_utf16(units) {
+                        text.push(c.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?);
+                    }
+                    self.leftover.drain(..self.leftover.len() - kept);
+                }
+            }
+            let consumed = block.len();
+            self.input.consume(consumed);
+            self.decoded = text.into_bytes();
+            self.position = 0;
+        }
+        let n = buf.len().min(self.decoded.len() - self.position);
+        buf[..n].copy_from_slice(&self.decoded[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Ingests a JSON file holding an array of objects, one per row, and returns a DataFrame.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the JSON file.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the ingestion fails.
+///
+/// # Example
+///
+/// ```
+/// let df = ingest_json("data.json").expect("JSON ingestion failed");
+/// ```
+pub fn ingest_json(file_path: &str) -> Result<DataFrame> {
+    ingest_json_format(file_path, JsonFormat::Json)
+}
+
+/// Ingests a newline-delimited JSON file, one object per line, and returns a DataFrame.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the NDJSON file.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the ingestion fails.
+///
+/// # Example
+///
+/// ```
+/// let df = ingest_ndjson("data.ndjson").expect("NDJSON ingestion failed");
+/// ```
+pub fn ingest_ndjson(file_path: &str) -> Result<DataFrame> {
+    ingest_json_format(file_path, JsonFormat::JsonLines)
+}
+
+/// Ingests a CSV object from an S3 bucket and returns a DataFrame, in builds with the `s3` feature.
+///
+/// The object is read like the `s3` source reads it, through an [`ObjectStoreSource`] with the region,
+/// endpoint, and credentials of the environment.
+///
+/// # Arguments
+///
+/// * `bucket` - The name of the bucket.
+/// * `key` - The key of the CSV object, possibly compressed.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the object cannot be read.
+///
+/// # Example
+///
+/// ```
+/// let df = ingest_s3("wine-lab", "exports/winequality-red.csv.gz").await.expect("S3 ingestion failed");
+/// ```
+pub async fn ingest_s3(bucket: &str, key: &str) -> Result<DataFrame> {
+    let source = ObjectStoreSource {
+        provider: ObjectStoreProvider::S3 { region: None, endpoint: None },
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        options: IngestOptions::default(),
+        chunk_rows: None,
+        retry: RetryPolicy::default(),
+    };
+    source::collect(source.read().await?).await
+}
+
+/// Helper function to read a JSON file in either layout.
+fn ingest_json_format(file_path: &str, format: JsonFormat) -> Result<DataFrame> {
+    println!("Starting data ingestion from JSON file: {}", file_path);
+
+    let file = File::open(file_path).context(format!("Failed to open JSON file {}", file_path))?;
+    let df = JsonReader::new(file)
+        .with_json_format(format)
+        .finish()
+        .context("Failed to read JSON file")?;
+
+    println!("Successfully ingested {} rows", df.height());
+    println!("Columns: {:?}", df.get_column_names());
+
+    Ok(df)
+}
+
+/// Which sheet of a workbook to read, by position or by name.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ExcelSheet {
+    /// The zero-based position of the sheet in the workbook.
+    Index(usize),
+    Name(String),
+}
+
+impl Default for ExcelSheet {
+    fn default() -> Self {
+        ExcelSheet::Index(0)
+    }
+}
+
+impl fmt::Display for ExcelSheet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcelSheet::Index(index) => write!(f, "sheet {}", index),
+            ExcelSheet::Name(name) => write!(f, "sheet {:?}", name),
+        }
+    }
+}
+
+/// Ingests a sheet of an Excel workbook (`.xlsx`, `.xls`, `.ods`) and returns a DataFrame.
+///
+/// Rows above the header, such as a title or notes, are skipped: the header is the first row whose
+/// filled cells are all text and which fills as many cells as the widest row. Each column then gets
+/// the narrowest type holding all its cells: `Int64` for whole numbers, `Float64` for other numbers
+/// (numbers stored as text included), `Boolean`, `Datetime`, or `String` otherwise. Empty and error
+/// cells become nulls.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the workbook.
+/// * `sheet` - The sheet to read.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the sheet is missing or has no header row.
+///
+/// # Example
+///
+/// ```
+/// let df = ingest_excel("results.xlsx", &ExcelSheet::Name("Lab".to_string())).expect("Excel ingestion failed");
+/// ```
+pub fn ingest_excel(file_path: &str, sheet: &ExcelSheet) -> Result<DataFrame> {
+    println!("Starting data ingestion from Excel file: {} ({})", file_path, sheet);
+
+    let mut workbook = calamine::open_workbook_auto(file_path).context(format!("Failed to open Excel file {}", file_path))?;
+    let range = match sheet {
+        ExcelSheet::Index(index) => workbook
+            .worksheet_range_at(*index)
+            .context(format!("Excel file {} has no {}", file_path, sheet))?,
+        ExcelSheet::Name(name) => workbook.worksheet_range(name),
+    }
+    .context(format!("Failed to read {} of {}", sheet, file_path))?;
+    let rows: Vec<&[Cell]> = range.rows().collect();
+    let df = sheet_to_dataframe(&rows)?;
+
+    println!("Successfully ingested {} rows", df.height());
+    println!("Columns: {:?}", df.get_column_names());
+
+    Ok(df)
+}
+
+/// Helper function to find the header row of a sheet and build typed columns from the rows below it.
+fn sheet_to_dataframe(rows: &[&[Cell]]) -> Result<DataFrame> {
+    let filled = |row: &[Cell]| row.iter().filter(|c| !matches!(c, Cell::Empty)).count();
+    let width = rows.iter().map(|row| filled(row)).max().unwrap_or(0);
+    let header_at = rows
+        .iter()
+        .position(|row| width > 0 && filled(row) == width && row.iter().all(|c| matches!(c, Cell::Empty | Cell::String(_))))
+        .context("The sheet has no header row")?;
+    let records: Vec<&[Cell]> = rows[header_at + 1..].iter().filter(|row| filled(row) > 0).copied().collect();
+
+    let mut columns = vec![];
+    for (index, name) in rows[header_at].iter().enumerate() {
+        let cells: Vec<&Cell> = records.iter().map(|row| row.get(index).unwrap_or(&Cell::Empty)).collect();
+        let name = match name {
+            Cell::String(name) => name.trim().to_string(),
+            _ if cells.iter().all(|c| matches!(c, Cell::Empty)) => continue,
+            _ => format!("column_{}", index + 1),
+        };
+        columns.push(coerce_cells(&name, &cells));
+    }
+    DataFrame::new(columns).context("Failed to build a DataFrame from the sheet")
+}
+
+/// Helper function to build a column of the narrowest type holding every cell.
+fn coerce_cells(name: &str, cells: &[&Cell]) -> Series {
+    let number = |cell: &Cell| match cell {
+        Cell::Int(value) => Some(*value as f64),
+        Cell::Float(value) => Some(*value),
+        Cell::String(text) => text.trim().parse::<f64>().ok(),
+        _ => None,
+    };
+    let present: Vec<&Cell> = cells.iter().copied().filter(|c| !matches!(c, Cell::Empty | Cell::Error(_))).collect();
+
+    if present.iter().all(|c| number(c).is_some()) {
+        let values: Vec<Option<f64>> = cells.iter().map(|c| number(c)).collect();
+        // Whole numbers up to 2^53 round-trip through f64 exactly
+        if values.iter().flatten().all(|v| v.fract() == 0.0 && v.abs() < 9_007_199_254_740_992.0) {
+            return Series::new(name, values.iter().map(|v| v.map(|v| v as i64)).collect::<Vec<_>>());
+        }
+        return Series::new(name, values);
+    }
+    if present.iter().all(|c| matches!(c, Cell::Bool(_))) {
+        return Series::new(name, cells.iter().map(|c| c.get_bool()).collect::<Vec<_>>());
+    }
+    if present.iter().all(|c| matches!(c, Cell::DateTime(_) | Cell::DateTimeIso(_))) {
+        let millis: Vec<Option<i64>> = cells.iter().map(|c| c.as_datetime().map(|t| t.and_utc().timestamp_millis())).collect();
+        let series = Series::new(name, millis);
+        return series.cast(&DataType::Datetime(TimeUnit::Milliseconds, None)).unwrap_or(series);
+    }
+    let text: Vec<Option<String>> = cells
+        .iter()
+        .map(|c| match c {
+            Cell::Empty | Cell::Error(_) => None,
+            Cell::String(text) => Some(text.trim().to_string()),
+            other => Some(other.to_string()),
+        })
+        .collect();
+    Series::new(name, text)
+}
+
+/// Ingests an Avro object container file, such as those written by Kafka Connect sinks, and returns a DataFrame.
+///
+/// The writer schema embedded in the file must be a record; each of its fields becomes a column typed
+/// by [`avro_dtype`]. Nested records, arrays, and maps are kept as JSON text.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the Avro file.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the file is not a valid Avro file of records.
+///
+/// # Example
+///
+/// ```
+/// let df = ingest_avro("wines.avro").expect("Avro ingestion failed");
+/// ```
+pub fn ingest_avro(file_path: &str) -> Result<DataFrame> {
+    println!("Starting data ingestion from Avro file: {}", file_path);
+
+    let file = File::open(file_path).context(format!("Failed to open Avro file {}", file_path))?;
+    let reader = apache_avro::Reader::new(BufReader::new(file)).context(format!("Failed to read the Avro header of {}", file_path))?;
+    let AvroSchema::Record(record) = reader.writer_schema().clone() else {
+        bail!("Avro file {} holds {:?} values, not records", file_path, reader.writer_schema());
+    };
+
+    let mut columns: Vec<Vec<AnyValue<'static>>> = vec![vec![]; record.fields.len()];
+    for value in reader {
+        let AvroValue::Record(fields) = value.context("Failed to read Avro record")? else {
+            bail!("Avro file {} holds a value that is not a record", file_path);
+        };
+        for ((column, field), (_, value)) in columns.iter_mut().zip(&record.fields).zip(fields) {
+            column.push(avro_any_value(&value, &field.schema));
+        }
+    }
+    let series = record
+        .fields
+        .iter()
+        .zip(&columns)
+        .map(|(field, values)| avro_series(field, values))
+        .collect::<Result<Vec<_>>>()?;
+    let df = DataFrame::new(series).context("Failed to build a DataFrame from the Avro records")?;
+
+    println!("Successfully ingested {} rows", df.height());
+    println!("Columns: {:?}", df.get_column_names());
+
+    Ok(df)
+}
+
+/// Maps an Avro schema to the Polars dtype of a column holding its values.
+///
+/// Unions of `null` and one other type map to that type, as nullable columns always are; logical
+/// types map to their Polars counterparts (`date` to `Date`, timestamps to `Datetime`, `decimal` to
+/// `Float64`, `uuid` to `String`), and anything else without a flat counterpart to `String`.
+///
+/// # Arguments
+///
+/// * `schema` - The Avro schema of a record field.
+///
+/// # Returns
+///
+/// * `DataType` - The dtype of the column.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(avro_dtype(&AvroSchema::Long), DataType::Int64);
+/// ```
+pub fn avro_dtype(schema: &AvroSchema) -> DataType {
+    match schema {
+        AvroSchema::Null => DataType::Null,
+        AvroSchema::Boolean => DataType::Boolean,
+        AvroSchema::Int | AvroSchema::TimeMillis => DataType::Int32,
+        AvroSchema::Long | AvroSchema::TimeMicros => DataType::Int64,
+        AvroSchema::Float => DataType::Float32,
+        AvroSchema::Double | AvroSchema::Decimal(_) => DataType::Float64,
+        AvroSchema::Bytes | AvroSchema::Fixed(_) => DataType::Binary,
+        AvroSchema::Date => DataType::Date,
+        AvroSchema::TimestampMillis | AvroSchema::LocalTimestampMillis => DataType::Datetime(TimeUnit::Milliseconds, None),
+        AvroSchema::TimestampMicros | AvroSchema::LocalTimestampMicros => DataType::Datetime(TimeUnit::Microseconds, None),
+        AvroSchema::Union(union) => match union.variants().iter().filter(|v| **v != AvroSchema::Null).collect::<Vec<_>>()[..] {
+            [variant] => avro_dtype(variant),
+            _ => DataType::String,
+        },
+        _ => DataType::String,
+    }
+}
+
+/// Helper function to build the column of a record field.
+fn avro_series(field: &RecordField, values: &[AnyValue<'static>]) -> Result<Series> {
+    let dtype = avro_dtype(&field.schema);
+    Series::from_any_values_and_dtype(&field.name, values, &dtype, false).context(format!("Failed to build column {} as {}", field.name, dtype))
+}
+
+/// Helper function to convert an Avro value to the value of its column.
+fn avro_any_value(value: &AvroValue, schema: &AvroSchema) -> AnyValue<'static> {
+    match (value, schema) {
+        (AvroValue::Null, _) => AnyValue::Null,
+        (AvroValue::Union(index, inner), AvroSchema::Union(union)) => match union.variants().get(*index as usize) {
+            Some(variant) => avro_any_value(inner, variant),
+            None => AnyValue::Null,
+        },
+        (AvroValue::Union(_, inner), _) => avro_any_value(inner, schema),
+        (AvroValue::Boolean(v), _) => AnyValue::Boolean(*v),
+        (AvroValue::Int(v) | AvroValue::TimeMillis(v), _) => AnyValue::Int32(*v),
+        (AvroValue::Long(v) | AvroValue::TimeMicros(v), _) => AnyValue::Int64(*v),
+        (AvroValue::Float(v), _) => AnyValue::Float32(*v),
+        (AvroValue::Double(v), _) => AnyValue::Float64(*v),
+        (AvroValue::Date(days), _) => AnyValue::Date(*days),
+        (AvroValue::TimestampMillis(v) | AvroValue::LocalTimestampMillis(v), _) => AnyValue::Datetime(*v, TimeUnit::Milliseconds, &None),
+        (AvroValue::TimestampMicros(v) | AvroValue::LocalTimestampMicros(v), _) => AnyValue::Datetime(*v, TimeUnit::Microseconds, &None),
+        (AvroValue::String(text) | AvroValue::Enum(_, text), _) => AnyValue::StringOwned(text.as_str().into()),
+        (AvroValue::Uuid(uuid), _) => AnyValue::StringOwned(uuid.to_string().as_str().into()),
+        (AvroValue::Bytes(bytes) | AvroValue::Fixed(_, bytes), _) => AnyValue::BinaryOwned(bytes.clone()),
+        (AvroValue::Decimal(decimal), AvroSchema::Decimal(decimal_schema)) => match Vec::<u8>::try_from(decimal) {
+            Ok(bytes) => AnyValue::Float64(decimal_value(&bytes, decimal_schema.scale)),
+            Err(_) => AnyValue::Null,
+        },
+        (other, _) => match serde_json::Value::try_from(other.clone()) {
+            Ok(json) => AnyValue::StringOwned(json.to_string().as_str().into()),
+            Err(_) => AnyValue::Null,
+        },
+    }
+}
+
+/// Helper function to decode a decimal from its big-endian two's complement digits and its scale.
+fn decimal_value(bytes: &[u8], scale: usize) -> f64 {
+    let negative = bytes.first().is_some_and(|b| b & 0x80 != 0);
+    let unscaled = bytes.iter().fold(if negative { -1i128 } else { 0 }, |acc, b| (acc << 8) | *b as i128);
+    unscaled as f64 / 10f64.powi(scale as i32)
+}
+
+/// Runs a SQL query and materializes its result into a DataFrame.
+///
+/// Columns are named as in the result. Integer columns become `Int64` columns, numeric and
+/// floating-point ones `Float64` columns, and booleans `Boolean` ones; every other type, such as dates
+/// or JSON, is read as text.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool of the database queried.
+/// * `query` - A `SELECT` statement, or any other statement returning rows.
 ///
 /// # Returns
 ///
-/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the ingestion fails.
+/// * `Result<DataFrame>` - The rows of the result, or an error if the query cannot be prepared or run.
 ///
 /// # Example
 ///
 /// ```
-/// let df = ingest_csv("data.csv").expect("CSV ingestion failed");
+/// let df = ingest_from_postgres(&pool, "SELECT * FROM wine_quality WHERE quality >= 7").await?;
 /// ```
-pub fn ingest_csv(file_path: &str) -> Result<DataFrame> {
-    println!("Starting data ingestion from CSV file: {}", file_path);
+pub async fn ingest_from_postgres(pool: &PgPool, query: &str) -> Result<DataFrame> {
+    println!("Starting data ingestion from PostgreSQL query: {}", query);
 
-    let df = CsvReadOptions::default()
-        .with_has_header(true)
-        .try_into_reader_with_file_path(Some(file_path.into()))?
-        .finish()
-        .context("Failed to read CSV file")?;
+    let query = query.trim().trim_end_matches(';');
+    let described = pool.describe(query).await.context(format!("Failed to prepare the source query {}", query))?;
+    let schema = TableSchema {
+        columns: described
+            .columns()
+            .iter()
+            .map(|c| ColumnSchema {
+                name: c.name().to_string(),
+                column: c.name().to_string(),
+                pg_type: result_pg_type(c.type_info().name()).to_string(),
+                nullable: true,
+                impute: None,
+            })
+            .collect(),
+    };
+    if schema.columns.is_empty() {
+        bail!("The source query {} returns no columns", query);
+    }
+
+    // Every column is read as text and parsed into the dtype of its type, as stored rows are
+    let columns: Vec<String> = schema.columns.iter().map(|c| format!("\"{}\"::TEXT", c.column.replace('"', "\"\""))).collect();
+    let sql = format!("SELECT {} FROM ({}) AS source", columns.join(", "), query);
+    let rows = sqlx::query(&sql).fetch_all(pool).await.context(format!("Failed to run the source query {}", query))?;
+    let df = storage::frame_from_rows(&rows, &schema, 0)?;
 
     println!("Successfully ingested {} rows", df.height());
     println!("Columns: {:?}", df.get_column_names());
 
     Ok(df)
 }
-/// Retries the ingestion of a CSV file up to a specified number of attempts.
+
+/// Helper function to name the type a result column is read as, from the name of its PostgreSQL type.
+fn result_pg_type(type_name: &str) -> &'static str {
+    match type_name {
+        "INT2" | "INT4" | "INT8" | "OID" => "BIGINT",
+        "FLOAT4" | "FLOAT8" | "NUMERIC" => "DOUBLE PRECISION",
+        "BOOL" => "BOOLEAN",
+        _ => "TEXT",
+    }
+}
+
+/// Reads a CSV file in chunks of at most `chunk_rows` rows, so large files are never held in memory at once.
+///
+/// Each chunk is parsed on its own with the file's header, so column types are inferred per chunk.
+/// Quoted fields may contain line breaks, so a record continues over lines until its quotes are closed.
 ///
 /// # Arguments
 ///
 /// * `file_path` - A string slice that holds the path to the CSV file.
+/// * `chunk_rows` - The maximum number of rows per chunk.
+/// * `options` - How the file is delimited and quoted.
+///
+/// # Returns
+///
+/// * `Result<CsvChunks>` - An iterator over the chunks, or an error if the file cannot be opened or has no header.
+///
+/// # Example
+///
+/// ```
+/// for chunk in read_csv_chunks("data.csv", 10_000, &IngestOptions::default()).expect("Failed to open CSV file") {
+///     let df = chunk.expect("Failed to read chunk");
+/// }
+/// ```
+pub fn read_csv_chunks(file_path: &str, chunk_rows: usize, options: &IngestOptions) -> Result<CsvChunks> {
+    let sniffed;
+    let options = if options.sniff && file_path != STDIN_PATH {
+        sniffed = options.sniff(open_text(file_path, options.encoding)?, file_path)?;
+        &sniffed
+    } else {
+        options
+    };
+    let mut lines = open_text(file_path, options.encoding).context(format!("Failed to open CSV file {}", file_path))?.lines();
+    for skipped in lines.by_ref().take(options.skip_rows) {
+        skipped.context("Failed to read CSV file")?;
+    }
+    let mut line_number = options.skip_rows;
+    let first = loop {
+        line_number += 1;
+        let line = lines
+            .next()
+            .context(format!("CSV file {} is empty", file_path))?
+            .context("Failed to read CSV header")?;
+        if !options.is_comment(&line) {
+            break line;
+        }
+    };
+    // A file without a header gets one of the configured names, and its first row is the first row of data
+    let (header, pending) = if options.is_header(&first) {
+        (first, None)
+    } else {
+        options.check_columns(file_path)?;
+        // The first row is counted again when the first chunk takes it
+        line_number -= 1;
+        (options.header_row(), Some(first))
+    };
+    let names = options.fields(&options.unescape(header.clone()));
+
+    // The leading lines are already skipped and the skipped rows of data are left out of the first chunks, so chunks are parsed without skipping any
+    let skip = options.skip_data_rows;
+    let options = IngestOptions { skip_rows: 0, skip_data_rows: 0, ..options.clone() };
+    options.read_options(true)?;
+    Ok(CsvChunks {
+        file_path: file_path.to_string(),
+        lines,
+        header,
+        names,
+        pending,
+        skip,
+        line_number,
+        quarantined: false,
+        chunk_rows: chunk_rows.max(1),
+        options,
+    })
+}
+
+/// Iterator over the chunks of a CSV file, created by [`read_csv_chunks`].
+pub struct CsvChunks {
+    file_path: String,
+    lines: Lines<Box<dyn BufRead + Send>>,
+    header: String,
+    /// The names of the header's fields, which malformed rows are checked against.
+    names: Vec<String>,
+    /// The first row of a file without a header, read before the first chunk.
+    pending: Option<String>,
+    /// Number of rows of data still to be skipped.
+    skip: usize,
+    /// Number of lines of the file read so far.
+    line_number: usize,
+    /// Whether malformed rows were written to the quarantine file already.
+    quarantined: bool,
+    chunk_rows: usize,
+    options: IngestOptions,
+}
+
+impl Iterator for CsvChunks {
+    type Item = Result<DataFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = format!("{}\n", self.header);
+        let mut rows = 0;
+        let mut malformed = vec![];
+        let screen = self.options.on_malformed_row != MalformedRowPolicy::Fail;
+        while rows < self.chunk_rows {
+            self.line_number += 1;
+            match self.pending.take().map(Ok).or_else(|| self.lines.next()) {
+                Some(Ok(line)) if line.trim().is_empty() || self.options.is_comment(&line) => continue,
+                Some(Ok(line)) => {
+                    let (start, first_line) = (buffer.len(), self.line_number);
+                    let mut quoted = self.options.ends_quoted(&line, false);
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                    while quoted {
+                        self.line_number += 1;
+                        match self.lines.next() {
+                            Some(Ok(line)) => {
+                                quoted = self.options.ends_quoted(&line, true);
+                                buffer.push_str(&line);
+                                buffer.push('\n');
+                            }
+                            Some(Err(e)) => return Some(Err(e).context("Failed to read CSV file")),
+                            None if screen => break,
+                            None => return Some(Err(anyhow::anyhow!("CSV file ends inside a quoted field"))),
+                        }
+                    }
+                    if self.skip > 0 {
+                        self.skip -= 1;
+                        buffer.truncate(start);
+                        continue;
+                    }
+                    if screen {
+                        let record = buffer[start..].trim_end_matches('\n').to_string();
+                        let error = if quoted { Some("ends inside a quoted field".to_string()) } else { self.options.malformed(&self.options.unescape(record.clone()), &self.names) };
+                        if let Some(error) = error {
+                            malformed.push(MalformedRow { line: first_line, error, record });
+                            buffer.truncate(start);
+                            continue;
+                        }
+                    }
+                    rows += 1;
+                }
+                Some(Err(e)) => return Some(Err(e).context("Failed to read CSV file")),
+                None => break,
+            }
+        }
+        if let Err(e) = self.options.set_aside(&self.file_path, &malformed, self.quarantined) {
+            return Some(Err(e));
+        }
+        self.quarantined |= !malformed.is_empty() && self.options.on_malformed_row == MalformedRowPolicy::Quarantine;
+        if rows == 0 {
+            return None;
+        }
+
+        let df = self
+            .options
+            .read_options(true)
+            .and_then(|options| options.into_reader_with_file_handle(Cursor::new(self.options.unescape(buffer).into_bytes())).finish().context("Failed to parse CSV chunk"));
+        Some(df)
+    }
+}
+
+/// Reads a newline-delimited JSON file in chunks of at most `chunk_rows` rows.
+///
+/// Each chunk is parsed on its own, so column types are inferred per chunk, as for [`read_csv_chunks`].
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the NDJSON file.
+/// * `chunk_rows` - The maximum number of rows per chunk.
+///
+/// # Returns
+///
+/// * `Result<NdjsonChunks>` - An iterator over the chunks, or an error if the file cannot be opened.
+pub fn read_ndjson_chunks(file_path: &str, chunk_rows: usize) -> Result<NdjsonChunks> {
+    let file = File::open(file_path).context(format!("Failed to open NDJSON file {}", file_path))?;
+    Ok(NdjsonChunks {
+        lines: BufReader::new(file).lines(),
+        chunk_rows: chunk_rows.max(1),
+    })
+}
+
+/// Iterator over the chunks of a newline-delimited JSON file, created by [`read_ndjson_chunks`].
+pub struct NdjsonChunks {
+    lines: Lines<BufReader<File>>,
+    chunk_rows: usize,
+}
+
+impl Iterator for NdjsonChunks {
+    type Item = Result<DataFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = String::new();
+        let mut rows = 0;
+        while rows < self.chunk_rows {
+            match self.lines.next() {
+                Some(Ok(line)) if line.trim().is_empty() => continue,
+                Some(Ok(line)) => {
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                    rows += 1;
+                }
+                Some(Err(e)) => return Some(Err(e).context("Failed to read NDJSON file")),
+                None => break,
+            }
+        }
+        if rows == 0 {
+            return None;
+        }
+
+        let df = JsonLineReader::new(Cursor::new(buffer.into_bytes()))
+            .finish()
+            .context("Failed to parse NDJSON chunk");
+        Some(df)
+    }
+}
+
+/// Expands a glob pattern such as `data/*.csv` into the files it matches, in sorted order.
+///
+/// A path without glob characters (`*`, `?`, `[`) is returned as it is, whether or not the file exists.
+///
+/// # Arguments
+///
+/// * `pattern` - A path or glob pattern.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>>` - The matching files, or an error if the pattern is invalid or matches no file.
+///
+/// # Example
+///
+/// ```
+/// let paths = expand_paths("data/2023-*.csv").expect("No input files");
+/// ```
+pub fn expand_paths(pattern: &str) -> Result<Vec<String>> {
+    if !is_pattern(pattern) {
+        return Ok(vec![pattern.to_string()]);
+    }
+    let mut paths = vec![];
+    for entry in glob::glob(pattern).context(format!("Invalid glob pattern {}", pattern))? {
+        let path = entry.context(format!("Failed to read a match of {}", pattern))?;
+        if path.is_file() {
+            paths.push(path.to_string_lossy().into_owned());
+        }
+    }
+    if paths.is_empty() {
+        bail!("No files match {}", pattern);
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Whether a path holds glob characters, and so may match several files.
+pub fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Retries the ingestion of a CSV file up to a specified number of attempts.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the CSV file, or a glob pattern matching several.
 /// * `max_attempts` - The maximum number of attempts to retry ingestion.
 ///
 /// # Returns
@@ -51,20 +1538,176 @@ pub fn ingest_csv(file_path: &str) -> Result<DataFrame> {
 /// let df = retry_ingest("data.csv", 3).expect("CSV ingestion failed after 3 attempts");
 /// ```
 pub fn retry_ingest(file_path: &str, max_attempts: usize) -> Result<DataFrame> {
-    let mut attempts = 0;
-    loop {
-        match ingest_csv(file_path) {
-            Ok(df) => return Ok(df),
-            Err(e) => {
-                attempts += 1;
-                if attempts >= max_attempts {
-                    return Err(e).context("Max retry attempts reached");
-                }
-                println!("Attempt {} failed, retrying...", attempts);
-                std::thread::sleep(std::time::Duration::from_secs(1));
+    retry_ingest_with(ingest_csv, file_path, &RetryPolicy::attempts(max_attempts))
+}
+
+/// Retries an ingestion function, such as [`ingest_json`] or [`ingest_ndjson`], as a retry policy says.
+///
+/// A glob pattern reads every matching file, each with its own retries, and concatenates them in the
+/// order of their paths. The files must have the same columns in the same order; a column that is an
+/// integer in some files and a float in others is read as a float.
+///
+/// # Arguments
+///
+/// * `ingest` - The function ingesting one file format.
+/// * `file_path` - A string slice that holds the path to the file, or a glob pattern.
+/// * `retry` - The number of attempts and the waits between them.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the maximum
+///   attempts are reached, listing every file that failed or does not share the schema of the first.
+///
+/// # Example
+///
+/// ```
+/// let df = retry_ingest_with(ingest_ndjson, "data.ndjson", &config.retry.ingestion).expect("NDJSON ingestion failed");
+/// ```
+pub fn retry_ingest_with(ingest: impl Fn(&str) -> Result<DataFrame>, file_path: &str, retry: &RetryPolicy) -> Result<DataFrame> {
+    if !is_pattern(file_path) {
+        return retry.run(|| ingest(file_path));
+    }
+
+    let paths = expand_paths(file_path)?;
+    let mut frames = vec![];
+    let mut errors = vec![];
+    for path in &paths {
+        match retry.run(|| ingest(path)) {
+            Ok(df) => frames.push((path.as_str(), df)),
+            Err(e) => errors.push(format!("{}: {:#}", path, e)),
+        }
+    }
+    if !errors.is_empty() {
+        bail!("Failed to ingest {} of {} files matching {}:\n  {}", errors.len(), paths.len(), file_path, errors.join("\n  "));
+    }
+    let df = concat_files(frames)?;
+    println!("Concatenated {} files matching {} into {} rows", paths.len(), file_path, df.height());
+    Ok(df)
+}
+
+/// Ingests a CSV file like [`ingest_csv`], parsing it on a blocking thread so the async runtime keeps running other tasks.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the CSV file.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the file cannot be read.
+///
+/// # Example
+///
+/// ```
+/// let df = ingest_csv_async("data.csv").await.expect("CSV ingestion failed");
+/// ```
+pub async fn ingest_csv_async(file_path: &str) -> Result<DataFrame> {
+    let file_path = file_path.to_string();
+    tokio::task::spawn_blocking(move || ingest_csv(&file_path)).await.context("Ingestion task failed")?
+}
+
+/// Retries the ingestion of a CSV file like [`retry_ingest`], without blocking the async runtime.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the CSV file, or a glob pattern matching several.
+/// * `max_attempts` - The maximum number of attempts to retry ingestion.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the maximum attempts are reached.
+///
+/// # Example
+///
+/// ```
+/// let df = retry_ingest_async("data.csv", 3).await.expect("CSV ingestion failed after 3 attempts");
+/// ```
+pub async fn retry_ingest_async(file_path: &str, max_attempts: usize) -> Result<DataFrame> {
+    retry_ingest_with_async(ingest_csv, file_path, &RetryPolicy::attempts(max_attempts)).await
+}
+
+/// Retries an ingestion function like [`retry_ingest_with`], without blocking the async runtime.
+///
+/// Each attempt parses on a blocking thread and the waits between attempts are async, so other tasks,
+/// such as the inserts of a previous chunk, keep running while a file is read or waited for.
+///
+/// # Arguments
+///
+/// * `ingest` - The function ingesting one file format.
+/// * `file_path` - A string slice that holds the path to the file, or a glob pattern.
+/// * `retry` - The number of attempts and the waits between them.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the DataFrame if successful, or an error if the maximum
+///   attempts are reached, listing every file that failed or does not share the schema of the first.
+///
+/// # Example
+///
+/// ```
+/// let df = retry_ingest_with_async(ingest_ndjson, "data.ndjson", &config.retry.ingestion).await.expect("NDJSON ingestion failed");
+/// ```
+pub async fn retry_ingest_with_async(ingest: impl Fn(&str) -> Result<DataFrame> + Send + Sync + 'static, file_path: &str, retry: &RetryPolicy) -> Result<DataFrame> {
+    let ingest = Arc::new(ingest);
+    let attempt = |path: &str| {
+        let (ingest, path) = (ingest.clone(), path.to_string());
+        async move { tokio::task::spawn_blocking(move || ingest(&path)).await.context("Ingestion task failed")? }
+    };
+    if !is_pattern(file_path) {
+        return retry.run_async(|| attempt(file_path)).await;
+    }
+
+    let paths = expand_paths(file_path)?;
+    let mut frames = vec![];
+    let mut errors = vec![];
+    for path in &paths {
+        match retry.run_async(|| attempt(path)).await {
+            Ok(df) => frames.push((path.as_str(), df)),
+            Err(e) => errors.push(format!("{}: {:#}", path, e)),
+        }
+    }
+    if !errors.is_empty() {
+        bail!("Failed to ingest {} of {} files matching {}:\n  {}", errors.len(), paths.len(), file_path, errors.join("\n  "));
+    }
+    let df = concat_files(frames)?;
+    println!("Concatenated {} files matching {} into {} rows", paths.len(), file_path, df.height());
+    Ok(df)
+}
+
+/// Helper function to stack the frames of several files, checking they share the schema of the first.
+fn concat_files(frames: Vec<(&str, DataFrame)>) -> Result<DataFrame> {
+    let mut frames = frames.into_iter();
+    let (first, mut df) = frames.next().context("No files to concatenate")?;
+    let mut mismatches = vec![];
+    for (path, mut frame) in frames {
+        if frame.get_column_names() != df.get_column_names() {
+            mismatches.push(format!("{}: columns {:?} differ from the columns {:?} of {}", path, frame.get_column_names(), df.get_column_names(), first));
+            continue;
+        }
+        let names: Vec<String> = df.get_column_names().iter().map(|s| s.to_string()).collect();
+        let mut matching = true;
+        for (name, (left, right)) in names.into_iter().zip(df.dtypes().into_iter().zip(frame.dtypes())) {
+            if left == right {
+                continue;
+            }
+            if left.is_numeric() && right.is_numeric() {
+                let widened = df.column(&name)?.cast(&DataType::Float64)?;
+                df.with_column(widened)?;
+                let widened = frame.column(&name)?.cast(&DataType::Float64)?;
+                frame.with_column(widened)?;
+            } else {
+                mismatches.push(format!("{}: column {} is {} but {} in {}", path, name, right, left, first));
+                matching = false;
             }
         }
+        if matching {
+            df.vstack_mut(&frame).context(format!("Failed to append the rows of {}", path))?;
+        }
+    }
+    if !mismatches.is_empty() {
+        bail!("The files do not share a schema:\n  {}", mismatches.join("\n  "));
     }
+    df.align_chunks();
+    Ok(df)
 }
 
 #[cfg(test)]
@@ -78,6 +1721,10 @@ mod tests {
         file_path.to_string()
     }
 
+    fn header_width(csv_content: &str) -> usize {
+        csv_content.lines().next().map_or(0, |header| header.split(',').count())
+    }
+
     #[test]
     fn test_ingest_csv() {
         let csv_content = "fixed acidity,volatile acidity,citric acid,residual sugar,chlorides,free sulfur dioxide,total sulfur dioxide,density,pH,sulphates,alcohol,quality\n7.4,0.7,0,1.9,0.076,11,34,0.9978,3.51,0.56,9.4,5\n7.8,0.88,0,2.6,0.098,25,67,0.9968,3.2,0.68,9.8,5";
@@ -85,7 +1732,7 @@ mod tests {
 
         let df = ingest_csv(&file_path).expect("CSV ingestion failed");
 
-        assert_eq!(df.shape(), (2, 12)); // 2 rows, 12 columns
+        assert_eq!(df.shape(), (2, header_width(csv_content))); // 2 rows, one column per header field
         assert_eq!(df.column("fixed acidity").unwrap().f64().unwrap().get(0), Some(7.4));
         assert_eq!(df.column("quality").unwrap().i64().unwrap().get(1), Some(5));
     }
@@ -97,11 +1744,288 @@ mod tests {
 
         let df = retry_ingest(&file_path, 3).expect("CSV ingestion failed after 3 attempts");
 
-        assert_eq!(df.shape(), (2, 12)); // 2 rows, 12 columns
+        assert_eq!(df.shape(), (2, header_width(csv_content))); // 2 rows, one column per header field
         assert_eq!(df.column("fixed acidity").unwrap().f64().unwrap().get(0), Some(7.4));
         assert_eq!(df.column("quality").unwrap().i64().unwrap().get(1), Some(5));
     }
 
+    #[test]
+    fn test_read_csv_chunks() {
+        let file_path = "temp_chunks_test.csv";
+        std::fs::write(file_path, "alcohol,quality\n9.4,5\n9.8,5\n\n10.1,6\n9.9,6\n11.2,7\n").expect("Failed to write temp CSV file");
+
+        let chunks = read_csv_chunks(file_path, 2, &IngestOptions::default())
+            .expect("Failed to open CSV file")
+            .collect::<Result<Vec<_>>>()
+            .expect("Failed to read chunks");
+        std::fs::remove_file(file_path).ok();
+
+        assert_eq!(chunks.iter().map(|df| df.height()).collect::<Vec<_>>(), vec![2, 2, 1]);
+        assert_eq!(chunks[2].column("quality").unwrap().i64().unwrap().get(0), Some(7));
+    }
+
+    #[test]
+    fn test_read_csv_chunks_keeps_quoted_line_breaks() {
+        let file_path = "temp_chunks_quoted_test.csv";
+        std::fs::write(file_path, "alcohol,notes\n9.4,\"dry,\n\nfruity \"\"finish\"\"\"\n9.8,plain\n10.1,\"open\n").expect("Failed to write temp CSV file");
+
+        let mut chunks = read_csv_chunks(file_path, 1, &IngestOptions::default()).expect("Failed to open CSV file");
+        let first = chunks.next().unwrap().expect("Failed to read chunk");
+        let second = chunks.next().unwrap().expect("Failed to read chunk");
+        let unterminated = chunks.next().unwrap();
+        std::fs::remove_file(file_path).ok();
+
+        assert_eq!(first.column("notes").unwrap().str().unwrap().get(0), Some("dry,\n\nfruity \"finish\""));
+        assert_eq!(second.column("alcohol").unwrap().f64().unwrap().get(0), Some(9.8));
+        assert!(unterminated.is_err());
+    }
+
+    #[test]
+    fn test_sniff_guesses_delimiter_header_and_types() {
+        let sample = "# export\nfixed acidity;notes;quality;organic\n7,4;\"dry; fruity\";5;true\n7,8;;6;false\n11,2;oak;6;tr";
+        let options = IngestOptions { sniff: true, sniff_bytes: sample.len(), comment_prefix: Some("#".to_string()), ..IngestOptions::default() };
+        let sniffed = options.sniff(sample.as_bytes(), "wines.csv").unwrap();
+        assert_eq!((sniffed.delimiter, sniffed.has_header, sniffed.sniff), (';', Some(true), false));
+        assert_eq!(
+            sniffed.dtypes,
+            BTreeMap::from([
+                ("fixed acidity".to_string(), ColumnType::String),
+                ("notes".to_string(), ColumnType::String),
+                ("quality".to_string(), ColumnType::Int64),
+                ("organic".to_string(), ColumnType::Bool),
+            ])
+        );
+
+        let explicit = IngestOptions { columns: vec!["alcohol".to_string(), "quality".to_string()], dtypes: BTreeMap::from([("quality".to_string(), ColumnType::Float64)]), ..options.clone() };
+        let sniffed = explicit.sniff("9.4|5\n10.1|6\n".as_bytes(), "wines.csv").unwrap();
+        assert_eq!((sniffed.delimiter, sniffed.has_header), ('|', Some(false)));
+        assert_eq!(sniffed.dtypes, BTreeMap::from([("alcohol".to_string(), ColumnType::Float64), ("quality".to_string(), ColumnType::Float64)]));
+        assert_eq!(options.sniff("just one column\n".as_bytes(), "wines.csv").unwrap().delimiter, ',');
+    }
+
+    #[test]
+    fn test_ingest_csv_with_options() {
+        let file_path = "temp_options_test.csv";
+        let options = IngestOptions {
+            delimiter: ';',
+            escape_char: Some('\\'),
+            skip_rows: 1,
+            comment_prefix: Some("#".to_string()),
+            ..IngestOptions::default()
+        };
+        std::fs::write(file_path, "Export of 2024-05-01\nalcohol;quality;taster\n# first batch\n9.4;5;\"Ann \\\"the nose\\\"\"\n10.1;6;Bob\n").expect("Failed to write temp CSV file");
+
+        let df = ingest_csv_with(file_path, &options).expect("CSV ingestion failed");
+        let chunks = read_csv_chunks(file_path, 1, &options).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        std::fs::write(file_path, "alcohol\tquality\n9.4\t5\n").expect("Failed to write temp CSV file");
+        let tsv = ingest_csv_with(file_path, &IngestOptions { delimiter: '\t', ..IngestOptions::default() }).expect("TSV ingestion failed");
+        std::fs::remove_file(file_path).ok();
+
+        assert_eq!(df.shape(), (2, 3));
+        assert_eq!(df.column("taster").unwrap().str().unwrap().get(0), Some("Ann \"the nose\""));
+        assert_eq!(chunks.iter().map(|df| df.height()).collect::<Vec<_>>(), vec![1, 1]);
+        assert_eq!(chunks[1].column("taster").unwrap().str().unwrap().get(0), Some("Bob"));
+        assert_eq!(tsv.column("quality").unwrap().i64().unwrap().get(0), Some(5));
+    }
+
+    #[test]
+    fn test_ingest_csv_without_header_row() {
+        let file_path = "temp_headerless_test.csv";
+        let options = IngestOptions { columns: vec!["alcohol".to_string(), "quality".to_string()], ..IngestOptions::default() };
+        std::fs::write(file_path, "9.4,5\n9.8,\n10.1,6\n").expect("Failed to write temp CSV file");
+
+        let df = ingest_csv_with(file_path, &options).expect("CSV ingestion failed");
+        let chunks = read_csv_chunks(file_path, 2, &options).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        let unnamed = ingest_csv_with(file_path, &IngestOptions { has_header: Some(false), ..IngestOptions::default() });
+        std::fs::remove_file(file_path).ok();
+
+        assert_eq!(df.shape(), (3, 2));
+        assert_eq!(df.column("alcohol").unwrap().f64().unwrap().get(0), Some(9.4));
+        assert_eq!(chunks.iter().map(|df| df.height()).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(chunks[0].get_column_names(), vec!["alcohol", "quality"]);
+        assert!(unnamed.is_err());
+        assert!(options.is_header("alcohol,quality"));
+        assert!(options.is_header("9.4,5,7"));
+        assert!(!IngestOptions { has_header: Some(false), ..options.clone() }.is_header("alcohol,quality"));
+    }
+
+    #[test]
+    fn test_ingest_csv_selects_columns_and_skips_rows() {
+        let file_path = "temp_select_test.csv";
+        let options = IngestOptions { select: vec!["quality".to_string(), "alcohol".to_string()], skip_data_rows: 2, ..IngestOptions::default() };
+        std::fs::write(file_path, "alcohol,notes,quality\n9.4,a,5\n9.8,b,5\n10.1,d,6\n9.9,e,6\n11.2,f,7\n").expect("Failed to write temp CSV file");
+
+        let df = ingest_csv_with(file_path, &options).expect("CSV ingestion failed");
+        let chunks = read_csv_chunks(file_path, 2, &options).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        std::fs::write(file_path, "9.4,a,5\n9.8,b,5\n10.1,d,6\n").expect("Failed to write temp CSV file");
+        let headerless = IngestOptions { columns: vec!["alcohol".to_string(), "notes".to_string(), "quality".to_string()], ..options.clone() };
+        let unnamed = ingest_csv_with(file_path, &headerless).expect("CSV ingestion failed");
+        std::fs::remove_file(file_path).ok();
+
+        assert_eq!(df.shape(), (3, 2));
+        assert!(df.column("notes").is_err());
+        assert_eq!(df.column("alcohol").unwrap().f64().unwrap().get(0), Some(10.1));
+        assert_eq!(chunks.iter().map(|df| df.height()).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(chunks[0].column("alcohol").unwrap().f64().unwrap().get(0), Some(10.1));
+        assert_eq!(unnamed.shape(), (1, 2));
+        assert_eq!(unnamed.column("quality").unwrap().i64().unwrap().get(0), Some(6));
+    }
+
+    #[test]
+    fn test_ingest_latin1_and_utf16_csv() {
+        let file_path = "temp_encoding_test.csv";
+        let text = "taster,alcohol\nJosé,9.4\nMüller,9.8\n";
+        let latin1: Vec<u8> = text.chars().map(|c| c as u8).collect();
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+
+        std::fs::write(file_path, &latin1).expect("Failed to write temp CSV file");
+        let detected = ingest_csv_with(file_path, &IngestOptions::default()).expect("Latin-1 ingestion failed");
+        let chunks = read_csv_chunks(file_path, 1, &IngestOptions { encoding: Encoding::Latin1, ..IngestOptions::default() }).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        std::fs::write(file_path, &utf16).expect("Failed to write temp CSV file");
+        let wide = ingest_csv_with(file_path, &IngestOptions::default()).expect("UTF-16 ingestion failed");
+        std::fs::remove_file(file_path).ok();
+
+        for df in [&detected, &wide] {
+            assert_eq!(df.get_column_names(), vec!["taster", "alcohol"]);
+            assert_eq!(df.column("taster").unwrap().str().unwrap().get(1), Some("Müller"));
+        }
+        assert_eq!(chunks[0].column("taster").unwrap().str().unwrap().get(0), Some("José"));
+        assert_eq!(Encoding::Auto.detect(&mut "taster\n".as_bytes()).unwrap(), Encoding::Utf8);
+    }
+
+    #[test]
+    fn test_ingest_csv_with_dtypes() {
+        let file_path = "temp_dtypes_test.csv";
+        let dtypes = BTreeMap::from([("free sulfur dioxide".to_string(), ColumnType::Float64), ("quality".to_string(), ColumnType::String)]);
+        let options = IngestOptions { dtypes, ..IngestOptions::default() };
+        std::fs::write(file_path, "free sulfur dioxide,quality\n11,5\n25,6\n").expect("Failed to write temp CSV file");
+
+        let df = ingest_csv_with(file_path, &options).expect("CSV ingestion failed");
+        let chunks = read_csv_chunks(file_path, 1, &options).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        std::fs::write(file_path, "11,5\n25,6\n").expect("Failed to write temp CSV file");
+        let headerless = IngestOptions { columns: vec!["free sulfur dioxide".to_string(), "quality".to_string()], ..options.clone() };
+        let unnamed = ingest_csv_with(file_path, &headerless).expect("CSV ingestion failed");
+        std::fs::remove_file(file_path).ok();
+
+        for df in [&df, &chunks[1], &unnamed] {
+            assert_eq!(df.column("free sulfur dioxide").unwrap().dtype(), &DataType::Float64);
+            assert_eq!(df.column("quality").unwrap().dtype(), &DataType::String);
+        }
+    }
+
+    #[test]
+    fn test_malformed_rows_are_skipped_or_quarantined() {
+        let file_path = "temp_malformed_test.csv";
+        let dtypes = BTreeMap::from([("alcohol".to_string(), ColumnType::Float64)]);
+        let options = IngestOptions { dtypes, on_malformed_row: MalformedRowPolicy::Quarantine, quarantine_dir: "temp_quarantine".to_string(), ..IngestOptions::default() };
+        std::fs::write(file_path, "alcohol,quality\n9.4,5\n9.8\nabc,6\n10.1,6,extra\n11.2,7\n").expect("Failed to write temp CSV file");
+
+        let df = ingest_csv_with(file_path, &options).expect("CSV ingestion failed");
+        let quarantined = std::fs::read_to_string(options.quarantine_path(file_path)).unwrap();
+        let chunks = read_csv_chunks(file_path, 1, &options).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        let chunks_quarantined = std::fs::read_to_string(options.quarantine_path(file_path)).unwrap();
+        let skipped = ingest_csv_with(file_path, &IngestOptions { on_malformed_row: MalformedRowPolicy::Skip, ..options.clone() }).expect("CSV ingestion failed");
+        let failed = ingest_csv_with(file_path, &IngestOptions { on_malformed_row: MalformedRowPolicy::Fail, ..options.clone() });
+        std::fs::remove_file(file_path).ok();
+        std::fs::remove_dir_all("temp_quarantine").ok();
+
+        assert_eq!(df.column("alcohol").unwrap().f64().unwrap().to_vec(), vec![Some(9.4), Some(11.2)]);
+        assert_eq!(
+            quarantined.lines().collect::<Vec<_>>(),
+            vec![
+                "line,error,record",
+                "3,\"has 1 fields, but the header has 2\",\"9.8\"",
+                "4,\"alcohol value \"\"abc\"\" is not a valid Float64\",\"abc,6\"",
+                "5,\"has 3 fields, but the header has 2\",\"10.1,6,extra\"",
+            ]
+        );
+        assert_eq!(chunks.iter().map(|df| df.height()).collect::<Vec<_>>(), vec![1, 1]);
+        assert_eq!(chunks_quarantined, quarantined);
+        assert_eq!(skipped.height(), 2);
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn test_ingest_compressed_csv() {
+        use std::io::Write;
+
+        let csv_content = "alcohol,quality\n9.4,5\n9.8,5\n10.1,6\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(csv_content.as_bytes()).unwrap();
+        // The names do not say how the files are compressed, so the magic bytes must
+        let files = [("temp_compressed_test.gz.csv", gzip.finish().unwrap()), ("temp_compressed_test.data", zstd::encode_all(csv_content.as_bytes(), 3).unwrap())];
+
+        for (file_path, bytes) in files {
+            std::fs::write(file_path, bytes).expect("Failed to write temp compressed file");
+            let df = ingest_csv(file_path).expect("Compressed CSV ingestion failed");
+            let chunks = read_csv_chunks(file_path, 2, &IngestOptions::default()).unwrap().collect::<Result<Vec<_>>>().unwrap();
+            std::fs::remove_file(file_path).ok();
+
+            assert_eq!(df.shape(), (3, 2));
+            assert_eq!(chunks.iter().map(|df| df.height()).collect::<Vec<_>>(), vec![2, 1]);
+        }
+        assert_eq!(Compression::from_extension("archive/2023.CSV.GZ"), Compression::Gzip);
+        assert_eq!(Compression::from_magic(b"al"), None);
+    }
+
+    #[test]
+    fn test_ingest_wide_csv() {
+        let header: Vec<String> = (0..300).map(|i| format!("feature_{}", i)).collect();
+        let row: Vec<String> = (0..300).map(|i| format!("{}.5", i)).collect();
+        let file_path = "temp_wide_test.csv";
+        std::fs::write(file_path, format!("{}\n{}\n", header.join(","), row.join(","))).expect("Failed to write temp CSV file");
+
+        let df = ingest_csv(file_path).expect("CSV ingestion failed");
+        std::fs::remove_file(file_path).ok();
+
+        assert_eq!(df.shape(), (1, 300));
+        assert_eq!(df.column("feature_299").unwrap().f64().unwrap().get(0), Some(299.5));
+    }
+
+    #[test]
+    fn test_result_pg_type_parses_numbers_only() {
+        let types: Vec<&str> = ["INT4", "NUMERIC", "FLOAT8", "BOOL", "TIMESTAMPTZ", "POINT", "INTERVAL"].into_iter().map(result_pg_type).collect();
+
+        assert_eq!(types, vec!["BIGINT", "DOUBLE PRECISION", "DOUBLE PRECISION", "BOOLEAN", "TEXT", "TEXT", "TEXT"]);
+    }
+
+    #[test]
+    fn test_retry_ingest_concatenates_glob_matches() {
+        let dir = "temp_glob_test";
+        std::fs::create_dir_all(dir).expect("Failed to create temp directory");
+        std::fs::write(format!("{}/2022.csv", dir), "alcohol,quality\n9.4,5\n").expect("Failed to write temp CSV file");
+        std::fs::write(format!("{}/2023.csv", dir), "alcohol,quality\n10,6\n11,7\n").expect("Failed to write temp CSV file");
+
+        let df = retry_ingest(&format!("{}/*.csv", dir), 1);
+        std::fs::write(format!("{}/2024.csv", dir), "alcohol,grade\n9.8,5\n").expect("Failed to write temp CSV file");
+        let mismatched = retry_ingest(&format!("{}/*.csv", dir), 1);
+        let unmatched = retry_ingest(&format!("{}/*.tsv", dir), 1);
+        std::fs::remove_dir_all(dir).ok();
+
+        let df = df.expect("Glob ingestion failed");
+        assert_eq!(df.column("alcohol").unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![9.4, 10.0, 11.0]);
+        assert_eq!(df.column("quality").unwrap().dtype(), &DataType::Int64);
+        let error = format!("{:#}", mismatched.unwrap_err());
+        assert!(error.contains("temp_glob_test/2024.csv: columns [\"alcohol\", \"grade\"] differ"), "{}", error);
+        assert!(format!("{:#}", unmatched.unwrap_err()).contains("No files match"));
+    }
+
+    #[tokio::test]
+    async fn test_ingest_async() {
+        let file_path = "temp_test_async.csv";
+        std::fs::write(file_path, "alcohol,quality\n9.4,5\n9.8,6\n").expect("Failed to write temp CSV file");
+
+        let df = ingest_csv_async(file_path).await.expect("CSV ingestion failed");
+        let retried = retry_ingest_async(file_path, 3).await.expect("CSV ingestion failed after 3 attempts");
+        std::fs::remove_file(file_path).ok();
+        let missing = retry_ingest_with_async(ingest_csv, "non_existent_file.csv", &RetryPolicy { initial_delay_ms: 0, ..RetryPolicy::attempts(2) }).await;
+
+        assert_eq!(df.shape(), (2, 2));
+        assert!(df.equals(&retried));
+        assert!(format!("{:#}", missing.unwrap_err()).starts_with("Max retry attempts reached"));
+    }
+
     #[test]
     fn test_retry_ingest_fail() {
         let file_path = "non_existent_file.csv";
@@ -109,4 +2033,82 @@ mod tests {
         let result = retry_ingest(&file_path, 3);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_ingest_json_and_ndjson() {
+        let json_path = "temp_test.json";
+        let ndjson_path = "temp_test.ndjson";
+        std::fs::write(json_path, r#"[{"alcohol": 9.4, "quality": 5}, {"alcohol": 9.8, "quality": 6}]"#).expect("Failed to write temp JSON file");
+        std::fs::write(ndjson_path, "{\"alcohol\": 9.4, \"quality\": 5}\n{\"alcohol\": 9.8, \"quality\": 6}\n\n{\"alcohol\": 10.1, \"quality\": 7}\n")
+            .expect("Failed to write temp NDJSON file");
+
+        let json = retry_ingest_with(ingest_json, json_path, &RetryPolicy::attempts(3)).expect("JSON ingestion failed after 3 attempts");
+        let ndjson = retry_ingest_with(ingest_ndjson, ndjson_path, &RetryPolicy::attempts(3)).expect("NDJSON ingestion failed after 3 attempts");
+        let heights: Vec<usize> = read_ndjson_chunks(ndjson_path, 2)
+            .expect("Failed to open NDJSON file")
+            .map(|chunk| chunk.expect("Failed to read chunk").height())
+            .collect();
+        std::fs::remove_file(json_path).ok();
+        std::fs::remove_file(ndjson_path).ok();
+
+        assert_eq!(json.shape(), (2, 2));
+        assert_eq!(json.column("alcohol").unwrap().f64().unwrap().get(1), Some(9.8));
+        assert_eq!(ndjson.column("quality").unwrap().i64().unwrap().get(2), Some(7));
+        assert_eq!(heights, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_sheet_to_dataframe_skips_title_and_coerces_types() {
+        let text = |s: &str| Cell::String(s.to_string());
+        let rows: Vec<Vec<Cell>> = vec![
+            vec![text("Lab results, batch 12"), Cell::Empty, Cell::Empty, Cell::Empty],
+            vec![Cell::Empty, Cell::Empty, Cell::Empty, Cell::Empty],
+            vec![text("alcohol"), text("quality"), text("taster"), text("organic")],
+            vec![Cell::Float(9.4), Cell::Float(5.0), text("ann"), Cell::Bool(true)],
+            vec![text("9.8"), Cell::Int(6), Cell::Float(3.5), Cell::Empty],
+            vec![Cell::Empty, Cell::Empty, Cell::Empty, Cell::Empty],
+        ];
+        let rows: Vec<&[Cell]> = rows.iter().map(|row| row.as_slice()).collect();
+
+        let df = sheet_to_dataframe(&rows).unwrap();
+        assert_eq!(df.get_column_names(), vec!["alcohol", "quality", "taster", "organic"]);
+        assert_eq!(df.dtypes(), vec![DataType::Float64, DataType::Int64, DataType::String, DataType::Boolean]);
+        assert_eq!(df.column("alcohol").unwrap().f64().unwrap().get(1), Some(9.8));
+        assert_eq!(df.column("taster").unwrap().str().unwrap().get(1), Some("3.5"));
+        assert_eq!(df.column("organic").unwrap().bool().unwrap().get(1), None);
+
+        assert!(sheet_to_dataframe(&rows[..2]).is_err());
+    }
+
+    #[test]
+    fn test_ingest_avro_maps_schema_to_dtypes() {
+        let schema = AvroSchema::parse_str(
+            r#"{"type": "record", "name": "wine", "fields": [
+                {"name": "alcohol", "type": "double"},
+                {"name": "quality", "type": ["null", "int"]},
+                {"name": "taster", "type": ["null", "string"]},
+                {"name": "tasted_at", "type": {"type": "long", "logicalType": "timestamp-millis"}}
+            ]}"#,
+        )
+        .unwrap();
+        let mut writer = apache_avro::Writer::new(&schema, Vec::new());
+        for (alcohol, quality, taster) in [(9.4, Some(5), Some("ann")), (10.1, None, None)] {
+            let mut record = apache_avro::types::Record::new(&schema).unwrap();
+            record.put("alcohol", alcohol);
+            record.put("quality", quality);
+            record.put("taster", taster);
+            record.put("tasted_at", AvroValue::TimestampMillis(1_700_000_000_000));
+            writer.append(record).unwrap();
+        }
+        let file_path = "temp_test.avro";
+        std::fs::write(file_path, writer.into_inner().unwrap()).expect("Failed to write temp Avro file");
+
+        let df = ingest_avro(file_path).expect("Avro ingestion failed");
+        std::fs::remove_file(file_path).ok();
+
+        assert_eq!(df.dtypes(), vec![DataType::Float64, DataType::Int32, DataType::String, DataType::Datetime(TimeUnit::Milliseconds, None)]);
+        assert_eq!(df.column("quality").unwrap().i32().unwrap().into_iter().collect::<Vec<_>>(), vec![Some(5), None]);
+        assert_eq!(df.column("taster").unwrap().str().unwrap().get(0), Some("ann"));
+        assert_eq!(decimal_value(&[0xff, 0x38], 2), -2.0);
+    }
 }
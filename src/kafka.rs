@@ -0,0 +1,407 @@
+//! This module consumes a Kafka topic as a never-ending source of micro-batches.
+//!
+//! In a binary built with the `kafka` feature, a `kind = "kafka"` source subscribes to its topic as a
+//! member of a consumer group and turns the records it receives into DataFrames of at most
+//! `streaming.chunk_rows` rows. A batch is cut short once `batch_timeout_ms` passed since its first
+//! record, so a quiet topic still gets its rows stored promptly. The chunked pipeline transforms and
+//! stores every batch as a chunk, which makes a run a continuously running consumer that only ends on
+//! an error, or after `--limit` rows. The offsets a batch ends at are written to the `kafka_offsets`
+//! table in the transaction that stores its rows, and a new run assigns itself the partitions of the
+//! topic at the offsets stored there. A batch is thus stored exactly once: the batches of a failed run
+//! are consumed again, and no batch whose rows were committed is, whatever the consumer group committed.
+
+use crate::seed;
+use crate::source::{DataFrameStream, Source};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use polars::prelude::*;
+use serde::Deserialize;
+use sqlx::postgres::{PgConnection, PgPool};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Consumer group of a Kafka source that names none.
+pub const DEFAULT_GROUP_ID: &str = "wine_quality_pipeline";
+
+/// Wait for more records after the first record of a batch, of a Kafka source that sets none.
+pub const DEFAULT_BATCH_TIMEOUT_MS: u64 = 1000;
+
+/// How the records of a Kafka topic are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordFormat {
+    /// One JSON object per record.
+    #[default]
+    Json,
+    /// One comma-separated line per record, without a header; the source's `columns` name the fields.
+    Csv,
+}
+
+/// Consumes a Kafka topic in micro-batches.
+#[derive(Debug, Clone)]
+pub struct KafkaSource {
+    pub brokers: String,
+    pub topic: String,
+    pub group_id: String,
+    pub format: RecordFormat,
+    /// Column names of CSV records.
+    pub columns: Vec<String>,
+    /// Maximum number of records per batch, at least 1.
+    pub batch_rows: usize,
+    /// Wait for more records after the first record of a batch.
+    pub batch_timeout: Duration,
+    /// The offsets the run resumes from and those of the batches handed out, shared by the clones of the source.
+    pub tracker: Arc<Mutex<OffsetTracker>>,
+}
+
+/// The offsets a run of a Kafka source resumes from, and those of the batches it consumed but did not store yet.
+#[derive(Debug, Default)]
+pub struct OffsetTracker {
+    /// Per partition, the offset after the last record earlier runs stored.
+    resumed: BTreeMap<i32, i64>,
+    /// Per batch, oldest first, the offset after its last record in each partition.
+    pending: VecDeque<BTreeMap<i32, i64>>,
+}
+
+/// The offsets a batch of a Kafka topic ends at, stored in the transaction of its rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchOffsets {
+    pub group_id: String,
+    pub topic: String,
+    /// Per partition, the offset after the last record of the batch.
+    pub next: BTreeMap<i32, i64>,
+}
+
+/// Table of the offsets each consumer group stored the partitions of a topic up to.
+const OFFSETS_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS kafka_offsets (
+    group_id TEXT NOT NULL,
+    topic TEXT NOT NULL,
+    partition INTEGER NOT NULL,
+    next_offset BIGINT NOT NULL,
+    stored_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (group_id, topic, partition)
+)";
+
+#[async_trait]
+impl Source for KafkaSource {
+    fn describe(&self) -> String {
+        format!("kafka://{}/{}", self.brokers, self.topic)
+    }
+
+    /// The checksum names, per partition, the offsets from where the run resumes to the end of the
+    /// topic, so a run over records no earlier run stored counts as a new load.
+    async fn checksum(&self) -> Result<String> {
+        Ok(format!("{}#{}", self.describe(), offset_ranges(self).await?))
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        consume(self).await
+    }
+
+    /// Loads the offsets earlier runs stored the partitions of the topic up to, for the group of the source.
+    async fn resume(&self, pool: &PgPool) -> Result<()> {
+        let resumed = stored_offsets(pool, &self.group_id, &self.topic).await?;
+        self.tracker.lock().expect("Kafka offset tracker poisoned").resumed = resumed;
+        Ok(())
+    }
+
+    /// Takes the offsets of the oldest batch consumed and not stored yet.
+    fn take_offsets(&self) -> Option<BatchOffsets> {
+        let next = self.tracker.lock().expect("Kafka offset tracker poisoned").pending.pop_front()?;
+        Some(BatchOffsets { group_id: self.group_id.clone(), topic: self.topic.clone(), next })
+    }
+}
+
+impl KafkaSource {
+    /// Parses the payloads of a batch of records into a DataFrame.
+    ///
+    /// # Arguments
+    ///
+    /// * `payloads` - The payloads of the records, in the order they were received.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DataFrame>` - The rows of the batch, or an error naming the first record that does not parse.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let df = source.records_frame(&[r#"{"alcohol": 9.4, "quality": 5}"#.to_string()])?;
+    /// ```
+    pub fn records_frame(&self, payloads: &[String]) -> Result<DataFrame> {
+        let mut buffer = String::new();
+        match self.format {
+            RecordFormat::Json => {
+                for (i, payload) in payloads.iter().enumerate() {
+                    // Re-serialized, so pretty-printed records become one line each
+                    let record: serde_json::Value = serde_json::from_str(payload).context(format!("Record {} of the batch is not valid JSON", i))?;
+                    if !record.is_object() {
+                        bail!("Record {} of the batch is not a JSON object", i);
+                    }
+                    buffer.push_str(&record.to_string());
+                    buffer.push('\n');
+                }
+                JsonLineReader::new(Cursor::new(buffer.into_bytes())).finish().context("Failed to parse the batch of JSON records")
+            }
+            RecordFormat::Csv => {
+                if self.columns.is_empty() {
+                    bail!("CSV records of Kafka topic {} need the source's `columns`", self.topic);
+                }
+                buffer.push_str(&self.columns.join(","));
+                for payload in payloads {
+                    buffer.push('\n');
+                    buffer.push_str(payload.trim_end_matches(['\r', '\n']));
+                }
+                CsvReadOptions::default()
+                    .with_has_header(true)
+                    .into_reader_with_file_handle(Cursor::new(buffer.into_bytes()))
+                    .finish()
+                    .context("Failed to parse the batch of CSV records")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+async fn consume(source: &KafkaSource) -> Result<DataFrameStream> {
+    use futures::stream::{self, StreamExt};
+    use rdkafka::config::ClientConfig;
+    use rdkafka::consumer::{Consumer, StreamConsumer};
+    use rdkafka::{Message, Offset, TopicPartitionList};
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &source.brokers)
+        .set("group.id", &source.group_id)
+        // The offsets are stored with the rows of their batch instead
+        .set("enable.auto.commit", "false")
+        .create()
+        .context(format!("Failed to create a Kafka consumer for {}", source.brokers))?;
+    let consumer = Arc::new(consumer);
+    let (lookup, topic) = (consumer.clone(), source.topic.clone());
+    let partitions = tokio::task::spawn_blocking(move || topic_partitions(lookup.as_ref(), &topic)).await.context("Kafka metadata lookup panicked")??;
+    let resumed = source.tracker.lock().expect("Kafka offset tracker poisoned").resumed.clone();
+    let mut assignment = TopicPartitionList::new();
+    for partition in &partitions {
+        // A partition no run stored records of starts from its oldest retained record
+        let offset = resumed.get(partition).map_or(Offset::Beginning, |next| Offset::Offset(*next));
+        assignment
+            .add_partition_offset(&source.topic, *partition, offset)
+            .context(format!("Invalid offset {:?} of partition {} of Kafka topic {}", offset, partition, source.topic))?;
+    }
+    consumer.assign(&assignment).context(format!("Failed to assign the partitions of Kafka topic {}", source.topic))?;
+    println!("Consuming {} partitions of Kafka topic {} as group {}", partitions.len(), source.topic, source.group_id);
+
+    let batch = Arc::new(source.clone());
+    let batches = stream::unfold(consumer, move |consumer| {
+        let batch = batch.clone();
+        async move {
+            let mut payloads = vec![];
+            let mut offsets = BTreeMap::new();
+            // The first record of a batch is awaited indefinitely, the rest until the batch times out
+            let mut deadline = None;
+            while payloads.len() < batch.batch_rows {
+                let received = match deadline {
+                    Some(deadline) => match tokio::time::timeout_at(deadline, consumer.recv()).await {
+                        Ok(received) => received,
+                        Err(_) => break,
+                    },
+                    None => consumer.recv().await,
+                };
+                let message = match received {
+                    Ok(message) => message,
+                    Err(e) => return Some((Err(anyhow::Error::new(e).context(format!("Failed to consume Kafka topic {}", batch.topic))), consumer)),
+                };
+                offsets.insert(message.partition(), message.offset() + 1);
+                let payload = match message.payload_view::<str>() {
+                    Some(Ok(payload)) => Ok(payload.to_string()),
+                    Some(Err(_)) => Err(anyhow::anyhow!("Record at offset {} of partition {} is not UTF-8", message.offset(), message.partition())),
+                    // Tombstones carry no row
+                    None => continue,
+                };
+                // The message borrows the consumer until it is dropped
+                drop(message);
+                match payload {
+                    Ok(payload) => payloads.push(payload),
+                    Err(e) => return Some((Err(e), consumer)),
+                }
+                deadline.get_or_insert_with(|| tokio::time::Instant::now() + batch.batch_timeout);
+            }
+            let frame = batch.records_frame(&payloads);
+            if frame.is_ok() {
+                batch.tracker.lock().expect("Kafka offset tracker poisoned").pending.push_back(offsets);
+            }
+            Some((frame, consumer))
+        }
+    });
+    Ok(batches.boxed())
+}
+
+#[cfg(not(feature = "kafka"))]
+async fn consume(_source: &KafkaSource) -> Result<DataFrameStream> {
+    bail!("Reading from Kafka requires building with the `kafka` feature")
+}
+
+/// Helper function to list the partitions of a Kafka topic, in order.
+#[cfg(feature = "kafka")]
+fn topic_partitions<C: rdkafka::consumer::Consumer>(consumer: &C, topic: &str) -> Result<Vec<i32>> {
+    let metadata = consumer.fetch_metadata(Some(topic), Duration::from_secs(10)).context(format!("Failed to look up Kafka topic {}", topic))?;
+    let described = metadata.topics().first().context(format!("Kafka topic {} does not exist", topic))?;
+    let mut partitions: Vec<i32> = described.partitions().iter().map(|partition| partition.id()).collect();
+    partitions.sort_unstable();
+    Ok(partitions)
+}
+
+/// Helper function to describe the offset range of each partition a run starts to consume, e.g. `0:120-340,1:98-311`.
+#[cfg(feature = "kafka")]
+async fn offset_ranges(source: &KafkaSource) -> Result<String> {
+    use rdkafka::config::ClientConfig;
+    use rdkafka::consumer::{BaseConsumer, Consumer};
+
+    let source = source.clone();
+    let resumed = source.tracker.lock().expect("Kafka offset tracker poisoned").resumed.clone();
+    tokio::task::spawn_blocking(move || -> Result<String> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &source.brokers)
+            .set("group.id", &source.group_id)
+            .set("enable.auto.commit", "false")
+            .create()
+            .context(format!("Failed to create a Kafka consumer for {}", source.brokers))?;
+        let mut ranges = vec![];
+        for partition in topic_partitions(&consumer, &source.topic)? {
+            let (low, high) = consumer
+                .fetch_watermarks(&source.topic, partition, Duration::from_secs(10))
+                .context(format!("Failed to look up the offsets of partition {} of Kafka topic {}", partition, source.topic))?;
+            // A partition no run stored records of starts from the oldest retained record
+            let start = resumed.get(&partition).copied().unwrap_or(low);
+            ranges.push(format!("{}:{}-{}", partition, start, high));
+        }
+        Ok(ranges.join(","))
+    })
+    .await
+    .context("Kafka offset lookup panicked")?
+}
+
+#[cfg(not(feature = "kafka"))]
+async fn offset_ranges(_source: &KafkaSource) -> Result<String> {
+    bail!("Reading from Kafka requires building with the `kafka` feature")
+}
+
+/// Reads the offsets a consumer group stored the partitions of a topic up to.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `group_id` - The consumer group of the source.
+/// * `topic` - The topic consumed.
+///
+/// # Returns
+///
+/// * `Result<BTreeMap<i32, i64>>` - Per partition, the offset after the last stored record; empty before the first stored batch.
+///
+/// # Example
+///
+/// ```
+/// let resumed = stored_offsets(&pool, DEFAULT_GROUP_ID, "wine-samples").await?;
+/// ```
+pub async fn stored_offsets(pool: &PgPool, group_id: &str, topic: &str) -> Result<BTreeMap<i32, i64>> {
+    // Looked up before the run is admitted, so a skipped run creates no table
+    if !seed::table_exists(pool, "kafka_offsets").await? {
+        return Ok(BTreeMap::new());
+    }
+    let rows: Vec<(i32, i64)> = sqlx::query_as("SELECT partition, next_offset FROM kafka_offsets WHERE group_id = $1 AND topic = $2")
+        .bind(group_id)
+        .bind(topic)
+        .fetch_all(pool)
+        .await
+        .context(format!("Failed to read the stored offsets of Kafka topic {}", topic))?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Records the offsets a batch ends at, inside the transaction storing its rows.
+///
+/// # Arguments
+///
+/// * `conn` - The connection of the open transaction.
+/// * `offsets` - The offsets of the batch.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of recording the offsets.
+///
+/// # Example
+///
+/// ```
+/// store_offsets(&mut tx, &offsets).await?;
+/// ```
+pub async fn store_offsets(conn: &mut PgConnection, offsets: &BatchOffsets) -> Result<()> {
+    sqlx::query(OFFSETS_TABLE_SQL).execute(&mut *conn).await.context("Failed to create the kafka_offsets table")?;
+    for (partition, next) in &offsets.next {
+        sqlx::query(
+            "INSERT INTO kafka_offsets (group_id, topic, partition, next_offset) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (group_id, topic, partition) DO UPDATE SET next_offset = EXCLUDED.next_offset, stored_at = now()",
+        )
+        .bind(&offsets.group_id)
+        .bind(&offsets.topic)
+        .bind(partition)
+        .bind(next)
+        .execute(&mut *conn)
+        .await
+        .context(format!("Failed to store the offset of partition {} of Kafka topic {}", partition, offsets.topic))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_frame_parses_json_and_csv() {
+        let mut source = KafkaSource {
+            brokers: "localhost:9092".to_string(),
+            topic: "wines".to_string(),
+            group_id: DEFAULT_GROUP_ID.to_string(),
+            format: RecordFormat::Json,
+            columns: vec![],
+            batch_rows: 100,
+            batch_timeout: Duration::from_millis(DEFAULT_BATCH_TIMEOUT_MS),
+            tracker: Default::default(),
+        };
+        let json = source.records_frame(&["{\"alcohol\": 9.4,\n \"quality\": 5}".to_string(), r#"{"alcohol": 9.8, "quality": 6}"#.to_string()]).unwrap();
+        assert_eq!(json.shape(), (2, 2));
+        assert!(source.records_frame(&["[1, 2]".to_string()]).is_err());
+
+        source.format = RecordFormat::Csv;
+        assert!(source.records_frame(&["9.4,5".to_string()]).is_err());
+        source.columns = vec!["alcohol".to_string(), "quality".to_string()];
+        let csv = source.records_frame(&["9.4,5\n".to_string(), "9.8,6".to_string()]).unwrap();
+        assert!(csv.equals(&json));
+        assert_eq!(source.describe(), "kafka://localhost:9092/wines");
+    }
+
+    #[tokio::test]
+    async fn test_offsets_are_stored_only_with_the_committed_transaction() -> Result<()> {
+        dotenv::dotenv().ok();
+        let pool = crate::storage::create_connection_pool().await?;
+        let group_id = format!("test-{}", ulid::Ulid::new());
+        let offsets = BatchOffsets { group_id: group_id.clone(), topic: "wines".to_string(), next: BTreeMap::from([(0, 120), (1, 98)]) };
+
+        let mut tx = pool.begin().await?;
+        store_offsets(&mut tx, &offsets).await?;
+        tx.rollback().await?;
+        let rolled_back = stored_offsets(&pool, &group_id, "wines").await?;
+
+        let mut tx = pool.begin().await?;
+        store_offsets(&mut tx, &offsets).await?;
+        store_offsets(&mut tx, &BatchOffsets { next: BTreeMap::from([(1, 311)]), ..offsets.clone() }).await?;
+        tx.commit().await?;
+        let committed = stored_offsets(&pool, &group_id, "wines").await?;
+        sqlx::query("DELETE FROM kafka_offsets WHERE group_id = $1").bind(&group_id).execute(&pool).await?;
+
+        assert!(rolled_back.is_empty());
+        assert_eq!(committed, BTreeMap::from([(0, 120), (1, 311)]));
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! This module appends the stored rows to an Apache Iceberg table, alongside PostgreSQL.
+//!
+//! With `[iceberg] enabled` in a binary built with the `iceberg` feature, the rows of each load that
+//! PostgreSQL accepted are written as a Parquet data file to the table's warehouse (S3 or MinIO) and
+//! committed as one append snapshot through the REST catalog. Declared columns are named as in
+//! PostgreSQL, and columns encrypted there are encrypted in Iceberg too. Missing namespaces and tables
+//! are created, the table from the columns of the first rows appended; later loads must fit its schema.
+
+use crate::config::IcebergConfig;
+use crate::encryption::ColumnCipher;
+use crate::run::RunContext;
+use crate::schema::TableSchema;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+
+/// Appends rows to the configured Iceberg table.
+///
+/// # Arguments
+///
+/// * `config` - The Iceberg settings.
+/// * `schema` - The declared schema, naming the table columns.
+/// * `cipher` - The cipher of the encrypted columns, if any.
+/// * `run` - The context of the current run.
+/// * `df` - The rows stored in PostgreSQL.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the build lacks the `iceberg` feature, the rows do not fit the table,
+///   or the data file cannot be written or committed.
+///
+/// # Example
+///
+/// ```
+/// lakehouse::append(&config.iceberg, &config.schema, cipher.as_ref(), &run, &accepted).await?;
+/// ```
+pub async fn append(config: &IcebergConfig, schema: &TableSchema, cipher: Option<&ColumnCipher>, run: &RunContext, df: &DataFrame) -> Result<()> {
+    if !config.enabled || df.height() == 0 {
+        return Ok(());
+    }
+    let df = lake_frame(df, schema, cipher)?;
+    let files = append_frame(config, run, &df).await?;
+    run.log(format_args!("Appended {} rows to Iceberg table {}.{} in {} data files", df.height(), config.namespace, config.table, files));
+    Ok(())
+}
+
+/// Names declared columns like their PostgreSQL columns and encrypts the encrypted ones, for lake outputs.
+///
+/// # Arguments
+///
+/// * `df` - The rows stored in PostgreSQL.
+/// * `schema` - The declared schema, naming the table columns.
+/// * `cipher` - The cipher of the encrypted columns, if any.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The renamed and encrypted rows, or an error if two columns end up with the same name.
+pub fn lake_frame(df: &DataFrame, schema: &TableSchema, cipher: Option<&ColumnCipher>) -> Result<DataFrame> {
+    let encrypted = cipher.map(|cipher| cipher.encrypt_frame(df)).transpose()?;
+    let df = encrypted.as_ref().unwrap_or(df);
+    let mut columns = Vec::with_capacity(df.width());
+    for series in df.get_columns() {
+        let name = series.name();
+        let column = schema.columns.iter().find(|c| c.name == name).map_or(name, |c| c.column.as_str());
+        let mut series = series.clone();
+        series.rename(column);
+        columns.push(series);
+    }
+    DataFrame::new(columns).context("Columns of the lake rows collide after renaming")
+}
+
+#[cfg(feature = "iceberg")]
+async fn append_frame(config: &IcebergConfig, run: &RunContext, df: &DataFrame) -> Result<usize> {
+    use iceberg::spec::DataFileFormat;
+    use iceberg::transaction::Transaction;
+    use iceberg::writer::base_writer::data_file_writer::{DataFileWriterBuilder, DataFileWriterConfig};
+    use iceberg::writer::file_writer::location_generator::{DefaultFileNameGenerator, DefaultLocationGenerator};
+    use iceberg::writer::file_writer::ParquetWriterBuilder;
+    use iceberg::writer::{IcebergWriter, IcebergWriterBuilder};
+    use iceberg::{Catalog, NamespaceIdent, TableCreation, TableIdent};
+    use iceberg_catalog_rest::RestCatalog;
+    use parquet::file::properties::WriterProperties;
+    use std::collections::HashMap;
+
+    let catalog = RestCatalog::new(catalog_config(config)?);
+    let namespace = NamespaceIdent::from_strs(config.namespace.split('.')).context(format!("Invalid Iceberg namespace {}", config.namespace))?;
+    let ident = TableIdent::new(namespace.clone(), config.table.clone());
+    if !catalog.namespace_exists(&namespace).await.context(format!("Failed to reach the Iceberg catalog at {}", config.catalog_uri))? {
+        catalog.create_namespace(&namespace, HashMap::new()).await.context(format!("Failed to create Iceberg namespace {}", config.namespace))?;
+    }
+    let table = if catalog.table_exists(&ident).await? {
+        catalog.load_table(&ident).await.context(format!("Failed to load Iceberg table {}.{}", config.namespace, config.table))?
+    } else {
+        let creation = TableCreation::builder().name(config.table.clone()).schema(iceberg_schema(df)?).build();
+        println!("Creating Iceberg table {}.{}", config.namespace, config.table);
+        catalog.create_table(&namespace, creation).await.context(format!("Failed to create Iceberg table {}.{}", config.namespace, config.table))?
+    };
+
+    let table_schema = table.metadata().current_schema().clone();
+    let batch = record_batch(df, &table_schema)?;
+    let writer = ParquetWriterBuilder::new(
+        WriterProperties::builder().build(),
+        table_schema,
+        table.file_io().clone(),
+        DefaultLocationGenerator::new(table.metadata().clone())?,
+        DefaultFileNameGenerator::new(run.id.clone(), None, DataFileFormat::Parquet),
+    );
+    let mut writer = DataFileWriterBuilder::new(writer).build(DataFileWriterConfig::new(None)).await?;
+    writer.write(batch).await.context("Failed to write the Iceberg data file")?;
+    let data_files = writer.close().await.context("Failed to write the Iceberg data file")?;
+    let written = data_files.len();
+
+    let transaction = Transaction::new(&table);
+    let mut append = transaction.fast_append(None, vec![])?;
+    append.add_data_files(data_files)?;
+    append
+        .apply()
+        .await?
+        .commit(&catalog)
+        .await
+        .context(format!("Failed to commit the append to Iceberg table {}.{}", config.namespace, config.table))?;
+    Ok(written)
+}
+
+#[cfg(not(feature = "iceberg"))]
+async fn append_frame(_config: &IcebergConfig, _run: &RunContext, _df: &DataFrame) -> Result<usize> {
+    bail!("Appending to Iceberg requires building with the `iceberg` feature")
+}
+
+/// Helper function to configure the REST catalog and the S3 access of the table's files.
+#[cfg(feature = "iceberg")]
+fn catalog_config(config: &IcebergConfig) -> Result<iceberg_catalog_rest::RestCatalogConfig> {
+    let mut props = std::collections::HashMap::from([("s3.region".to_string(), config.s3_region.clone())]);
+    if let Some(endpoint) = &config.s3_endpoint {
+        props.insert("s3.endpoint".to_string(), endpoint.clone());
+    }
+    if config.s3_path_style {
+        props.insert("s3.path-style-access".to_string(), "true".to_string());
+    }
+    for (variable, prop) in [("AWS_ACCESS_KEY_ID", "s3.access-key-id"), ("AWS_SECRET_ACCESS_KEY", "s3.secret-access-key")] {
+        if let Ok(value) = std::env::var(variable) {
+            props.insert(prop.to_string(), value);
+        }
+    }
+    if let Some(name) = &config.token_env {
+        let token = std::env::var(name).context(format!("The Iceberg catalog token expects the environment variable {}", name))?;
+        props.insert("token".to_string(), token);
+    }
+    Ok(iceberg_catalog_rest::RestCatalogConfig::builder()
+        .uri(config.catalog_uri.clone())
+        .warehouse(config.warehouse.clone())
+        .props(props)
+        .build())
+}
+
+/// Helper function to derive the schema of a new table from the columns of a DataFrame.
+#[cfg(feature = "iceberg")]
+fn iceberg_schema(df: &DataFrame) -> Result<iceberg::spec::Schema> {
+    use iceberg::spec::{NestedField, PrimitiveType, Schema, Type};
+
+    let fields = df
+        .get_columns()
+        .iter()
+        .zip(1..)
+        .map(|(series, id)| {
+            let primitive = match series.dtype() {
+                DataType::Boolean => PrimitiveType::Boolean,
+                DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::UInt8 | DataType::UInt16 => PrimitiveType::Int,
+                DataType::Int64 | DataType::UInt32 => PrimitiveType::Long,
+                DataType::Float32 => PrimitiveType::Float,
+                DataType::Float64 => PrimitiveType::Double,
+                DataType::Date => PrimitiveType::Date,
+                DataType::Datetime(_, None) => PrimitiveType::Timestamp,
+                DataType::Datetime(_, Some(_)) => PrimitiveType::Timestamptz,
+                _ => PrimitiveType::String,
+            };
+            NestedField::optional(id, series.name(), Type::Primitive(primitive)).into()
+        })
+        .collect();
+    Schema::builder().with_fields(fields).build().context("Failed to build the Iceberg schema")
+}
+
+/// Helper function to convert a DataFrame into an Arrow batch of the table's schema.
+#[cfg(feature = "iceberg")]
+fn record_batch(df: &DataFrame, schema: &iceberg::spec::Schema) -> Result<arrow_array::RecordBatch> {
+    use arrow_array::{new_null_array, ArrayRef, BooleanArray, Date32Array, Float64Array, Int64Array, StringArray, TimestampMicrosecondArray};
+    use std::sync::Arc;
+
+    let arrow_schema = iceberg::arrow::schema_to_arrow_schema(schema).context("Failed to convert the Iceberg schema to Arrow")?;
+    let unknown: Vec<&str> = df.get_column_names().into_iter().filter(|name| arrow_schema.field_with_name(name).is_err()).collect();
+    if !unknown.is_empty() {
+        bail!("The Iceberg table lacks the columns {}; add them to the table before loading", unknown.join(", "));
+    }
+
+    let mut arrays: Vec<ArrayRef> = vec![];
+    for field in arrow_schema.fields() {
+        let Ok(series) = df.column(field.name()) else {
+            arrays.push(new_null_array(field.data_type(), df.height()));
+            continue;
+        };
+        let array: ArrayRef = match series.dtype() {
+            DataType::Boolean => Arc::new(series.bool()?.into_iter().collect::<BooleanArray>()),
+            DataType::Float32 | DataType::Float64 => Arc::new(series.cast(&DataType::Float64)?.f64()?.into_iter().collect::<Float64Array>()),
+            dtype if dtype.is_integer() => Arc::new(series.cast(&DataType::Int64)?.i64()?.into_iter().collect::<Int64Array>()),
+            DataType::Date => {
+                let days = series.cast(&DataType::Int32)?;
+                Arc::new(days.i32()?.into_iter().collect::<Date32Array>())
+            }
+            DataType::Datetime(_, _) => {
+                let micros = series.cast(&DataType::Datetime(TimeUnit::Microseconds, None))?.cast(&DataType::Int64)?;
+                Arc::new(micros.i64()?.into_iter().collect::<TimestampMicrosecondArray>())
+            }
+            _ => {
+                let text = series.cast(&DataType::String)?;
+                Arc::new(text.str()?.into_iter().collect::<StringArray>())
+            }
+        };
+        let array = arrow_cast::cast(&array, field.data_type()).context(format!("Column {} does not fit its Iceberg type {}", field.name(), field.data_type()))?;
+        arrays.push(array);
+    }
+    arrow_array::RecordBatch::try_new(Arc::new(arrow_schema), arrays).context("Failed to build the Arrow batch of the Iceberg rows")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lake_frame_renames_and_encrypts() {
+        let df = polars::df!("fixed acidity" => &[7.4, 7.8], "taster" => &["ann", "bob"], "batch" => &[1i64, 2]).unwrap();
+        let cipher = ColumnCipher::new(&[7u8; 32], vec!["taster".to_string()]).unwrap();
+
+        let lake = lake_frame(&df, &TableSchema::default(), Some(&cipher)).unwrap();
+        assert_eq!(lake.get_column_names(), vec!["fixed_acidity", "taster", "batch"]);
+        let stored = lake.column("taster").unwrap().str().unwrap().get(0).unwrap().to_string();
+        assert_eq!(cipher.decrypt("taster", &stored).unwrap(), "ann");
+        assert!(lake.column("batch").unwrap().equals(df.column("batch").unwrap()));
+    }
+}
@@ -0,0 +1,184 @@
+//! This module loads the files that land in a watched directory, in daemon mode.
+//!
+//! With `[daemon.landing] dir` set, the daemon watches the directory for files whose names match
+//! `pattern` and requests a run for each, which reads the file as the configured source reads its own
+//! path. A file is requested once no change to it was seen for `settle_secs`, so files still being
+//! copied in are not read half-written; files already in the directory when the daemon starts are
+//! requested too. After its run, a file is moved to `archive_dir`, or to `failed_dir` if the run
+//! failed, so every file is loaded once and a failed one can be inspected and dropped back in. A file
+//! whose run finds the queue full stays in the directory and is requested again when the daemon restarts.
+
+use crate::config::LandingConfig;
+use crate::daemon::{RunRequest, Trigger, PIPELINE_NAME};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How often files are checked for having settled.
+const SETTLE_CHECK: Duration = Duration::from_millis(500);
+
+/// Watches the landing directory and requests a run for every file that settles in it, until the daemon stops.
+///
+/// # Arguments
+///
+/// * `config` - The landing settings; `dir` must be set.
+/// * `tx` - The daemon's queue of run requests.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the directory cannot be created or watched, or the pattern is invalid.
+///
+/// # Example
+///
+/// ```
+/// tokio::spawn(landing::watch(config.daemon.landing.clone(), tx.clone()));
+/// ```
+pub async fn watch(config: LandingConfig, tx: mpsc::UnboundedSender<RunRequest>) -> Result<()> {
+    let dir = PathBuf::from(config.dir.as_deref().context("No landing directory is configured")?);
+    let pattern = glob::Pattern::new(&config.pattern).context(format!("Invalid landing file pattern {}", config.pattern))?;
+    std::fs::create_dir_all(&dir).context(format!("Failed to create landing directory {}", dir.display()))?;
+
+    let (events_tx, mut events) = mpsc::unbounded_channel::<PathBuf>();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+        Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+            for path in event.paths {
+                // Sending only fails once the daemon stops
+                let _ = events_tx.send(path);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to watch the landing directory: {}", e),
+    })
+    .context("Failed to create the landing directory watcher")?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .context(format!("Failed to watch landing directory {}", dir.display()))?;
+    println!("Watching landing directory {} for files matching {}", dir.display(), config.pattern);
+
+    // The last change seen of each file not requested yet, and the files requested but not moved yet
+    let mut changes: HashMap<PathBuf, Instant> = HashMap::new();
+    let mut requested: HashSet<PathBuf> = HashSet::new();
+    for entry in std::fs::read_dir(&dir).context(format!("Failed to list landing directory {}", dir.display()))? {
+        let path = entry?.path();
+        if is_landed(&path, &pattern) {
+            changes.insert(path, Instant::now());
+        }
+    }
+
+    let settle = Duration::from_secs(config.settle_secs);
+    let mut interval = tokio::time::interval(SETTLE_CHECK);
+    loop {
+        tokio::select! {
+            path = events.recv() => {
+                let Some(path) = path else { return Ok(()) };
+                if is_landed(&path, &pattern) && !requested.contains(&path) {
+                    changes.insert(path, Instant::now());
+                }
+            }
+            _ = interval.tick() => {
+                requested.retain(|path| path.exists());
+                let settled: Vec<PathBuf> = changes.iter().filter(|(_, changed)| changed.elapsed() >= settle).map(|(path, _)| path.clone()).collect();
+                for path in settled {
+                    changes.remove(&path);
+                    // Files moved away again before they settled are not loaded
+                    if !path.is_file() {
+                        continue;
+                    }
+                    println!("File {} landed", path.display());
+                    let input = path.to_string_lossy().into_owned();
+                    requested.insert(path);
+                    if tx.send(RunRequest { pipeline: PIPELINE_NAME.to_string(), trigger: Trigger::Landing, input: Some(input) }).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Moves a loaded file out of the landing directory, to the archive or, after a failed run, the failed directory.
+///
+/// A file of the same name already there is kept; the moved file's name is then prefixed with the time.
+///
+/// # Arguments
+///
+/// * `config` - The landing settings.
+/// * `path` - The loaded file.
+/// * `succeeded` - Whether the run loading it succeeded.
+///
+/// # Returns
+///
+/// * `Result<PathBuf>` - The new path of the file, or an error if it cannot be moved.
+///
+/// # Example
+///
+/// ```
+/// let archived = landing::finish(&config.daemon.landing, Path::new("landing/2024-05-01.csv"), true)?;
+/// ```
+pub fn finish(config: &LandingConfig, path: &Path, succeeded: bool) -> Result<PathBuf> {
+    let dir = Path::new(if succeeded { &config.archive_dir } else { &config.failed_dir });
+    std::fs::create_dir_all(dir).context(format!("Failed to create directory {}", dir.display()))?;
+    let name = path.file_name().context(format!("{} names no file", path.display()))?;
+    let mut target = dir.join(name);
+    if target.exists() {
+        target = dir.join(format!("{}_{}", Utc::now().format("%Y%m%dT%H%M%S%.3f"), name.to_string_lossy()));
+    }
+    // Renaming fails across file systems, where the file is copied instead
+    if std::fs::rename(path, &target).is_err() {
+        std::fs::copy(path, &target).context(format!("Failed to move {} to {}", path.display(), target.display()))?;
+        std::fs::remove_file(path).context(format!("Failed to remove {} after copying it", path.display()))?;
+    }
+    Ok(target)
+}
+
+/// Helper function to tell whether a path is a file to load, by its name.
+fn is_landed(path: &Path, pattern: &glob::Pattern) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else { return false };
+    // Dot files are the partial uploads and editor files of many tools
+    !name.starts_with('.') && pattern.matches(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_landed_matches_file_names() {
+        let pattern = glob::Pattern::new("*.csv").unwrap();
+        assert!(is_landed(Path::new("landing/2024-05-01.csv"), &pattern));
+        assert!(!is_landed(Path::new("landing/2024-05-01.csv.part"), &pattern));
+        assert!(!is_landed(Path::new("landing/.2024-05-01.csv"), &pattern));
+    }
+
+    #[test]
+    fn test_finish_moves_files_without_overwriting() {
+        let root = "temp_landing_test";
+        std::fs::create_dir_all(root).unwrap();
+        let config = LandingConfig {
+            dir: Some(root.to_string()),
+            archive_dir: format!("{}/archive", root),
+            failed_dir: format!("{}/failed", root),
+            ..LandingConfig::default()
+        };
+        let path = Path::new(root).join("wines.csv");
+
+        std::fs::write(&path, "alcohol\n9.4\n").unwrap();
+        let archived = finish(&config, &path, true).unwrap();
+        std::fs::write(&path, "alcohol\n9.8\n").unwrap();
+        let again = finish(&config, &path, true).unwrap();
+        std::fs::write(&path, "alcohol\nn/a\n").unwrap();
+        let failed = finish(&config, &path, false).unwrap();
+        let contents = (std::fs::read_to_string(&archived).unwrap(), std::fs::read_to_string(&again).unwrap());
+        std::fs::remove_dir_all(root).ok();
+
+        assert_eq!(archived, Path::new(root).join("archive/wines.csv"));
+        assert_ne!(again, archived);
+        assert_eq!(contents, ("alcohol\n9.4\n".to_string(), "alcohol\n9.8\n".to_string()));
+        assert_eq!(failed, Path::new(root).join("failed/wines.csv"));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,89 @@
+//! This is the library root of the data pipeline, for embedding it in other services.
+//!
+//! The `pipeline` binary is a thin wrapper around it that owns its Tokio runtime. Nothing here starts a
+//! runtime of its own, so a service that already runs one, multi-threaded or current-thread, can drive
+//! the pipeline directly: [`pipeline::run_with`] takes the configuration, the hooks, and a
+//! [`storage::PoolProvider`] that hands over the service's own connection pool.
+//!
+//! # Example
+//!
+//! ```
+//! let config = wine_quality_pipeline::config::load_config("pipeline.toml")?;
+//! let pools = PoolProvider::Injected(pool.clone());
+//! pipeline::run_with(&config, &Hooks::from_config(&config), &pools).await?;
+//! ```
+
+pub mod aggregates;
+pub mod alerts;
+pub mod analysis;
+pub mod api;
+pub mod artifacts;
+pub mod audit;
+pub mod bookmarks;
+pub mod bugreport;
+pub mod catalog;
+pub mod chaos;
+pub mod cli;
+pub mod clustering;
+pub mod codec;
+pub mod column_stats;
+pub mod compare;
+pub mod config;
+pub mod daemon;
+pub mod dataset;
+pub mod doctor;
+pub mod downcast;
+pub mod embedded_db;
+pub mod encryption;
+pub mod evolution;
+pub mod expectations;
+pub mod export;
+pub mod fingerprint;
+pub mod generate;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod ingestion;
+pub mod kafka;
+pub mod lakehouse;
+pub mod landing;
+pub mod live;
+pub mod locale;
+pub mod manifest;
+pub mod mapping;
+pub mod model;
+pub mod overflow;
+pub mod pca;
+pub mod pipeline;
+pub mod processed_files;
+pub mod profile;
+pub mod records;
+pub mod reference;
+pub mod replay;
+pub mod resources;
+pub mod retention;
+pub mod retry;
+pub mod rounding;
+pub mod run;
+pub mod schema;
+pub mod seed;
+pub mod selftest;
+pub mod source;
+pub mod spill;
+pub mod staging;
+pub mod status;
+pub mod storage;
+pub mod streaming;
+pub mod supervisor;
+pub mod tenant;
+pub mod timetravel;
+pub mod transformation;
+pub mod tune;
+pub mod typemap;
+pub mod ui;
+pub mod validation;
+pub mod visualization;
+pub mod webhooks;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,178 @@
+//! This module streams the progress of running pipelines to live dashboards.
+//!
+//! In daemon mode, a [`LiveFeed`] is registered as a callback on every hook point, so each hook event
+//! of a run (ingestion, transformation and storage of every chunk, quality violations, success or
+//! failure) is broadcast as it fires, together with the rows the run has stored and rejected so far.
+//! The control API forwards the feed to WebSocket clients of `GET /runs/live` as JSON text messages.
+//! Subscribers that fall behind skip the events they missed instead of holding runs back.
+
+use crate::hooks::{HookEvent, HookPoint, Hooks};
+use axum::extract::ws::{Message, WebSocket};
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Number of events buffered for a subscriber before it starts missing events.
+const FEED_CAPACITY: usize = 1024;
+
+/// One progress update of a run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LiveEvent {
+    /// The hook point, e.g. `after_store`.
+    pub event: &'static str,
+    pub run_id: String,
+    /// Rows of the current DataFrame or chunk, where one exists.
+    pub rows: Option<usize>,
+    /// Rows of the current DataFrame or chunk sent to the rejects table, for `after_store`.
+    pub rejected: Option<usize>,
+    /// Chunks (or, outside chunked mode, DataFrames) the run has stored so far.
+    pub chunks_stored: usize,
+    pub rows_stored: usize,
+    pub rows_rejected: usize,
+    /// The error message or failed expectations, where the event has one.
+    pub error: Option<String>,
+    pub sent_at: String,
+}
+
+/// Running totals of a run, kept until it finishes.
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    chunks: usize,
+    stored: usize,
+    rejected: usize,
+}
+
+/// Broadcasts the hook events of all runs to any number of subscribers.
+pub struct LiveFeed {
+    tx: broadcast::Sender<LiveEvent>,
+    totals: Mutex<HashMap<String, Totals>>,
+}
+
+impl LiveFeed {
+    /// Creates a feed without subscribers.
+    pub fn new() -> Self {
+        Self {
+            tx: broadcast::channel(FEED_CAPACITY).0,
+            totals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers the feed on every hook point of `hooks`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let live = Arc::new(LiveFeed::new());
+    /// live.register(&mut hooks);
+    /// ```
+    pub fn register(self: &Arc<Self>, hooks: &mut Hooks) {
+        for point in HookPoint::ALL {
+            let feed = self.clone();
+            hooks.register(point, move |event| {
+                feed.publish(&event);
+                async { Ok(()) }
+            });
+        }
+    }
+
+    /// Subscribes to the events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Updates the totals of the event's run and broadcasts the event.
+    fn publish(&self, event: &HookEvent) {
+        let totals = {
+            let mut runs = self.totals.lock().expect("live totals poisoned");
+            let totals = runs.entry(event.run_id.clone()).or_default();
+            if event.point == HookPoint::AfterStore {
+                totals.chunks += 1;
+                totals.stored += event.rows.unwrap_or(0);
+                totals.rejected += event.rejected.unwrap_or(0);
+            }
+            let current = *totals;
+            if matches!(event.point, HookPoint::OnSuccess | HookPoint::OnFailure) {
+                runs.remove(&event.run_id);
+            }
+            current
+        };
+        // Sending only fails when nobody is subscribed
+        let _ = self.tx.send(LiveEvent {
+            event: event.point.as_str(),
+            run_id: event.run_id.clone(),
+            rows: event.rows,
+            rejected: event.rejected,
+            chunks_stored: totals.chunks,
+            rows_stored: totals.stored,
+            rows_rejected: totals.rejected,
+            error: event.error.clone(),
+            sent_at: Utc::now().to_rfc3339(),
+        });
+    }
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends the events of a subscription to a WebSocket client until either side closes.
+///
+/// # Arguments
+///
+/// * `socket` - The upgraded WebSocket connection.
+/// * `events` - A subscription to the feed.
+pub async fn forward(mut socket: WebSocket, mut events: broadcast::Receiver<LiveEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        eprintln!("Live dashboard client fell behind and missed {} events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let Ok(json) = serde_json::to_string(&event) else { continue };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            // Clients only send close frames and pings, which axum answers itself
+            message = socket.recv() => {
+                if !matches!(message, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_feed_broadcasts_hook_events_with_totals() {
+        let live = Arc::new(LiveFeed::new());
+        let mut hooks = Hooks::default();
+        live.register(&mut hooks);
+        let mut events = live.subscribe();
+
+        for (rows, rejected) in [(1000, 2), (400, 0)] {
+            hooks.fire(HookEvent::new(HookPoint::AfterIngest, "run-1").with_rows(rows + rejected)).await.unwrap();
+            hooks.fire(HookEvent::new(HookPoint::AfterStore, "run-1").with_rows(rows).with_rejected(rejected)).await.unwrap();
+        }
+        hooks.fire(HookEvent::new(HookPoint::OnSuccess, "run-1").with_rows(1400)).await.unwrap();
+
+        let received: Vec<LiveEvent> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert_eq!(received.iter().map(|e| e.event).collect::<Vec<_>>(), vec!["after_ingest", "after_store", "after_ingest", "after_store", "on_success"]);
+        let done = received.last().unwrap();
+        assert_eq!((done.chunks_stored, done.rows_stored, done.rows_rejected), (2, 1400, 2));
+        assert!(live.totals.lock().unwrap().is_empty());
+    }
+}
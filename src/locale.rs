@@ -0,0 +1,142 @@
+//! This module formats the numbers and units of the reports read by people outside the pipeline team.
+//!
+//! Staging verification reports, `compare-tables` diffs, and the performance summary of each run write
+//! their numbers with the decimal and thousands separators of `[reports] locale`, e.g. `1.599` rows and
+//! a mean of `10,4200` with `de`, and label the columns listed in `[reports.units]` with their unit,
+//! e.g. `chlorides (g/dm³)`. Without a locale, numbers are written plainly, with a decimal point and no
+//! thousands separator, as scripts parsing the reports expect.
+
+use crate::config::ReportsConfig;
+use std::collections::BTreeMap;
+
+/// The separators numbers are written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    /// Separator of the groups of three digits of the integer part, if they are separated.
+    pub group: Option<char>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self { decimal: '.', group: None }
+    }
+}
+
+impl NumberFormat {
+    /// Returns the separators of a locale, e.g. `de`, `fr-FR`, or `pt_BR`, or `None` for an unknown language.
+    pub fn for_locale(locale: &str) -> Option<Self> {
+        let locale = locale.trim().replace('_', "-").to_ascii_lowercase();
+        // Swiss German writes a decimal point and apostrophes between digit groups
+        if locale == "de-ch" {
+            return Some(Self { decimal: '.', group: Some('\'') });
+        }
+        let (decimal, group) = match locale.split('-').next().unwrap_or_default() {
+            "en" | "ja" | "zh" | "ko" | "he" | "th" => ('.', ','),
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "hr" | "sl" | "sr" => (',', '.'),
+            // A no-break space, so a number is never split across lines
+            "fr" | "sv" | "nb" | "no" | "fi" | "pl" | "cs" | "sk" | "ru" | "uk" | "hu" | "bg" | "lt" | "lv" | "et" => (',', '\u{a0}'),
+            _ => return None,
+        };
+        Some(Self { decimal, group: Some(group) })
+    }
+
+    /// Writes a number with a given number of decimal places.
+    pub fn decimal(&self, value: f64, decimals: usize) -> String {
+        if !value.is_finite() {
+            return value.to_string();
+        }
+        let plain = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = plain.split_once('.').unwrap_or((&plain, ""));
+        let mut formatted = String::new();
+        if value < 0.0 {
+            formatted.push('-');
+        }
+        for (i, digit) in integer.chars().enumerate() {
+            if let Some(group) = self.group.filter(|_| i > 0 && (integer.len() - i) % 3 == 0) {
+                formatted.push(group);
+            }
+            formatted.push(digit);
+        }
+        if !fraction.is_empty() {
+            formatted.push(self.decimal);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+
+    /// Writes a number like [`NumberFormat::decimal`], with a `+` before positive numbers and zero, for deltas.
+    pub fn signed(&self, value: f64, decimals: usize) -> String {
+        let formatted = self.decimal(value, decimals);
+        if formatted.starts_with('-') { formatted } else { format!("+{}", formatted) }
+    }
+}
+
+/// How the numbers and units of reports are written.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReportFormat {
+    pub numbers: NumberFormat,
+    /// The unit of each table column, e.g. `g/dm³` for `chlorides`.
+    pub units: BTreeMap<String, String>,
+}
+
+impl ReportFormat {
+    /// Creates the format of the `[reports]` section; an unknown locale, rejected by validation, writes numbers plainly.
+    pub fn from_config(config: &ReportsConfig) -> Self {
+        Self {
+            numbers: config.locale.as_deref().and_then(NumberFormat::for_locale).unwrap_or_default(),
+            units: config.units.clone(),
+        }
+    }
+
+    /// Writes a number with a given number of decimal places.
+    pub fn decimal(&self, value: f64, decimals: usize) -> String {
+        self.numbers.decimal(value, decimals)
+    }
+
+    /// Writes a number that may be missing, as `-` if it is.
+    pub fn optional(&self, value: Option<f64>, decimals: usize) -> String {
+        value.map_or_else(|| "-".to_string(), |value| self.decimal(value, decimals))
+    }
+
+    /// Writes a count, such as a number of rows.
+    pub fn count(&self, value: i64) -> String {
+        self.decimal(value as f64, 0)
+    }
+
+    /// Writes a delta, with its sign, or `-` if it is missing.
+    pub fn signed(&self, value: Option<f64>, decimals: usize) -> String {
+        value.map_or_else(|| "-".to_string(), |value| self.numbers.signed(value, decimals))
+    }
+
+    /// Labels a table column with its unit, e.g. `chlorides (g/dm³)`, if it has one.
+    pub fn column(&self, column: &str) -> String {
+        match self.units.get(column) {
+            Some(unit) => format!("{} ({})", column, unit),
+            None => column.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers_follow_the_locale() {
+        let plain = NumberFormat::default();
+        assert_eq!(plain.decimal(1234567.891, 2), "1234567.89");
+        assert_eq!(NumberFormat::for_locale("en-US").unwrap().decimal(1234567.891, 2), "1,234,567.89");
+        assert_eq!(NumberFormat::for_locale("de").unwrap().decimal(-1599.0, 0), "-1.599");
+        assert_eq!(NumberFormat::for_locale("fr_FR").unwrap().decimal(10.42, 4), "10,4200");
+        assert_eq!(NumberFormat::for_locale("fr").unwrap().decimal(25000.5, 1), "25\u{a0}000,5");
+        assert_eq!(NumberFormat::for_locale("de-CH").unwrap().signed(1000.0, 1), "+1'000.0");
+        assert!(NumberFormat::for_locale("klingon").is_none());
+
+        let format = ReportFormat { numbers: NumberFormat::for_locale("it").unwrap(), units: BTreeMap::from([("chlorides".to_string(), "g/dm³".to_string())]) };
+        assert_eq!(format.column("chlorides"), "chlorides (g/dm³)");
+        assert_eq!(format.column("quality"), "quality");
+        assert_eq!(format.optional(None, 4), "-");
+        assert_eq!(format.signed(Some(-0.08), 4), "-0,0800");
+    }
+}
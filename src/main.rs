@@ -2,17 +2,17 @@
 //!
 //! It coordinates the ingestion, transformation, and storage of data.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::Parser;
 use dotenv::dotenv;
-
-
-mod ingestion;
-mod transformation;
-mod storage;
-mod seed;
+use wine_quality_pipeline::ingestion::{self, IngestOptions};
+use wine_quality_pipeline::source::SourceConfig;
+use wine_quality_pipeline::{bugreport, chaos, cli, compare, config, daemon, doctor, embedded_db, export, generate, hooks, manifest, pipeline, profile, replay, seed, selftest, staging, status, tenant, tune};
 
 /// The main entry point for the data pipeline application.
 ///
+/// Runs the full pipeline unless a subcommand is given.
+///
 /// # Returns
 ///
 /// * `Result<()>` - A result indicating success or failure of the data pipeline execution.
@@ -26,32 +26,75 @@ mod seed;
 async fn main() -> Result<()> {
     // Load environment variables from .env file
     dotenv().ok();
+    let cli = cli::Cli::parse();
+    // `kill -USR1` dumps the runs in progress to the log
+    status::listen();
+    if let Some(tenant) = &cli.tenant {
+        tenant::select(tenant)?;
+    }
+    // Reports an invalid configuration among its findings, so it runs before the configuration is loaded
+    if let Some(cli::Command::Doctor) = cli.command {
+        return doctor::run_doctor(&cli.config).await.map(|_| ());
+    }
 
-    // Uncomment to run database setup (run once, then comment out)
-    seed::run_db_setup().await?;
-
-    println!("Starting data pipeline...");
-
-    // Ingest data
-    let df = ingestion::retry_ingest("data/dataset.csv", 3)?;
-    println!("Data ingestion complete. DataFrame shape: {:?}", df.shape());
-    println!("DataFrame: {:?}", df);
-
-    // Transform data
-    let transformed_df = transformation::transform_data(df)?;
-    println!("Data transformation complete. Transformed DataFrame shape: {:?}", transformed_df.shape());
-    println!("DataFrame dtypes: {:?}", transformed_df.dtypes());
-
-    // Store data
-    let pool = storage::create_connection_pool().await?;
-    storage::store_data(&pool, &transformed_df).await?;
-    println!("Data storage complete.");
+    // Load pipeline configuration
+    let mut config = config::load_config(&cli.config)?;
+    config.labels.extend(cli.labels);
+    if let Some(retries) = cli.auto_retry {
+        config.retry.max_retries = retries;
+    }
+    if let Some(limit) = cli.limit {
+        config.row_limit = Some(limit);
+    }
+    match cli.sample {
+        Some(cli::SampleSize::Fraction(fraction)) => (config.sampling.fraction, config.sampling.rows) = (Some(fraction), None),
+        Some(cli::SampleSize::Rows(rows)) => (config.sampling.fraction, config.sampling.rows) = (None, Some(rows)),
+        None => {}
+    }
+    if let Some(seed) = cli.sample_seed {
+        config.sampling.seed = seed;
+    }
+    if cli.stdin {
+        // The delimiter and quoting of a configured CSV source apply to standard input too
+        let options = match config.source {
+            SourceConfig::Csv { options, .. } => options,
+            _ => IngestOptions::default(),
+        };
+        config.source = SourceConfig::Csv { path: ingestion::STDIN_PATH.to_string(), options };
+    }
+    if let Some(fraction) = cli.validate_sample {
+        config.expectations.sample_fraction = Some(fraction);
+    }
 
-    // Retrieve and print first 5 rows
-    storage::get_first_5_rows(&pool).await?;
-    println!("Data retrieved and printed successfully.");
+    chaos::install(&config.chaos)?;
 
-    println!("Data pipeline finished successfully.");
+    // Kept alive until the command finishes
+    let _embedded_db = if cli.embedded_db { Some(embedded_db::start().await?) } else { None };
 
-    Ok(())
+    match cli.command {
+        Some(cli::Command::Profile { file }) => profile::run_profile(&file),
+        Some(cli::Command::SuggestRules(request)) => profile::run_suggest_rules(&request).map(|_| ()),
+        Some(cli::Command::ReplayDlq { run }) => replay::replay_dlq(&config, &run).await.map(|_| ()),
+        Some(cli::Command::Selftest) => selftest::run_selftest().await,
+        Some(cli::Command::Tune { rows, write }) => tune::run_tune(&config, &cli.config, rows, write).await.map(|_| ()),
+        Some(cli::Command::Export(request)) => export::run_export(&request, &config.schema, &config.downcast).await.map(|_| ()),
+        Some(cli::Command::Promote { run, discard }) => staging::run_promote(&config, &run, discard).await.map(|_| ()),
+        Some(cli::Command::CompareTables(request)) => compare::run_compare(&request, &config).await.map(|_| ()),
+        Some(cli::Command::Generate(request)) => generate::run_generate(&request, &config.schema).map(|_| ()),
+        Some(cli::Command::BugReport(request)) => bugreport::run_bug_report(&request, &config, &cli.config).await.map(|_| ()),
+        Some(cli::Command::Doctor) => unreachable!("Handled before the configuration is loaded"),
+        Some(cli::Command::Daemon) => daemon::run_daemon(config, &cli.config).await,
+        Some(cli::Command::ResetDb { yes }) => {
+            if !yes {
+                bail!("reset-db deletes every row of {}; run it again with --yes to confirm", config.storage.table);
+            }
+            seed::run_db_setup(&config.schema, &config.storage.table).await?;
+            println!("Table {} was dropped and created again", config.storage.table);
+            Ok(())
+        }
+        None => match &cli.manifest {
+            Some(path) => manifest::run_manifest(&manifest::load_manifest(path)?, &config).await,
+            None => pipeline::run(&config, &hooks::Hooks::from_config(&config)).await,
+        },
+    }
 }
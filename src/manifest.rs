@@ -0,0 +1,267 @@
+//! This module loads several datasets in one invocation, as listed in a manifest file.
+//!
+//! A manifest, written in YAML or JSON, lists the datasets with the file each is read from, its format,
+//! and the table it is stored in:
+//!
+//! ```yaml
+//! datasets:
+//!   - name: red
+//!     path: data/winequality-red.csv
+//!     table: wine_quality_red
+//!   - name: white
+//!     path: data/winequality-white.jsonl
+//!     format: ndjson
+//!     table: wine_quality_white
+//! ```
+//!
+//! `pipeline --manifest datasets.yaml` runs the pipeline once per dataset, in order, with the loaded
+//! configuration reading the dataset's file and storing its rows in `storage.table`. The format
+//! defaults to the one the file extension says; a dataset of the configured source's format keeps its
+//! `[source.options]`, such as the CSV delimiter. Each dataset is a run of its own, with its own run ID,
+//! history, and hooks, labelled `dataset=<name>` and registered in the catalog under its name. A failed
+//! dataset does not stop the ones after it; the invocation fails at the end, naming those that failed.
+
+use crate::config::PipelineConfig;
+use crate::hooks::Hooks;
+use crate::ingestion::{ExcelSheet, IngestOptions};
+use crate::pipeline;
+use crate::source::SourceConfig;
+use crate::validation;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// The datasets of a manifest, loaded in order.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub datasets: Vec<DatasetEntry>,
+}
+
+/// A dataset of a manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatasetEntry {
+    /// Name of the dataset in logs, labels, and the catalog.
+    pub name: String,
+    /// The input file, or a glob pattern matching several.
+    pub path: String,
+    /// Format of the file; defaults to the one its extension says.
+    #[serde(default)]
+    pub format: Option<DatasetFormat>,
+    /// Table the rows are stored in, created by the dataset's first run.
+    pub table: String,
+}
+
+/// The file formats a dataset can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetFormat {
+    Csv,
+    Json,
+    Ndjson,
+    Excel,
+    Avro,
+}
+
+impl DatasetFormat {
+    /// Returns the format a file extension says, looking past a `.gz` or `.zst` compression suffix.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let name = Path::new(path).file_name()?.to_str()?.to_ascii_lowercase();
+        let name = [".gz", ".gzip", ".zst", ".zstd"].iter().fold(name.as_str(), |name, suffix| name.strip_suffix(suffix).unwrap_or(name));
+        match Path::new(name).extension()?.to_str()? {
+            "csv" | "tsv" => Some(DatasetFormat::Csv),
+            "json" => Some(DatasetFormat::Json),
+            "ndjson" | "jsonl" => Some(DatasetFormat::Ndjson),
+            "xlsx" | "xls" | "ods" => Some(DatasetFormat::Excel),
+            "avro" => Some(DatasetFormat::Avro),
+            _ => None,
+        }
+    }
+
+    /// Returns the format of a source, for sources reading a local file.
+    fn of(source: &SourceConfig) -> Option<Self> {
+        match source {
+            SourceConfig::Csv { .. } => Some(DatasetFormat::Csv),
+            SourceConfig::Json { .. } => Some(DatasetFormat::Json),
+            SourceConfig::Ndjson { .. } => Some(DatasetFormat::Ndjson),
+            SourceConfig::Excel { .. } => Some(DatasetFormat::Excel),
+            SourceConfig::Avro { .. } => Some(DatasetFormat::Avro),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a manifest, in YAML for `.yaml` and `.yml` files and in JSON for `.json` files.
+///
+/// # Arguments
+///
+/// * `path` - Path to the manifest file.
+///
+/// # Returns
+///
+/// * `Result<Manifest>` - The manifest, or an error if it cannot be read or parsed, or lists no datasets or a name or table twice.
+///
+/// # Example
+///
+/// ```
+/// let manifest = load_manifest("datasets.yaml").expect("Failed to load the manifest");
+/// ```
+pub fn load_manifest(path: &str) -> Result<Manifest> {
+    let contents = std::fs::read_to_string(path).context(format!("Failed to read manifest {}", path))?;
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+    let manifest: Manifest = match extension.as_deref() {
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents).context(format!("Failed to parse manifest {}", path))?,
+        Some("json") => serde_json::from_str(&contents).context(format!("Failed to parse manifest {}", path))?,
+        _ => bail!("Manifest {} must be a .yaml, .yml, or .json file", path),
+    };
+
+    let issues = check(&manifest);
+    if !issues.is_empty() {
+        bail!("Invalid manifest {}:\n{}", path, issues.iter().map(|issue| format!("  {}", issue)).collect::<Vec<_>>().join("\n"));
+    }
+    println!("Loaded manifest {} with {} datasets", path, manifest.datasets.len());
+    Ok(manifest)
+}
+
+/// Derives the configuration of a dataset's run from the loaded configuration.
+///
+/// # Arguments
+///
+/// * `config` - The loaded pipeline configuration.
+/// * `entry` - The dataset.
+///
+/// # Returns
+///
+/// * `Result<PipelineConfig>` - The configuration reading the dataset's file into its table, or an error if the format is unknown or the configuration is invalid.
+///
+/// # Example
+///
+/// ```
+/// let red = dataset_config(&config, &manifest.datasets[0])?;
+/// ```
+pub fn dataset_config(config: &PipelineConfig, entry: &DatasetEntry) -> Result<PipelineConfig> {
+    let format = entry
+        .format
+        .or_else(|| DatasetFormat::from_path(&entry.path))
+        .context(format!("Cannot tell the format of {} from its extension; set the format of dataset {}", entry.path, entry.name))?;
+
+    let mut dataset = config.clone();
+    dataset.source = match config.source.with_path(&entry.path).filter(|_| DatasetFormat::of(&config.source) == Some(format)) {
+        Some(source) => source,
+        None => {
+            let path = entry.path.clone();
+            match format {
+                DatasetFormat::Csv => SourceConfig::Csv { path, options: IngestOptions::default() },
+                DatasetFormat::Json => SourceConfig::Json { path },
+                DatasetFormat::Ndjson => SourceConfig::Ndjson { path },
+                DatasetFormat::Excel => SourceConfig::Excel { path, sheet: ExcelSheet::default() },
+                DatasetFormat::Avro => SourceConfig::Avro { path },
+            }
+        }
+    };
+    dataset.storage.table = entry.table.clone();
+    dataset.catalog.dataset = entry.name.clone();
+    dataset.labels.insert("dataset".to_string(), entry.name.clone());
+
+    let issues = validation::validate(&dataset);
+    if !issues.is_empty() {
+        let issues: Vec<String> = issues.iter().map(|issue| format!("  {}", issue)).collect();
+        bail!("Invalid configuration for dataset {}:\n{}", entry.name, issues.join("\n"));
+    }
+    Ok(dataset)
+}
+
+/// Runs the pipeline once per dataset of a manifest, in order.
+///
+/// # Arguments
+///
+/// * `manifest` - The datasets to load.
+/// * `config` - The loaded pipeline configuration, which each dataset's configuration is derived from.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error naming the datasets that failed, after every dataset was attempted.
+///
+/// # Example
+///
+/// ```
+/// run_manifest(&load_manifest("datasets.yaml")?, &config).await?;
+/// ```
+pub async fn run_manifest(manifest: &Manifest, config: &PipelineConfig) -> Result<()> {
+    let mut failed = vec![];
+    for (i, entry) in manifest.datasets.iter().enumerate() {
+        println!("Loading dataset {} ({} of {}) from {} into {}", entry.name, i + 1, manifest.datasets.len(), entry.path, entry.table);
+        let result = match dataset_config(config, entry) {
+            Ok(dataset) => pipeline::run(&dataset, &Hooks::from_config(&dataset)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Dataset {} failed: {:#}", entry.name, e);
+            failed.push(entry.name.as_str());
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!("{} of {} datasets failed: {}", failed.len(), manifest.datasets.len(), failed.join(", "));
+    }
+    println!("Loaded all {} datasets", manifest.datasets.len());
+    Ok(())
+}
+
+/// Helper function to list the problems of a manifest: no datasets, or names or tables given twice.
+fn check(manifest: &Manifest) -> Vec<String> {
+    let mut issues = vec![];
+    if manifest.datasets.is_empty() {
+        issues.push("datasets: must list at least one dataset".to_string());
+    }
+    let mut names = HashSet::new();
+    let mut tables = HashSet::new();
+    for (i, entry) in manifest.datasets.iter().enumerate() {
+        if !names.insert(entry.name.as_str()) {
+            issues.push(format!("datasets[{}].name: dataset {} is listed twice", i, entry.name));
+        }
+        // PostgreSQL folds unquoted names to lower case, so `Red` and `red` are the same table
+        if !tables.insert(entry.table.to_ascii_lowercase()) {
+            issues.push(format!("datasets[{}].table: {} is the table of another dataset, whose rows would mix with its own", i, entry.table));
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datasets_read_their_file_into_their_table() {
+        let manifest: Manifest = serde_yaml::from_str(
+            r#"
+datasets:
+  - name: red
+    path: data/winequality-red.csv.gz
+    table: wine_quality_red
+  - name: white
+    path: data/winequality-white.jsonl
+    table: wine_quality_white
+  - name: rosé
+    path: data/winequality-rose.dat
+    table: Wine_Quality_Red
+"#,
+        )
+        .unwrap();
+        assert_eq!(check(&manifest), vec!["datasets[2].table: Wine_Quality_Red is the table of another dataset, whose rows would mix with its own".to_string()]);
+
+        let mut config = PipelineConfig::default();
+        config.source = SourceConfig::Csv { path: "data/dataset.csv".to_string(), options: IngestOptions { delimiter: ';', ..IngestOptions::default() } };
+        let red = dataset_config(&config, &manifest.datasets[0]).unwrap();
+        assert_eq!(red.source, SourceConfig::Csv { path: "data/winequality-red.csv.gz".to_string(), options: IngestOptions { delimiter: ';', ..IngestOptions::default() } });
+        assert_eq!((red.storage.table.as_str(), red.catalog.dataset.as_str()), ("wine_quality_red", "red"));
+        assert_eq!(red.labels.get("dataset").map(String::as_str), Some("red"));
+
+        let white = dataset_config(&config, &manifest.datasets[1]).unwrap();
+        assert_eq!(white.source, SourceConfig::Ndjson { path: "data/winequality-white.jsonl".to_string() });
+        assert!(dataset_config(&config, &manifest.datasets[2]).unwrap_err().to_string().contains("set the format of dataset rosé"));
+    }
+}
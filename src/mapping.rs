@@ -0,0 +1,160 @@
+//! This module maps source headers to the columns of the stored table.
+//!
+//! Source files name their columns freely (`fixed acidity`, `Total Sulfur Dioxide`), while table
+//! columns should be plain identifiers. A schema column without an explicit `column` is stored under
+//! the target given for its header in `[column_mapping.rename]`, or else under the name derived from
+//! the header by the naming convention, `snake_case` by default.
+//!
+//! Before that, right after reading, the headers of the input itself can be brought to the names the
+//! schema declares, so a file with `fixed_acidity` or ` Fixed  Acidity ` loads like one with `fixed acidity`:
+//! `[headers.rename]` renames given headers, and with `[headers] normalize` every header is trimmed,
+//! lowercased, and its runs of whitespace collapsed, and read under the schema column it then matches.
+
+use crate::config::{ColumnMappingConfig, HeadersConfig};
+use crate::schema::TableSchema;
+use anyhow::{bail, Context, Result};
+use polars::prelude::DataFrame;
+use serde::Deserialize;
+
+/// How table column names are derived from source headers without an explicit mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NamingConvention {
+    /// Lowercase, with every run of other characters than letters and digits replaced by `_`.
+    #[default]
+    SnakeCase,
+    /// The header unchanged.
+    AsIs,
+}
+
+/// Fills in the table column of every schema column that does not declare one.
+///
+/// # Arguments
+///
+/// * `schema` - The schema to resolve.
+/// * `config` - The explicit mappings and naming convention.
+///
+/// # Example
+///
+/// ```
+/// resolve(&mut config.schema, &config.column_mapping);
+/// ```
+pub fn resolve(schema: &mut TableSchema, config: &ColumnMappingConfig) {
+    for spec in schema.columns.iter_mut().filter(|c| c.column.is_empty()) {
+        spec.column = target_column(&spec.name, config);
+    }
+}
+
+/// Returns the table column a source header is stored under, unless the schema declares one.
+pub fn target_column(header: &str, config: &ColumnMappingConfig) -> String {
+    if let Some(target) = config.rename.get(header) {
+        return target.clone();
+    }
+    match config.convention {
+        NamingConvention::SnakeCase => snake_case(header),
+        NamingConvention::AsIs => header.to_string(),
+    }
+}
+
+/// Converts a header to `snake_case`, e.g. `Free Sulfur Dioxide` to `free_sulfur_dioxide`.
+pub fn snake_case(header: &str) -> String {
+    let mut column = String::with_capacity(header.len());
+    for c in header.trim().chars() {
+        if c.is_alphanumeric() {
+            column.extend(c.to_lowercase());
+        } else if !column.is_empty() && !column.ends_with('_') {
+            column.push('_');
+        }
+    }
+    column.trim_end_matches('_').to_string()
+}
+
+/// Normalizes a header: trimmed, lowercase, and with every run of whitespace replaced by one space.
+pub fn normalize_header(header: &str) -> String {
+    header.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Returns the names the columns of an input are read under, given its headers.
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the input, in order.
+/// * `schema` - The schema, whose column names normalized headers are matched against.
+/// * `config` - The renamed headers, and whether headers are normalized.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>>` - The names, or an error if two headers end up with the same name.
+///
+/// # Example
+///
+/// ```
+/// let names = canonical_headers(&["fixed_acidity", " pH "], &config.schema, &config.headers)?;
+/// ```
+pub fn canonical_headers(headers: &[&str], schema: &TableSchema, config: &HeadersConfig) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::with_capacity(headers.len());
+    for header in headers {
+        let name = canonical_header(header, schema, config);
+        if let Some(i) = names.iter().position(|n| n == &name) {
+            bail!("The input columns \"{}\" and \"{}\" would both be read as \"{}\"; rename one in [headers.rename]", headers[i], header, name);
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Renames the columns of an input DataFrame to their canonical names, as [`canonical_headers`] returns them.
+pub fn canonicalize(mut df: DataFrame, schema: &TableSchema, config: &HeadersConfig) -> Result<DataFrame> {
+    let names = canonical_headers(&df.get_column_names(), schema, config)?;
+    df.set_column_names(names.as_slice()).context("Failed to rename the input columns")?;
+    Ok(df)
+}
+
+/// Helper function to name one header: by its rename, matched as given or normalized, or else the schema column it matches once normalized.
+fn canonical_header(header: &str, schema: &TableSchema, config: &HeadersConfig) -> String {
+    if let Some(name) = config.rename.get(header) {
+        return name.clone();
+    }
+    if !config.normalize {
+        return header.to_string();
+    }
+    let normalized = normalize_header(header);
+    if let Some((_, name)) = config.rename.iter().find(|(renamed, _)| normalize_header(renamed) == normalized) {
+        return name.clone();
+    }
+    match schema.columns.iter().find(|c| normalize_header(&c.name) == normalized) {
+        Some(column) => column.name.clone(),
+        None => normalized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_target_column() {
+        let config = ColumnMappingConfig {
+            rename: HashMap::from([("pH".to_string(), "acidity_ph".to_string())]),
+            convention: NamingConvention::SnakeCase,
+        };
+
+        assert_eq!(target_column("Total Sulfur  Dioxide (mg/L)", &config), "total_sulfur_dioxide_mg_l");
+        assert_eq!(target_column("pH", &config), "acidity_ph");
+        assert_eq!(target_column("Alcohol", &ColumnMappingConfig { convention: NamingConvention::AsIs, ..config }), "Alcohol");
+    }
+
+    #[test]
+    fn test_canonical_headers() {
+        let config = HeadersConfig { normalize: true, rename: HashMap::from([("fixed_acidity".to_string(), "fixed acidity".to_string())]) };
+        let schema = TableSchema::default();
+
+        let names = canonical_headers(&["FIXED_ACIDITY", " Volatile   Acidity", "PH", "Taster Name"], &schema, &config).unwrap();
+        assert_eq!(names, vec!["fixed acidity", "volatile acidity", "pH", "taster name"]);
+        assert!(canonical_headers(&["pH", "ph "], &schema, &config).is_err());
+
+        let exact = HeadersConfig { normalize: false, ..config };
+        assert_eq!(canonical_headers(&["fixed_acidity", "PH"], &schema, &exact).unwrap(), vec!["fixed acidity", "PH"]);
+    }
+}
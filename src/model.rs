@@ -0,0 +1,302 @@
+//! This module trains a model predicting wine quality from the transformed features.
+//!
+//! It fits a linear regression, evaluates it on a held-out share of the rows, and persists the model
+//! together with its metrics as a JSON artifact for the run. A previously trained artifact can be loaded
+//! again to score new data.
+
+use crate::config::ModelConfig;
+use crate::run::RunContext;
+use anyhow::{anyhow, bail, Context, Result};
+use linfa::traits::{Fit, Predict};
+use linfa::Dataset;
+use linfa_linear::LinearRegression;
+use ndarray::{Array1, Array2};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// File name of the model artifact inside the run's artifact directory.
+pub const MODEL_FILE_NAME: &str = "quality_model.json";
+
+/// Name of the column appended by [`score_stage`].
+pub const PREDICTION_COLUMN: &str = "predicted_quality";
+
+/// Evaluation metrics computed on the held-out rows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelMetrics {
+    /// Root mean squared error of the predictions.
+    pub rmse: f64,
+    /// Share of rows where the rounded prediction equals the target.
+    pub accuracy: f64,
+    pub train_rows: usize,
+    pub test_rows: usize,
+}
+
+/// A trained linear model and the metadata needed to apply it again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityModel {
+    pub run_id: String,
+    pub target: String,
+    pub features: Vec<String>,
+    pub coefficients: Vec<f64>,
+    pub intercept: f64,
+    pub metrics: ModelMetrics,
+}
+
+impl QualityModel {
+    /// Predicts the target for every row of a DataFrame.
+    ///
+    /// Rows with a null in any feature column get a null prediction.
+    ///
+    /// # Arguments
+    ///
+    /// * `df` - A reference to the DataFrame to score. It must contain all feature columns of the model.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Series>` - A result containing the predictions as a `predicted_quality` series, or an error if a feature column is missing.
+    pub fn predict(&self, df: &DataFrame) -> Result<Series> {
+        let mut columns = vec![];
+        for name in &self.features {
+            columns.push(float_values(df, name)?);
+        }
+
+        let predictions: Vec<Option<f64>> = (0..df.height())
+            .map(|row| {
+                columns
+                    .iter()
+                    .zip(&self.coefficients)
+                    .try_fold(self.intercept, |acc, (column, coefficient)| Some(acc + column[row]? * coefficient))
+            })
+            .collect();
+
+        Ok(Series::new(PREDICTION_COLUMN, predictions))
+    }
+}
+
+/// Trains and persists the quality model for a run, if training is enabled.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the transformed DataFrame.
+/// * `config` - The model settings.
+/// * `run` - The context of the current run, used to pick the artifact directory.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of training.
+///
+/// # Example
+///
+/// ```
+/// train_stage(&transformed_df, &config.model, &run).expect("Model training failed");
+/// ```
+pub fn train_stage(df: &DataFrame, config: &ModelConfig, run: &RunContext) -> Result<()> {
+    if !config.train {
+        return Ok(());
+    }
+
+    let model = train_model(df, config, &run.id)?;
+    println!(
+        "Trained quality model on {} rows: RMSE {:.4}, accuracy {:.2}% on {} held-out rows",
+        model.metrics.train_rows,
+        model.metrics.rmse,
+        model.metrics.accuracy * 100.0,
+        model.metrics.test_rows
+    );
+
+    let path = save_model(&model, &run.artifact_dir(&config.artifact_dir))?;
+    println!("Model artifact written to {}", path.display());
+    Ok(())
+}
+
+/// Fits a linear regression of the target column on the feature columns.
+///
+/// Every `n`-th row, with `n` derived from `test_fraction`, is held out for evaluation. Rows with a null
+/// in any used column are skipped.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to train on.
+/// * `config` - The model settings.
+/// * `run_id` - The ID of the run producing the model.
+///
+/// # Returns
+///
+/// * `Result<QualityModel>` - The trained model with its metrics, or an error if there are too few rows.
+pub fn train_model(df: &DataFrame, config: &ModelConfig, run_id: &str) -> Result<QualityModel> {
+    let features = feature_columns(df, config);
+    if features.is_empty() {
+        bail!("No numeric feature columns available to predict {}", config.target);
+    }
+
+    let mut columns = vec![];
+    for name in features.iter().chain(std::iter::once(&config.target)) {
+        columns.push(float_values(df, name)?);
+    }
+
+    let test_every = (1.0 / config.test_fraction.clamp(0.01, 0.5)).round() as usize;
+    let (mut train_x, mut train_y, mut test_x, mut test_y) = (vec![], vec![], vec![], vec![]);
+    let mut complete_rows = 0;
+    for row in 0..df.height() {
+        let Some(values) = columns.iter().map(|c| c[row]).collect::<Option<Vec<f64>>>() else { continue };
+        let (target, record) = values.split_last().expect("row contains the target");
+        complete_rows += 1;
+        if complete_rows % test_every == 0 {
+            test_x.extend_from_slice(record);
+            test_y.push(*target);
+        } else {
+            train_x.extend_from_slice(record);
+            train_y.push(*target);
+        }
+    }
+
+    if train_y.len() <= features.len() || test_y.is_empty() {
+        bail!("Not enough complete rows ({}) to train the quality model", complete_rows);
+    }
+
+    let train_rows = train_y.len();
+    let train_records = Array2::from_shape_vec((train_rows, features.len()), train_x)?;
+    let test_records = Array2::from_shape_vec((test_y.len(), features.len()), test_x)?;
+    let dataset = Dataset::new(train_records, Array1::from(train_y));
+
+    let fitted = LinearRegression::new()
+        .fit(&dataset)
+        .map_err(|e| anyhow!("Failed to train quality model: {}", e))?;
+    let predictions = fitted.predict(&test_records);
+
+    Ok(QualityModel {
+        run_id: run_id.to_string(),
+        target: config.target.clone(),
+        features,
+        coefficients: fitted.params().to_vec(),
+        intercept: fitted.intercept(),
+        metrics: evaluate(&predictions.to_vec(), &test_y, train_rows),
+    })
+}
+
+/// Appends a `predicted_quality` column from a previously trained model, if scoring is configured.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame to score.
+/// * `config` - The model settings; `score_with` names the model artifact to load.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with the prediction column appended, or unchanged if scoring is disabled.
+///
+/// # Example
+///
+/// ```
+/// let scored_df = score_stage(transformed_df, &config.model).expect("Model scoring failed");
+/// ```
+pub fn score_stage(mut df: DataFrame, config: &ModelConfig) -> Result<DataFrame> {
+    let Some(path) = &config.score_with else {
+        return Ok(df);
+    };
+
+    let model = load_model(Path::new(path))?;
+    let predictions = model.predict(&df)?;
+    df.with_column(predictions).context("Failed to append predictions")?;
+    println!("Scored {} rows with model trained in run {}", df.height(), model.run_id);
+    Ok(df)
+}
+
+/// Reads a model artifact written by [`save_model`].
+pub fn load_model(path: &Path) -> Result<QualityModel> {
+    let json = std::fs::read_to_string(path).context(format!("Failed to read model artifact {}", path.display()))?;
+    serde_json::from_str(&json).context(format!("Failed to parse model artifact {}", path.display()))
+}
+
+/// Writes a model as JSON into the given directory and returns the artifact path.
+pub fn save_model(model: &QualityModel, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).context(format!("Failed to create model directory {}", dir.display()))?;
+    let path = dir.join(MODEL_FILE_NAME);
+    let json = serde_json::to_string_pretty(model)?;
+    std::fs::write(&path, json).context(format!("Failed to write model artifact {}", path.display()))?;
+    Ok(path)
+}
+
+/// Helper function to pick the configured features, or every numeric column except the target.
+fn feature_columns(df: &DataFrame, config: &ModelConfig) -> Vec<String> {
+    if !config.features.is_empty() {
+        return config.features.clone();
+    }
+
+    df.get_columns()
+        .iter()
+        .filter(|s| s.dtype().is_numeric() && s.name() != config.target)
+        .map(|s| s.name().to_string())
+        .collect()
+}
+
+/// Helper function to fetch a column as f64 values, keeping nulls.
+fn float_values(df: &DataFrame, column: &str) -> Result<Vec<Option<f64>>> {
+    let series = df
+        .column(column)
+        .context(format!("Error fetching column {}", column))?
+        .cast(&DataType::Float64)
+        .context(format!("Error converting {} column to f64", column))?;
+    let values = series.f64()?.into_iter().collect();
+    Ok(values)
+}
+
+fn evaluate(predictions: &[f64], targets: &[f64], train_rows: usize) -> ModelMetrics {
+    let n = targets.len() as f64;
+    let squared_error: f64 = predictions.iter().zip(targets).map(|(p, t)| (p - t).powi(2)).sum();
+    let correct = predictions.iter().zip(targets).filter(|(p, t)| p.round() == t.round()).count();
+
+    ModelMetrics {
+        rmse: (squared_error / n).sqrt(),
+        accuracy: correct as f64 / n,
+        train_rows,
+        test_rows: targets.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_train_model() {
+        let x: Vec<f64> = (0..50).map(|i| i as f64 / 10.0).collect();
+        let y: Vec<f64> = x.iter().map(|v| 2.0 * v + 1.0).collect();
+        let df = df!("alcohol" => &x, "quality" => &y).unwrap();
+
+        let model = train_model(&df, &ModelConfig::default(), "test-run").expect("Training failed");
+
+        assert_eq!(model.features, vec!["alcohol".to_string()]);
+        assert!((model.coefficients[0] - 2.0).abs() < 1e-6);
+        assert!((model.intercept - 1.0).abs() < 1e-6);
+        assert!(model.metrics.rmse < 1e-6);
+        assert_eq!(model.metrics.train_rows + model.metrics.test_rows, 50);
+    }
+
+    #[test]
+    fn test_predict() {
+        let model = QualityModel {
+            run_id: "test-run".to_string(),
+            target: "quality".to_string(),
+            features: vec!["alcohol".to_string(), "pH".to_string()],
+            coefficients: vec![0.5, -1.0],
+            intercept: 2.0,
+            metrics: ModelMetrics { rmse: 0.0, accuracy: 1.0, train_rows: 0, test_rows: 0 },
+        };
+        let df = df!("alcohol" => &[Some(10.0), None], "pH" => &[Some(3.0), Some(3.5)]).unwrap();
+
+        let predictions = model.predict(&df).expect("Prediction failed");
+
+        assert_eq!(predictions.name(), PREDICTION_COLUMN);
+        assert_eq!(predictions.f64().unwrap().get(0), Some(4.0));
+        assert_eq!(predictions.f64().unwrap().get(1), None);
+    }
+
+    #[test]
+    fn test_train_model_too_few_rows() {
+        let df = df!("alcohol" => &[9.4], "quality" => &[5.0]).unwrap();
+        assert!(train_model(&df, &ModelConfig::default(), "test-run").is_err());
+    }
+}
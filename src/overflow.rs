@@ -0,0 +1,208 @@
+//! This module checks numeric values against the precision and scale of their target columns before insert.
+//!
+//! A value with more integer digits than a `NUMERIC(p, s)` column holds makes PostgreSQL fail the whole
+//! insert statement with "numeric field overflow", naming neither the column nor the value, and the
+//! load then falls back to inserting the batch row by row. The precision and scale of the target
+//! table's columns are instead read from `information_schema` before the rows are bound, and every
+//! value that would overflow its column once rounded to the scale is handled by `on_numeric_overflow`:
+//! replaced by the nearest value the column holds, rejected with a clear error, or failing the load.
+
+use crate::schema::TableSchema;
+use crate::storage::RejectedRow;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use std::collections::{BTreeMap, HashMap};
+
+/// What happens to values out of the range of their `NUMERIC` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Values are replaced by the nearest value the column holds, its largest or smallest.
+    Round,
+    /// Rows holding such a value are rejected, with the column and value in the error.
+    #[default]
+    Quarantine,
+    /// The load fails before any row is inserted.
+    Fail,
+}
+
+/// Precision and scale of a `NUMERIC(p, s)` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumericBounds {
+    pub precision: u32,
+    pub scale: u32,
+}
+
+impl NumericBounds {
+    /// Returns the nearest value the column holds, if `value` is out of its range once rounded to the scale.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to store.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<f64>` - `None` if the column holds the value, else its largest or smallest value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let bounds = NumericBounds { precision: 4, scale: 2 };
+    /// assert_eq!(bounds.overflow(120.5), Some(99.99));
+    /// ```
+    pub fn overflow(&self, value: f64) -> Option<f64> {
+        let digits = self.precision.checked_sub(self.scale)?;
+        // Magnitudes of 10^29 and more are beyond the decimal range, and NaN and infinities are left to PostgreSQL
+        if !value.is_finite() || digits > 28 || self.scale > 28 {
+            return None;
+        }
+        let limit = Decimal::from_i128_with_scale(10i128.pow(digits), 0);
+        // As PostgreSQL does, the shortest decimal representation is rounded half away from zero before the check
+        let fits = match Decimal::from_str_exact(&value.to_string()).ok().or_else(|| Decimal::from_f64(value)) {
+            Some(decimal) => decimal.round_dp_with_strategy(self.scale, RoundingStrategy::MidpointAwayFromZero).abs() < limit,
+            None => false,
+        };
+        if fits {
+            return None;
+        }
+        (limit - Decimal::new(1, self.scale)).to_f64().map(|largest| largest.copysign(value))
+    }
+}
+
+impl std::fmt::Display for NumericBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NUMERIC({}, {})", self.precision, self.scale)
+    }
+}
+
+/// Reads the precision and scale of the `NUMERIC` columns of a table that declare them.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `table` - The table the rows are inserted into.
+///
+/// # Returns
+///
+/// * `Result<HashMap<String, NumericBounds>>` - The bounds by table column name, empty if the table does not exist.
+///
+/// # Example
+///
+/// ```
+/// let bounds = column_bounds(&pool, "wine_quality").await?;
+/// ```
+pub async fn column_bounds(pool: &PgPool, table: &str) -> Result<HashMap<String, NumericBounds>> {
+    let columns: Vec<(String, i32, i32)> = sqlx::query_as(
+        "SELECT column_name::TEXT, numeric_precision::INT4, numeric_scale::INT4 FROM information_schema.columns \
+         WHERE table_schema = current_schema() AND table_name = $1 AND data_type = 'numeric' AND numeric_precision IS NOT NULL",
+    )
+    .bind(table)
+    .fetch_all(pool)
+    .await
+    .context(format!("Failed to read the numeric columns of {}", table))?;
+    Ok(columns
+        .into_iter()
+        .filter_map(|(column, precision, scale)| Some((column, NumericBounds { precision: u32::try_from(precision).ok()?, scale: u32::try_from(scale).ok()? })))
+        .collect())
+}
+
+/// Handles the values out of the range of their column, by the policy.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame about to be stored.
+/// * `schema` - The schema naming the table column of each DataFrame column.
+/// * `bounds` - The bounds of the table's `NUMERIC` columns, as read by [`column_bounds`].
+/// * `policy` - What happens to values out of range.
+///
+/// # Returns
+///
+/// * `Result<(DataFrame, Vec<RejectedRow>)>` - The DataFrame, with values replaced under [`OverflowPolicy::Round`],
+///   and the rows rejected under [`OverflowPolicy::Quarantine`], or an error naming the first value out of
+///   range under [`OverflowPolicy::Fail`].
+///
+/// # Example
+///
+/// ```
+/// let (df, rejects) = guard(&df, &config.schema, &bounds, OverflowPolicy::Quarantine)?;
+/// ```
+pub fn guard(df: &DataFrame, schema: &TableSchema, bounds: &HashMap<String, NumericBounds>, policy: OverflowPolicy) -> Result<(DataFrame, Vec<RejectedRow>)> {
+    let mut df = df.clone();
+    let mut rejects: BTreeMap<usize, String> = BTreeMap::new();
+    for spec in &schema.columns {
+        let Some(column_bounds) = bounds.get(&spec.column) else { continue };
+        let Ok(series) = df.column(&spec.name) else { continue };
+        // Text values are parsed, and checked, by PostgreSQL
+        if !series.dtype().is_numeric() {
+            continue;
+        }
+
+        let values = series.cast(&DataType::Float64)?;
+        let mut replaced = 0;
+        let mut fitted = Vec::with_capacity(values.len());
+        for (row, value) in values.f64()?.into_iter().enumerate() {
+            let nearest = value.and_then(|v| column_bounds.overflow(v));
+            let (Some(value), Some(nearest)) = (value, nearest) else {
+                fitted.push(value);
+                continue;
+            };
+            let error = format!("{} = {} is out of the range of {} column {}", spec.name, value, column_bounds, spec.column);
+            match policy {
+                OverflowPolicy::Round => {
+                    fitted.push(Some(nearest));
+                    replaced += 1;
+                }
+                OverflowPolicy::Quarantine => {
+                    rejects.entry(row).or_insert(error);
+                    fitted.push(Some(value));
+                }
+                OverflowPolicy::Fail => bail!("Row {}: {}; set storage.on_numeric_overflow to \"round\" or \"quarantine\" to store the other rows", row, error),
+            }
+        }
+        if replaced > 0 {
+            eprintln!("Replaced {} values of {} out of the range of {} by the nearest value it holds", replaced, spec.name, column_bounds);
+            df.with_column(Series::new(&spec.name, fitted)).context(format!("Error replacing {} column", spec.name))?;
+        }
+    }
+    Ok((df, rejects.into_iter().map(|(row, error)| RejectedRow { row, error }).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow_rounds_before_checking() {
+        let bounds = NumericBounds { precision: 4, scale: 2 };
+        assert_eq!(bounds.overflow(99.99), None);
+        assert_eq!(bounds.overflow(99.994), None);
+        assert_eq!(bounds.overflow(99.995), Some(99.99));
+        assert_eq!(bounds.overflow(-120.5), Some(-99.99));
+        assert_eq!(bounds.overflow(1e300), Some(99.99));
+        assert_eq!(NumericBounds { precision: 4, scale: 4 }.overflow(1.0), Some(0.9999));
+        assert_eq!(bounds.overflow(f64::NAN), None);
+    }
+
+    #[test]
+    fn test_guard_applies_policy() {
+        let df = polars::df!("fixed acidity" => &[7.4, 120.5, 8.1], "quality" => &[5i64, 6, 7]).unwrap();
+        let bounds = HashMap::from([("fixed_acidity".to_string(), NumericBounds { precision: 4, scale: 2 })]);
+        let schema = TableSchema::default();
+
+        let (rounded, rejects) = guard(&df, &schema, &bounds, OverflowPolicy::Round).unwrap();
+        assert!(rejects.is_empty());
+        assert_eq!(rounded.column("fixed acidity").unwrap().f64().unwrap().to_vec(), vec![Some(7.4), Some(99.99), Some(8.1)]);
+
+        let (kept, rejects) = guard(&df, &schema, &bounds, OverflowPolicy::Quarantine).unwrap();
+        assert!(kept.equals(&df));
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].row, 1);
+        assert!(rejects[0].error.contains("NUMERIC(4, 2)"));
+
+        assert!(guard(&df, &schema, &bounds, OverflowPolicy::Fail).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! This module reduces the numeric features to their top principal components.
+//!
+//! It standardizes the features, decomposes their covariance matrix, and appends the projections
+//! as `pc1..pcK` columns. The fitted loadings are persisted so later runs can project onto the same axes.
+
+use crate::config::PcaConfig;
+use crate::run::RunContext;
+use anyhow::{bail, Context, Result};
+use ndarray::{Array1, Array2};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File name of the loadings artifact inside the run's artifact directory.
+pub const LOADINGS_FILE_NAME: &str = "pca_loadings.json";
+
+/// Maximum number of Jacobi sweeps when decomposing the covariance matrix.
+const MAX_SWEEPS: usize = 100;
+
+/// Fitted principal components.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PcaModel {
+    pub features: Vec<String>,
+    pub means: Vec<f64>,
+    pub stds: Vec<f64>,
+    /// One row of feature weights per component, strongest component first.
+    pub loadings: Vec<Vec<f64>>,
+    pub explained_variance_ratio: Vec<f64>,
+}
+
+impl PcaModel {
+    /// Projects the rows of a DataFrame onto the components.
+    ///
+    /// Rows with a null in any feature column get null components.
+    ///
+    /// # Arguments
+    ///
+    /// * `df` - A reference to the DataFrame to project. It must contain all feature columns.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<Series>>` - One `pcN` series per component, or an error if a feature column is missing.
+    pub fn transform(&self, df: &DataFrame) -> Result<Vec<Series>> {
+        let mut columns = vec![];
+        for name in &self.features {
+            columns.push(float_values(df, name)?);
+        }
+
+        let series = self
+            .loadings
+            .iter()
+            .enumerate()
+            .map(|(component, weights)| {
+                let values: Vec<Option<f64>> = (0..df.height())
+                    .map(|row| {
+                        let mut sum = 0.0;
+                        for (i, column) in columns.iter().enumerate() {
+                            sum += (column[row]? - self.means[i]) / self.stds[i] * weights[i];
+                        }
+                        Some(sum)
+                    })
+                    .collect();
+                Series::new(&format!("pc{}", component + 1), values)
+            })
+            .collect();
+
+        Ok(series)
+    }
+}
+
+/// Appends principal component columns, if PCA is enabled.
+///
+/// Loadings are read from `loadings_path` when that file exists; otherwise they are fitted on the
+/// DataFrame and written to the run's artifact directory.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame to project.
+/// * `config` - The PCA settings.
+/// * `run` - The context of the current run.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with the component columns appended.
+///
+/// # Example
+///
+/// ```
+/// let df = pca_stage(transformed_df, &config.pca, &run).expect("PCA failed");
+/// ```
+pub fn pca_stage(mut df: DataFrame, config: &PcaConfig, run: &RunContext) -> Result<DataFrame> {
+    if !config.enabled {
+        return Ok(df);
+    }
+
+    let model = match config.loadings_path.as_deref().map(Path::new).filter(|p| p.exists()) {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).context(format!("Failed to read PCA loadings {}", path.display()))?;
+            let model: PcaModel = serde_json::from_str(&json).context(format!("Failed to parse PCA loadings {}", path.display()))?;
+            println!("Loaded PCA loadings from {}", path.display());
+            model
+        }
+        None => {
+            let features = feature_columns(&df, config);
+            let model = fit_pca(&df, &features, config.components)?;
+            let dir = run.artifact_dir(&config.artifact_dir);
+            std::fs::create_dir_all(&dir).context(format!("Failed to create PCA directory {}", dir.display()))?;
+            let path = dir.join(LOADINGS_FILE_NAME);
+            std::fs::write(&path, serde_json::to_string_pretty(&model)?).context(format!("Failed to write PCA loadings {}", path.display()))?;
+            println!("PCA loadings written to {}", path.display());
+            model
+        }
+    };
+
+    println!("Explained variance ratio of principal components: {:?}", model.explained_variance_ratio);
+    for series in model.transform(&df)? {
+        df.with_column(series).context("Failed to append principal component")?;
+    }
+    Ok(df)
+}
+
+/// Fits the top `components` principal components of the standardized features.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to fit on. Rows with a null in any feature are skipped.
+/// * `features` - The feature columns.
+/// * `components` - The number of components to keep.
+///
+/// # Returns
+///
+/// * `Result<PcaModel>` - The fitted model, or an error if there are fewer than two complete rows.
+pub fn fit_pca(df: &DataFrame, features: &[String], components: usize) -> Result<PcaModel> {
+    let mut columns = vec![];
+    for name in features {
+        columns.push(float_values(df, name)?);
+    }
+
+    let rows: Vec<Vec<f64>> = (0..df.height())
+        .filter_map(|row| columns.iter().map(|c| c[row]).collect::<Option<Vec<f64>>>())
+        .collect();
+    if rows.len() < 2 {
+        bail!("PCA needs at least two complete rows, found {}", rows.len());
+    }
+
+    let n = rows.len() as f64;
+    let k = features.len();
+    let data = Array2::from_shape_vec((rows.len(), k), rows.concat())?;
+    let means = data.mean_axis(ndarray::Axis(0)).context("Error calculating feature means")?;
+    let stds = data.std_axis(ndarray::Axis(0), 1.0).mapv(|s| if s > 0.0 { s } else { 1.0 });
+    let standardized = (&data - &means) / &stds;
+    let covariance = standardized.t().dot(&standardized) / (n - 1.0);
+
+    let (eigenvalues, eigenvectors) = symmetric_eigen(covariance);
+    let mut order: Vec<usize> = (0..k).collect();
+    order.sort_by(|&a, &b| eigenvalues[b].partial_cmp(&eigenvalues[a]).unwrap_or(std::cmp::Ordering::Equal));
+
+    let total: f64 = eigenvalues.iter().map(|v| v.max(0.0)).sum();
+    let mut loadings = vec![];
+    let mut explained_variance_ratio = vec![];
+    for &index in order.iter().take(components.min(k)) {
+        let mut vector = eigenvectors.column(index).to_vec();
+        // Make the sign deterministic: the largest weight is positive
+        let largest = vector.iter().copied().fold(0.0f64, |acc, w| if w.abs() > acc.abs() { w } else { acc });
+        if largest < 0.0 {
+            vector.iter_mut().for_each(|w| *w = -*w);
+        }
+        loadings.push(vector);
+        explained_variance_ratio.push(if total > 0.0 { eigenvalues[index].max(0.0) / total } else { 0.0 });
+    }
+
+    Ok(PcaModel {
+        features: features.to_vec(),
+        means: means.to_vec(),
+        stds: stds.to_vec(),
+        loadings,
+        explained_variance_ratio,
+    })
+}
+
+/// Decomposes a symmetric matrix with the cyclic Jacobi method.
+///
+/// Returns the eigenvalues and a matrix whose columns are the corresponding eigenvectors.
+fn symmetric_eigen(mut a: Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = a.nrows();
+    let mut v = Array2::<f64>::eye(n);
+
+    for _ in 0..MAX_SWEEPS {
+        let mut off_diagonal = 0.0;
+        for p in 0..n {
+            for q in 0..n {
+                if p != q {
+                    off_diagonal += a[[p, q]].powi(2);
+                }
+            }
+        }
+        if off_diagonal < 1e-18 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[[p, q]].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[[q, q]] - a[[p, p]]) / (2.0 * a[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[[k, p]], a[[k, q]]);
+                    a[[k, p]] = c * akp - s * akq;
+                    a[[k, q]] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * apk - s * aqk;
+                    a[[q, k]] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[[k, p]], v[[k, q]]);
+                    v[[k, p]] = c * vkp - s * vkq;
+                    v[[k, q]] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    (a.diag().to_owned(), v)
+}
+
+/// Helper function to pick the configured features, or every numeric column not excluded.
+fn feature_columns(df: &DataFrame, config: &PcaConfig) -> Vec<String> {
+    if !config.features.is_empty() {
+        return config.features.clone();
+    }
+
+    df.get_columns()
+        .iter()
+        .filter(|s| s.dtype().is_numeric() && !config.exclude.iter().any(|e| e == s.name()))
+        .map(|s| s.name().to_string())
+        .collect()
+}
+
+/// Helper function to fetch a column as f64 values, keeping nulls.
+fn float_values(df: &DataFrame, column: &str) -> Result<Vec<Option<f64>>> {
+    let series = df
+        .column(column)
+        .context(format!("Error fetching column {}", column))?
+        .cast(&DataType::Float64)
+        .context(format!("Error converting {} column to f64", column))?;
+    let values = series.f64()?.into_iter().collect();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_fit_pca_correlated_features() {
+        let x: Vec<f64> = (0..20).map(|i| i as f64).collect();
+        let y: Vec<f64> = x.iter().map(|v| 2.0 * v + 3.0).collect();
+        let df = df!("a" => &x, "b" => &y).unwrap();
+
+        let model = fit_pca(&df, &["a".to_string(), "b".to_string()], 1).expect("PCA failed");
+
+        assert_eq!(model.loadings.len(), 1);
+        let expected = std::f64::consts::FRAC_1_SQRT_2;
+        assert!((model.loadings[0][0] - expected).abs() < 1e-6);
+        assert!((model.loadings[0][1] - expected).abs() < 1e-6);
+        assert!((model.explained_variance_ratio[0] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_transform_appends_named_components() {
+        let df = df!("a" => &[1.0, 2.0, 3.0], "b" => &[3.0, 1.0, 2.0]).unwrap();
+        let model = fit_pca(&df, &["a".to_string(), "b".to_string()], 2).unwrap();
+
+        let components = model.transform(&df).unwrap();
+
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[1].name(), "pc2");
+        // Projections of standardized data are centred
+        let mean = components[0].f64().unwrap().mean().unwrap();
+        assert!(mean.abs() < 1e-9);
+    }
+}
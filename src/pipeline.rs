@@ -0,0 +1,573 @@
+//! This module runs the pipeline stages for a single run.
+//!
+//! It coordinates ingestion, transformation, the optional model and analysis stages, and storage,
+//! and fires the configured hooks around them.
+
+use crate::artifacts::ArtifactStore;
+use crate::config::{DowncastConfig, PipelineConfig, ResumableConfig};
+use crate::encryption::ColumnCipher;
+use crate::hooks::{HookEvent, HookPoint, Hooks};
+use crate::locale::ReportFormat;
+use crate::resources::{PeakUsage, ResourceMonitor};
+use crate::run::RunContext;
+use crate::source::{Source, SourceConfig};
+use crate::storage::PoolProvider;
+use crate::typemap::TypeRegistry;
+use crate::{aggregates, alerts, analysis, audit, bookmarks, catalog, chaos, clustering, column_stats, dataset, downcast, evolution, expectations, fingerprint, history, ingestion, kafka, lakehouse, model, pca, processed_files, retention, rounding, seed, source, spill, staging, status, storage, streaming, transformation, visualization};
+use anyhow::{bail, Result};
+use polars::prelude::DataFrame;
+use sqlx::postgres::PgPool;
+use std::time::Duration;
+
+/// Runs the ingestion, transformation, and storage stages, firing hooks around them.
+///
+/// A failed attempt is retried up to `retry.max_retries` times, waiting `retry.backoff_secs` before
+/// the first retry and twice as long before each further one. Retries resume from the checkpoint of
+/// the failed attempt: stages that completed are not run again. A store that failed part-way is
+/// written again, so with retries an unpartitioned store commits in chunks tracked in `load_progress`,
+/// even without `[storage.resumable]`, and the retry skips the chunks the failed attempt committed;
+/// partitions written without `overwrite` are not tracked and are stored again. Each attempt is
+/// recorded in the run history. Chunked runs are not retried, since a failed chunked run may already
+/// have stored some chunks.
+///
+/// When the run fails for good, the `on_failure` hooks are fired before the error is returned. Errors
+/// of those hooks are reported but do not replace the original error. With `[alerts] deduplicate`,
+/// they only fire if the previous run of the input succeeded, and a successful run after failed ones
+/// fires the `on_recovery` hooks.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the loaded pipeline configuration.
+/// * `hooks` - The hooks to fire during the run.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of the pipeline run.
+///
+/// # Example
+///
+/// ```
+/// run(&config, &Hooks::from_config(&config)).await.expect("Data pipeline execution failed");
+/// ```
+pub async fn run(config: &PipelineConfig, hooks: &Hooks) -> Result<()> {
+    run_with(config, hooks, &PoolProvider::Connect).await
+}
+
+/// Runs the pipeline like [`run`], with the database connections of the given provider.
+///
+/// This is the entry point for services embedding the pipeline: it only needs a Tokio runtime to be
+/// running, whether multi-threaded or current-thread, and with [`PoolProvider::Injected`] every
+/// stage uses the service's own pool instead of connecting with `DATABASE_URL`.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the loaded pipeline configuration.
+/// * `hooks` - The hooks to fire during the run.
+/// * `pools` - Where the run gets its database connections from.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of the pipeline run.
+///
+/// # Example
+///
+/// ```
+/// run_with(&config, &Hooks::default(), &PoolProvider::Injected(pool.clone())).await?;
+/// ```
+pub async fn run_with(config: &PipelineConfig, hooks: &Hooks, pools: &PoolProvider) -> Result<()> {
+    let mut labels = config.labels.clone();
+    if let Some(limit) = config.row_limit {
+        // Marks every stored row as part of a partial load
+        labels.insert(audit::ROW_LIMIT_LABEL.to_string(), limit.to_string());
+    }
+    if let Some(sample) = config.sampling.describe() {
+        labels.insert(audit::SAMPLE_LABEL.to_string(), sample);
+    }
+    let run = RunContext::new().with_labels(labels);
+    let _tracked = status::track(&run);
+    if let Some(limit) = config.row_limit {
+        run.warn(format_args!("Processing at most {} rows of the input", limit));
+    }
+    if let Some(sample) = config.sampling.describe() {
+        run.warn(format_args!("Processing a random sample of {} of the input, drawn with seed {}", sample, config.sampling.seed));
+    }
+    let max_retries = if config.streaming.enabled { 0 } else { config.retry.max_retries };
+    let monitor = ResourceMonitor::start(&config.resources);
+    let mut checkpoint = Checkpoint::default();
+    let mut attempt = 1;
+
+    loop {
+        let Err(e) = run_stages(config, hooks, pools, &run, &mut checkpoint, attempt, monitor.as_ref()).await else { return Ok(()) };
+        if let Err(history_error) = record_failure(pools, &run, &checkpoint, attempt, &e, monitor.as_ref()).await {
+            run.warn(format_args!("Failed to record attempt {}: {:#}", attempt, history_error));
+        }
+
+        if attempt > max_retries {
+            if !should_alert(config, pools, &run).await {
+                return Err(e.context(format!("Run {} failed", run.id)));
+            }
+            if let Err(hook_error) = hooks.fire(HookEvent::new(HookPoint::OnFailure, &run.id).with_error(&e)).await {
+                run.warn(format_args!("on_failure hook failed: {:#}", hook_error));
+            }
+            return Err(e.context(format!("Run {} failed", run.id)));
+        }
+
+        let backoff = backoff(config.retry.backoff_secs, attempt);
+        run.warn(format_args!("Attempt {} failed: {:#}. Retrying in {:?} from the {} checkpoint", attempt, e, backoff, checkpoint.stage()));
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+/// Helper function to compute the wait before retry number `attempt`: `base_secs`, doubled for every further retry.
+fn backoff(base_secs: u64, attempt: u32) -> Duration {
+    Duration::from_secs(base_secs.saturating_mul(1u64 << (attempt - 1).min(16)))
+}
+
+/// Helper function to decide whether the final failure of a run is notified, recording it in the incident of its input.
+async fn should_alert(config: &PipelineConfig, pools: &PoolProvider, run: &RunContext) -> bool {
+    if !config.alerts.deduplicate {
+        return true;
+    }
+    let incident = match pools.get().await {
+        Ok(pool) => alerts::record_failure(&pool, &alerts::alert_key(config), &run.id).await,
+        Err(e) => Err(e),
+    };
+    match incident {
+        Ok(incident) if !incident.is_new() => {
+            run.warn(format_args!(
+                "Not notifying the failure: {} runs of this input failed since {} (first failed run {})",
+                incident.failed_runs, incident.failing_since, incident.first_run_id
+            ));
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            run.warn(format_args!("Notifying the failure, since it could not be deduplicated: {:#}", e));
+            true
+        }
+    }
+}
+
+async fn record_failure(pools: &PoolProvider, run: &RunContext, checkpoint: &Checkpoint, attempt: u32, error: &anyhow::Error, monitor: Option<&ResourceMonitor>) -> Result<()> {
+    let pool = pools.get().await?;
+    let usage = monitor.map(ResourceMonitor::usage);
+    history::record_attempt(&pool, run, checkpoint.fingerprint.as_deref(), attempt, None, Some(error), usage.as_ref()).await
+}
+
+/// Progress of a run, kept across attempts so a retry resumes after the last completed stage.
+#[derive(Default)]
+struct Checkpoint {
+    /// Whether the `on_run_start` hooks fired.
+    started: bool,
+    /// Whether the tables the run writes to were created.
+    setup: bool,
+    fingerprint: Option<String>,
+    /// The claim on the fingerprint, held until the run ends.
+    claim: Option<fingerprint::Claim>,
+    /// The input files to load and those earlier runs loaded, with `[deduplication] skip_processed_files`.
+    files: Option<processed_files::FileSelection>,
+    /// The parts of the input files appended since earlier runs read them, with `[deduplication] incremental`.
+    tails: Option<bookmarks::Tails>,
+    /// The ingested DataFrame.
+    ingested: Option<DataFrame>,
+    /// The offsets the ingested DataFrame ends at, for a Kafka source, stored with its rows.
+    offsets: Option<kafka::BatchOffsets>,
+    /// The DataFrame ready to be stored, after transformation, expectations, and the model and analysis stages.
+    prepared: Option<DataFrame>,
+    /// The stored DataFrame.
+    stored: Option<DataFrame>,
+    /// Rejected rows of the stored DataFrame not yet persisted.
+    rejects: Option<DataFrame>,
+    /// Number of rows of the stored DataFrame that were rejected.
+    rejected: usize,
+    /// Stored rows not yet appended to the Iceberg table and the Parquet dataset.
+    lake: Option<DataFrame>,
+}
+
+impl Checkpoint {
+    /// Returns the name of the last completed stage.
+    fn stage(&self) -> &'static str {
+        if self.stored.is_some() {
+            "store"
+        } else if self.prepared.is_some() {
+            "prepare"
+        } else if self.ingested.is_some() {
+            "ingest"
+        } else if self.setup {
+            "setup"
+        } else {
+            "start"
+        }
+    }
+}
+
+async fn run_stages(
+    config: &PipelineConfig,
+    hooks: &Hooks,
+    pools: &PoolProvider,
+    run: &RunContext,
+    checkpoint: &mut Checkpoint,
+    attempt: u32,
+    monitor: Option<&ResourceMonitor>,
+) -> Result<()> {
+    let pool = pools.get().await?;
+    status::attach_pool(&run.id, &pool);
+    if !checkpoint.started {
+        hooks.fire(HookEvent::new(HookPoint::OnRunStart, &run.id)).await?;
+        checkpoint.started = true;
+    }
+
+    run.log("Starting data pipeline...");
+    status::enter(&run.id, "ingest");
+
+    let Some(source) = select_files(&pool, run, config, checkpoint).await? else { return Ok(()) };
+    source.resume(&pool).await?;
+    let fingerprint = match &checkpoint.fingerprint {
+        Some(fingerprint) => fingerprint.clone(),
+        None => {
+            let fingerprint = fingerprint::compute(&source.checksum().await?, config, &config.storage.table);
+            // Claimed before any table is created, so a second process loading the same input changes nothing
+            if checkpoint.claim.is_none() {
+                checkpoint.claim = fingerprint::claim(&pool, &fingerprint, config.deduplication.on_duplicate).await?;
+            }
+            if !fingerprint::admit(&pool, run, &fingerprint, config.deduplication.on_duplicate).await? {
+                return Ok(());
+            }
+            checkpoint.fingerprint = Some(fingerprint.clone());
+            fingerprint
+        }
+    };
+
+    // Set up only once the run is admitted, so a skipped run creates nothing
+    if !checkpoint.setup {
+        status::enter(&run.id, "setup");
+        seed::setup_database(&pool, &config.schema, &config.storage.table).await?;
+        checkpoint.setup = true;
+    }
+
+    if config.streaming.enabled {
+        status::enter(&run.id, "stream");
+        let stored = streaming::run_chunked(&pool, run, config, hooks, source.as_ref()).await?;
+        status::enter(&run.id, "finish");
+        if config.staging.enabled {
+            stage_for_review(&pool, run, config).await?;
+        } else {
+            aggregates::refresh_stage(&pool, &config.schema, &config.aggregates).await?;
+        }
+        storage::get_first_5_rows(&pool, &config.schema, &config.storage.table).await?;
+        enforce_retention(&pool, config, ArtifactStore::from_config(&config.artifacts)?.as_ref()).await?;
+        let usage = monitor.map(ResourceMonitor::usage);
+        history::record_attempt(&pool, run, Some(&fingerprint), attempt, Some(stored), None, usage.as_ref()).await?;
+        record_files(&pool, run, config, checkpoint).await?;
+        report_performance(&pool, run, config, hooks, stored, usage.as_ref()).await?;
+        hooks.fire(HookEvent::new(HookPoint::OnSuccess, &run.id).with_rows(stored)).await?;
+        report_recovery(&pool, run, config, hooks, stored).await?;
+        report_warnings(run);
+        run.log("Data pipeline finished successfully.");
+        return Ok(());
+    }
+
+    let artifacts = ArtifactStore::from_config(&config.artifacts)?;
+    let cipher = ColumnCipher::from_config(&config.storage.encryption)?;
+    let dictionaries = expectations::Dictionaries::load(&pool, &config.expectations.suite, &config.reference_cache).await?;
+
+    // Ingest data
+    let df = match &checkpoint.ingested {
+        Some(df) => df.clone(),
+        None => {
+            let df = chaos::corrupt_rows(source::collect(source.read().await?).await?)?;
+            status::add_rows(&run.id, df.height());
+            // The frame holds the rows of every batch read, so it ends at the offsets of the last one
+            while let Some(batch) = source.take_offsets() {
+                checkpoint.offsets.get_or_insert_with(|| batch.clone()).next.extend(batch.next);
+            }
+            run.log(format_args!("Data ingestion complete. DataFrame shape: {:?}", df.shape()));
+            persist(&artifacts, &config.downcast, cipher.as_ref(), run, "raw", &df).await?;
+            hooks.fire(HookEvent::new(HookPoint::AfterIngest, &run.id).with_rows(df.height())).await?;
+            visualization::render_stage(&df, &config.visualization, run, "before")?;
+            checkpoint.ingested = Some(df.clone());
+            check_resources(monitor)?;
+            df
+        }
+    };
+
+    let transformed_df = match &checkpoint.prepared {
+        Some(df) => df.clone(),
+        None => {
+            // Transform data
+            status::enter(&run.id, "transform");
+            let mut intermediates = vec![];
+            // The ingested DataFrame is kept by the checkpoint anyway
+            let transformed_df = spill::transform_within_budget(df.clone(), &config.spill, &config.transform.passthrough, |name, df| {
+                if artifacts.as_ref().is_some_and(|store| store.wants(name)) {
+                    intermediates.push((name, df.clone()));
+                }
+                Ok(())
+            })?;
+            for (name, df) in &intermediates {
+                persist(&artifacts, &config.downcast, cipher.as_ref(), run, name, df).await?;
+            }
+            let transformed_df = transformation::apply_schema(transformed_df, &config.schema)?;
+            for warning in transformation::fill_warnings(&df, &transformed_df, config.transform.max_filled_rate) {
+                run.add_warning("transform", warning);
+            }
+            run.log(format_args!("Data transformation complete. Transformed DataFrame shape: {:?}", transformed_df.shape()));
+            run.log(format_args!("DataFrame dtypes: {:?}", transformed_df.dtypes()));
+            hooks.fire(HookEvent::new(HookPoint::AfterTransform, &run.id).with_rows(transformed_df.height())).await?;
+            visualization::render_stage(&transformed_df, &config.visualization, run, "after")?;
+
+            // Check expectations before loading
+            let results = expectations::run_suite(&pool, run, &transformed_df, &config.expectations, &dictionaries).await?;
+            if let Some(failures) = expectations::describe_failures(&results) {
+                hooks.fire(HookEvent::new(HookPoint::OnQualityViolation, &run.id).with_rows(transformed_df.height()).with_message(failures)).await?;
+            }
+            expectations::enforce(&results, &config.expectations)?;
+
+            // Train the quality model
+            model::train_stage(&transformed_df, &config.model, run)?;
+            let transformed_df = model::score_stage(transformed_df, &config.model)?;
+
+            // Derive analysis columns
+            let transformed_df = pca::pca_stage(transformed_df, &config.pca, run)?;
+            let transformed_df = clustering::clustering_stage(transformed_df, &config.clustering, run)?;
+            analysis::run_tests(&pool, run, &transformed_df, &config.analysis).await?;
+
+            let transformed_df = rounding::round_stage(transformed_df, &config.rounding, &config.schema)?;
+            let transformed_df = downcast::downcast_stage(transformed_df, &config.downcast, &config.schema)?;
+            let transformed_df = audit::add_audit_columns(transformed_df, run)?;
+            checkpoint.prepared = Some(transformed_df.clone());
+            check_resources(monitor)?;
+            transformed_df
+        }
+    };
+
+    // Store data
+    let transformed_df = match &checkpoint.stored {
+        Some(df) => df.clone(),
+        None => {
+            status::enter(&run.id, "store");
+            hooks.fire(HookEvent::new(HookPoint::BeforeStore, &run.id).with_rows(transformed_df.height())).await?;
+            let registry = TypeRegistry::with_overrides(&config.storage.column_types);
+            let table = staging::target_table(&pool, config).await?;
+            let schema = evolution::evolve(&pool, table, &transformed_df, config, &registry).await?;
+            let mut options = storage::InsertOptions { table, load_id: Some(&run.id), offsets: checkpoint.offsets.as_ref(), ..storage::InsertOptions::from_config(&config.storage) };
+            // A retry stores the rows again, so it must know which of them the failed attempt committed
+            let tracked = ResumableConfig { enabled: true, ..config.storage.resumable.clone() };
+            if config.retry.max_retries > 0 && options.resumable.is_none() && options.partitioning.is_none() && options.offsets.is_none() {
+                options.resumable = Some(&tracked);
+            }
+            let rejects = storage::store_data(&pool, &transformed_df, &schema, &registry, cipher.as_ref(), options).await?;
+            run.log(format_args!("Data storage complete. {} rows rejected.", rejects.len()));
+            if !rejects.is_empty() {
+                run.add_warning("store", format_args!("{} of {} rows were rejected by the database", rejects.len(), transformed_df.height()));
+            }
+            checkpoint.rejected = rejects.len();
+            checkpoint.rejects = (!rejects.is_empty()).then(|| storage::rejects_frame(&transformed_df, &rejects)).transpose()?;
+            checkpoint.lake = (config.iceberg.enabled || config.dataset.enabled).then(|| storage::accepted_frame(&transformed_df, &rejects)).transpose()?;
+            checkpoint.stored = Some(transformed_df.clone());
+            hooks
+                .fire(HookEvent::new(HookPoint::AfterStore, &run.id).with_rows(transformed_df.height() - rejects.len()).with_rejected(rejects.len()))
+                .await?;
+            check_resources(monitor)?;
+            transformed_df
+        }
+    };
+    status::enter(&run.id, "finish");
+    if let Some(rejects) = &checkpoint.rejects {
+        persist(&artifacts, &config.downcast, cipher.as_ref(), run, "rejects", rejects).await?;
+        checkpoint.rejects = None;
+    }
+    if let Some(accepted) = &checkpoint.lake {
+        lakehouse::append(&config.iceberg, &config.schema, cipher.as_ref(), run, accepted).await?;
+        dataset::append(&config.dataset, &config.schema, cipher.as_ref(), run, accepted).await?;
+        checkpoint.lake = None;
+    }
+    column_stats::record(&pool, run, &transformed_df, &config.column_stats).await?;
+    catalog::publish(&pool, run, &transformed_df, &config.catalog, &config.storage.table, &source.describe(), &config.enabled_stages()).await?;
+    if config.staging.enabled {
+        stage_for_review(&pool, run, config).await?;
+    } else {
+        aggregates::refresh_stage(&pool, &config.schema, &config.aggregates).await?;
+    }
+
+    // Retrieve and print first 5 rows
+    storage::get_first_5_rows(&pool, &config.schema, &config.storage.table).await?;
+    run.log("Data retrieved and printed successfully.");
+
+    enforce_retention(&pool, config, artifacts.as_ref()).await?;
+
+    let stored = transformed_df.height() - checkpoint.rejected;
+    let usage = monitor.map(ResourceMonitor::usage);
+    history::record_attempt(&pool, run, Some(&fingerprint), attempt, Some(stored), None, usage.as_ref()).await?;
+    record_files(&pool, run, config, checkpoint).await?;
+    report_performance(&pool, run, config, hooks, stored, usage.as_ref()).await?;
+    hooks.fire(HookEvent::new(HookPoint::OnSuccess, &run.id).with_rows(stored)).await?;
+    report_recovery(&pool, run, config, hooks, stored).await?;
+    report_warnings(run);
+    run.log("Data pipeline finished successfully.");
+
+    Ok(())
+}
+
+/// Helper function to create the source of the run, leaving out the input files earlier runs loaded with `[deduplication] skip_processed_files`.
+///
+/// Returns `None` if every input file was loaded before, and the run is skipped.
+async fn select_files(pool: &PgPool, run: &RunContext, config: &PipelineConfig, checkpoint: &mut Checkpoint) -> Result<Option<Box<dyn Source>>> {
+    let path = config.source.path().filter(|path| *path != ingestion::STDIN_PATH);
+    if let Some(path) = path.filter(|_| config.deduplication.incremental) {
+        return select_tails(pool, run, config, checkpoint, path).await;
+    }
+    let Some(path) = path.filter(|_| config.deduplication.skip_processed_files) else {
+        return Ok(Some(source::from_config(config)));
+    };
+    if checkpoint.files.is_none() {
+        let selection = processed_files::select(pool, path).await?;
+        for (file, reason) in &selection.skipped {
+            run.log(format_args!("Skipping input file {}: {}", file.path, reason));
+        }
+        checkpoint.files = Some(selection);
+    }
+    let selection = checkpoint.files.as_ref().expect("files selected above");
+    if selection.new.is_empty() {
+        run.log(format_args!("Skipping run: every file of {} was loaded before", path));
+        return Ok(None);
+    }
+    if selection.skipped.is_empty() {
+        return Ok(Some(source::from_config(config)));
+    }
+    let files: Vec<String> = selection.new.iter().map(|file| file.path.clone()).collect();
+    Ok(Some(source::from_files(config, &files)))
+}
+
+/// Helper function to create the source reading only the rows appended to the input files since earlier runs, with `[deduplication] incremental`.
+///
+/// Returns `None` if no rows were appended to any file, and the run is skipped.
+async fn select_tails(pool: &PgPool, run: &RunContext, config: &PipelineConfig, checkpoint: &mut Checkpoint, path: &str) -> Result<Option<Box<dyn Source>>> {
+    let SourceConfig::Csv { options, .. } = &config.source else {
+        bail!("deduplication.incremental reads only CSV files");
+    };
+    if checkpoint.tails.is_none() {
+        let tails = bookmarks::plan(pool, path, options).await?;
+        for tail in &tails.tails {
+            run.log(tail.describe());
+        }
+        checkpoint.tails = Some(tails);
+    }
+    let tails = checkpoint.tails.as_ref().expect("tails planned above");
+    if tails.files.is_empty() {
+        run.log(format_args!("Skipping run: no rows were appended to {} since the last run", path));
+        return Ok(None);
+    }
+    Ok(Some(source::from_files(config, &tails.files)))
+}
+
+/// Helper function to record the input files a run stored, and how far it read them, unless it stored only part of them.
+async fn record_files(pool: &PgPool, run: &RunContext, config: &PipelineConfig, checkpoint: &Checkpoint) -> Result<()> {
+    if config.row_limit.is_some() || config.sampling.describe().is_some() {
+        return Ok(());
+    }
+    if let Some(tails) = &checkpoint.tails {
+        bookmarks::record(pool, &run.id, &tails.tails).await?;
+    }
+    match &checkpoint.files {
+        Some(selection) => processed_files::record(pool, &run.id, &selection.new).await,
+        None => Ok(()),
+    }
+}
+
+/// Helper function to list the warnings of a run at its end.
+fn report_warnings(run: &RunContext) {
+    if let Some(warnings) = run.warnings.describe() {
+        run.warn(format_args!("Finished with {}", warnings));
+    }
+}
+
+/// Helper function to delete the rows and artifacts of the runs older than their retention periods.
+async fn enforce_retention(pool: &PgPool, config: &PipelineConfig, artifacts: Option<&ArtifactStore>) -> Result<()> {
+    if let Some(store) = artifacts {
+        store.apply_retention().await?;
+    }
+    if config.retention.enabled {
+        retention::apply_retention(pool, &config.schema, &config.storage.table, &config.retention).await?;
+    }
+    Ok(())
+}
+
+/// Helper function to write an intermediate DataFrame to the artifact store, if one is configured.
+///
+/// Rejects are written as they are, since replaying them stores them into the declared columns. The
+/// encrypted columns are encrypted first, as in PostgreSQL, so no artifact holds their plaintext.
+async fn persist(artifacts: &Option<ArtifactStore>, downcast: &DowncastConfig, cipher: Option<&ColumnCipher>, run: &RunContext, name: &str, df: &DataFrame) -> Result<()> {
+    let Some(store) = artifacts.as_ref().filter(|store| store.wants(name)) else { return Ok(()) };
+    let df = match cipher {
+        Some(cipher) => cipher.encrypt_frame(df)?,
+        None => df.clone(),
+    };
+    if downcast.enabled && name != "rejects" {
+        store.put(run, name, &downcast::narrow_all(df)?).await
+    } else {
+        store.put(run, name, &df).await
+    }
+}
+
+/// Helper function to compare the run with previous runs, alerting through hooks if it regressed.
+async fn report_performance(pool: &PgPool, run: &RunContext, config: &PipelineConfig, hooks: &Hooks, stored: usize, usage: Option<&PeakUsage>) -> Result<()> {
+    let format = ReportFormat::from_config(&config.reports);
+    let summary = history::compare_with_history(pool, run, stored, &config.performance).await?;
+    run.log(summary.describe(&format));
+    if let Some(usage) = usage {
+        run.log(usage.describe(&format));
+    }
+    if let Some(regressions) = summary.describe_regressions() {
+        run.warn(&regressions);
+        hooks.fire(HookEvent::new(HookPoint::OnPerformanceRegression, &run.id).with_rows(stored).with_message(regressions)).await?;
+    }
+    Ok(())
+}
+
+/// Helper function to abort the attempt at the end of a stage if the memory of the process exceeded the ceiling during it.
+fn check_resources(monitor: Option<&ResourceMonitor>) -> Result<()> {
+    match monitor {
+        Some(monitor) => monitor.check(),
+        None => Ok(()),
+    }
+}
+
+/// Helper function to close the incident of the input after a successful run, notifying the recovery through hooks.
+async fn report_recovery(pool: &PgPool, run: &RunContext, config: &PipelineConfig, hooks: &Hooks, stored: usize) -> Result<()> {
+    if !config.alerts.deduplicate {
+        return Ok(());
+    }
+    if let Some(incident) = alerts::record_success(pool, &alerts::alert_key(config)).await? {
+        let recovery = incident.describe_recovery();
+        run.log(&recovery);
+        hooks.fire(HookEvent::new(HookPoint::OnRecovery, &run.id).with_rows(stored).with_message(recovery)).await?;
+    }
+    Ok(())
+}
+
+/// Helper function to record a staged run and point to its verification report.
+async fn stage_for_review(pool: &PgPool, run: &RunContext, config: &PipelineConfig) -> Result<()> {
+    let report = staging::record(pool, run, config).await?;
+    run.log(format_args!(
+        "Rows staged in {} for review; see {} and run `pipeline promote --run {}` to load them",
+        config.staging.table,
+        report.display(),
+        run.id
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles() {
+        assert_eq!(backoff(5, 1), Duration::from_secs(5));
+        assert_eq!(backoff(5, 2), Duration::from_secs(10));
+        assert_eq!(backoff(5, 4), Duration::from_secs(40));
+        assert_eq!(backoff(0, 3), Duration::ZERO);
+    }
+}
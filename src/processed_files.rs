@@ -0,0 +1,144 @@
+//! This module keeps track of the input files runs have loaded, so a file is never loaded twice.
+//!
+//! With `[deduplication] skip_processed_files`, a run reading a local file, or the files a glob pattern
+//! matches, checksums each file with SHA-256 and leaves out those whose checksum is recorded in the
+//! `processed_files` table, along with files repeating the content of another file of the same input.
+//! Once the run has stored the remaining files, their checksums are recorded too. Running the binary
+//! again on the same directory then loads only the files added since, and a run whose files were all
+//! loaded before is skipped. Since a file is known by its content, a renamed copy is skipped as well,
+//! while a file rewritten in place is loaded again.
+
+use crate::ingestion;
+use crate::seed;
+use crate::source;
+use anyhow::{Context, Result};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+
+/// DDL of the table of loaded files, one row per distinct content.
+pub const PROCESSED_FILES_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS processed_files (
+    checksum TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+)";
+
+/// An input file and the SHA-256 checksum of its content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputFile {
+    pub path: String,
+    pub checksum: String,
+}
+
+/// The files of a run's input, split into those to load and those left out.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileSelection {
+    pub new: Vec<InputFile>,
+    /// Files left out, each with the reason, e.g. the run that loaded it.
+    pub skipped: Vec<(InputFile, String)>,
+}
+
+/// Checksums the files of an input and looks up which of them earlier runs loaded.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `path` - The input file, or a glob pattern matching several.
+///
+/// # Returns
+///
+/// * `Result<FileSelection>` - The files to load and those to leave out, or an error if a file cannot be read or the lookup fails.
+///
+/// # Example
+///
+/// ```
+/// let selection = select(&pool, "data/2023-*.csv").await?;
+/// ```
+pub async fn select(pool: &PgPool, path: &str) -> Result<FileSelection> {
+    let mut files = vec![];
+    for path in ingestion::expand_paths(path)? {
+        let checksum = source::file_checksum(&path).await?;
+        files.push(InputFile { path, checksum });
+    }
+    let checksums: Vec<&str> = files.iter().map(|f| f.checksum.as_str()).collect();
+    // Looked up before the run sets up the database, so on a fresh one no file was loaded yet
+    let processed: Vec<(String, String, String)> = if seed::table_exists(pool, "processed_files").await? {
+        sqlx::query_as("SELECT checksum, path, run_id FROM processed_files WHERE checksum = ANY($1)")
+            .bind(&checksums)
+            .fetch_all(pool)
+            .await
+            .context("Failed to look up the processed files")?
+    } else {
+        vec![]
+    };
+    let processed = processed.into_iter().map(|(checksum, path, run_id)| (checksum, format!("run {} loaded it as {}", run_id, path))).collect();
+    Ok(split(files, processed))
+}
+
+/// Records the files a run loaded, so later runs leave them out.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run_id` - The ID of the run that loaded the files.
+/// * `files` - The loaded files.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the files cannot be recorded.
+pub async fn record(pool: &PgPool, run_id: &str, files: &[InputFile]) -> Result<()> {
+    let checksums: Vec<&str> = files.iter().map(|f| f.checksum.as_str()).collect();
+    let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+    sqlx::query(
+        "INSERT INTO processed_files (checksum, path, run_id) SELECT checksum, path, $3 FROM UNNEST($1::TEXT[], $2::TEXT[]) AS f (checksum, path) \
+         ON CONFLICT (checksum) DO NOTHING",
+    )
+    .bind(&checksums)
+    .bind(&paths)
+    .bind(run_id)
+    .execute(pool)
+    .await
+    .context(format!("Failed to record the files loaded by run {}", run_id))?;
+    Ok(())
+}
+
+/// Helper function to split files into new ones and those whose checksum is processed, or repeats that of an earlier file.
+fn split(files: Vec<InputFile>, mut processed: HashMap<String, String>) -> FileSelection {
+    let mut selection = FileSelection::default();
+    for file in files {
+        match processed.get(&file.checksum) {
+            Some(reason) => {
+                let reason = reason.clone();
+                selection.skipped.push((file, reason));
+            }
+            None => {
+                processed.insert(file.checksum.clone(), format!("same content as {}", file.path));
+                selection.new.push(file);
+            }
+        }
+    }
+    selection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_skips_processed_and_repeated_files() {
+        let file = |path: &str, checksum: &str| InputFile { path: path.to_string(), checksum: checksum.to_string() };
+        let files = vec![file("data/2023-01.csv", "a1"), file("data/2023-02.csv", "b2"), file("data/2023-02 (copy).csv", "b2"), file("data/2023-03.csv", "c3")];
+        let processed = HashMap::from([("a1".to_string(), "run 01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B loaded it as data/2023-01.csv".to_string())]);
+
+        let selection = split(files, processed);
+
+        assert_eq!(selection.new, vec![file("data/2023-02.csv", "b2"), file("data/2023-03.csv", "c3")]);
+        assert_eq!(
+            selection.skipped,
+            vec![
+                (file("data/2023-01.csv", "a1"), "run 01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B loaded it as data/2023-01.csv".to_string()),
+                (file("data/2023-02 (copy).csv", "b2"), "same content as data/2023-02.csv".to_string()),
+            ]
+        );
+    }
+}
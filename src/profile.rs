@@ -0,0 +1,480 @@
+//! This module profiles input files before they are wired into the pipeline.
+//!
+//! It provides functions for computing per-column statistics, outlier counts, pairwise correlations
+//! and suggested validation rules, and for printing them as tables. `pipeline suggest-rules` turns the
+//! profile of a trusted historical file into a draft `[expectations]` suite: the values of each numeric
+//! column between two percentiles, the observed values of low-cardinality integer columns, and no
+//! nulls in the columns that had none. The draft is meant to be reviewed before it is pasted into the
+//! configuration.
+
+use crate::expectations::Expectation;
+use crate::ingestion;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use prettytable::{row, Table};
+use std::collections::BTreeSet;
+use std::fmt;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Value};
+
+/// Integer columns with at most this many distinct values get an allowed-values rule suggestion.
+const MAX_CATEGORIES: usize = 20;
+
+/// Statistics computed for numeric columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumericStats {
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std: f64,
+    /// Number of values outside 1.5 interquartile ranges of the quartiles.
+    pub outliers: usize,
+}
+
+/// Profile of a single column.
+#[derive(Debug, Clone)]
+pub struct ColumnProfile {
+    pub name: String,
+    pub dtype: DataType,
+    pub null_count: usize,
+    pub distinct: usize,
+    pub numeric: Option<NumericStats>,
+    /// Observed values of low-cardinality integer columns.
+    pub categories: Option<Vec<i64>>,
+}
+
+/// Profile of a whole DataFrame.
+#[derive(Debug, Clone)]
+pub struct DataProfile {
+    pub rows: usize,
+    pub columns: Vec<ColumnProfile>,
+    /// Pearson correlation for every pair of numeric columns.
+    pub correlations: Vec<(String, String, f64)>,
+}
+
+/// A validation rule suggested from the observed data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SuggestedRule {
+    NotNull { column: String },
+    Range { column: String, min: f64, max: f64 },
+    AllowedValues { column: String, values: Vec<i64> },
+}
+
+impl fmt::Display for SuggestedRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuggestedRule::NotNull { column } => write!(f, "{} must not be null", column),
+            SuggestedRule::Range { column, min, max } => write!(f, "{} must be between {} and {}", column, min, max),
+            SuggestedRule::AllowedValues { column, values } => write!(f, "{} must be one of {:?}", column, values),
+        }
+    }
+}
+
+/// What `pipeline suggest-rules` profiles and writes.
+#[derive(Debug, Clone, clap::Args)]
+pub struct SuggestRulesRequest {
+    /// Path to a trusted historical file, whose values the suggested rules accept.
+    pub file: String,
+    /// Path of the TOML draft to write; printed to standard output when omitted.
+    #[arg(long)]
+    pub output: Option<String>,
+    /// Percentile of the lower bound of each suggested range.
+    #[arg(long, default_value_t = 0.1)]
+    pub low_percentile: f64,
+    /// Percentile of the upper bound of each suggested range.
+    #[arg(long, default_value_t = 99.9)]
+    pub high_percentile: f64,
+}
+
+/// Ingests a file and prints its full profile.
+///
+/// # Arguments
+///
+/// * `file_path` - A string slice that holds the path to the file to profile.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of profiling.
+///
+/// # Example
+///
+/// ```
+/// run_profile("data/dataset.csv").expect("Profiling failed");
+/// ```
+pub fn run_profile(file_path: &str) -> Result<()> {
+    let df = ingestion::ingest_csv(file_path)?;
+    let profile = profile_dataframe(&df)?;
+    print_profile(&profile);
+    Ok(())
+}
+
+/// Computes the profile of a DataFrame.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to profile.
+///
+/// # Returns
+///
+/// * `Result<DataProfile>` - A result containing the profile, or an error if a column cannot be analysed.
+pub fn profile_dataframe(df: &DataFrame) -> Result<DataProfile> {
+    let mut columns = vec![];
+    let mut numeric_values = vec![];
+
+    for series in df.get_columns() {
+        let distinct = series.n_unique().context(format!("Error counting distinct values of {}", series.name()))?;
+        let (numeric, categories) = if series.dtype().is_numeric() {
+            let values = float_values(series)?;
+            let stats = numeric_stats(&values)?;
+            let categories = (series.dtype().is_integer() && distinct <= MAX_CATEGORIES).then(|| {
+                let observed: BTreeSet<i64> = values.iter().flatten().map(|&v| v as i64).collect();
+                observed.into_iter().collect()
+            });
+            numeric_values.push((series.name().to_string(), values));
+            (stats, categories)
+        } else {
+            (None, None)
+        };
+
+        columns.push(ColumnProfile {
+            name: series.name().to_string(),
+            dtype: series.dtype().clone(),
+            null_count: series.null_count(),
+            distinct,
+            numeric,
+            categories,
+        });
+    }
+
+    let mut correlations = vec![];
+    for (i, (left_name, left)) in numeric_values.iter().enumerate() {
+        for (right_name, right) in &numeric_values[i + 1..] {
+            if let Some(r) = pearson(left, right) {
+                correlations.push((left_name.clone(), right_name.clone(), r));
+            }
+        }
+    }
+
+    Ok(DataProfile {
+        rows: df.height(),
+        columns,
+        correlations,
+    })
+}
+
+/// Suggests validation rules that the profiled data satisfies.
+///
+/// Columns without nulls get a not-null rule, numeric columns get their observed range, and
+/// low-cardinality integer columns get their observed set of values.
+pub fn suggest_rules(profile: &DataProfile) -> Vec<SuggestedRule> {
+    let mut rules = vec![];
+
+    for column in &profile.columns {
+        if column.null_count == 0 {
+            rules.push(SuggestedRule::NotNull { column: column.name.clone() });
+        }
+
+        if let Some(values) = &column.categories {
+            rules.push(SuggestedRule::AllowedValues { column: column.name.clone(), values: values.clone() });
+        } else if let Some(stats) = &column.numeric {
+            rules.push(SuggestedRule::Range { column: column.name.clone(), min: stats.min, max: stats.max });
+        }
+    }
+
+    rules
+}
+
+/// Profiles a trusted file and writes a draft expectation suite that its values satisfy.
+///
+/// # Arguments
+///
+/// * `request` - The file to profile, the percentiles of the ranges, and where to write the draft.
+///
+/// # Returns
+///
+/// * `Result<String>` - The draft configuration, or an error if the percentiles are out of order or a file cannot be read or written.
+///
+/// # Example
+///
+/// ```
+/// run_suggest_rules(&request).expect("Failed to suggest rules");
+/// ```
+pub fn run_suggest_rules(request: &SuggestRulesRequest) -> Result<String> {
+    if !(0.0 <= request.low_percentile && request.low_percentile < request.high_percentile && request.high_percentile <= 100.0) {
+        bail!("--low-percentile and --high-percentile must satisfy 0 <= low < high <= 100, got {} and {}", request.low_percentile, request.high_percentile);
+    }
+    let df = ingestion::ingest_csv(&request.file)?;
+    let suite = suggest_suite(&df, request.low_percentile / 100.0, request.high_percentile / 100.0)?;
+    let draft = format!(
+        "# Draft expectations suggested by `pipeline suggest-rules` from {} ({} rows).\n# Review every rule before pasting the section into the configuration.\n{}",
+        request.file,
+        df.height(),
+        draft_config(&suite)
+    );
+
+    match &request.output {
+        Some(output) => {
+            std::fs::write(output, &draft).context(format!("Failed to write {}", output))?;
+            println!("Wrote {} suggested expectations to {}", suite.len(), output);
+        }
+        None => print!("{}", draft),
+    }
+    Ok(draft)
+}
+
+/// Suggests an expectation suite that the data satisfies, trimming the ranges to percentiles.
+///
+/// Unlike [`suggest_rules`], which reports the full observed range, each numeric range is bound by the
+/// values at the `low` and `high` quantiles, so a few extreme values in the trusted data do not widen it.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the trusted data.
+/// * `low` - Quantile of the lower bounds, e.g. 0.001.
+/// * `high` - Quantile of the upper bounds, e.g. 0.999.
+///
+/// # Returns
+///
+/// * `Result<Vec<Expectation>>` - The suggested expectations, in column order.
+pub fn suggest_suite(df: &DataFrame, low: f64, high: f64) -> Result<Vec<Expectation>> {
+    let profile = profile_dataframe(df)?;
+    let mut suite = vec![];
+
+    for column in &profile.columns {
+        if column.null_count == 0 {
+            suite.push(Expectation::ColumnValuesNotNull { column: column.name.clone() });
+        }
+
+        if let Some(values) = &column.categories {
+            suite.push(Expectation::ColumnValuesInSet { column: column.name.clone(), values: values.iter().map(|&v| v as f64).collect() });
+        } else if column.numeric.is_some() {
+            let values = df.column(&column.name)?.cast(&DataType::Float64)?;
+            let values = values.f64()?;
+            // Bounds are values of the data, never interpolated between two of them
+            let bound = |q: f64, interpolation: QuantileInterpolOptions| -> Result<Option<f64>> {
+                values.quantile(q, interpolation).context(format!("Error calculating quantile {} of {}", q, column.name))
+            };
+            suite.push(Expectation::ColumnValuesBetween {
+                column: column.name.clone(),
+                min: bound(low, QuantileInterpolOptions::Lower)?,
+                max: bound(high, QuantileInterpolOptions::Higher)?,
+            });
+        }
+    }
+
+    Ok(suite)
+}
+
+/// Helper function to render a suite as the `[expectations]` section of a configuration file.
+fn draft_config(suite: &[Expectation]) -> String {
+    // Whole numbers are written as integers, which also read as the bounds of integer columns
+    let number = |v: f64| -> Value { if v.fract() == 0.0 && v.abs() < 1e15 { Value::from(v as i64) } else { Value::from(v) } };
+
+    let mut tables = ArrayOfTables::new();
+    for expectation in suite {
+        let mut table = toml_edit::Table::new();
+        let (expect, column) = match expectation {
+            Expectation::ColumnValuesNotNull { column } => ("column_values_not_null", column),
+            Expectation::ColumnValuesInSet { column, .. } => ("column_values_in_set", column),
+            Expectation::ColumnValuesBetween { column, .. } => ("column_values_between", column),
+            // Only the expectations above are suggested
+            _ => continue,
+        };
+        table["expect"] = toml_edit::value(expect);
+        table["column"] = toml_edit::value(column.as_str());
+        match expectation {
+            Expectation::ColumnValuesInSet { values, .. } => table["values"] = toml_edit::value(values.iter().map(|&v| number(v)).collect::<toml_edit::Array>()),
+            Expectation::ColumnValuesBetween { min, max, .. } => {
+                if let Some(min) = min {
+                    table["min"] = Item::Value(number(*min));
+                }
+                if let Some(max) = max {
+                    table["max"] = Item::Value(number(*max));
+                }
+            }
+            _ => {}
+        }
+        tables.push(table);
+    }
+
+    let mut document = DocumentMut::new();
+    document["expectations"] = toml_edit::table();
+    document["expectations"]["suite"] = Item::ArrayOfTables(tables);
+    document.to_string()
+}
+
+/// Prints a profile as tables: column overview, distributions, strongest correlations and suggested rules.
+pub fn print_profile(profile: &DataProfile) {
+    println!("Rows: {}, Columns: {}", profile.rows, profile.columns.len());
+
+    let mut overview = Table::new();
+    overview.add_row(row!["Column", "Dtype", "Nulls", "Null %", "Distinct"]);
+    for column in &profile.columns {
+        let null_pct = if profile.rows > 0 { 100.0 * column.null_count as f64 / profile.rows as f64 } else { 0.0 };
+        overview.add_row(row![column.name, column.dtype, column.null_count, format!("{:.2}", null_pct), column.distinct]);
+    }
+    overview.printstd();
+
+    let mut distributions = Table::new();
+    distributions.add_row(row!["Column", "Min", "P25", "Median", "P75", "Max", "Mean", "Std", "Outliers"]);
+    for column in &profile.columns {
+        if let Some(s) = &column.numeric {
+            distributions.add_row(row![
+                column.name,
+                format!("{:.4}", s.min),
+                format!("{:.4}", s.p25),
+                format!("{:.4}", s.median),
+                format!("{:.4}", s.p75),
+                format!("{:.4}", s.max),
+                format!("{:.4}", s.mean),
+                format!("{:.4}", s.std),
+                s.outliers
+            ]);
+        }
+    }
+    distributions.printstd();
+
+    let mut correlations = profile.correlations.clone();
+    correlations.sort_by(|a, b| b.2.abs().partial_cmp(&a.2.abs()).unwrap_or(std::cmp::Ordering::Equal));
+    let mut correlation_table = Table::new();
+    correlation_table.add_row(row!["Column", "Column", "Pearson r"]);
+    for (left, right, r) in correlations.iter().take(10) {
+        correlation_table.add_row(row![left, right, format!("{:.3}", r)]);
+    }
+    println!("Strongest correlations:");
+    correlation_table.printstd();
+
+    println!("Suggested validation rules:");
+    for rule in suggest_rules(profile) {
+        println!("  - {}", rule);
+    }
+}
+
+/// Helper function to convert a numeric column into f64 values, keeping nulls.
+fn float_values(series: &Series) -> Result<Vec<Option<f64>>> {
+    let series = series
+        .cast(&DataType::Float64)
+        .context(format!("Error converting {} column to f64", series.name()))?;
+    let values = series.f64()?.into_iter().collect();
+    Ok(values)
+}
+
+/// Computes distribution statistics over the non-null values, or `None` if there are none.
+fn numeric_stats(values: &[Option<f64>]) -> Result<Option<NumericStats>> {
+    let ca = Float64Chunked::from_iter(values.iter().copied());
+    let (Some(min), Some(max), Some(mean)) = (ca.min(), ca.max(), ca.mean()) else {
+        return Ok(None);
+    };
+
+    let quantile = |q: f64| -> Result<f64> {
+        ca.quantile(q, QuantileInterpolOptions::Linear)?
+            .context(format!("Error calculating quantile {}", q))
+    };
+    let p25 = quantile(0.25)?;
+    let p75 = quantile(0.75)?;
+    let iqr = p75 - p25;
+    let (low, high) = (p25 - 1.5 * iqr, p75 + 1.5 * iqr);
+    let outliers = values.iter().flatten().filter(|&&v| v < low || v > high).count();
+
+    Ok(Some(NumericStats {
+        min,
+        p25,
+        median: quantile(0.5)?,
+        p75,
+        max,
+        mean,
+        std: ca.std(1).unwrap_or(0.0),
+        outliers,
+    }))
+}
+
+/// Pearson correlation over the rows where both values are present.
+fn pearson(x: &[Option<f64>], y: &[Option<f64>]) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = x
+        .iter()
+        .zip(y)
+        .filter_map(|(a, b)| Some(((*a)?, (*b)?)))
+        .collect();
+    if pairs.len() < 2 {
+        return None;
+    }
+
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (a, b) in &pairs {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x).powi(2);
+        var_y += (b - mean_y).powi(2);
+    }
+
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x.sqrt() * var_y.sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_profile_dataframe() {
+        let df = df!(
+            "alcohol" => &[Some(9.0), Some(10.0), Some(11.0), Some(12.0), None, Some(40.0)],
+            "quality" => &[5i64, 5, 6, 6, 7, 8]
+        )
+        .unwrap();
+
+        let profile = profile_dataframe(&df).expect("Profiling failed");
+        assert_eq!(profile.rows, 6);
+
+        let alcohol = &profile.columns[0];
+        assert_eq!(alcohol.null_count, 1);
+        let stats = alcohol.numeric.as_ref().unwrap();
+        assert_eq!(stats.min, 9.0);
+        assert_eq!(stats.max, 40.0);
+        assert_eq!(stats.outliers, 1); // 40.0 is far outside the interquartile range
+
+        assert_eq!(profile.correlations.len(), 1);
+        assert!(profile.correlations[0].2 > 0.0);
+    }
+
+    #[test]
+    fn test_suggest_rules() {
+        let df = df!(
+            "alcohol" => &[Some(9.5), None, Some(12.0)],
+            "quality" => &[5i64, 6, 7]
+        )
+        .unwrap();
+
+        let rules = suggest_rules(&profile_dataframe(&df).unwrap());
+        assert!(rules.contains(&SuggestedRule::Range { column: "alcohol".to_string(), min: 9.5, max: 12.0 }));
+        assert!(rules.contains(&SuggestedRule::NotNull { column: "quality".to_string() }));
+        assert!(rules.contains(&SuggestedRule::AllowedValues { column: "quality".to_string(), values: vec![5, 6, 7] }));
+        assert!(!rules.contains(&SuggestedRule::NotNull { column: "alcohol".to_string() }));
+    }
+
+    #[test]
+    fn test_suggest_suite_trims_ranges_to_percentiles() {
+        let mut alcohol: Vec<f64> = (0..1000).map(|i| 9.0 + i as f64 / 1000.0).collect();
+        alcohol[500] = 80.0;
+        let quality: Vec<i64> = (0..1000).map(|i| 3 + i % 6).collect();
+        let df = df!("alcohol" => &alcohol, "quality" => &quality).unwrap();
+
+        let suite = suggest_suite(&df, 0.01, 0.99).unwrap();
+        let Expectation::ColumnValuesBetween { min: Some(min), max: Some(max), .. } = suite[1] else { panic!("Expected a range, got {:?}", suite[1]) };
+        assert!((9.0..9.01).contains(&min));
+        assert!(max < 10.0, "the outlier widened the range to {}", max);
+        assert_eq!(suite[3], Expectation::ColumnValuesInSet { column: "quality".to_string(), values: vec![3.0, 4.0, 5.0, 6.0, 7.0, 8.0] });
+
+        // The draft reads back as the expectations section of a configuration
+        let draft: toml::Table = toml::from_str(&draft_config(&suite)).unwrap();
+        let config: crate::config::ExpectationsConfig = draft["expectations"].clone().try_into().unwrap();
+        assert_eq!(config.suite.iter().map(|rule| rule.expectation.clone()).collect::<Vec<_>>(), suite);
+    }
+}
@@ -0,0 +1,136 @@
+//! This module reads stored wines back as typed records.
+//!
+//! [`WineQualityRecord`] has one field per column of the default wine schema and is decoded with
+//! `sqlx::FromRow`, so code reading the `wine_quality` table works with plain Rust values rather than
+//! `try_get` calls on untyped rows. Columns are looked up in the configured schema by their DataFrame
+//! name, so tables whose columns are renamed by `[column_mapping]` are read the same way. Wines can be
+//! read as the table held them when a given run completed, through [`timetravel`].
+
+use crate::schema::TableSchema;
+use crate::timetravel;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+
+/// The DataFrame column, record field and decoded type of each field of [`WineQualityRecord`], and whether it may be absent.
+const RECORD_FIELDS: [(&str, &str, &str, bool); 15] = [
+    ("fixed acidity", "fixed_acidity", "DOUBLE PRECISION", false),
+    ("volatile acidity", "volatile_acidity", "DOUBLE PRECISION", false),
+    ("citric acid", "citric_acid", "DOUBLE PRECISION", false),
+    ("residual sugar", "residual_sugar", "DOUBLE PRECISION", false),
+    ("chlorides", "chlorides", "DOUBLE PRECISION", false),
+    ("free sulfur dioxide", "free_sulfur_dioxide", "INTEGER", false),
+    ("total sulfur dioxide", "total_sulfur_dioxide", "INTEGER", false),
+    ("density", "density", "DOUBLE PRECISION", false),
+    ("pH", "ph", "DOUBLE PRECISION", false),
+    ("sulphates", "sulphates", "DOUBLE PRECISION", false),
+    ("alcohol", "alcohol", "DOUBLE PRECISION", false),
+    ("quality", "quality", "INTEGER", false),
+    ("predicted_quality", "predicted_quality", "DOUBLE PRECISION", true),
+    ("cluster", "cluster", "INTEGER", true),
+    ("run_id", "run_id", "TEXT", true),
+];
+
+/// A stored wine of the `wine_quality` table.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct WineQualityRecord {
+    pub id: i32,
+    pub fixed_acidity: f64,
+    pub volatile_acidity: f64,
+    pub citric_acid: f64,
+    pub residual_sugar: f64,
+    pub chlorides: f64,
+    pub free_sulfur_dioxide: i32,
+    pub total_sulfur_dioxide: i32,
+    pub density: f64,
+    pub ph: f64,
+    pub sulphates: f64,
+    pub alcohol: f64,
+    pub quality: i32,
+    /// Set when the run scored the data with a trained model.
+    pub predicted_quality: Option<f64>,
+    /// Set when the run labeled the data with k-means clusters.
+    pub cluster: Option<i32>,
+    /// The run that stored the wine.
+    pub run_id: Option<String>,
+}
+
+/// Reads the stored wines of at least a given quality.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The schema of the `wine_quality` table, mapping record fields to table columns.
+/// * `min_quality` - The lowest quality to read.
+/// * `as_of` - The ID of a run to read the table as it was when that run completed, or `None` to read it as it is.
+///
+/// # Returns
+///
+/// * `Result<Vec<WineQualityRecord>>` - The matching wines in insertion order, or an error if the schema
+///   lacks a column of the record, the run never succeeded, or the query fails.
+///
+/// # Example
+///
+/// ```
+/// let wines = fetch_by_quality(&pool, &config.schema, 7, None).await.expect("Failed to read wines");
+/// ```
+pub async fn fetch_by_quality(pool: &PgPool, schema: &TableSchema, min_quality: i32, as_of: Option<&str>) -> Result<Vec<WineQualityRecord>> {
+    let quality = column(schema, "quality")?;
+    let (source, at) = match as_of {
+        Some(run_id) => (timetravel::snapshot_sql("wine_quality", timetravel::run_column(schema)?, "$2"), Some(timetravel::load_time(pool, run_id).await?)),
+        None => ("wine_quality".to_string(), None),
+    };
+    let sql = format!("{} WHERE {} >= $1 ORDER BY id", select_sql(schema, &source)?, quality);
+    let mut query = sqlx::query_as::<_, WineQualityRecord>(&sql).bind(min_quality);
+    if let Some(at) = at {
+        query = query.bind(at);
+    }
+    query
+        .fetch_all(pool)
+        .await
+        .context(format!("Failed to fetch wines of quality {} or more", min_quality))
+}
+
+/// Helper function to build the `SELECT` decoding rows of the table, or of a subquery with its columns, into [`WineQualityRecord`]s.
+fn select_sql(schema: &TableSchema, source: &str) -> Result<String> {
+    let mut fields = vec!["id".to_string()];
+    for (name, field, pg_type, optional) in RECORD_FIELDS {
+        let value = match schema.columns.iter().find(|c| c.name == name) {
+            Some(spec) => format!("{}::{}", spec.column, pg_type),
+            None if optional => format!("NULL::{}", pg_type),
+            None => column(schema, name)?.to_string(),
+        };
+        fields.push(format!("{} AS {}", value, field));
+    }
+    Ok(format!("SELECT {} FROM {}", fields.join(", "), source))
+}
+
+/// Helper function to look up the table column of a DataFrame column.
+fn column<'a>(schema: &'a TableSchema, name: &str) -> Result<&'a str> {
+    schema
+        .columns
+        .iter()
+        .find(|c| c.name == name)
+        .map(|c| c.column.as_str())
+        .context(format!("The schema has no column {:?}, which WineQualityRecord requires", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_sql_follows_schema() {
+        let mut schema = TableSchema::default();
+        schema.columns.retain(|c| c.name != "cluster");
+        schema.columns.iter_mut().find(|c| c.name == "pH").unwrap().column = "acidity_ph".to_string();
+
+        let sql = select_sql(&schema, "wine_quality").unwrap();
+        assert!(sql.starts_with("SELECT id, fixed_acidity::DOUBLE PRECISION AS fixed_acidity, "));
+        assert!(sql.contains("acidity_ph::DOUBLE PRECISION AS ph, "));
+        assert!(sql.contains("NULL::INTEGER AS cluster, run_id::TEXT AS run_id FROM wine_quality"));
+
+        schema.columns.retain(|c| c.name != "quality");
+        assert!(select_sql(&schema, "wine_quality").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! This module caches reference tables in memory across the runs of a process.
+//!
+//! Dictionary tables that expectations check values against, such as valid region codes, rarely
+//! change, yet every run reads them again. With `[reference_cache] enabled`, the values read from a
+//! table are kept for `ttl_secs` and shared by the following runs, so a daemon triggering micro-batch
+//! runs queries them once per TTL instead of once per run. `POST /cache/invalidate` of the control API
+//! drops cached tables at once, e.g. after a dictionary was updated.
+
+use crate::config::ReferenceCacheConfig;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static CACHE: OnceLock<Mutex<HashMap<(String, String), Entry>>> = OnceLock::new();
+
+/// The cached values of a key column.
+struct Entry {
+    values: Arc<HashSet<String>>,
+    loaded_at: Instant,
+}
+
+/// Helper function to access the cache of the process.
+fn cache() -> &'static Mutex<HashMap<(String, String), Entry>> {
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Returns the values of a key column of a reference table, from the cache while they are fresh.
+///
+/// # Arguments
+///
+/// * `table` - The reference table.
+/// * `key` - The key column of the table.
+/// * `config` - The cache settings; with the cache disabled, the values are always loaded.
+/// * `load` - Reads the values from the database.
+///
+/// # Returns
+///
+/// * `Result<Arc<HashSet<String>>>` - The values, or the error of `load`.
+///
+/// # Example
+///
+/// ```
+/// let values = reference::get_or_load("regions", "code", &config.reference_cache, || read_column(&pool, "regions", "code")).await?;
+/// ```
+pub async fn get_or_load<F, Fut>(table: &str, key: &str, config: &ReferenceCacheConfig, load: F) -> Result<Arc<HashSet<String>>>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Vec<String>>>,
+{
+    let ttl = Duration::from_secs(config.ttl_secs);
+    let name = (table.to_string(), key.to_string());
+    if config.enabled {
+        let cached = cache().lock().expect("reference cache poisoned");
+        if let Some(entry) = cached.get(&name).filter(|entry| entry.loaded_at.elapsed() < ttl) {
+            println!("Using the cached values of {}.{}, read {}s ago", table, key, entry.loaded_at.elapsed().as_secs());
+            return Ok(entry.values.clone());
+        }
+    }
+
+    let values: Arc<HashSet<String>> = Arc::new(load().await?.into_iter().collect());
+    if config.enabled {
+        let entry = Entry { values: values.clone(), loaded_at: Instant::now() };
+        cache().lock().expect("reference cache poisoned").insert(name, entry);
+    }
+    Ok(values)
+}
+
+/// Drops cached reference tables, so the next run reads them again.
+///
+/// # Arguments
+///
+/// * `table` - The table to drop, or `None` to drop every table.
+///
+/// # Returns
+///
+/// * `usize` - The number of cached key columns dropped.
+pub fn invalidate(table: Option<&str>) -> usize {
+    let mut cached = cache().lock().expect("reference cache poisoned");
+    let before = cached.len();
+    cached.retain(|(cached_table, _), _| table.is_some_and(|table| table != cached_table));
+    before - cached.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_cache_serves_fresh_values_until_invalidated() {
+        let loads = AtomicUsize::new(0);
+        let load = || async {
+            loads.fetch_add(1, Ordering::SeqCst);
+            Ok(vec!["BDX".to_string(), "RHN".to_string()])
+        };
+        let config = ReferenceCacheConfig { enabled: true, ttl_secs: 3600 };
+
+        let values = get_or_load("test_regions", "code", &config, load).await.unwrap();
+        get_or_load("test_regions", "code", &config, load).await.unwrap();
+        assert_eq!((values.len(), loads.load(Ordering::SeqCst)), (2, 1));
+
+        assert_eq!(invalidate(Some("test_grades")), 0);
+        assert_eq!(invalidate(Some("test_regions")), 1);
+        get_or_load("test_regions", "code", &config, load).await.unwrap();
+        get_or_load("test_regions", "code", &ReferenceCacheConfig { enabled: false, ..config }, load).await.unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+    }
+}
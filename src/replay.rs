@@ -0,0 +1,113 @@
+//! This module replays rows that a previous run could not store.
+//!
+//! The `rejects` artifact of the run is read back, transformed again with the current configuration,
+//! and inserted again. Rows that still fail are written as the `rejects` artifact of the replay, so
+//! they can be replayed once more after the next fix.
+
+use crate::artifacts::ArtifactStore;
+use crate::config::PipelineConfig;
+use crate::encryption::ColumnCipher;
+use crate::run::RunContext;
+use crate::storage::{self, ERROR_COLUMN, SOURCE_ROW_COLUMN};
+use crate::{audit, rounding, transformation};
+use crate::typemap::TypeRegistry;
+use anyhow::{Context, Result};
+use polars::prelude::*;
+use prettytable::{row, Table};
+
+/// Outcome of replaying one rejected row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOutcome {
+    pub source_row: Option<u64>,
+    pub previous_error: String,
+    /// `None` if the row is now stored, otherwise the new error.
+    pub error: Option<String>,
+}
+
+/// Replays the rejected rows of a run and prints which rows now succeed.
+///
+/// # Arguments
+///
+/// * `config` - The pipeline configuration; transformation and storage use its current settings.
+/// * `run_id` - The ID of the run whose rejects are replayed.
+///
+/// # Returns
+///
+/// * `Result<Vec<ReplayOutcome>>` - The outcome per rejected row, or an error if the artifact cannot be read or the rows cannot be transformed.
+///
+/// # Example
+///
+/// ```
+/// replay_dlq(&config, "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B").await.expect("Replay failed");
+/// ```
+pub async fn replay_dlq(config: &PipelineConfig, run_id: &str) -> Result<Vec<ReplayOutcome>> {
+    let store = ArtifactStore::from_config(&config.artifacts)?.context("Replaying rejects requires the [artifacts] section to be enabled")?;
+    // Runs encrypt the encrypted columns of their artifacts
+    let cipher = ColumnCipher::from_config(&config.storage.encryption)?;
+    let rejected = store.get(run_id, "rejects").await?;
+    let rejected = match &cipher {
+        Some(cipher) => cipher.decrypt_frame(&rejected)?,
+        None => rejected,
+    };
+    println!("Replaying {} rejected rows of run {}", rejected.height(), run_id);
+
+    let source_rows: Vec<Option<u64>> = match rejected.column(SOURCE_ROW_COLUMN) {
+        Ok(series) => series.cast(&DataType::UInt64)?.u64()?.into_iter().collect(),
+        Err(_) => vec![None; rejected.height()],
+    };
+    let previous_errors: Vec<String> = rejected
+        .column(ERROR_COLUMN)
+        .context(format!("Error fetching column {}", ERROR_COLUMN))?
+        .str()?
+        .into_iter()
+        .map(|e| e.unwrap_or_default().to_string())
+        .collect();
+
+    // The replay stores the rows as a run of its own
+    let replay = RunContext::new().with_labels(config.labels.clone());
+    let df = audit::drop_audit_columns(rejected.drop_many(&[SOURCE_ROW_COLUMN, ERROR_COLUMN]));
+    let df = transformation::transform_data(df, &config.transform.passthrough)?;
+    let df = transformation::apply_schema(df, &config.schema)?;
+    let df = rounding::round_stage(df, &config.rounding, &config.schema)?;
+    let df = audit::add_audit_columns(df, &replay)?;
+
+    let pool = storage::create_connection_pool().await?;
+    let registry = TypeRegistry::with_overrides(&config.storage.column_types);
+    let rejects = storage::store_data(&pool, &df, &config.schema, &registry, cipher.as_ref(), storage::InsertOptions::from_config(&config.storage)).await?;
+
+    let outcomes: Vec<ReplayOutcome> = (0..df.height())
+        .map(|i| ReplayOutcome {
+            source_row: source_rows[i],
+            previous_error: previous_errors[i].clone(),
+            error: rejects.iter().find(|r| r.row == i).map(|r| r.error.clone()),
+        })
+        .collect();
+    print_outcomes(&outcomes);
+
+    if !rejects.is_empty() && store.wants("rejects") {
+        let mut still_rejected = storage::rejects_frame(&df, &rejects)?;
+        // Keep pointing at the rows of the original run
+        let original_rows: Vec<Option<u64>> = rejects.iter().map(|r| source_rows[r.row]).collect();
+        still_rejected.with_column(Series::new(SOURCE_ROW_COLUMN, original_rows))?;
+        store.put(&replay, "rejects", &still_rejected).await?;
+        println!("{} rows still fail; replay them with `pipeline replay-dlq --run {}`", rejects.len(), replay.id);
+    }
+
+    Ok(outcomes)
+}
+
+fn print_outcomes(outcomes: &[ReplayOutcome]) {
+    let mut table = Table::new();
+    table.add_row(row!["Source row", "Previous error", "Result"]);
+    for outcome in outcomes {
+        table.add_row(row![
+            outcome.source_row.map(|r| r.to_string()).unwrap_or_default(),
+            outcome.previous_error,
+            outcome.error.as_deref().unwrap_or("stored")
+        ]);
+    }
+    table.printstd();
+
+    let stored = outcomes.iter().filter(|o| o.error.is_none()).count();
+    println!("{} of {} rejected rows stored", stored, outcomes.len());
+}
@@ -0,0 +1,182 @@
+//! This module tracks the memory and CPU the process uses while a run is in progress.
+//!
+//! With `[resources] monitor`, a background task samples the resident set size (RSS) and the CPU time
+//! of the process every `interval_ms`, from `/proc/self`. The run report gives the peak RSS and the CPU
+//! time the run used, which are stored in `pipeline_runs` too, so worker nodes can be sized from the
+//! runs they actually had. With `max_rss_mb`, a sample above the ceiling marks the run for abort: the
+//! pipeline checks the mark at the end of each stage and fails the attempt there, keeping the stages
+//! completed so far in its checkpoint, rather than being killed by the out-of-memory killer halfway
+//! through an insert. Sampling is only available on Linux; elsewhere monitoring is disabled with a
+//! warning.
+
+use crate::config::ResourcesConfig;
+use crate::locale::ReportFormat;
+use anyhow::{bail, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Clock ticks per second of the CPU times in `/proc/self/stat`, which Linux fixes at 100 for user space.
+const CLOCK_TICKS_PER_SEC: f64 = 100.0;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+/// Memory and CPU use of the process at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub rss_bytes: u64,
+    /// User and system CPU time since the process started.
+    pub cpu_secs: f64,
+}
+
+impl Usage {
+    /// Samples the usage of the process, or returns `None` where `/proc/self` is not available.
+    pub fn sample() -> Option<Self> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+        Some(Self { rss_bytes: parse_rss(&status)?, cpu_secs: parse_cpu_secs(&stat)? })
+    }
+}
+
+/// The resources a run used, for its report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakUsage {
+    pub peak_rss_bytes: u64,
+    /// CPU time the process used during the run.
+    pub cpu_secs: f64,
+    pub wall_secs: f64,
+}
+
+impl PeakUsage {
+    /// Average number of cores the run kept busy, e.g. 0.5 for half of one core.
+    pub fn cores(&self) -> f64 {
+        if self.wall_secs > 0.0 { self.cpu_secs / self.wall_secs } else { 0.0 }
+    }
+
+    /// Describes the usage, with numbers written as `format` says.
+    pub fn describe(&self, format: &ReportFormat) -> String {
+        format!(
+            "Peak memory {} MB RSS; {} s of CPU time ({} cores on average)",
+            format.decimal(self.peak_rss_bytes as f64 / BYTES_PER_MB as f64, 1),
+            format.decimal(self.cpu_secs, 1),
+            format.decimal(self.cores(), 2)
+        )
+    }
+}
+
+/// Samples the usage of the process in the background until it is dropped.
+pub struct ResourceMonitor {
+    peak_rss_bytes: Arc<AtomicU64>,
+    /// RSS of the first sample above the ceiling since the last check, 0 if there was none.
+    exceeded_rss_bytes: Arc<AtomicU64>,
+    max_rss_bytes: Option<u64>,
+    started: Instant,
+    start: Usage,
+    task: JoinHandle<()>,
+}
+
+impl ResourceMonitor {
+    /// Starts sampling, if monitoring is enabled and available.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The resource monitoring settings.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ResourceMonitor>` - The monitor, or `None` if monitoring is disabled or `/proc/self` cannot be read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let monitor = ResourceMonitor::start(&config.resources);
+    /// ```
+    pub fn start(config: &ResourcesConfig) -> Option<Self> {
+        if !config.monitor {
+            return None;
+        }
+        let Some(start) = Usage::sample() else {
+            eprintln!("Resource monitoring needs /proc/self, which is not available; running without it");
+            return None;
+        };
+
+        let peak_rss_bytes = Arc::new(AtomicU64::new(start.rss_bytes));
+        let exceeded_rss_bytes = Arc::new(AtomicU64::new(0));
+        let max_rss_bytes = config.max_rss_mb.map(|mb| mb.saturating_mul(BYTES_PER_MB));
+        let interval = Duration::from_millis(config.interval_ms.max(1));
+        let task = tokio::spawn({
+            let (peak_rss_bytes, exceeded_rss_bytes) = (peak_rss_bytes.clone(), exceeded_rss_bytes.clone());
+            async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    let Some(usage) = Usage::sample() else { continue };
+                    peak_rss_bytes.fetch_max(usage.rss_bytes, Ordering::Relaxed);
+                    if max_rss_bytes.is_some_and(|max| usage.rss_bytes > max) {
+                        let _ = exceeded_rss_bytes.compare_exchange(0, usage.rss_bytes, Ordering::Relaxed, Ordering::Relaxed);
+                    }
+                }
+            }
+        });
+        Some(Self { peak_rss_bytes, exceeded_rss_bytes, max_rss_bytes, started: Instant::now(), start, task })
+    }
+
+    /// Fails if the memory of the process exceeded the ceiling since the last check, clearing the mark so a retry starts afresh.
+    pub fn check(&self) -> Result<()> {
+        let exceeded = self.exceeded_rss_bytes.swap(0, Ordering::Relaxed);
+        if let (Some(max), true) = (self.max_rss_bytes, exceeded > 0) {
+            bail!("Memory use of {} MB exceeded resources.max_rss_mb of {} MB", exceeded / BYTES_PER_MB, max / BYTES_PER_MB);
+        }
+        Ok(())
+    }
+
+    /// Returns the peak memory and the CPU time used since the monitor started.
+    pub fn usage(&self) -> PeakUsage {
+        let now = Usage::sample().unwrap_or(self.start);
+        PeakUsage {
+            peak_rss_bytes: self.peak_rss_bytes.fetch_max(now.rss_bytes, Ordering::Relaxed).max(now.rss_bytes),
+            cpu_secs: (now.cpu_secs - self.start.cpu_secs).max(0.0),
+            wall_secs: self.started.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+impl Drop for ResourceMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Helper function to read the resident set size from the contents of `/proc/self/status`, given in kB.
+fn parse_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Helper function to read the user and system CPU time from the contents of `/proc/self/stat`.
+fn parse_cpu_secs(stat: &str) -> Option<f64> {
+    // The command name may contain spaces and parentheses, so fields are counted after its closing one
+    let fields: Vec<&str> = stat.get(stat.rfind(')')? + 1..)?.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) as f64 / CLOCK_TICKS_PER_SEC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_proc_usage() {
+        let status = "Name:\tpipeline\nVmPeak:\t  912340 kB\nVmRSS:\t  524288 kB\nThreads:\t12\n";
+        assert_eq!(parse_rss(status), Some(512 * BYTES_PER_MB));
+        assert_eq!(parse_rss("Name:\tpipeline\n"), None);
+
+        let stat = "4242 (tokio (worker)) S 1 4242 4242 0 -1 4194560 1234 0 0 0 2150 310 0 0 20 0 12 0 98765 912340000 131072";
+        assert_eq!(parse_cpu_secs(stat), Some(24.6));
+
+        let usage = PeakUsage { peak_rss_bytes: 1536 * BYTES_PER_MB, cpu_secs: 24.6, wall_secs: 12.3 };
+        assert_eq!(usage.describe(&ReportFormat::default()), "Peak memory 1536.0 MB RSS; 24.6 s of CPU time (2.00 cores on average)");
+    }
+}
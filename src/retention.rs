@@ -0,0 +1,136 @@
+//! This module keeps the stored table bounded as loads accumulate.
+//!
+//! Every stored row carries the ID of the run that loaded it, so a run's rows form a batch that is
+//! rotated out as a whole: once the last successful attempt of a run finished longer ago than
+//! `retention.max_age_days`, its rows are deleted from the table of `storage.table`, after being exported to a
+//! Parquet file per run when `retention.archive_dir` is set. Rows without a run ID, loaded before the
+//! audit columns existed, are never deleted.
+
+use crate::audit;
+use crate::config::RetentionConfig;
+use crate::history;
+use crate::schema::TableSchema;
+use crate::storage;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use polars::prelude::*;
+use sqlx::postgres::PgPool;
+use std::path::Path;
+
+/// Deletes, and optionally archives, the rows of runs older than the configured age.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The schema of the table; it must include the `run_id` audit column.
+/// * `table` - The table the rows are deleted from, e.g. `wine_quality`.
+/// * `config` - The retention settings.
+///
+/// # Returns
+///
+/// * `Result<Vec<String>>` - The IDs of the runs whose rows were removed, or an error if the table has no run ID column or a deletion fails.
+///
+/// # Example
+///
+/// ```
+/// let expired = apply_retention(&pool, &config.schema, &config.storage.table, &config.retention).await.expect("Failed to apply retention");
+/// ```
+pub async fn apply_retention(pool: &PgPool, schema: &TableSchema, table: &str, config: &RetentionConfig) -> Result<Vec<String>> {
+    let run_column = schema
+        .columns
+        .iter()
+        .find(|c| c.name == audit::RUN_ID_COLUMN)
+        .context("Table retention requires the run_id column in the schema")?
+        .column
+        .clone();
+
+    let expired = expired_runs(pool, table, &run_column, cutoff(Utc::now(), config.max_age_days)).await?;
+    for run_id in &expired {
+        if let Some(dir) = &config.archive_dir {
+            let mut df = storage::fetch_frame(pool, schema, table, &format!("{} = $1", run_column), &[run_id.clone()]).await?;
+            if df.height() > 0 {
+                let path = Path::new(dir).join(format!("{}.parquet", run_id));
+                std::fs::create_dir_all(dir).context(format!("Failed to create archive directory {}", dir))?;
+                let file = std::fs::File::create(&path).context(format!("Failed to create {}", path.display()))?;
+                ParquetWriter::new(file)
+                    .with_compression(config.compression.parquet()?)
+                    .finish(&mut df)
+                    .context(format!("Failed to archive run {} to {}", run_id, path.display()))?;
+                println!("Archived {} rows of run {} to {}", df.height(), run_id, path.display());
+            }
+        }
+
+        let deleted = sqlx::query(&format!("DELETE FROM {} WHERE {} = $1", table, run_column))
+            .bind(run_id)
+            .execute(pool)
+            .await
+            .context(format!("Failed to delete the rows of run {}", run_id))?
+            .rows_affected();
+        println!("Deleted {} rows of run {}, older than {} days", deleted, run_id, config.max_age_days);
+    }
+
+    Ok(expired)
+}
+
+/// Helper function to compute the time before which runs expire.
+fn cutoff(now: DateTime<Utc>, max_age_days: u64) -> DateTime<Utc> {
+    now - Duration::days(max_age_days as i64)
+}
+
+/// Helper function to list the runs with rows still stored whose last successful attempt finished before the cutoff.
+async fn expired_runs(pool: &PgPool, table: &str, run_column: &str, cutoff: DateTime<Utc>) -> Result<Vec<String>> {
+    let sql = format!(
+        "SELECT run_id FROM pipeline_runs WHERE status = $1 AND run_id IN (SELECT DISTINCT {} FROM {}) \
+         GROUP BY run_id HAVING max(finished_at) < $2 ORDER BY max(finished_at)",
+        run_column, table
+    );
+    sqlx::query_scalar(&sql)
+        .bind(history::SUCCEEDED)
+        .bind(cutoff)
+        .fetch_all(pool)
+        .await
+        .context("Failed to look up expired runs")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_cutoff() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap();
+
+        assert_eq!(cutoff(now, 30), Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap());
+        assert_eq!(cutoff(now, 0), now);
+    }
+
+    #[tokio::test]
+    async fn test_only_expired_runs_rotate_out_across_runs() -> Result<()> {
+        use crate::hooks::Hooks;
+        use crate::pipeline;
+        use crate::testing::{Scratch, ROWS};
+
+        dotenv::dotenv().ok();
+        let pool = storage::create_connection_pool().await?;
+        let mut scratch = Scratch::default();
+        let table = scratch.table("temp_retention_runs");
+        let mut run_ids = vec![];
+        for (i, rows) in [&ROWS[..2], &ROWS[2..3], &ROWS[3..4]].into_iter().enumerate() {
+            if i == 1 {
+                // The first run finished longer ago than the retention period
+                sqlx::query("UPDATE pipeline_runs SET finished_at = now() - INTERVAL '100 days' WHERE run_id = $1").bind(&run_ids[0]).execute(&pool).await?;
+            }
+            let mut config = Scratch::config(&table, &scratch.csv("temp_retention_runs", rows)?);
+            // Retention runs after the last load only, so the earlier runs accumulate
+            config.retention.enabled = i == 2;
+            pipeline::run(&config, &Hooks::from_commands(&[])).await?;
+            let run_id: String = sqlx::query_scalar(&format!("SELECT run_id FROM {} ORDER BY id DESC LIMIT 1", table)).fetch_one(&pool).await?;
+            run_ids.push(run_id);
+        }
+
+        let stored: Vec<String> = sqlx::query_scalar(&format!("SELECT run_id FROM {} ORDER BY id", table)).fetch_all(&pool).await?;
+        assert_eq!(stored, vec![run_ids[1].clone(), run_ids[2].clone()]);
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! This module computes the waits between the attempts of operations that fail transiently.
+//!
+//! A [`RetryPolicy`] waits `initial_delay_ms` before the first retry and `multiplier` times longer
+//! before each further one, never longer than `max_delay_ms`. With `jitter`, each wait is shortened by
+//! a random fraction of up to `jitter` of it, so processes that failed together, such as daemon runs
+//! hitting a restarting database, do not retry in lockstep. Ingestion retries a file with the policy
+//! in `[retry.ingestion]`; storage waits by the policy in `[storage.retry]` between the retries of a
+//! partition and the reconnects of a resumable load.
+
+use crate::generate::Rng;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many times an operation is attempted and how long is waited between attempts.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Wait before the first retry, in milliseconds.
+    pub initial_delay_ms: u64,
+    /// Factor each further wait is longer than the one before; 1 waits the same before every retry.
+    pub multiplier: f64,
+    /// Longest wait, in milliseconds.
+    pub max_delay_ms: u64,
+    /// Largest fraction, from 0 to 1, each wait is randomly shortened by; 0 waits exactly.
+    pub jitter: f64,
+    /// Number of attempts, the first included, before the operation fails.
+    pub max_attempts: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1_000,
+            multiplier: 2.0,
+            max_delay_ms: 30_000,
+            jitter: 0.1,
+            max_attempts: 3,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns a policy attempting an operation a given number of times, with the default waits.
+    pub fn attempts(max_attempts: usize) -> Self {
+        Self { max_attempts, ..Self::default() }
+    }
+
+    /// Returns the wait before a retry, jittered.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The number of the retry, 1 for the first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// tokio::time::sleep(config.storage.retry.delay(retries)).await;
+    /// ```
+    pub fn delay(&self, retry: usize) -> Duration {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |elapsed| elapsed.as_nanos() as u64);
+        self.jittered(retry, Rng::new(seed).uniform())
+    }
+
+    /// Runs an operation until it succeeds or has been attempted `max_attempts` times, sleeping the thread between attempts.
+    ///
+    /// # Arguments
+    ///
+    /// * `operation` - The operation, attempted again after each error.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T>` - The result of the first successful attempt, or the error of the last one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let df = config.retry.ingestion.run(|| ingestion::ingest_csv("data.csv"))?;
+    /// ```
+    pub fn run<T>(&self, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempts = 0;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    attempts += 1;
+                    if attempts >= self.max_attempts {
+                        return Err(e).context("Max retry attempts reached");
+                    }
+                    let delay = self.delay(attempts);
+                    println!("Attempt {} failed, retrying in {} ms...", attempts, delay.as_millis());
+                    std::thread::sleep(delay);
+                }
+            }
+        }
+    }
+
+    /// Runs an async operation like [`RetryPolicy::run`], waiting between attempts without blocking the runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `operation` - Creates the future of an attempt of the operation.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T>` - The result of the first successful attempt, or the error of the last one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let df = config.retry.ingestion.run_async(|| ingestion::ingest_csv_async("data.csv")).await?;
+    /// ```
+    pub async fn run_async<T, Fut>(&self, mut operation: impl FnMut() -> Fut) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempts = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    attempts += 1;
+                    if attempts >= self.max_attempts {
+                        return Err(e).context("Max retry attempts reached");
+                    }
+                    let delay = self.delay(attempts);
+                    println!("Attempt {} failed, retrying in {} ms...", attempts, delay.as_millis());
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    /// Helper function to compute the wait before a retry, shortened by `draw`, a fraction in `[0, 1)`, of the jitter.
+    fn jittered(&self, retry: usize, draw: f64) -> Duration {
+        let exponent = retry.max(1).saturating_sub(1).min(i32::MAX as usize) as i32;
+        let delay = (self.initial_delay_ms as f64 * self.multiplier.powi(exponent)).min(self.max_delay_ms as f64);
+        Duration::from_millis((delay * (1.0 - self.jitter.clamp(0.0, 1.0) * draw)) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delays_grow_up_to_the_maximum() {
+        let policy = RetryPolicy { initial_delay_ms: 100, multiplier: 3.0, max_delay_ms: 1_000, jitter: 0.5, max_attempts: 5 };
+        let delays: Vec<u128> = (1..=4).map(|retry| policy.jittered(retry, 0.0).as_millis()).collect();
+        assert_eq!(delays, vec![100, 300, 900, 1_000]);
+        assert_eq!(policy.jittered(2, 0.5).as_millis(), 225);
+        assert!(policy.delay(60) <= Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn test_run_stops_after_max_attempts() {
+        let policy = RetryPolicy { initial_delay_ms: 0, ..RetryPolicy::attempts(3) };
+        let mut calls = 0;
+        let failed: Result<()> = policy.run(|| {
+            calls += 1;
+            anyhow::bail!("transient")
+        });
+        assert!(format!("{:#}", failed.unwrap_err()).starts_with("Max retry attempts reached"));
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let value = policy.run(|| {
+            calls += 1;
+            if calls < 2 { anyhow::bail!("transient") } else { Ok(calls) }
+        });
+        assert_eq!(value.unwrap(), 2);
+    }
+}
@@ -0,0 +1,126 @@
+//! This module rounds numeric columns to their stored scale before storage.
+//!
+//! Without it, float values are coerced to the `DECIMAL(p, s)` columns of the table by PostgreSQL,
+//! which rounds the binary approximation of each value. The rounding stage instead rounds the decimal
+//! representation of each value to the scale declared in the schema (or configured per column) with
+//! an explicit rounding mode, so the stored values are deterministic.
+
+use crate::config::RoundingConfig;
+use crate::schema::TableSchema;
+use anyhow::{Context, Result};
+use polars::prelude::*;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+
+/// How values are rounded to the scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Ties go to the even neighbour (banker's rounding).
+    HalfEven,
+    /// Ties go away from zero, as PostgreSQL rounds `NUMERIC`.
+    HalfUp,
+    /// Digits beyond the scale are dropped.
+    TowardZero,
+    Floor,
+    Ceiling,
+}
+
+impl RoundingMode {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::TowardZero => RoundingStrategy::ToZero,
+            RoundingMode::Floor => RoundingStrategy::ToNegativeInfinity,
+            RoundingMode::Ceiling => RoundingStrategy::ToPositiveInfinity,
+        }
+    }
+}
+
+/// Rounds the float columns to their decimal places, if rounding is enabled.
+///
+/// A column's decimal places come from `config.decimals`, otherwise from the scale of its
+/// `DECIMAL`/`NUMERIC` type in the schema. Columns with neither are left unchanged.
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame about to be stored.
+/// * `config` - The rounding settings.
+/// * `schema` - The schema of the stored table.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with rounded columns, or an error if a column cannot be rounded.
+///
+/// # Example
+///
+/// ```
+/// let df = round_stage(transformed_df, &config.rounding, &config.schema).expect("Rounding failed");
+/// ```
+pub fn round_stage(mut df: DataFrame, config: &RoundingConfig, schema: &TableSchema) -> Result<DataFrame> {
+    if !config.enabled {
+        return Ok(df);
+    }
+
+    for spec in &schema.columns {
+        let Some(places) = config.decimals.get(&spec.name).copied().or_else(|| spec.scale()) else { continue };
+        let Ok(series) = df.column(&spec.name) else { continue };
+        if !series.dtype().is_float() {
+            continue;
+        }
+
+        let rounded: Float64Chunked = series
+            .cast(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .map(|v| v.map(|v| round(v, places, config.mode)))
+            .collect();
+        df.with_column(rounded.with_name(&spec.name).into_series())
+            .context(format!("Error replacing rounded {} column", spec.name))?;
+    }
+
+    Ok(df)
+}
+
+/// Helper function to round the shortest decimal representation of `value`, so `2.675` rounds as written rather than as `2.67499..`.
+fn round(value: f64, places: u32, mode: RoundingMode) -> f64 {
+    match Decimal::from_str_exact(&value.to_string()).ok().or_else(|| Decimal::from_f64(value)) {
+        Some(decimal) => decimal.round_dp_with_strategy(places, mode.strategy()).to_f64().unwrap_or(value),
+        // NaN, infinities, and magnitudes beyond the decimal range
+        None => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_round_modes() {
+        assert_eq!(round(2.675, 2, RoundingMode::HalfUp), 2.68);
+        assert_eq!(round(2.665, 2, RoundingMode::HalfEven), 2.66);
+        assert_eq!(round(-2.675, 2, RoundingMode::TowardZero), -2.67);
+        assert_eq!(round(2.671, 2, RoundingMode::Ceiling), 2.68);
+        assert_eq!(round(2.679, 2, RoundingMode::Floor), 2.67);
+        assert!(round(f64::NAN, 2, RoundingMode::HalfUp).is_nan());
+    }
+
+    #[test]
+    fn test_round_stage_uses_schema_scale_and_overrides() {
+        let df = polars::df!("alcohol" => &[9.45, 10.05], "chlorides" => &[0.07649, 0.0981]).unwrap();
+        let config = RoundingConfig {
+            enabled: true,
+            mode: RoundingMode::HalfUp,
+            decimals: HashMap::from([("chlorides".to_string(), 3)]),
+        };
+
+        let rounded = round_stage(df, &config, &TableSchema::default()).unwrap();
+
+        // alcohol is DECIMAL(4, 1) in the default schema
+        assert_eq!(rounded.column("alcohol").unwrap().f64().unwrap().to_vec(), vec![Some(9.5), Some(10.1)]);
+        assert_eq!(rounded.column("chlorides").unwrap().f64().unwrap().to_vec(), vec![Some(0.076), Some(0.098)]);
+    }
+}
@@ -0,0 +1,144 @@
+//! This module holds the context shared by all stages of a single pipeline run.
+//!
+//! Besides identifying the run, the context collects the non-fatal issues its stages come across, such
+//! as expectations of severity warning that failed, columns that needed many values filled in, or rows
+//! the database rejected. They are listed at the end of the run and stored with its history, instead of
+//! only scrolling by in the log.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use ulid::Ulid;
+
+/// Identifies one execution of the pipeline.
+#[derive(Debug, Clone)]
+pub struct RunContext {
+    /// Run identifier: a ULID, unique even across concurrent runs and sorting by start time. It names
+    /// per-run artifacts, tags log lines, and is stored with every row, result, and history entry of the run.
+    pub id: String,
+    /// Time at which the run started.
+    pub started_at: DateTime<Utc>,
+    /// Key/value labels describing the batch, e.g. `vintage=2023`, stored with every row of the run.
+    pub labels: BTreeMap<String, String>,
+    /// Non-fatal issues of the run so far, shared by every clone of the context.
+    pub warnings: Warnings,
+}
+
+impl RunContext {
+    /// Creates a context for a run starting now.
+    pub fn new() -> Self {
+        let started_at = Utc::now();
+        Self {
+            id: Ulid::from_datetime(SystemTime::from(started_at)).to_string(),
+            started_at,
+            labels: BTreeMap::new(),
+            warnings: Warnings::default(),
+        }
+    }
+
+    /// Attaches labels to the run.
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Prints a progress message tagged with the run ID, so the output of concurrent runs can be told apart.
+    pub fn log(&self, message: impl fmt::Display) {
+        println!("[run {}] {}", self.id, message);
+    }
+
+    /// Prints a warning or error tagged with the run ID.
+    pub fn warn(&self, message: impl fmt::Display) {
+        eprintln!("[run {}] {}", self.id, message);
+    }
+
+    /// Prints a non-fatal issue of a stage and collects it for the end of the run.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// run.add_warning("store", format_args!("{} rows rejected", rejects.len()));
+    /// ```
+    pub fn add_warning(&self, stage: &'static str, message: impl fmt::Display) {
+        let message = message.to_string();
+        self.warn(format_args!("Warning ({}): {}", stage, message));
+        self.warnings.0.lock().expect("run warnings poisoned").push(Warning { stage, message });
+    }
+
+    /// Returns the directory below `base` where this run's artifacts are written.
+    pub fn artifact_dir(&self, base: &str) -> PathBuf {
+        Path::new(base).join(&self.id)
+    }
+}
+
+impl Default for RunContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A non-fatal issue found by a stage of a run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Warning {
+    /// The stage that found it, e.g. `transform`.
+    pub stage: &'static str,
+    pub message: String,
+}
+
+/// The warnings of a run, collected by [`RunContext::add_warning`].
+#[derive(Debug, Clone, Default)]
+pub struct Warnings(Arc<Mutex<Vec<Warning>>>);
+
+impl Warnings {
+    /// Returns the warnings collected so far, in the order they were added.
+    pub fn list(&self) -> Vec<Warning> {
+        self.0.lock().expect("run warnings poisoned").clone()
+    }
+
+    /// Serializes the warnings collected so far as a JSON array.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.list()).expect("warnings serialize to JSON")
+    }
+
+    /// Describes the warnings for the end of a run, one per line, or `None` if there were none.
+    pub fn describe(&self) -> Option<String> {
+        let warnings = self.list();
+        if warnings.is_empty() {
+            return None;
+        }
+        let lines: Vec<String> = warnings.iter().map(|w| format!("  [{}] {}", w.stage, w.message)).collect();
+        Some(format!("{} warnings:\n{}", warnings.len(), lines.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_ids_are_unique_and_sort_by_start() {
+        let first = RunContext::new();
+        let second = RunContext::new();
+
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.id.len(), 26);
+        assert!(first.id[..10] <= second.id[..10]);
+    }
+
+    #[test]
+    fn test_warnings_are_shared_by_clones() {
+        let run = RunContext::new();
+        assert_eq!(run.warnings.describe(), None);
+
+        run.clone().add_warning("store", "2 rows rejected");
+        run.add_warning("transform", "Filled 30 of 100 values of column alcohol");
+
+        assert_eq!(run.warnings.list()[0], Warning { stage: "store", message: "2 rows rejected".to_string() });
+        assert_eq!(run.warnings.describe().unwrap(), "2 warnings:\n  [store] 2 rows rejected\n  [transform] Filled 30 of 100 values of column alcohol");
+        assert_eq!(run.warnings.to_json(), r#"[{"stage":"store","message":"2 rows rejected"},{"stage":"transform","message":"Filled 30 of 100 values of column alcohol"}]"#);
+    }
+}
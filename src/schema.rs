@@ -0,0 +1,188 @@
+//! This module declares the schema of the stored table.
+//!
+//! Each column names its DataFrame source, its PostgreSQL column and type, whether missing values are
+//! allowed, and how they are imputed. The schema generates the table DDL and drives null handling in
+//! transformation and storage. It is configured in the `[[schema.columns]]` section and defaults to the
+//! wine quality table.
+
+use crate::{audit, clustering, mapping, model};
+use serde::Deserialize;
+
+/// The columns of the stored table, in order.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TableSchema {
+    pub columns: Vec<ColumnSchema>,
+}
+
+/// A column of the stored table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSchema {
+    /// The DataFrame column the values are read from.
+    pub name: String,
+    /// The PostgreSQL column name; when omitted, derived from `name` by the `[column_mapping]` settings.
+    #[serde(default)]
+    pub column: String,
+    /// The PostgreSQL column type, e.g. `DECIMAL(4, 2)`.
+    pub pg_type: String,
+    /// Whether missing values are stored as NULL. Nullable columns may also be absent from the DataFrame.
+    #[serde(default)]
+    pub nullable: bool,
+    /// How missing values are filled during transformation.
+    #[serde(default)]
+    pub impute: Option<Imputation>,
+}
+
+/// How missing values of a column are filled.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Imputation {
+    Mean,
+    Median,
+    Zero,
+    /// A fixed value, written as `impute = { constant = 0.5 }`.
+    Constant(f64),
+}
+
+impl ColumnSchema {
+    /// Helper function to declare a column stored under the `snake_case` form of its name.
+    fn derived(name: &str, pg_type: &str, nullable: bool) -> Self {
+        Self::new(name, &mapping::snake_case(name), pg_type, nullable)
+    }
+
+    fn new(name: &str, column: &str, pg_type: &str, nullable: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            column: column.to_string(),
+            pg_type: pg_type.to_string(),
+            nullable,
+            impute: None,
+        }
+    }
+
+    /// Returns the scale of a `DECIMAL(p, s)` or `NUMERIC(p, s)` column, i.e. its number of decimal places.
+    pub fn scale(&self) -> Option<u32> {
+        let pg_type = self.pg_type.to_ascii_uppercase();
+        let arguments = pg_type
+            .strip_prefix("DECIMAL")
+            .or_else(|| pg_type.strip_prefix("NUMERIC"))?
+            .trim()
+            .strip_prefix('(')?
+            .strip_suffix(')')?;
+        match arguments.split_once(',') {
+            Some((_, scale)) => scale.trim().parse().ok(),
+            // DECIMAL(p) has scale 0
+            None => Some(0),
+        }
+    }
+}
+
+impl Default for TableSchema {
+    fn default() -> Self {
+        Self {
+            columns: vec![
+                ColumnSchema::derived("fixed acidity", "DECIMAL(4, 2)", false),
+                ColumnSchema::derived("volatile acidity", "DECIMAL(4, 2)", false),
+                ColumnSchema::derived("citric acid", "DECIMAL(4, 2)", false),
+                ColumnSchema::derived("residual sugar", "DECIMAL(4, 2)", false),
+                ColumnSchema::derived("chlorides", "DECIMAL(5, 4)", false),
+                ColumnSchema::derived("free sulfur dioxide", "INTEGER", false),
+                ColumnSchema::derived("total sulfur dioxide", "INTEGER", false),
+                ColumnSchema::derived("density", "DECIMAL(6, 5)", false),
+                ColumnSchema::derived("pH", "DECIMAL(3, 2)", false),
+                ColumnSchema::derived("sulphates", "DECIMAL(4, 2)", false),
+                ColumnSchema::derived("alcohol", "DECIMAL(4, 1)", false),
+                ColumnSchema::derived("quality", "INTEGER", false),
+                // Only present when the run scored the data with a trained model
+                ColumnSchema::derived("predicted_quality", "DECIMAL(4, 2)", true),
+                // Only present when the run labeled the data with k-means clusters
+                ColumnSchema::derived("cluster", "INTEGER", true),
+                // Audit columns added to every stored row
+                ColumnSchema::new(audit::RUN_ID_COLUMN, audit::RUN_ID_COLUMN, "TEXT", true),
+                ColumnSchema::new(audit::LABELS_COLUMN, audit::LABELS_COLUMN, "JSONB", true),
+            ],
+        }
+    }
+}
+
+impl TableSchema {
+    /// Generates the `CREATE TABLE` statement for the schema, with a serial `id` primary key.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The name of the table to create.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The DDL statement.
+    pub fn create_table_sql(&self, table: &str) -> String {
+        let columns: Vec<String> = std::iter::once("id SERIAL PRIMARY KEY".to_string())
+            .chain(self.columns.iter().map(|c| {
+                format!("{} {}{}", c.column, c.pg_type, if c.nullable { "" } else { " NOT NULL" })
+            }))
+            .collect();
+        format!("CREATE TABLE IF NOT EXISTS {} (\n    {}\n);", table, columns.join(",\n    "))
+    }
+
+    /// The columns read from the input, leaving out those the pipeline adds itself, such as the run ID.
+    pub fn input_columns(&self) -> impl Iterator<Item = &ColumnSchema> {
+        self.columns
+            .iter()
+            .filter(|c| ![audit::RUN_ID_COLUMN, audit::LABELS_COLUMN, model::PREDICTION_COLUMN, clustering::CLUSTER_COLUMN].contains(&c.name.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_table_sql() {
+        let schema = TableSchema {
+            columns: vec![
+                ColumnSchema::new("alcohol", "alcohol", "DECIMAL(4, 1)", false),
+                ColumnSchema::new("vintage", "vintage", "DATE", true),
+            ],
+        };
+
+        assert_eq!(
+            schema.create_table_sql("wines"),
+            "CREATE TABLE IF NOT EXISTS wines (\n    id SERIAL PRIMARY KEY,\n    alcohol DECIMAL(4, 1) NOT NULL,\n    vintage DATE\n);"
+        );
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(ColumnSchema::new("pH", "pH", "DECIMAL(3, 2)", false).scale(), Some(2));
+        assert_eq!(ColumnSchema::new("alcohol", "alcohol", "numeric(4,1)", false).scale(), Some(1));
+        assert_eq!(ColumnSchema::new("quality", "quality", "NUMERIC(2)", false).scale(), Some(0));
+        assert_eq!(ColumnSchema::new("quality", "quality", "INTEGER", false).scale(), None);
+        assert_eq!(ColumnSchema::new("ratio", "ratio", "NUMERIC", false).scale(), None);
+    }
+
+    #[test]
+    fn test_parse_imputation() {
+        let schema: TableSchema = toml::from_str(
+            r#"
+            [[columns]]
+            name = "residual sugar"
+            column = "residual_sugar"
+            pg_type = "DECIMAL(4, 2)"
+            nullable = true
+            impute = { constant = 2.5 }
+
+            [[columns]]
+            name = "pH"
+            column = "pH"
+            pg_type = "DECIMAL(3, 2)"
+            impute = "median"
+            "#,
+        )
+        .expect("Failed to parse schema");
+
+        assert_eq!(schema.columns[0].impute, Some(Imputation::Constant(2.5)));
+        assert_eq!(schema.columns[1].impute, Some(Imputation::Median));
+        assert!(!schema.columns[1].nullable);
+    }
+}
@@ -1,52 +1,227 @@
 //! This module handles the initial setup of the database.
 //!
-//! It provides a function to create the necessary tables and schema in the database.
+//! It provides a function to create the necessary tables and schema in the database. Runs only create
+//! the tables that do not exist yet, so the rows of earlier runs are kept; dropping the stored rows
+//! takes an explicit `pipeline reset-db --yes`.
 
+use crate::audit;
+use crate::bookmarks;
+use crate::processed_files;
+use crate::schema::TableSchema;
+use crate::staging;
 use crate::storage;
-use anyhow::Result;
+use crate::timetravel;
+use anyhow::{Context, Result};
+use sqlx::postgres::PgPool;
 
-/// Sets up the database by creating the connection pool and initializing the `wine_quality` table.
+/// Resets the database by creating the connection pool, dropping the table the rows are stored in, and creating it and the per-run result tables again.
+///
+/// Every stored row of the table is deleted; this is what `pipeline reset-db --yes` runs, and runs never do.
+///
+/// # Arguments
+///
+/// * `schema` - The declared schema of the table.
+/// * `table` - The table, e.g. `wine_quality`.
 ///
 /// # Returns
 ///
-/// * `Result<()>` - A result indicating success or failure of the database setup.
+/// * `Result<()>` - A result indicating success or failure of the database reset.
 ///
 /// # Example
 ///
 /// ```
-/// run_db_setup().await.expect("Failed to set up the database");
+/// run_db_setup(&config.schema, &config.storage.table).await.expect("Failed to reset the database");
 /// ```
-pub async fn run_db_setup() -> Result<()> {
+pub async fn run_db_setup(schema: &TableSchema, table: &str) -> Result<()> {
     dotenv::dotenv().ok();
     let pool = storage::create_connection_pool().await?;
+    reset_database(&pool, schema, table).await
+}
 
+/// Drops the table the rows are stored in and sets up the database again on an existing pool.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The declared schema of the table.
+/// * `table` - The table, e.g. `wine_quality`; it is dropped and created again.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of the database reset.
+pub async fn reset_database(pool: &PgPool, schema: &TableSchema, table: &str) -> Result<()> {
     // Drop the table if it exists
-    let drop_table_sql = "DROP TABLE IF EXISTS wine_quality CASCADE;";
-    sqlx::query(drop_table_sql).execute(&pool).await?;
+    let drop_table_sql = format!("DROP TABLE IF EXISTS {} CASCADE;", table);
+    sqlx::query(&drop_table_sql).execute(pool).await?;
+    setup_database(pool, schema, table).await
+}
 
-    // Create the table
-    let create_table_sql = r#"
-    CREATE TABLE IF NOT EXISTS wine_quality (
+/// Creates the table the rows are stored in and the per-run result tables on an existing pool, unless they exist.
+///
+/// Existing tables and their rows are left as they are, so each run adds to the rows of earlier runs.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The declared schema of the table.
+/// * `table` - The table, e.g. `wine_quality`.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of the database setup.
+///
+/// # Example
+///
+/// ```
+/// setup_database(&pool, &config.schema, &config.storage.table).await?;
+/// ```
+pub async fn setup_database(pool: &PgPool, schema: &TableSchema, table: &str) -> Result<()> {
+    // Create the table from the declared schema
+    let create_table_sql = schema.create_table_sql(table);
+    sqlx::query(&create_table_sql).execute(pool).await?;
+
+    // Index the audit columns, so rows can be selected by run and label
+    if let Some(run_id) = schema.columns.iter().find(|c| c.name == audit::RUN_ID_COLUMN) {
+        sqlx::query(&format!("CREATE INDEX IF NOT EXISTS {0}_run_id_idx ON {0} ({1});", table, run_id.column))
+            .execute(pool)
+            .await?;
+    }
+    if let Some(labels) = schema.columns.iter().find(|c| c.name == audit::LABELS_COLUMN && c.pg_type.eq_ignore_ascii_case("JSONB")) {
+        sqlx::query(&format!("CREATE INDEX IF NOT EXISTS {0}_labels_idx ON {0} USING GIN ({1});", table, labels.column))
+            .execute(pool)
+            .await?;
+    }
+
+    // Create the expectation results table, keeping results of earlier runs
+    let create_expectation_results_sql = r#"
+    CREATE TABLE IF NOT EXISTS expectation_results (
         id SERIAL PRIMARY KEY,
-        fixed_acidity DECIMAL(4, 2) NOT NULL,
-        volatile_acidity DECIMAL(4, 2) NOT NULL,
-        citric_acid DECIMAL(4, 2) NOT NULL,
-        residual_sugar DECIMAL(4, 2) NOT NULL,
-        chlorides DECIMAL(5, 4) NOT NULL,
-        free_sulfur_dioxide INTEGER NOT NULL,
-        total_sulfur_dioxide INTEGER NOT NULL,
-        density DECIMAL(6, 5) NOT NULL,
-        pH DECIMAL(3, 2) NOT NULL,
-        sulphates DECIMAL(4, 2) NOT NULL,
-        alcohol DECIMAL(4, 1) NOT NULL,
-        quality INTEGER NOT NULL
+        run_id TEXT NOT NULL,
+        expectation TEXT NOT NULL,
+        passed BOOLEAN NOT NULL,
+        observed TEXT NOT NULL,
+        evaluated_at TIMESTAMPTZ NOT NULL DEFAULT now()
     );
     "#;
-    sqlx::query(create_table_sql).execute(&pool).await?;
+    sqlx::query(create_expectation_results_sql).execute(pool).await?;
+    sqlx::query("ALTER TABLE expectation_results ADD COLUMN IF NOT EXISTS severity TEXT NOT NULL DEFAULT 'error';").execute(pool).await?;
+
+    // Create the hypothesis test results table
+    let create_analysis_results_sql = r#"
+    CREATE TABLE IF NOT EXISTS analysis_results (
+        id SERIAL PRIMARY KEY,
+        run_id TEXT NOT NULL,
+        test TEXT NOT NULL,
+        statistic DOUBLE PRECISION NOT NULL,
+        degrees_of_freedom DOUBLE PRECISION NOT NULL,
+        p_value DOUBLE PRECISION NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    "#;
+    sqlx::query(create_analysis_results_sql).execute(pool).await?;
+
+    // Create the per-column statistics table, one row per column and run
+    let create_column_stats_sql = r#"
+    CREATE TABLE IF NOT EXISTS column_stats (
+        id SERIAL PRIMARY KEY,
+        run_id TEXT NOT NULL,
+        column_name TEXT NOT NULL,
+        row_count BIGINT NOT NULL,
+        null_rate DOUBLE PRECISION NOT NULL,
+        mean DOUBLE PRECISION,
+        std DOUBLE PRECISION,
+        min DOUBLE PRECISION,
+        max DOUBLE PRECISION,
+        computed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    "#;
+    sqlx::query(create_column_stats_sql).execute(pool).await?;
+
+    // Create the run history, one row per attempt, used to detect duplicate loads
+    let create_pipeline_runs_sql = r#"
+    CREATE TABLE IF NOT EXISTS pipeline_runs (
+        id SERIAL PRIMARY KEY,
+        run_id TEXT NOT NULL,
+        fingerprint TEXT,
+        attempt INTEGER NOT NULL DEFAULT 1,
+        status TEXT NOT NULL,
+        error TEXT,
+        started_at TIMESTAMPTZ NOT NULL,
+        finished_at TIMESTAMPTZ NOT NULL
+    );
+    "#;
+    sqlx::query(create_pipeline_runs_sql).execute(pool).await?;
+    sqlx::query("ALTER TABLE pipeline_runs ADD COLUMN IF NOT EXISTS row_count BIGINT;").execute(pool).await?;
+    sqlx::query("ALTER TABLE pipeline_runs ADD COLUMN IF NOT EXISTS warnings JSONB NOT NULL DEFAULT '[]';").execute(pool).await?;
+    sqlx::query("ALTER TABLE pipeline_runs ADD COLUMN IF NOT EXISTS peak_rss_bytes BIGINT;").execute(pool).await?;
+    sqlx::query("ALTER TABLE pipeline_runs ADD COLUMN IF NOT EXISTS cpu_secs DOUBLE PRECISION;").execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS pipeline_runs_fingerprint_idx ON pipeline_runs (fingerprint);")
+        .execute(pool)
+        .await?;
+
+    // Create the local data catalog, one row per dataset
+    let create_data_catalog_sql = r#"
+    CREATE TABLE IF NOT EXISTS data_catalog (
+        dataset TEXT PRIMARY KEY,
+        table_name TEXT NOT NULL,
+        owner TEXT NOT NULL,
+        description TEXT NOT NULL,
+        schema JSONB NOT NULL,
+        row_count BIGINT NOT NULL,
+        lineage JSONB NOT NULL,
+        last_run_id TEXT NOT NULL,
+        last_loaded_at TIMESTAMPTZ NOT NULL
+    );
+    "#;
+    sqlx::query(create_data_catalog_sql).execute(pool).await?;
+
+    // Create the record of staged runs awaiting, or past, review
+    let create_staged_runs_sql = r#"
+    CREATE TABLE IF NOT EXISTS staged_runs (
+        run_id TEXT PRIMARY KEY,
+        staging_table TEXT NOT NULL,
+        row_count BIGINT NOT NULL,
+        report TEXT NOT NULL,
+        status TEXT NOT NULL,
+        staged_at TIMESTAMPTZ NOT NULL,
+        decided_at TIMESTAMPTZ
+    );
+    "#;
+    sqlx::query(create_staged_runs_sql).execute(pool).await?;
+
+    // Create the history of rows superseded by later loads, which time-travel reads go back to
+    sqlx::query(timetravel::HISTORY_TABLE_SQL).execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS row_history_table_idx ON row_history (table_name, superseded_at);")
+        .execute(pool)
+        .await?;
+
+    // Create the record of input files already loaded, by checksum
+    sqlx::query(processed_files::PROCESSED_FILES_TABLE_SQL).execute(pool).await?;
+
+    // Create the bookmarks of input files read up to a byte offset
+    sqlx::query(bookmarks::FILE_BOOKMARKS_TABLE_SQL).execute(pool).await?;
 
     Ok(())
 }
 
+/// Returns whether a table exists, for the lookups a run makes before it sets up the database.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `table` - The table, e.g. `processed_files`.
+///
+/// # Returns
+///
+/// * `Result<bool>` - Whether the table exists, or an error if the lookup fails.
+pub async fn table_exists(pool: &PgPool, table: &str) -> Result<bool> {
+    sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(table)
+        .fetch_one(pool)
+        .await
+        .context(format!("Failed to look up table {}", table))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,7 +265,7 @@ mod tests {
         create_temp_table(&pool).await?;
 
         // Run the database setup function
-        run_db_setup().await?;
+        run_db_setup(&TableSchema::default(), staging::PRODUCTION_TABLE).await?;
 
         // Check if the table was created
         let table_exists = sqlx::query_scalar::<_, bool>(
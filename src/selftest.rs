@@ -0,0 +1,72 @@
+//! This module implements `pipeline selftest`.
+//!
+//! The self-test runs the default pipeline over a small built-in sample against the configured
+//! database and checks that every sample row was stored. Combined with `--embedded-db`, it verifies a
+//! fresh installation without any external prerequisite. The sample is stored in a table of its own,
+//! created again empty on every self-test, so the rows of real runs are left alone.
+
+use crate::config::PipelineConfig;
+use crate::fingerprint::DuplicatePolicy;
+use crate::hooks::Hooks;
+use crate::source::SourceConfig;
+use crate::{pipeline, seed, storage};
+use anyhow::{bail, Context, Result};
+
+/// Table the sample is stored in.
+const SELFTEST_TABLE: &str = "pipeline_selftest";
+
+/// Sample rows of the wine quality dataset.
+const SAMPLE_CSV: &str = "fixed acidity,volatile acidity,citric acid,residual sugar,chlorides,free sulfur dioxide,total sulfur dioxide,density,pH,sulphates,alcohol,quality
+7.4,0.7,0,1.9,0.076,11,34,0.9978,3.51,0.56,9.4,5
+7.8,0.88,0,2.6,0.098,25,67,0.9968,3.2,0.68,9.8,5
+7.8,0.76,0.04,2.3,0.092,15,54,0.997,3.26,0.65,9.8,5
+11.2,0.28,0.56,1.9,0.075,17,60,0.998,3.16,0.58,9.8,6
+7.3,0.65,0,1.2,0.065,15,21,0.9946,3.39,0.47,10,7
+";
+
+/// Runs the default pipeline over the sample and verifies the stored rows.
+///
+/// The configuration file is not used: the self-test always runs the default stages, so its outcome
+/// only depends on the installation and the database.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating whether the self-test passed.
+///
+/// # Example
+///
+/// ```
+/// run_selftest().await.expect("Self-test failed");
+/// ```
+pub async fn run_selftest() -> Result<()> {
+    let path = std::env::temp_dir().join("pipeline_selftest.csv");
+    std::fs::write(&path, SAMPLE_CSV).context(format!("Failed to write the sample to {}", path.display()))?;
+
+    let mut config = PipelineConfig::default();
+    config.source = SourceConfig::Csv {
+        path: path.display().to_string(),
+        options: Default::default(),
+    };
+    // The sample is the same on every self-test
+    config.deduplication.on_duplicate = DuplicatePolicy::Run;
+    config.storage.table = SELFTEST_TABLE.to_string();
+
+    let pool = storage::create_connection_pool().await?;
+    seed::reset_database(&pool, &config.schema, SELFTEST_TABLE).await.context("Failed to reset the self-test table")?;
+
+    let result = pipeline::run(&config, &Hooks::from_commands(&[])).await;
+    std::fs::remove_file(&path).ok();
+    result.context("Self-test run failed")?;
+
+    let stored: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {}", SELFTEST_TABLE))
+        .fetch_one(&pool)
+        .await
+        .context("Failed to count the stored rows")?;
+    let expected = SAMPLE_CSV.lines().count() as i64 - 1;
+    if stored != expected {
+        bail!("Self-test stored {} rows, expected {}", stored, expected);
+    }
+
+    println!("Self-test passed: {} sample rows ingested, transformed and stored", stored);
+    Ok(())
+}
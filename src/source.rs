@@ -0,0 +1,1066 @@
+//! This module defines where the pipeline reads its input from.
+//!
+//! Every input connector implements [`Source`], which yields the input as a stream of DataFrames:
+//! a single frame for whole-dataset runs, or one frame per chunk in chunked mode. The pipeline only
+//! talks to the trait, so new connectors (S3, Kafka, SQL) plug in by adding a [`SourceConfig`]
+//! variant, without touching the pipeline core. CSV files were the first implementation; JSON,
+//! newline-delimited JSON, Excel, and Avro files follow, as do CSV objects in S3, Google Cloud Storage,
+//! and Azure Blob Storage, read through one [`ObjectStoreSource`] in builds with the `s3`, `gcs`, or
+//! `azure` feature. The result of a SQL query reads data already in PostgreSQL. A Kafka topic, consumed in micro-batches in builds with the `kafka` feature, is the
+//! only source whose stream never ends.
+
+use crate::chaos;
+use crate::config::{HeadersConfig, PipelineConfig, SamplingConfig};
+use crate::generate::Rng;
+use crate::ingestion::{self, ExcelSheet, IngestOptions};
+use crate::kafka::{self, BatchOffsets, KafkaSource, RecordFormat};
+use crate::mapping;
+use crate::retry::RetryPolicy;
+use crate::schema::TableSchema;
+use crate::storage;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use object_store::ObjectStore;
+use polars::prelude::*;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use ulid::Ulid;
+
+/// A stream of DataFrames produced by a source.
+pub type DataFrameStream = BoxStream<'static, Result<DataFrame>>;
+
+/// An input connector.
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// Describes the input for logs and lineage, e.g. its path or URL.
+    fn describe(&self) -> String;
+
+    /// Returns a checksum of the input's content, used to recognize a repeated load of the same data.
+    async fn checksum(&self) -> Result<String>;
+
+    /// Starts reading the input.
+    async fn read(&self) -> Result<DataFrameStream>;
+
+    /// Loads the position earlier runs stored the input up to, for a source that reads on from where
+    /// they stopped, e.g. a Kafka topic. Does nothing by default.
+    async fn resume(&self, _pool: &PgPool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Takes the offsets the oldest frame read and not stored yet ends at, for a source whose position
+    /// is stored in the transaction of the frame's rows. Returns `None` by default.
+    fn take_offsets(&self) -> Option<BatchOffsets> {
+        None
+    }
+}
+
+/// Which source a run reads from, selected in the `[source]` section with `kind`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SourceConfig {
+    /// A CSV file with a header row, delimited and quoted as its `[source.options]` say.
+    Csv {
+        path: String,
+        #[serde(default)]
+        options: IngestOptions,
+    },
+    /// A JSON file holding an array of objects, one per row.
+    Json { path: String },
+    /// A newline-delimited JSON file, one object per line.
+    Ndjson { path: String },
+    /// A sheet of an Excel workbook, the first one unless `sheet` names or numbers another.
+    Excel {
+        path: String,
+        #[serde(default)]
+        sheet: ExcelSheet,
+    },
+    /// An Avro object container file of records.
+    Avro { path: String },
+    /// A CSV object in an S3 bucket, possibly compressed, in builds with the `s3` feature.
+    ///
+    /// Credentials are resolved from the environment (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`,
+    /// `AWS_SESSION_TOKEN`), a web identity token, or the instance profile, in that order.
+    S3 {
+        bucket: String,
+        key: String,
+        /// Region of the bucket; defaults to `AWS_REGION`, or `us-east-1`.
+        region: Option<String>,
+        /// Endpoint of an S3-compatible store such as MinIO; defaults to AWS.
+        endpoint: Option<String>,
+        #[serde(default)]
+        options: IngestOptions,
+    },
+    /// A CSV object in a Google Cloud Storage bucket, in builds with the `gcs` feature.
+    ///
+    /// Credentials are resolved from `GOOGLE_SERVICE_ACCOUNT` or `GOOGLE_APPLICATION_CREDENTIALS`, or
+    /// the metadata server of the instance.
+    Gcs {
+        bucket: String,
+        key: String,
+        #[serde(default)]
+        options: IngestOptions,
+    },
+    /// A CSV blob in an Azure Blob Storage container, in builds with the `azure` feature.
+    ///
+    /// Credentials are resolved from `AZURE_STORAGE_ACCOUNT_KEY`, a service principal, or the managed
+    /// identity of the instance.
+    Azure {
+        container: String,
+        key: String,
+        /// Storage account of the container; defaults to `AZURE_STORAGE_ACCOUNT_NAME`.
+        account: Option<String>,
+        #[serde(default)]
+        options: IngestOptions,
+    },
+    /// The result of a SQL query, e.g. over a table already in the warehouse.
+    Postgres {
+        query: String,
+        /// Environment variable holding the URL of the database queried; defaults to the pipeline's own database.
+        url_env: Option<String>,
+    },
+    /// A Kafka topic of JSON or CSV records, consumed continuously in builds with the `kafka` feature.
+    ///
+    /// Runs need `[streaming] enabled`, or a row limit, since the topic never runs out of records; each
+    /// batch of at most `streaming.chunk_rows` records is transformed and stored as one chunk.
+    Kafka {
+        /// Comma-separated `host:port` list of bootstrap brokers.
+        brokers: String,
+        topic: String,
+        /// Consumer group whose offsets stored in `kafka_offsets` the consumer resumes from; defaults to `wine_quality_pipeline`.
+        group_id: Option<String>,
+        #[serde(default)]
+        format: RecordFormat,
+        /// Column names of CSV records, which carry no header.
+        #[serde(default)]
+        columns: Vec<String>,
+        /// Milliseconds a batch waits for more records after its first one; defaults to 1000.
+        batch_timeout_ms: Option<u64>,
+    },
+}
+
+impl SourceConfig {
+    /// The path of the input file, for sources reading a local file.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            SourceConfig::Csv { path, .. } | SourceConfig::Json { path } | SourceConfig::Ndjson { path } | SourceConfig::Excel { path, .. } | SourceConfig::Avro { path } => Some(path),
+            SourceConfig::S3 { .. } | SourceConfig::Gcs { .. } | SourceConfig::Azure { .. } | SourceConfig::Postgres { .. } | SourceConfig::Kafka { .. } => None,
+        }
+    }
+
+    /// The same source reading another local file, for sources reading one.
+    pub fn with_path(&self, file_path: &str) -> Option<SourceConfig> {
+        let mut source = self.clone();
+        match &mut source {
+            SourceConfig::Csv { path, .. } | SourceConfig::Json { path } | SourceConfig::Ndjson { path } | SourceConfig::Excel { path, .. } | SourceConfig::Avro { path } => {
+                *path = file_path.to_string();
+            }
+            SourceConfig::S3 { .. } | SourceConfig::Gcs { .. } | SourceConfig::Azure { .. } | SourceConfig::Postgres { .. } | SourceConfig::Kafka { .. } => return None,
+        }
+        Some(source)
+    }
+
+    /// The object store source, for sources reading a cloud object.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_rows` - Rows per yielded frame; `None` yields the whole object as one frame.
+    pub fn object_source(&self, chunk_rows: Option<usize>) -> Option<ObjectStoreSource> {
+        let (provider, bucket, key, options) = match self {
+            SourceConfig::S3 { bucket, key, region, endpoint, options } => {
+                (ObjectStoreProvider::S3 { region: region.clone(), endpoint: endpoint.clone() }, bucket, key, options)
+            }
+            SourceConfig::Gcs { bucket, key, options } => (ObjectStoreProvider::Gcs, bucket, key, options),
+            SourceConfig::Azure { container, key, account, options } => (ObjectStoreProvider::Azure { account: account.clone() }, container, key, options),
+            _ => return None,
+        };
+        Some(ObjectStoreSource { provider, bucket: bucket.clone(), key: key.clone(), options: options.clone(), chunk_rows, retry: RetryPolicy::default() })
+    }
+}
+
+impl Default for SourceConfig {
+    fn default() -> Self {
+        SourceConfig::Csv {
+            path: "data/dataset.csv".to_string(),
+            options: IngestOptions::default(),
+        }
+    }
+}
+
+/// Builds the configured source. In chunked mode the source yields chunks of `streaming.chunk_rows` rows.
+///
+/// # Arguments
+///
+/// * `config` - The pipeline configuration.
+///
+/// # Returns
+///
+/// * `Box<dyn Source>` - The source.
+///
+/// # Example
+///
+/// ```
+/// let df = collect(from_config(&config).read().await?).await?;
+/// ```
+pub fn from_config(config: &PipelineConfig) -> Box<dyn Source> {
+    let chunk_rows = chunk_rows(config);
+    let retry = &config.retry.ingestion;
+    let source: Box<dyn Source> = match &config.source {
+        SourceConfig::Csv { path, options } => Box::new(CsvSource { path: path.clone(), options: csv_options(options, &config.schema), chunk_rows, retry: retry.clone() }),
+        SourceConfig::Json { path } => Box::new(JsonSource { path: path.clone(), lines: false, chunk_rows, retry: retry.clone() }),
+        SourceConfig::Ndjson { path } => Box::new(JsonSource { path: path.clone(), lines: true, chunk_rows, retry: retry.clone() }),
+        SourceConfig::Excel { path, sheet } => Box::new(ExcelSource { path: path.clone(), sheet: sheet.clone(), chunk_rows, retry: retry.clone() }),
+        SourceConfig::Avro { path } => Box::new(AvroSource { path: path.clone(), chunk_rows, retry: retry.clone() }),
+        SourceConfig::S3 { .. } | SourceConfig::Gcs { .. } | SourceConfig::Azure { .. } => {
+            let mut source = config.source.object_source(chunk_rows).expect("cloud sources read an object");
+            source.options = csv_options(&source.options, &config.schema);
+            source.retry = retry.clone();
+            Box::new(source)
+        }
+        SourceConfig::Postgres { query, url_env } => Box::new(PostgresSource { query: query.clone(), url_env: url_env.clone(), chunk_rows }),
+        SourceConfig::Kafka { brokers, topic, group_id, format, columns, batch_timeout_ms } => Box::new(KafkaSource {
+            brokers: brokers.clone(),
+            topic: topic.clone(),
+            group_id: group_id.clone().unwrap_or_else(|| kafka::DEFAULT_GROUP_ID.to_string()),
+            format: *format,
+            columns: columns.clone(),
+            batch_rows: chunk_rows.unwrap_or(config.streaming.chunk_rows).max(1),
+            batch_timeout: std::time::Duration::from_millis(batch_timeout_ms.unwrap_or(kafka::DEFAULT_BATCH_TIMEOUT_MS)),
+            tracker: Default::default(),
+        }),
+    };
+    let source: Box<dyn Source> = if config.headers.is_active() {
+        Box::new(HeaderSource { inner: source, schema: config.schema.clone(), headers: config.headers.clone() })
+    } else {
+        source
+    };
+    limit(config, source)
+}
+
+/// Creates the source reading some of the files of the configured local source, one after the other, as one input.
+///
+/// # Arguments
+///
+/// * `config` - The pipeline configuration, whose source reads a local file.
+/// * `files` - The paths of the files read, in order.
+///
+/// # Returns
+///
+/// * `Box<dyn Source>` - The source, limited or sampled as the configuration says.
+///
+/// # Example
+///
+/// ```
+/// let source = from_files(&config, &["data/2023-03.csv".to_string()]);
+/// ```
+pub fn from_files(config: &PipelineConfig, files: &[String]) -> Box<dyn Source> {
+    // The limit and the sample apply to the files together, but each file is still read in their chunks
+    let mut file_config = config.clone();
+    file_config.row_limit = None;
+    file_config.sampling = SamplingConfig::default();
+    if let Some(chunk_rows) = chunk_rows(config) {
+        file_config.streaming.enabled = true;
+        file_config.streaming.chunk_rows = chunk_rows;
+    }
+    let mut parts: Vec<Arc<dyn Source>> = vec![];
+    for file in files {
+        file_config.source = config.source.with_path(file).expect("only local sources are read by file");
+        parts.push(Arc::from(from_config(&file_config)));
+    }
+    limit(config, Box::new(FilesSource { parts }))
+}
+
+/// Helper function to choose the rows per frame a source yields, `None` to yield the whole input as one frame.
+fn chunk_rows(config: &PipelineConfig) -> Option<usize> {
+    // A limited run reads in chunks of the limit, so it stops reading once it has enough rows, and a
+    // sampled run reads in chunks, so it never holds more of the input than a chunk and its sample
+    let sampled = config.sampling.describe().is_some();
+    config
+        .streaming
+        .enabled
+        .then_some(config.streaming.chunk_rows)
+        .or(config.row_limit)
+        .or(sampled.then_some(config.streaming.chunk_rows))
+}
+
+/// Helper function to sample and limit the rows of a source, as `--sample` and `--limit` say.
+fn limit(config: &PipelineConfig, source: Box<dyn Source>) -> Box<dyn Source> {
+    let source: Box<dyn Source> = match config.sampling.describe() {
+        Some(_) => Box::new(SampledSource { inner: source, sampling: config.sampling.clone() }),
+        None => source,
+    };
+    match config.row_limit {
+        Some(rows) => Box::new(LimitedSource { inner: source, rows }),
+        None => source,
+    }
+}
+
+/// A source reading other sources one after the other, for runs leaving out some files of their input.
+struct FilesSource {
+    parts: Vec<Arc<dyn Source>>,
+}
+
+#[async_trait]
+impl Source for FilesSource {
+    fn describe(&self) -> String {
+        self.parts.iter().map(|part| part.describe()).collect::<Vec<_>>().join(", ")
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for part in &self.parts {
+            hasher.update(part.checksum().await?.as_bytes());
+        }
+        Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        let mut parts = self.parts.clone().into_iter();
+        // The first file is opened right away, so a missing input fails the read rather than its first frame
+        let first = parts.next().context("No files to read")?.read().await?;
+        let rest = stream::iter(parts).then(|part| async move { part.read().await }).try_flatten();
+        Ok(first.chain(rest).boxed())
+    }
+}
+
+/// Helper function to name the columns of CSV files without a header row after the schema's input columns, unless the options name them.
+fn csv_options(options: &IngestOptions, schema: &TableSchema) -> IngestOptions {
+    let mut options = options.clone();
+    if options.columns.is_empty() {
+        options.columns = schema.input_columns().map(|c| c.name.clone()).collect();
+    }
+    options
+}
+
+/// A source reading the columns of another under their canonical names, as the `[headers]` section says.
+struct HeaderSource {
+    inner: Box<dyn Source>,
+    schema: TableSchema,
+    headers: HeadersConfig,
+}
+
+#[async_trait]
+impl Source for HeaderSource {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        self.inner.checksum().await
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        let (schema, headers) = (self.schema.clone(), self.headers.clone());
+        Ok(self.inner.read().await?.map(move |frame| mapping::canonicalize(frame?, &schema, &headers)).boxed())
+    }
+
+    async fn resume(&self, pool: &PgPool) -> Result<()> {
+        self.inner.resume(pool).await
+    }
+
+    fn take_offsets(&self) -> Option<BatchOffsets> {
+        self.inner.take_offsets()
+    }
+}
+
+/// A source yielding only the first rows of another, for runs started with `--limit`.
+///
+/// The offsets of a Kafka batch cut short still cover the whole batch, so the next run resumes after it.
+struct LimitedSource {
+    inner: Box<dyn Source>,
+    rows: usize,
+}
+
+#[async_trait]
+impl Source for LimitedSource {
+    fn describe(&self) -> String {
+        format!("the first {} rows of {}", self.rows, self.inner.describe())
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        self.inner.checksum().await
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        Ok(limit_rows(self.inner.read().await?, self.rows))
+    }
+
+    async fn resume(&self, pool: &PgPool) -> Result<()> {
+        self.inner.resume(pool).await
+    }
+
+    fn take_offsets(&self) -> Option<BatchOffsets> {
+        self.inner.take_offsets()
+    }
+}
+
+/// Helper function to end a stream after its first `rows` rows, cutting the last frame short.
+fn limit_rows(stream: DataFrameStream, rows: usize) -> DataFrameStream {
+    stream::unfold((stream, rows), |(mut stream, remaining)| async move {
+        // Stopping before the next poll keeps the source from reading another chunk
+        if remaining == 0 {
+            return None;
+        }
+        let frame = stream.next().await?.map(|frame| frame.head(Some(remaining)));
+        let left = frame.as_ref().map_or(remaining, |frame| remaining - frame.height());
+        Some((frame, (stream, left)))
+    })
+    .boxed()
+}
+
+/// A source yielding a random sample of the rows of another, for runs started with `--sample`.
+struct SampledSource {
+    inner: Box<dyn Source>,
+    sampling: SamplingConfig,
+}
+
+#[async_trait]
+impl Source for SampledSource {
+    fn describe(&self) -> String {
+        format!("a sample of {} of {}", self.sampling.describe().unwrap_or_default(), self.inner.describe())
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        self.inner.checksum().await
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        let stream = self.inner.read().await?;
+        Ok(match (self.sampling.rows, self.sampling.fraction) {
+            (Some(rows), _) => reservoir_rows(stream, rows, self.sampling.seed),
+            (None, fraction) => sample_rows(stream, fraction.unwrap_or(1.0), self.sampling.seed),
+        })
+    }
+
+    async fn resume(&self, pool: &PgPool) -> Result<()> {
+        self.inner.resume(pool).await
+    }
+
+    fn take_offsets(&self) -> Option<BatchOffsets> {
+        self.inner.take_offsets()
+    }
+}
+
+/// Helper function to keep each row of a stream with probability `fraction`, frame by frame.
+fn sample_rows(stream: DataFrameStream, fraction: f64, seed: u64) -> DataFrameStream {
+    let mut rng = Rng::new(seed);
+    stream
+        .map(move |frame| {
+            let frame = frame?;
+            let kept: Vec<bool> = (0..frame.height()).map(|_| rng.chance(fraction)).collect();
+            frame.filter(&BooleanChunked::from_slice("sample", &kept)).context("Failed to sample rows")
+        })
+        .boxed()
+}
+
+/// Helper function to draw `rows` rows of a stream uniformly, as one frame once the stream ends.
+///
+/// Every row gets a random key, and the rows with the smallest keys are kept, so only the sample and
+/// the current frame are held in memory. The sampled rows keep their order in the input.
+fn reservoir_rows(mut stream: DataFrameStream, rows: usize, seed: u64) -> DataFrameStream {
+    stream::once(async move {
+        let mut rng = Rng::new(seed);
+        let mut sample: Option<DataFrame> = None;
+        // The key of each row of the sample, whose rows are in input order
+        let mut keys: Vec<u64> = vec![];
+        while let Some(frame) = stream.next().await {
+            let frame = frame?;
+            keys.extend((0..frame.height()).map(|_| rng.next_u64()));
+            let mut df = match sample.take() {
+                Some(mut df) => {
+                    df.vstack_mut(&frame).context("Failed to stack input frames")?;
+                    df
+                }
+                None => frame,
+            };
+            if keys.len() > rows {
+                let mut order: Vec<usize> = (0..keys.len()).collect();
+                order.sort_unstable_by_key(|&i| keys[i]);
+                order.truncate(rows);
+                order.sort_unstable();
+                df = df.take(&IdxCa::from_vec("sample", order.iter().map(|&i| i as IdxSize).collect())).context("Failed to sample rows")?;
+                keys = order.into_iter().map(|i| keys[i]).collect();
+            }
+            sample = Some(df);
+        }
+        sample.context("The source produced no data")
+    })
+    .boxed()
+}
+
+/// Reads a whole source stream into one DataFrame.
+///
+/// # Arguments
+///
+/// * `stream` - The stream returned by [`Source::read`].
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The frames stacked in order, or the first error of the stream.
+pub async fn collect(mut stream: DataFrameStream) -> Result<DataFrame> {
+    let mut df: Option<DataFrame> = None;
+    while let Some(frame) = stream.next().await {
+        let frame = frame?;
+        df = Some(match df {
+            Some(mut df) => {
+                df.vstack_mut(&frame).context("Failed to stack input frames")?;
+                df
+            }
+            None => frame,
+        });
+    }
+    df.context("The source produced no data")
+}
+
+/// Reads a CSV file, whole or in chunks.
+pub struct CsvSource {
+    pub path: String,
+    pub options: IngestOptions,
+    /// Rows per yielded frame; `None` yields the whole file as one frame.
+    pub chunk_rows: Option<usize>,
+    /// Attempts of reading the file whole, and the waits between them.
+    pub retry: RetryPolicy,
+}
+
+#[async_trait]
+impl Source for CsvSource {
+    fn describe(&self) -> String {
+        if self.path == ingestion::STDIN_PATH {
+            return "standard input".to_string();
+        }
+        self.path.clone()
+    }
+
+    /// Standard input can only be read once, so a run reading it gets a checksum of its own and counts as a new load.
+    async fn checksum(&self) -> Result<String> {
+        if self.path == ingestion::STDIN_PATH {
+            return Ok(format!("stdin#{}", Ulid::new()));
+        }
+        file_checksum(&self.path).await
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        chaos::slow_read().await;
+        match self.chunk_rows {
+            Some(chunk_rows) => {
+                let options = self.options.clone();
+                chunks_of_files(&self.path, move |path| ingestion::read_csv_chunks(path, chunk_rows, &options))
+            }
+            None => {
+                let options = self.options.clone();
+                read_whole(move |path| ingestion::ingest_csv_with(path, &options), &self.path, &self.retry).await
+            }
+        }
+    }
+}
+
+/// Reads a JSON or newline-delimited JSON file, whole or in chunks.
+///
+/// A JSON array can only be parsed whole, so in chunked mode it is read at once and then yielded in
+/// slices; newline-delimited files are read chunk by chunk.
+pub struct JsonSource {
+    pub path: String,
+    /// Whether the file holds one object per line rather than an array.
+    pub lines: bool,
+    /// Rows per yielded frame; `None` yields the whole file as one frame.
+    pub chunk_rows: Option<usize>,
+    /// Attempts of reading the file whole, and the waits between them.
+    pub retry: RetryPolicy,
+}
+
+#[async_trait]
+impl Source for JsonSource {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        file_checksum(&self.path).await
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        chaos::slow_read().await;
+        match (self.chunk_rows, self.lines) {
+            (Some(chunk_rows), true) => chunks_of_files(&self.path, move |path| ingestion::read_ndjson_chunks(path, chunk_rows)),
+            (Some(chunk_rows), false) => slice_stream(read_whole(ingestion::ingest_json, &self.path, &self.retry).await?, chunk_rows).await,
+            (None, true) => read_whole(ingestion::ingest_ndjson, &self.path, &self.retry).await,
+            (None, false) => read_whole(ingestion::ingest_json, &self.path, &self.retry).await,
+        }
+    }
+}
+
+/// Reads a sheet of an Excel workbook, whole or, in chunked mode, in slices of the whole sheet.
+pub struct ExcelSource {
+    pub path: String,
+    pub sheet: ExcelSheet,
+    /// Rows per yielded frame; `None` yields the whole sheet as one frame.
+    pub chunk_rows: Option<usize>,
+    /// Attempts of reading the sheet whole, and the waits between them.
+    pub retry: RetryPolicy,
+}
+
+#[async_trait]
+impl Source for ExcelSource {
+    fn describe(&self) -> String {
+        format!("{} ({})", self.path, self.sheet)
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        file_checksum(&self.path).await
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        chaos::slow_read().await;
+        let sheet = self.sheet.clone();
+        let whole = read_whole(move |path| ingestion::ingest_excel(path, &sheet), &self.path, &self.retry).await?;
+        match self.chunk_rows {
+            Some(chunk_rows) => slice_stream(whole, chunk_rows).await,
+            None => Ok(whole),
+        }
+    }
+}
+
+/// Reads an Avro file, whole or, in chunked mode, in slices of the whole file.
+pub struct AvroSource {
+    pub path: String,
+    /// Rows per yielded frame; `None` yields the whole file as one frame.
+    pub chunk_rows: Option<usize>,
+    /// Attempts of reading the file whole, and the waits between them.
+    pub retry: RetryPolicy,
+}
+
+#[async_trait]
+impl Source for AvroSource {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        file_checksum(&self.path).await
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        chaos::slow_read().await;
+        let whole = read_whole(ingestion::ingest_avro, &self.path, &self.retry).await?;
+        match self.chunk_rows {
+            Some(chunk_rows) => slice_stream(whole, chunk_rows).await,
+            None => Ok(whole),
+        }
+    }
+}
+
+/// The object store an [`ObjectStoreSource`] reads from, each in builds with the feature of the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectStoreProvider {
+    S3 { region: Option<String>, endpoint: Option<String> },
+    Gcs,
+    Azure { account: Option<String> },
+}
+
+impl ObjectStoreProvider {
+    /// The Cargo feature the provider needs.
+    pub fn feature(&self) -> &'static str {
+        match self {
+            ObjectStoreProvider::S3 { .. } => "s3",
+            ObjectStoreProvider::Gcs => "gcs",
+            ObjectStoreProvider::Azure { .. } => "azure",
+        }
+    }
+
+    /// Whether this binary was built with the provider's feature.
+    pub fn is_built(&self) -> bool {
+        match self {
+            ObjectStoreProvider::S3 { .. } => cfg!(feature = "s3"),
+            ObjectStoreProvider::Gcs => cfg!(feature = "gcs"),
+            ObjectStoreProvider::Azure { .. } => cfg!(feature = "azure"),
+        }
+    }
+
+    /// Helper function to name an object by URL.
+    fn url(&self, bucket: &str, key: &str) -> String {
+        let scheme = match self {
+            ObjectStoreProvider::S3 { .. } => "s3",
+            ObjectStoreProvider::Gcs => "gs",
+            ObjectStoreProvider::Azure { .. } => "az",
+        };
+        format!("{}://{}/{}", scheme, bucket, key)
+    }
+
+    /// Helper function to open a bucket, resolving credentials the way the provider's own tools do.
+    fn open(&self, bucket: &str) -> Result<Arc<dyn ObjectStore>> {
+        match self {
+            ObjectStoreProvider::S3 { region, endpoint } => s3_store(bucket, region.as_deref(), endpoint.as_deref()),
+            ObjectStoreProvider::Gcs => gcs_store(bucket),
+            ObjectStoreProvider::Azure { account } => azure_store(bucket, account.as_deref()),
+        }
+    }
+}
+
+#[cfg(feature = "s3")]
+fn s3_store(bucket: &str, region: Option<&str>, endpoint: Option<&str>) -> Result<Arc<dyn ObjectStore>> {
+    let mut builder = object_store::aws::AmazonS3Builder::from_env().with_bucket_name(bucket);
+    if let Some(region) = region {
+        builder = builder.with_region(region);
+    }
+    if let Some(endpoint) = endpoint {
+        builder = builder.with_endpoint(endpoint).with_allow_http(endpoint.starts_with("http://"));
+    }
+    Ok(Arc::new(builder.build().context(format!("Failed to open S3 bucket {}", bucket))?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_store(_bucket: &str, _region: Option<&str>, _endpoint: Option<&str>) -> Result<Arc<dyn ObjectStore>> {
+    anyhow::bail!("The s3 source requires building with the `s3` feature")
+}
+
+#[cfg(feature = "gcs")]
+fn gcs_store(bucket: &str) -> Result<Arc<dyn ObjectStore>> {
+    let store = object_store::gcp::GoogleCloudStorageBuilder::from_env()
+        .with_bucket_name(bucket)
+        .build()
+        .context(format!("Failed to open GCS bucket {}", bucket))?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "gcs"))]
+fn gcs_store(_bucket: &str) -> Result<Arc<dyn ObjectStore>> {
+    anyhow::bail!("The gcs source requires building with the `gcs` feature")
+}
+
+#[cfg(feature = "azure")]
+fn azure_store(container: &str, account: Option<&str>) -> Result<Arc<dyn ObjectStore>> {
+    let mut builder = object_store::azure::MicrosoftAzureBuilder::from_env().with_container_name(container);
+    if let Some(account) = account {
+        builder = builder.with_account(account);
+    }
+    Ok(Arc::new(builder.build().context(format!("Failed to open Azure container {}", container))?))
+}
+
+#[cfg(not(feature = "azure"))]
+fn azure_store(_container: &str, _account: Option<&str>) -> Result<Arc<dyn ObjectStore>> {
+    anyhow::bail!("The azure source requires building with the `azure` feature")
+}
+
+/// Reads a CSV object from S3, GCS, or Azure Blob Storage, whole or in chunks.
+///
+/// The object is downloaded to a temporary file first, so it is parsed like a local CSV file,
+/// compressed or not, and retries of the parsing do not download it again.
+pub struct ObjectStoreSource {
+    pub provider: ObjectStoreProvider,
+    /// The bucket, or the container for Azure.
+    pub bucket: String,
+    pub key: String,
+    pub options: IngestOptions,
+    /// Rows per yielded frame; `None` yields the whole object as one frame.
+    pub chunk_rows: Option<usize>,
+    /// Attempts of reading the object whole, and the waits between them.
+    pub retry: RetryPolicy,
+}
+
+#[async_trait]
+impl Source for ObjectStoreSource {
+    fn describe(&self) -> String {
+        self.provider.url(&self.bucket, &self.key)
+    }
+
+    async fn checksum(&self) -> Result<String> {
+        let store = self.provider.open(&self.bucket)?;
+        let meta = store.head(&self.key.as_str().into()).await.context(format!("Failed to find {}", self.describe()))?;
+        // The ETag changes whenever the object is rewritten; objects without one are hashed
+        match meta.e_tag {
+            Some(e_tag) => Ok(format!("etag:{}", e_tag.trim_matches('"'))),
+            None => {
+                let download = self.download(store).await?;
+                let checksum = file_checksum(&download.display().to_string()).await;
+                remove_download(&download);
+                checksum
+            }
+        }
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        chaos::slow_read().await;
+        let store = self.provider.open(&self.bucket)?;
+        let download = self.download(store).await?;
+        let path = download.display().to_string();
+        let stream = match self.chunk_rows {
+            // The chunks are read from the open file, which outlives its directory entry
+            Some(chunk_rows) => ingestion::read_csv_chunks(&path, chunk_rows, &self.options).map(stream_chunks),
+            None => {
+                let options = self.options.clone();
+                read_whole(move |path| ingestion::ingest_csv_with(path, &options), &path, &self.retry).await
+            }
+        };
+        remove_download(&download);
+        stream
+    }
+}
+
+impl ObjectStoreSource {
+    /// Helper function to download the object to a temporary file named like the key.
+    async fn download(&self, store: Arc<dyn ObjectStore>) -> Result<PathBuf> {
+        let name = self.key.rsplit('/').next().unwrap_or_default();
+        let path = std::env::temp_dir().join(format!("wine_quality_{}_{}_{}", self.provider.feature(), Ulid::new(), name));
+        let object = store.get(&self.key.as_str().into()).await.context(format!("Failed to read {}", self.describe()))?;
+        println!("Downloading {} ({} KB)", self.describe(), object.meta.size >> 10);
+
+        let mut file = tokio::fs::File::create(&path).await.context(format!("Failed to create {}", path.display()))?;
+        let mut bytes = object.into_stream();
+        while let Some(chunk) = bytes.next().await {
+            let written = match chunk {
+                Ok(chunk) => file.write_all(&chunk).await.map_err(anyhow::Error::from),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = written {
+                remove_download(&path);
+                return Err(e.context(format!("Failed to download {}", self.describe())));
+            }
+        }
+        file.flush().await?;
+        Ok(path)
+    }
+}
+
+/// Helper function to remove a downloaded object.
+fn remove_download(path: &std::path::Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        eprintln!("Failed to remove the downloaded object {}: {}", path.display(), e);
+    }
+}
+
+/// Helper function to checksum the content of an input file.
+pub(crate) async fn file_checksum(path: &str) -> Result<String> {
+    // The files a glob pattern matches are digested in order, as one input
+    let mut hasher = Sha256::new();
+    for file in ingestion::expand_paths(path)? {
+        let content = tokio::fs::read(&file).await.context(format!("Failed to read {} to checksum it", file))?;
+        hasher.update(&content);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Reads the result of a SQL query, whole or, in chunked mode, in slices of the whole result.
+pub struct PostgresSource {
+    pub query: String,
+    /// Environment variable holding the URL of the database queried; `None` queries the pipeline's database.
+    pub url_env: Option<String>,
+    /// Rows per yielded frame; `None` yields the whole result as one frame.
+    pub chunk_rows: Option<usize>,
+}
+
+impl PostgresSource {
+    /// Helper function to connect to the database queried.
+    async fn connect(&self) -> Result<PgPool> {
+        let Some(name) = &self.url_env else {
+            return storage::create_connection_pool().await;
+        };
+        let url = std::env::var(name).context(format!("The source query expects a database URL in the environment variable {}", name))?;
+        PgPoolOptions::new().max_connections(1).connect(&url).await.context(format!("Failed to connect to the source database in {}", name))
+    }
+}
+
+#[async_trait]
+impl Source for PostgresSource {
+    fn describe(&self) -> String {
+        format!("postgres query: {}", self.query)
+    }
+
+    /// Digests the rows of the result in the database, in an order of their own, so the rows are not transferred twice.
+    async fn checksum(&self) -> Result<String> {
+        let pool = self.connect().await?;
+        let sql = format!(
+            "SELECT COALESCE(md5(string_agg(md5(source::TEXT), '' ORDER BY md5(source::TEXT))), md5('')) FROM ({}) AS source",
+            self.query.trim().trim_end_matches(';')
+        );
+        sqlx::query_scalar(&sql).fetch_one(&pool).await.context(format!("Failed to checksum the result of the source query {}", self.query))
+    }
+
+    async fn read(&self) -> Result<DataFrameStream> {
+        chaos::slow_read().await;
+        let pool = self.connect().await?;
+        let df = ingestion::ingest_from_postgres(&pool, &self.query).await?;
+        let whole = stream::once(async { Ok(df) }).boxed();
+        match self.chunk_rows {
+            Some(chunk_rows) => slice_stream(whole, chunk_rows).await,
+            None => Ok(whole),
+        }
+    }
+}
+
+/// Helper function to ingest a whole file on blocking threads, with retries waited for asynchronously, as a one-frame stream.
+async fn read_whole(ingest: impl Fn(&str) -> Result<DataFrame> + Send + Sync + 'static, path: &str, retry: &RetryPolicy) -> Result<DataFrameStream> {
+    let df = ingestion::retry_ingest_with_async(ingest, path, retry).await?;
+    Ok(stream::once(async { Ok(df) }).boxed())
+}
+
+/// Helper function to re-yield an input read whole in slices of `chunk_rows` rows.
+async fn slice_stream(whole: DataFrameStream, chunk_rows: usize) -> Result<DataFrameStream> {
+    let df = collect(whole).await?;
+    let chunk_rows = chunk_rows.max(1);
+    let slices: Vec<Result<DataFrame>> = (0..df.height()).step_by(chunk_rows).map(|offset| Ok(df.slice(offset as i64, chunk_rows))).collect();
+    Ok(stream::iter(slices).boxed())
+}
+
+/// Helper function to stream the chunks of every file a path or glob pattern matches, one file after the other.
+fn chunks_of_files<I>(pattern: &str, open: impl Fn(&str) -> Result<I> + Send + 'static) -> Result<DataFrameStream>
+where
+    I: Iterator<Item = Result<DataFrame>> + Send + 'static,
+{
+    let mut paths = ingestion::expand_paths(pattern)?.into_iter();
+    // The first file is opened right away, so a missing input fails the read rather than its first chunk
+    let first = open(&paths.next().expect("expanded paths are never empty"))?;
+    let rest = paths.flat_map(move |path| -> Box<dyn Iterator<Item = Result<DataFrame>> + Send> {
+        match open(&path) {
+            Ok(chunks) => Box::new(chunks),
+            Err(e) => Box::new(std::iter::once(Err(e.context(format!("Failed to open {}", path))))),
+        }
+    });
+    Ok(stream_chunks(first.chain(rest)))
+}
+
+/// Helper function to stream the chunks of a file parsed on a blocking thread.
+fn stream_chunks(chunks: impl Iterator<Item = Result<DataFrame>> + Send + 'static) -> DataFrameStream {
+    // The channel holds the reader back while the consumer is busy
+    let (tx, rx) = mpsc::channel(1);
+    tokio::task::spawn_blocking(move || {
+        for chunk in chunks {
+            if tx.blocking_send(chunk).is_err() {
+                break;
+            }
+        }
+    });
+    stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) }).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_csv_source_reads_whole_or_chunked() {
+        let path = "temp_source_test.csv";
+        std::fs::write(path, "alcohol,quality\n9.4,5\n9.8,5\n10.1,6\n").expect("Failed to write temp CSV file");
+
+        let whole = CsvSource { path: path.to_string(), options: IngestOptions::default(), chunk_rows: None, retry: RetryPolicy::default() };
+        let df = collect(whole.read().await.unwrap()).await.unwrap();
+        let chunked = CsvSource { path: path.to_string(), options: IngestOptions::default(), chunk_rows: Some(2), retry: RetryPolicy::default() };
+        let heights: Vec<usize> = chunked.read().await.unwrap().map(|chunk| chunk.unwrap().height()).collect().await;
+        let collected = collect(chunked.read().await.unwrap()).await.unwrap();
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(df.shape(), (3, 2));
+        assert_eq!(heights, vec![2, 1]);
+        assert!(collected.equals(&df));
+    }
+
+    #[tokio::test]
+    async fn test_stdin_source_counts_every_run_as_a_new_load() {
+        let stdin = CsvSource { path: ingestion::STDIN_PATH.to_string(), options: IngestOptions::default(), chunk_rows: None, retry: RetryPolicy::default() };
+
+        assert_eq!(stdin.describe(), "standard input");
+        assert_ne!(stdin.checksum().await.unwrap(), stdin.checksum().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_limited_source_stops_after_the_limit() {
+        let path = "temp_limited_source_test.csv";
+        std::fs::write(path, "alcohol,quality\n9.4,5\n9.8,5\n10.1,6\n10.4,7\n").expect("Failed to write temp CSV file");
+        let config = PipelineConfig {
+            source: SourceConfig::Csv { path: path.to_string(), options: IngestOptions::default() },
+            row_limit: Some(3),
+            ..PipelineConfig::default()
+        };
+
+        let source = from_config(&config);
+        let df = collect(source.read().await.unwrap()).await.unwrap();
+        let frames = vec![Ok(polars::df!("alcohol" => &[9.4, 9.8]).unwrap()), Ok(polars::df!("alcohol" => &[10.1, 10.4]).unwrap())];
+        let heights: Vec<usize> = limit_rows(stream::iter(frames).boxed(), 3).map(|frame| frame.unwrap().height()).collect().await;
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(df.column("quality").unwrap().i64().unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![5, 5, 6]);
+        assert_eq!(source.describe(), format!("the first 3 rows of {}", path));
+        assert_eq!(heights, vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_sampled_rows_are_seeded() {
+        let frames = || stream::iter((0..4).map(|i| Ok(polars::df!("id" => &((i * 25)..(i * 25 + 25)).collect::<Vec<i64>>()).unwrap()))).boxed();
+        let ids = |df: &DataFrame| df.column("id").unwrap().i64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+
+        let drawn = collect(reservoir_rows(frames(), 10, 42)).await.unwrap();
+        let again = collect(reservoir_rows(frames(), 10, 42)).await.unwrap();
+        let other = collect(reservoir_rows(frames(), 10, 7)).await.unwrap();
+        let fraction = collect(sample_rows(frames(), 0.5, 42)).await.unwrap();
+
+        assert_eq!(drawn.height(), 10);
+        assert!(ids(&drawn).windows(2).all(|pair| pair[0] < pair[1]), "rows keep their input order");
+        assert_eq!(ids(&drawn), ids(&again));
+        assert_ne!(ids(&drawn), ids(&other));
+        assert!((25..=75).contains(&fraction.height()));
+        assert!(fraction.equals(&collect(sample_rows(frames(), 0.5, 42)).await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_json_source_chunks_arrays_and_lines() {
+        let json_path = "temp_source_test.json";
+        let ndjson_path = "temp_source_test.ndjson";
+        std::fs::write(json_path, r#"[{"alcohol": 9.4, "quality": 5}, {"alcohol": 9.8, "quality": 5}, {"alcohol": 10.1, "quality": 6}]"#)
+            .expect("Failed to write temp JSON file");
+        std::fs::write(ndjson_path, "{\"alcohol\": 9.4, \"quality\": 5}\n{\"alcohol\": 9.8, \"quality\": 5}\n{\"alcohol\": 10.1, \"quality\": 6}\n")
+            .expect("Failed to write temp NDJSON file");
+
+        let mut heights = vec![];
+        for (path, lines) in [(json_path, false), (ndjson_path, true)] {
+            let source = JsonSource { path: path.to_string(), lines, chunk_rows: Some(2), retry: RetryPolicy::default() };
+            heights.push(source.read().await.unwrap().map(|chunk| chunk.unwrap().height()).collect::<Vec<_>>().await);
+        }
+        let whole = collect(JsonSource { path: ndjson_path.to_string(), lines: true, chunk_rows: None, retry: RetryPolicy::default() }.read().await.unwrap()).await.unwrap();
+        std::fs::remove_file(json_path).ok();
+        std::fs::remove_file(ndjson_path).ok();
+
+        assert_eq!(heights, vec![vec![2, 1], vec![2, 1]]);
+        assert_eq!(whole.shape(), (3, 2));
+    }
+
+    #[test]
+    fn test_excel_source_config_selects_sheet() {
+        let by_index: SourceConfig = toml::from_str("kind = \"excel\"\npath = \"lab.xlsx\"\nsheet = 2").unwrap();
+        let by_name: SourceConfig = toml::from_str("kind = \"excel\"\npath = \"lab.xlsx\"\nsheet = \"Results\"").unwrap();
+        let first: SourceConfig = toml::from_str("kind = \"excel\"\npath = \"lab.xlsx\"").unwrap();
+
+        assert_eq!(by_index, SourceConfig::Excel { path: "lab.xlsx".to_string(), sheet: ExcelSheet::Index(2) });
+        assert_eq!(by_name, SourceConfig::Excel { path: "lab.xlsx".to_string(), sheet: ExcelSheet::Name("Results".to_string()) });
+        assert_eq!(first.path(), Some("lab.xlsx"));
+        assert!(matches!(first, SourceConfig::Excel { sheet: ExcelSheet::Index(0), .. }));
+    }
+
+    #[test]
+    fn test_s3_source_config() {
+        let config: SourceConfig = toml::from_str("kind = \"s3\"\nbucket = \"wine-datasets\"\nkey = \"archive/2023.csv.gz\"\nendpoint = \"http://localhost:9000\"").unwrap();
+        let source = from_config(&PipelineConfig { source: config.clone(), ..PipelineConfig::default() });
+
+        assert_eq!(config.path(), None);
+        assert_eq!(source.describe(), "s3://wine-datasets/archive/2023.csv.gz");
+        assert!(matches!(config, SourceConfig::S3 { region: None, endpoint: Some(_), .. }));
+
+        let blob: SourceConfig = toml::from_str("kind = \"azure\"\ncontainer = \"datasets\"\nkey = \"2023.csv\"\naccount = \"wines\"").unwrap();
+        let gcs: SourceConfig = toml::from_str("kind = \"gcs\"\nbucket = \"wine-datasets\"\nkey = \"2023.csv\"").unwrap();
+        assert_eq!(blob.object_source(None).unwrap().describe(), "az://datasets/2023.csv");
+        assert_eq!(gcs.object_source(Some(1000)).unwrap().provider, ObjectStoreProvider::Gcs);
+        assert!(SourceConfig::default().object_source(None).is_none());
+    }
+
+    #[test]
+    fn test_csv_source_config_reads_options() {
+        let tsv: SourceConfig = toml::from_str("kind = \"csv\"\npath = \"lab.tsv\"\n[options]\ndelimiter = \"\\t\"\nskip_rows = 2").unwrap();
+        let SourceConfig::Csv { options, .. } = tsv else { panic!("expected a CSV source") };
+
+        assert_eq!(options, IngestOptions { delimiter: '\t', skip_rows: 2, ..IngestOptions::default() });
+        assert!(toml::from_str::<SourceConfig>("kind = \"csv\"\npath = \"lab.csv\"\n[options]\nseparator = \";\"").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! This module keeps transformation within a memory budget by spilling partitions to disk.
+//!
+//! Transforming a DataFrame holds the input and the output of every stage in memory at the same time.
+//! When the estimated size of the input exceeds `[spill] memory_budget_mb`, the input is instead split
+//! into partitions that are written to temporary Parquet files and dropped from memory, then read back
+//! and transformed one at a time. Cleaning uses medians computed over the whole input, so the result
+//! equals that of transforming it at once; peak memory is the transformed data plus one partition.
+
+use crate::config::SpillConfig;
+use crate::transformation::{self, CleaningMedians};
+use anyhow::{Context, Result};
+use polars::prelude::*;
+use std::fs::File;
+use std::path::PathBuf;
+use ulid::Ulid;
+
+/// Share of the memory budget one partition may take, leaving room for its intermediates and the output.
+const PARTITION_SHARE: usize = 4;
+
+/// Transforms a DataFrame like [`transformation::transform_stages`], spilling partitions to disk if it exceeds the memory budget.
+///
+/// Spilled transformations do not hand intermediates to `on_stage`, since they never exist as a whole.
+///
+/// # Arguments
+///
+/// * `df` - A DataFrame containing the data to be transformed.
+/// * `config` - The memory budget and spill directory.
+/// * `passthrough` - Columns kept exactly as they are in the input.
+/// * `on_stage` - Called with the stage name and its output when the input fits the budget.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The transformed DataFrame, or an error if the transformation or a spill file fails.
+///
+/// # Example
+///
+/// ```
+/// let transformed_df = transform_within_budget(df, &config.spill, &config.transform.passthrough, |_, _| Ok(())).expect("Data transformation failed");
+/// ```
+pub fn transform_within_budget(
+    df: DataFrame,
+    config: &SpillConfig,
+    passthrough: &[String],
+    on_stage: impl FnMut(&'static str, &DataFrame) -> Result<()>,
+) -> Result<DataFrame> {
+    let size = df.estimated_size();
+    let budget = match config.memory_budget_mb {
+        Some(mb) if size > (mb as usize) << 20 => (mb as usize) << 20,
+        _ => return transformation::transform_stages(df, passthrough, on_stage),
+    };
+    spill_and_transform(df, budget, config.dir.as_deref(), passthrough)
+}
+
+/// Helper function to transform a DataFrame partition by partition, spilling the partitions to a temporary directory.
+fn spill_and_transform(df: DataFrame, budget: usize, dir: Option<&str>, passthrough: &[String]) -> Result<DataFrame> {
+    let size = df.estimated_size();
+    let medians = CleaningMedians::compute(&df)?;
+    let rows = partition_rows(df.height(), size, budget);
+    let dir = SpillDir::create(dir)?;
+    let mut partitions = vec![];
+    for (i, offset) in (0..df.height()).step_by(rows).enumerate() {
+        let mut part = df.slice(offset as i64, rows);
+        let path = dir.path.join(format!("input-{:05}.parquet", i));
+        let file = File::create(&path).context(format!("Failed to create spill file {}", path.display()))?;
+        ParquetWriter::new(file).finish(&mut part).context(format!("Failed to spill to {}", path.display()))?;
+        partitions.push(path);
+    }
+    drop(df);
+    println!(
+        "Input of {} MB exceeds the memory budget of {} MB; transforming {} partitions of up to {} rows spilled to {}",
+        size >> 20,
+        budget >> 20,
+        partitions.len(),
+        rows,
+        dir.path.display()
+    );
+
+    let mut transformed: Option<DataFrame> = None;
+    for path in &partitions {
+        let file = File::open(path).context(format!("Failed to open spill file {}", path.display()))?;
+        let part = ParquetReader::new(file).finish().context(format!("Failed to read spill file {}", path.display()))?;
+        std::fs::remove_file(path)?;
+        let part = transformation::transform_partition(part, &medians, passthrough)?;
+        match &mut transformed {
+            Some(df) => {
+                df.vstack_mut(&part).context("Failed to append transformed partition")?;
+            }
+            None => transformed = Some(part),
+        }
+    }
+    let mut transformed = transformed.context("No partitions were transformed")?;
+    transformed.align_chunks();
+    Ok(transformed)
+}
+
+/// Helper function to choose the rows per partition, so each partition takes at most its share of the budget.
+fn partition_rows(height: usize, size: usize, budget: usize) -> usize {
+    let bytes_per_row = (size / height.max(1)).max(1);
+    (budget / PARTITION_SHARE / bytes_per_row).clamp(1, height.max(1))
+}
+
+/// A temporary directory of spill files, removed when dropped.
+struct SpillDir {
+    path: PathBuf,
+}
+
+impl SpillDir {
+    fn create(parent: Option<&str>) -> Result<Self> {
+        let parent = parent.map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+        let path = parent.join(format!("wine_quality_spill_{}", Ulid::new()));
+        std::fs::create_dir_all(&path).context(format!("Failed to create spill directory {}", path.display()))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_spilled_transform_matches_in_memory() {
+        let df = df!(
+            "fixed acidity" => (0..1000).map(|i| if i % 7 == 0 { None } else { Some(7.0 + (i % 13) as f64 * 0.1) }).collect::<Vec<_>>(),
+            "volatile acidity" => (0..1000).map(|i| if i % 11 == 0 { None } else { Some(0.5 + (i % 5) as f64 * 0.05) }).collect::<Vec<_>>()
+        )
+        .unwrap();
+        assert_eq!(partition_rows(1000, 16_000, 16_000), 250);
+
+        let expected = transformation::transform_data(df.clone(), &[]).unwrap();
+        // A quarter of the input per partition
+        let spilled = spill_and_transform(df.clone(), df.estimated_size(), None, &[]).unwrap();
+
+        assert!(spilled.equals_missing(&expected));
+    }
+}
@@ -0,0 +1,427 @@
+//! This module implements the quarantine workflow, where loads are reviewed before they reach production.
+//!
+//! With `[staging] enabled`, a run stores its rows in the staging table instead of the production table
+//! of `storage.table`, `wine_quality` by default, and writes a verification report: the staged row
+//! count, the expectation results of the run, and per column the null count, range, and mean next to
+//! the mean of the production table. The run is recorded as `STAGED` in the `staged_runs` table.
+//! `pipeline promote --run <id>`, or `POST /staging/<id>/promote` on the control API, then moves its
+//! rows into the production table and marks it `PROMOTED` in one
+//! transaction; `--discard` deletes them instead.
+
+use crate::{aggregates, audit};
+use crate::config::{AggregatesConfig, PipelineConfig, StagingConfig};
+use crate::locale::ReportFormat;
+use crate::run::RunContext;
+use crate::schema::TableSchema;
+use crate::storage;
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use sqlx::postgres::PgPool;
+use sqlx::{Postgres, Transaction};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// The production table staged rows are promoted into, unless `storage.table` names another.
+pub const PRODUCTION_TABLE: &str = "wine_quality";
+
+/// Summary of one staged column in the verification report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSummary {
+    pub column: String,
+    pub nulls: i64,
+    /// Range and mean, for numeric columns.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// Mean of the column in the production table, if it has the column and rows.
+    pub production_mean: Option<f64>,
+}
+
+/// Returns the table a run stores its rows in, creating the staging table when staging is enabled.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `config` - The pipeline configuration.
+///
+/// # Returns
+///
+/// * `Result<&str>` - The staging table if staging is enabled, else `storage.table`.
+///
+/// # Example
+///
+/// ```
+/// let table = target_table(&pool, &config).await?;
+/// ```
+pub async fn target_table<'a>(pool: &PgPool, config: &'a PipelineConfig) -> Result<&'a str> {
+    if !config.staging.enabled {
+        return Ok(&config.storage.table);
+    }
+    let table = config.staging.table.as_str();
+    let run_column = run_column(&config.schema)?;
+    sqlx::query(&config.schema.create_table_sql(table))
+        .execute(pool)
+        .await
+        .context(format!("Failed to create staging table {}", table))?;
+    sqlx::query(&format!("CREATE INDEX IF NOT EXISTS {0}_run_id_idx ON {0} ({1})", table, run_column))
+        .execute(pool)
+        .await?;
+    Ok(table)
+}
+
+/// Records a staged run and writes its verification report.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the staged run.
+/// * `config` - The pipeline configuration.
+///
+/// # Returns
+///
+/// * `Result<PathBuf>` - The path of the report, or an error if it cannot be computed or written.
+pub async fn record(pool: &PgPool, run: &RunContext, config: &PipelineConfig) -> Result<PathBuf> {
+    let staging = &config.staging;
+    let run_column = run_column(&config.schema)?;
+    let rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {} WHERE {} = $1", staging.table, run_column))
+        .bind(&run.id)
+        .fetch_one(pool)
+        .await
+        .context(format!("Failed to count the staged rows of run {}", run.id))?;
+    let production = config.storage.table.as_str();
+    let production_rows: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM {}", production)).fetch_one(pool).await?;
+    let expectations: Vec<(String, bool, String, String)> =
+        sqlx::query_as("SELECT expectation, passed, observed, severity FROM expectation_results WHERE run_id = $1 ORDER BY id")
+            .bind(&run.id)
+            .fetch_all(pool)
+            .await
+            .context("Failed to read the expectation results")?;
+    let columns = summarize_columns(pool, &config.schema, staging, production, run_column, &run.id).await?;
+
+    let report = render_report(&run.id, config, rows, production_rows, &expectations, &columns, &ReportFormat::from_config(&config.reports));
+    std::fs::create_dir_all(&staging.report_dir).context(format!("Failed to create report directory {}", staging.report_dir))?;
+    let path = Path::new(&staging.report_dir).join(format!("{}.md", run.id));
+    std::fs::write(&path, &report).context(format!("Failed to write {}", path.display()))?;
+
+    sqlx::query(
+        "INSERT INTO staged_runs (run_id, staging_table, row_count, report, status, staged_at) VALUES ($1, $2, $3, $4, 'STAGED', now()) \
+         ON CONFLICT (run_id) DO UPDATE SET row_count = EXCLUDED.row_count, report = EXCLUDED.report, staged_at = EXCLUDED.staged_at",
+    )
+    .bind(&run.id)
+    .bind(&staging.table)
+    .bind(rows)
+    .bind(&report)
+    .execute(pool)
+    .await
+    .context(format!("Failed to record staged run {}", run.id))?;
+    Ok(path)
+}
+
+/// Runs `pipeline promote`, promoting or discarding the rows of a staged run.
+///
+/// # Arguments
+///
+/// * `config` - The pipeline configuration.
+/// * `run_id` - The ID of the staged run.
+/// * `discard` - Whether the rows are deleted instead of promoted.
+///
+/// # Returns
+///
+/// * `Result<u64>` - The number of promoted or discarded rows.
+pub async fn run_promote(config: &PipelineConfig, run_id: &str, discard: bool) -> Result<u64> {
+    let pool = storage::create_connection_pool().await?;
+    if discard {
+        self::discard(&pool, &config.schema, &config.staging, run_id).await
+    } else {
+        promote(&pool, &config.schema, &config.staging, &config.storage.table, &config.aggregates, run_id).await
+    }
+}
+
+/// Moves the rows of a staged run into the production table in one transaction, which also refreshes the aggregates.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The declared schema, naming the run ID column.
+/// * `config` - The staging settings.
+/// * `production` - The production table, e.g. `wine_quality`.
+/// * `aggregates` - The aggregate settings.
+/// * `run_id` - The ID of the staged run.
+///
+/// # Returns
+///
+/// * `Result<u64>` - The number of promoted rows, or an error if the run is not staged or the production
+///   table lacks a staged column.
+///
+/// # Example
+///
+/// ```
+/// let rows = promote(&pool, &config.schema, &config.staging, &config.storage.table, &config.aggregates, "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B").await?;
+/// ```
+pub async fn promote(pool: &PgPool, schema: &TableSchema, config: &StagingConfig, production: &str, aggregates: &AggregatesConfig, run_id: &str) -> Result<u64> {
+    let run_column = run_column(schema)?;
+    let mut tx = pool.begin().await?;
+    lock_staged_run(&mut tx, run_id).await?;
+
+    let staged = table_columns(&mut tx, &config.table).await?;
+    let production_columns: HashSet<String> = table_columns(&mut tx, production).await?.into_iter().collect();
+    let missing: Vec<&str> = staged.iter().filter(|c| !production_columns.contains(*c)).map(String::as_str).collect();
+    if !missing.is_empty() {
+        bail!("Table {} lacks the staged columns {}; add them before promoting run {}", production, missing.join(", "), run_id);
+    }
+
+    let columns = staged.join(", ");
+    let promoted = sqlx::query(&format!(
+        "INSERT INTO {} ({}) SELECT {} FROM {} WHERE {} = $1 ORDER BY id",
+        production, columns, columns, config.table, run_column
+    ))
+    .bind(run_id)
+    .execute(&mut *tx)
+    .await
+    .context(format!("Failed to promote run {}", run_id))?
+    .rows_affected();
+    finish(&mut tx, &config.table, run_column, run_id, "PROMOTED").await?;
+    aggregates::refresh(&mut tx, schema, aggregates).await?;
+    tx.commit().await?;
+
+    println!("Promoted {} rows of run {} from {} to {}", promoted, run_id, config.table, production);
+    Ok(promoted)
+}
+
+/// Deletes the rows of a staged run without promoting them.
+///
+/// # Returns
+///
+/// * `Result<u64>` - The number of deleted rows, or an error if the run is not staged.
+pub async fn discard(pool: &PgPool, schema: &TableSchema, config: &StagingConfig, run_id: &str) -> Result<u64> {
+    let run_column = run_column(schema)?;
+    let mut tx = pool.begin().await?;
+    lock_staged_run(&mut tx, run_id).await?;
+    let discarded = finish(&mut tx, &config.table, run_column, run_id, "DISCARDED").await?;
+    tx.commit().await?;
+
+    println!("Discarded {} staged rows of run {}", discarded, run_id);
+    Ok(discarded)
+}
+
+/// Helper function to find the run ID column, which identifies the rows of a staged run.
+pub(crate) fn run_column(schema: &TableSchema) -> Result<&str> {
+    schema
+        .columns
+        .iter()
+        .find(|c| c.name == audit::RUN_ID_COLUMN)
+        .map(|c| c.column.as_str())
+        .context("Staging requires the run_id column in the schema")
+}
+
+/// Helper function to lock the record of a staged run, failing unless it is still awaiting a decision.
+async fn lock_staged_run(tx: &mut Transaction<'_, Postgres>, run_id: &str) -> Result<()> {
+    let status: Option<String> = sqlx::query_scalar("SELECT status FROM staged_runs WHERE run_id = $1 FOR UPDATE")
+        .bind(run_id)
+        .fetch_optional(&mut **tx)
+        .await?;
+    match status.as_deref() {
+        Some("STAGED") => Ok(()),
+        Some(status) => bail!("Run {} is already {}", run_id, status),
+        None => bail!("Run {} was not staged", run_id),
+    }
+}
+
+/// Helper function to delete the staged rows of a run and record the decision.
+async fn finish(tx: &mut Transaction<'_, Postgres>, table: &str, run_column: &str, run_id: &str, status: &str) -> Result<u64> {
+    let deleted = sqlx::query(&format!("DELETE FROM {} WHERE {} = $1", table, run_column))
+        .bind(run_id)
+        .execute(&mut **tx)
+        .await
+        .context(format!("Failed to delete the staged rows of run {}", run_id))?
+        .rows_affected();
+    sqlx::query("UPDATE staged_runs SET status = $2, decided_at = now() WHERE run_id = $1")
+        .bind(run_id)
+        .bind(status)
+        .execute(&mut **tx)
+        .await?;
+    Ok(deleted)
+}
+
+/// Helper function to list the columns of a table other than `id`, in order.
+async fn table_columns(tx: &mut Transaction<'_, Postgres>, table: &str) -> Result<Vec<String>> {
+    sqlx::query_scalar(
+        "SELECT column_name::TEXT FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1 AND column_name <> 'id' ORDER BY ordinal_position",
+    )
+    .bind(table)
+    .fetch_all(&mut **tx)
+    .await
+    .context(format!("Failed to look up the columns of {}", table))
+}
+
+/// Helper function to summarize the staged columns of a run.
+async fn summarize_columns(pool: &PgPool, schema: &TableSchema, config: &StagingConfig, production_table: &str, run_column: &str, run_id: &str) -> Result<Vec<ColumnSummary>> {
+    let production: HashSet<String> = sqlx::query_scalar(
+        "SELECT column_name::TEXT FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+    )
+    .bind(production_table)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+
+    let mut summaries = vec![];
+    for spec in schema.columns.iter().filter(|c| c.name != audit::RUN_ID_COLUMN && c.name != audit::LABELS_COLUMN) {
+        let column = &spec.column;
+        let sql = if is_numeric(&spec.pg_type) {
+            format!(
+                "SELECT count(*) - count({0}), min({0})::DOUBLE PRECISION, max({0})::DOUBLE PRECISION, avg({0})::DOUBLE PRECISION FROM {1} WHERE {2} = $1",
+                column, config.table, run_column
+            )
+        } else {
+            format!(
+                "SELECT count(*) - count({}), NULL::DOUBLE PRECISION, NULL::DOUBLE PRECISION, NULL::DOUBLE PRECISION FROM {} WHERE {} = $1",
+                column, config.table, run_column
+            )
+        };
+        let (nulls, min, max, mean): (i64, Option<f64>, Option<f64>, Option<f64>) = sqlx::query_as(&sql)
+            .bind(run_id)
+            .fetch_one(pool)
+            .await
+            .context(format!("Failed to summarize staged column {}", column))?;
+
+        let production_mean = if is_numeric(&spec.pg_type) && production.contains(column) {
+            sqlx::query_scalar(&format!("SELECT avg({})::DOUBLE PRECISION FROM {}", column, production_table))
+                .fetch_one(pool)
+                .await?
+        } else {
+            None
+        };
+        summaries.push(ColumnSummary { column: column.clone(), nulls, min, max, mean, production_mean });
+    }
+    Ok(summaries)
+}
+
+/// Helper function to tell whether a PostgreSQL type is numeric, so its range and mean are reported.
+pub(crate) fn is_numeric(pg_type: &str) -> bool {
+    let pg_type = pg_type.to_ascii_uppercase();
+    ["DECIMAL", "NUMERIC", "INT", "SMALLINT", "BIGINT", "REAL", "DOUBLE", "FLOAT", "SERIAL"]
+        .iter()
+        .any(|prefix| pg_type.starts_with(prefix))
+}
+
+/// Renders the Markdown verification report of a staged run, with numbers and units written as `format` says.
+fn render_report(
+    run_id: &str,
+    config: &PipelineConfig,
+    rows: i64,
+    production_rows: i64,
+    expectations: &[(String, bool, String, String)],
+    columns: &[ColumnSummary],
+    format: &ReportFormat,
+) -> String {
+    let number = |value: Option<f64>| format.optional(value, 4);
+    let mut report = format!("# Staged run {}\n\n", run_id);
+    let _ = writeln!(
+        report,
+        "{} rows staged in `{}` at {}; `{}` holds {} rows.\n",
+        format.count(rows),
+        config.staging.table,
+        Utc::now().to_rfc3339(),
+        config.storage.table,
+        format.count(production_rows)
+    );
+
+    report.push_str("## Expectations\n\n");
+    if expectations.is_empty() {
+        report.push_str("No expectations are configured.\n\n");
+    } else {
+        let failed = expectations.iter().filter(|(_, passed, ..)| !passed).count();
+        let warnings = expectations.iter().filter(|(_, passed, _, severity)| !passed && severity == "warning").count();
+        let _ = writeln!(report, "{} of {} expectations failed, {} of them warnings.\n", format.count(failed as i64), format.count(expectations.len() as i64), format.count(warnings as i64));
+        report.push_str("| Expectation | Result | Observed |\n|---|---|---|\n");
+        for (expectation, passed, observed, severity) in expectations {
+            let result = match (*passed, severity.as_str()) {
+                (true, _) => "PASS",
+                (false, "warning") => "WARN",
+                (false, _) => "FAIL",
+            };
+            let _ = writeln!(report, "| {} | {} | {} |", expectation, result, observed);
+        }
+        report.push('\n');
+    }
+
+    report.push_str("## Columns\n\n| Column | Nulls | Min | Max | Mean | Production mean |\n|---|---|---|---|---|---|\n");
+    for c in columns {
+        let _ = writeln!(
+            report,
+            "| {} | {} | {} | {} | {} | {} |",
+            format.column(&c.column),
+            format.count(c.nulls),
+            number(c.min),
+            number(c.max),
+            number(c.mean),
+            number(c.production_mean)
+        );
+    }
+    let _ = writeln!(
+        report,
+        "\nCompare with production using `pipeline compare-tables --run {0}`. Promote with `pipeline promote --run {0}`, or discard with `pipeline promote --run {0} --discard`.",
+        run_id
+    );
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_report() {
+        let columns = vec![ColumnSummary {
+            column: "alcohol".to_string(),
+            nulls: 0,
+            min: Some(8.4),
+            max: Some(14.9),
+            mean: Some(10.42),
+            production_mean: None,
+        }];
+        let expectations = vec![
+            ("row count at least 1".to_string(), true, "1599 rows".to_string(), "error".to_string()),
+            ("values of sulphates at most 1".to_string(), false, "3 values out of range".to_string(), "warning".to_string()),
+        ];
+
+        let report = render_report("01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B", &PipelineConfig::default(), 1599, 0, &expectations, &columns, &ReportFormat::default());
+
+        assert!(report.starts_with("# Staged run 01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B\n\n1599 rows staged in `wine_quality_staging`"));
+        assert!(report.contains("| row count at least 1 | PASS | 1599 rows |"));
+        assert!(report.contains("1 of 2 expectations failed, 1 of them warnings."));
+        assert!(report.contains("| values of sulphates at most 1 | WARN | 3 values out of range |"));
+        assert!(report.contains("| alcohol | 0 | 8.4000 | 14.9000 | 10.4200 | - |"));
+        assert!(is_numeric("decimal(4, 1)") && !is_numeric("TEXT"));
+    }
+
+    #[tokio::test]
+    async fn test_report_compares_with_stored_production_rows() -> Result<()> {
+        use crate::hooks::Hooks;
+        use crate::pipeline;
+        use crate::testing::{Scratch, ROWS};
+
+        dotenv::dotenv().ok();
+        let pool = storage::create_connection_pool().await?;
+        let mut scratch = Scratch::default();
+        let production = scratch.table("temp_staging_production");
+
+        // Production holds the rows of an earlier run when the next one is staged
+        pipeline::run(&Scratch::config(&production, &scratch.csv("temp_staging_loaded", &ROWS[..2])?), &Hooks::from_commands(&[])).await?;
+        let mut config = Scratch::config(&production, &scratch.csv("temp_staging_staged", &ROWS[4..])?);
+        config.staging = StagingConfig {
+            enabled: true,
+            table: scratch.table("temp_staging_runs"),
+            report_dir: scratch.path("temp_staging_reports", "").display().to_string(),
+        };
+        pipeline::run(&config, &Hooks::from_commands(&[])).await?;
+
+        let report: String = sqlx::query_scalar("SELECT report FROM staged_runs WHERE staging_table = $1").bind(&config.staging.table).fetch_one(&pool).await?;
+        assert!(report.contains(&format!("1 rows staged in `{}`", config.staging.table)));
+        assert!(report.contains(&format!("`{}` holds 2 rows.", production)));
+        assert!(report.contains("| alcohol | 0 | 10.0000 | 10.0000 | 10.0000 | 9.6000 |"));
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! This module writes the state of the process to the log on demand, to diagnose a slow run without a debugger.
+//!
+//! Every run is listed on a process-wide board from its start to its end, with the stage it is in, the
+//! rows it has processed, the connection pool it uses and, in chunked mode, the handoffs between its
+//! stages. Sending the process `SIGUSR1`, e.g. `kill -USR1 $(pidof pipeline)`, or calling `GET /status`
+//! on the control API with a key granted `read_status`, writes a dump of the board: per run its stage
+//! and for how long it has been in it, its rows, the chunks waiting in each handoff, and the database
+//! connections in use and idle, and in daemon mode the runs waiting in the queue. A run stuck in `store`
+//! with every connection in use points at the database; one whose chunks pile up before `transform`
+//! points at the transformation.
+
+use crate::daemon::RunCoordinator;
+use crate::run::RunContext;
+use chrono::{DateTime, Utc};
+use polars::prelude::DataFrame;
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use tokio::sync::mpsc::{Sender, WeakSender};
+
+/// A run on the board.
+struct Entry {
+    started_at: DateTime<Utc>,
+    stage: &'static str,
+    stage_since: DateTime<Utc>,
+    rows: usize,
+    pool: Option<PgPool>,
+    /// Handoffs of a chunked run, by the stage receiving from them; weak, so they close when the run drops them.
+    handoffs: Vec<(&'static str, WeakSender<DataFrame>)>,
+}
+
+#[derive(Default)]
+struct Board {
+    runs: BTreeMap<String, Entry>,
+    queue: Option<Arc<RunCoordinator>>,
+}
+
+static BOARD: OnceLock<Mutex<Board>> = OnceLock::new();
+
+/// Helper function to lock the board, creating it on first use.
+fn board() -> MutexGuard<'static, Board> {
+    BOARD.get_or_init(Mutex::default).lock().expect("status board poisoned")
+}
+
+/// The connections of a run's pool.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Connections {
+    pub in_use: usize,
+    pub idle: usize,
+}
+
+/// The state of a run when the board was dumped.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunStatus {
+    pub run_id: String,
+    pub stage: &'static str,
+    /// Seconds since the run entered its stage.
+    pub stage_secs: f64,
+    /// Seconds since the run started.
+    pub elapsed_secs: f64,
+    /// Rows the run has read so far.
+    pub rows_processed: usize,
+    /// Chunks waiting in each handoff of a chunked run, by the stage receiving them.
+    pub handoffs: BTreeMap<&'static str, usize>,
+    /// The connections of the run's pool, once it has one.
+    pub connections: Option<Connections>,
+}
+
+/// The state of the process at one point in time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusDump {
+    pub written_at: String,
+    /// Runs waiting in the daemon's queue, in daemon mode.
+    pub queued_runs: Option<usize>,
+    pub runs: Vec<RunStatus>,
+}
+
+impl StatusDump {
+    /// Describes the state for the log, one line per run.
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!(
+            "Status at {}: {} runs in progress{}",
+            self.written_at,
+            self.runs.len(),
+            self.queued_runs.map(|queued| format!(", {} queued", queued)).unwrap_or_default()
+        )];
+        for run in &self.runs {
+            let mut line = format!(
+                "  run {}: {} for {:.1} s (running {:.1} s), {} rows processed",
+                run.run_id, run.stage, run.stage_secs, run.elapsed_secs, run.rows_processed
+            );
+            if !run.handoffs.is_empty() {
+                let handoffs: Vec<String> = run.handoffs.iter().map(|(stage, chunks)| format!("{} chunks waiting for {}", chunks, stage)).collect();
+                line.push_str(&format!("; {}", handoffs.join(", ")));
+            }
+            if let Some(connections) = run.connections {
+                line.push_str(&format!("; {} database connections in use, {} idle", connections.in_use, connections.idle));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
+
+/// Keeps a run on the board until it is dropped.
+pub struct Tracked {
+    run_id: String,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        board().runs.remove(&self.run_id);
+    }
+}
+
+/// Lists a run on the board, in the `start` stage, until the returned guard is dropped.
+///
+/// # Example
+///
+/// ```
+/// let _tracked = track(&run);
+/// ```
+pub fn track(run: &RunContext) -> Tracked {
+    let entry = Entry { started_at: run.started_at, stage: "start", stage_since: Utc::now(), rows: 0, pool: None, handoffs: vec![] };
+    board().runs.insert(run.id.clone(), entry);
+    Tracked { run_id: run.id.clone() }
+}
+
+/// Records that a run entered a stage, e.g. `ingest`.
+pub fn enter(run_id: &str, stage: &'static str) {
+    if let Some(entry) = board().runs.get_mut(run_id) {
+        (entry.stage, entry.stage_since) = (stage, Utc::now());
+    }
+}
+
+/// Adds rows a run has read to its count.
+pub fn add_rows(run_id: &str, rows: usize) {
+    if let Some(entry) = board().runs.get_mut(run_id) {
+        entry.rows += rows;
+    }
+}
+
+/// Records the connection pool a run uses.
+pub fn attach_pool(run_id: &str, pool: &PgPool) {
+    if let Some(entry) = board().runs.get_mut(run_id) {
+        entry.pool = Some(pool.clone());
+    }
+}
+
+/// Records a handoff of a chunked run, named after the stage receiving from it.
+pub fn watch_handoff(run_id: &str, stage: &'static str, tx: &Sender<DataFrame>) {
+    if let Some(entry) = board().runs.get_mut(run_id) {
+        entry.handoffs.push((stage, tx.downgrade()));
+    }
+}
+
+/// Records the daemon's run queue, whose waiting runs are dumped too.
+pub fn watch_queue(coordinator: Arc<RunCoordinator>) {
+    board().queue = Some(coordinator);
+}
+
+/// Returns the state of the board now.
+pub fn snapshot() -> StatusDump {
+    let now = Utc::now();
+    let board = board();
+    let runs = board
+        .runs
+        .iter()
+        .map(|(run_id, entry)| RunStatus {
+            run_id: run_id.clone(),
+            stage: entry.stage,
+            stage_secs: seconds(entry.stage_since, now),
+            elapsed_secs: seconds(entry.started_at, now),
+            rows_processed: entry.rows,
+            // A handoff that no longer upgrades was closed by its run
+            handoffs: entry.handoffs.iter().filter_map(|(stage, tx)| tx.upgrade().map(|tx| (*stage, tx.max_capacity() - tx.capacity()))).collect(),
+            connections: entry.pool.as_ref().map(|pool| {
+                let idle = pool.num_idle();
+                Connections { in_use: (pool.size() as usize).saturating_sub(idle), idle }
+            }),
+        })
+        .collect();
+    StatusDump { written_at: now.to_rfc3339(), queued_runs: board.queue.as_ref().map(|queue| queue.waiting()), runs }
+}
+
+/// Writes a dump of the board to the log whenever the process receives `SIGUSR1`, on Unix.
+///
+/// # Example
+///
+/// ```
+/// status::listen();
+/// ```
+pub fn listen() {
+    #[cfg(unix)]
+    tokio::spawn(async {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                eprintln!("Failed to listen for SIGUSR1, status dumps are only available through the control API: {}", e);
+                return;
+            }
+        };
+        while signals.recv().await.is_some() {
+            eprintln!("{}", snapshot().describe());
+        }
+    });
+}
+
+/// Helper function to compute the seconds between two times.
+fn seconds(since: DateTime<Utc>, now: DateTime<Utc>) -> f64 {
+    (now - since).num_milliseconds() as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_are_dumped_until_they_end() {
+        let run = RunContext::new();
+        let tracked = track(&run);
+        enter(&run.id, "ingest");
+        add_rows(&run.id, 1599);
+        let status = snapshot().runs.into_iter().find(|status| status.run_id == run.id).unwrap();
+        assert_eq!((status.stage, status.rows_processed, status.connections), ("ingest", 1599, None));
+        drop(tracked);
+        assert!(!snapshot().runs.iter().any(|status| status.run_id == run.id));
+
+        let dump = StatusDump {
+            written_at: "2024-05-01T02:00:00+00:00".to_string(),
+            queued_runs: Some(2),
+            runs: vec![RunStatus {
+                run_id: "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B".to_string(),
+                stage: "stream",
+                stage_secs: 12.34,
+                elapsed_secs: 95.0,
+                rows_processed: 40_000,
+                handoffs: BTreeMap::from([("store", 4), ("transform", 0)]),
+                connections: Some(Connections { in_use: 5, idle: 0 }),
+            }],
+        };
+        assert_eq!(
+            dump.describe(),
+            "Status at 2024-05-01T02:00:00+00:00: 1 runs in progress, 2 queued\n  run 01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B: stream for 12.3 s (running 95.0 s), 40000 rows processed; \
+             4 chunks waiting for store, 0 chunks waiting for transform; 5 database connections in use, 0 idle"
+        );
+    }
+}
@@ -2,27 +2,33 @@
 //!
 //! It provides functions for creating a connection pool, storing data from DataFrames, and retrieving data from the database.
 
-use anyhow::{Context, Result};
+use crate::chaos;
+use crate::config::{PartitioningConfig, ResumableConfig, StorageConfig};
+use crate::encryption::ColumnCipher;
+use crate::kafka::{self, BatchOffsets};
+use crate::overflow::{self, OverflowPolicy};
+use crate::retry::RetryPolicy;
+use crate::schema::TableSchema;
+use crate::tenant;
+use crate::timetravel;
+use crate::typemap::{BindStrategy, PgValue, TypeMapping, TypeRegistry};
+use anyhow::{bail, Context, Result};
 use futures::future::try_join_all;
 use polars::prelude::*;
-use sqlx::postgres::{PgPool, PgPoolOptions};
-use sqlx::Row;
-use bigdecimal::BigDecimal;
-use std::str::FromStr;
-
-fn main() {
-    // Convert f64 to BigDecimal
-    let fixed_acidity_bd = BigDecimal::from_str(&fixed_acidity_f64.to_string())
-        .expect("Failed to convert to BigDecimal");
-
-    // Now you can use fixed_acidity_bd as a BigDecimal
-    println!("Fixed acidity as BigDecimal: {}", fixed_acidity_bd);
-}
-
+use sqlx::postgres::{PgArguments, PgConnection, PgPool, PgPoolOptions, PgRow, Postgres};
+use sqlx::query::Query;
+use sqlx::{Executor, Row, Statement};
+use std::collections::{HashMap, HashSet};
+use std::slice::Chunks;
+use std::sync::Mutex;
 
+/// Maximum number of connections of the pool, and so of inserts running at once.
+pub const POOL_SIZE: usize = 5;
 
 /// Creates a connection pool to the PostgreSQL database.
 ///
+/// When a tenant is selected, the connections use the tenant's schema, which is created if needed.
+///
 /// # Returns
 ///
 /// * `Result<PgPool>` - A result containing the PostgreSQL connection pool if successful, or an error if the connection setup fails.
@@ -34,25 +40,141 @@ fn main() {
 /// ```
 pub async fn create_connection_pool() -> Result<PgPool> {
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    chaos::inject_db_error("connect to the database")?;
+
+    let Some(schema) = tenant::schema() else {
+        return Ok(PgPoolOptions::new().max_connections(POOL_SIZE as u32).connect(&database_url).await?);
+    };
 
+    // Route every unqualified table name to the tenant's schema
     let pool = PgPoolOptions::new()
-        .max_connections(5)
+        .max_connections(POOL_SIZE as u32)
+        .after_connect(move |conn, _meta| {
+            Box::pin(async move {
+                sqlx::query(&format!("SET search_path TO {}", schema)).execute(&mut *conn).await?;
+                Ok(())
+            })
+        })
         .connect(&database_url)
         .await?;
+    sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema))
+        .execute(&pool)
+        .await
+        .context(format!("Failed to create tenant schema {}", schema))?;
 
     Ok(pool)
 }
 
+/// Where a run gets its database connections from.
+///
+/// The binary connects with `DATABASE_URL`; a service embedding the pipeline can hand over a pool it
+/// already owns instead, created on whichever Tokio runtime the service runs.
+#[derive(Debug, Clone, Default)]
+pub enum PoolProvider {
+    /// Connects with `DATABASE_URL` through [`create_connection_pool`] whenever a pool is needed.
+    #[default]
+    Connect,
+    /// Uses the given pool, which every use shares.
+    Injected(PgPool),
+}
+
+impl PoolProvider {
+    /// Returns the pool of an injected provider, or connects a new one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let pool = PoolProvider::Injected(pool).get().await?;
+    /// ```
+    pub async fn get(&self) -> Result<PgPool> {
+        match self {
+            PoolProvider::Connect => create_connection_pool().await,
+            PoolProvider::Injected(pool) => Ok(pool.clone()),
+        }
+    }
+}
+
+/// Where and how [`store_data`] inserts the rows.
+#[derive(Debug, Clone, Copy)]
+pub struct InsertOptions<'a> {
+    /// The table the rows are inserted into.
+    pub table: &'a str,
+    /// Maximum number of rows per insert statement; wide tables get fewer, to stay below the bind parameter limit.
+    pub batch_rows: usize,
+    /// Maximum number of insert statements running at once.
+    pub concurrency: usize,
+    /// The key column whose partitions are written in a transaction each, if partitioned.
+    pub partitioning: Option<&'a PartitioningConfig>,
+    /// Settings for committing in chunks that a later attempt resumes after, if enabled.
+    pub resumable: Option<&'a ResumableConfig>,
+    /// Waits between the retries of a partition and the reconnects of a resumable load.
+    pub retry: &'a RetryPolicy,
+    /// Identifies the load across attempts, e.g. the run ID; resumable writes need one.
+    pub load_id: Option<&'a str>,
+    /// The offsets of the source the rows end at, if they are stored in the transaction of the rows.
+    pub offsets: Option<&'a BatchOffsets>,
+    /// What happens to values out of the range of their `NUMERIC` column, if they are checked before insert.
+    pub on_numeric_overflow: Option<OverflowPolicy>,
+}
+
+impl<'a> InsertOptions<'a> {
+    /// Inserts into the configured table with the configured batch size, concurrency, and partitioning.
+    pub fn from_config(config: &'a StorageConfig) -> Self {
+        Self {
+            table: &config.table,
+            batch_rows: config.batch_rows,
+            concurrency: config.concurrency,
+            partitioning: config.partitioning.column.is_some().then_some(&config.partitioning),
+            resumable: config.resumable.enabled.then_some(&config.resumable),
+            retry: &config.retry,
+            load_id: None,
+            offsets: None,
+            on_numeric_overflow: Some(config.on_numeric_overflow),
+        }
+    }
+}
+
 /// Stores data from a DataFrame into the PostgreSQL database.
 ///
+/// Each column of the schema is cast and bound according to its type mapping, so integer columns read as
+/// floats (or the other way round) are converted instead of failing the load. Missing values are stored
+/// as NULL; nullable columns absent from the DataFrame are left out of the insert. Rows are inserted in
+/// multi-row statements of up to `options.batch_rows` rows, split so no statement exceeds PostgreSQL's
+/// limit of 65,535 bind parameters whatever the width of the table. `options.concurrency` workers each
+/// prepare the statement once on their own connection and stream batches through it, which keeps the
+/// per-batch overhead low on high-latency databases; a failing statement is retried row by row. Columns the cipher
+/// encrypts are rendered as text, encrypted, and stored in their `TEXT` column.
+///
+/// With `options.on_numeric_overflow`, the values of the table's `NUMERIC(p, s)` columns are checked
+/// against their precision and scale first, and values out of range are replaced, rejected, or fail the
+/// load before any row is inserted, as [`overflow::guard`] describes.
+///
+/// With `options.partitioning`, the rows are grouped by the value of the key column and each group is
+/// written in a transaction of its own, optionally deleting the stored rows with its key first. A
+/// partition whose transaction fails is written again, up to `max_retries` times, without touching the
+/// partitions already committed, after a wait set by `options.retry`.
+///
+/// With `options.resumable` and a `load_id`, the rows are committed in transactions of `commit_rows`
+/// rows, one after the other, each recording a progress marker in `load_progress` as part of the
+/// transaction. A chunk whose transaction fails, e.g. because the connection dropped, is written again
+/// on a new connection after a wait set by `options.retry`, and a later call with the same load ID and table skips the chunks already committed.
+///
+/// With `options.offsets`, all rows are inserted in one transaction that also records the offsets in
+/// `kafka_offsets`, so the rows and the position of the source are committed together. Partitioned and
+/// resumable writes, which commit in several transactions, are refused then.
+///
 /// # Arguments
 ///
 /// * `pool` - A reference to the PostgreSQL connection pool.
 /// * `df` - A reference to the DataFrame containing the data to be stored.
+/// * `schema` - The schema of the `wine_quality` table.
+/// * `registry` - The type mappings used to bind each column.
+/// * `cipher` - The cipher for sensitive columns, if column encryption is configured.
+/// * `options` - The target table, batch size, and concurrency of the inserts.
 ///
 /// # Returns
 ///
-/// * `Result<()>` - A result indicating success or failure of the data storage operation.
+/// * `Result<Vec<RejectedRow>>` - The rows that could not be converted or inserted, or an error if the DataFrame does not match the schema.
 ///
 /// # Example
 ///
@@ -63,80 +185,462 @@ pub async fn create_connection_pool() -> Result<PgPool> {
 ///     // other columns...
 /// ]).unwrap();
 ///
-/// store_data(&pool, &df).await.expect("Failed to store data");
+/// let rejects = store_data(&pool, &df, &TableSchema::default(), &TypeRegistry::default(), None, InsertOptions::from_config(&StorageConfig::default())).await.expect("Failed to store data");
 /// ```
-pub async fn store_data(pool: &PgPool, df: &DataFrame) -> Result<()> {
-    let fixed_acidity_series = df.column("fixed acidity")?.f64()?;
-    let volatile_acidity_series = df.column("volatile acidity")?.f64()?;
-    let citric_acid_series = df.column("citric acid")?.f64()?;
-    let residual_sugar_series = df.column("residual sugar")?.f64()?;
-    let chlorides_series = df.column("chlorides")?.f64()?;
-    let free_sulfur_dioxide_series = df.column("free sulfur dioxide")?.i32()?;
-    let total_sulfur_dioxide_series = df.column("total sulfur dioxide")?.i32()?;
-    let density_series = df.column("density")?.f64()?;
-    let ph_series = df.column("pH")?.f64()?;
-    let sulphates_series = df.column("sulphates")?.f64()?;
-    let alcohol_series = df.column("alcohol")?.f64()?;
-    let quality_series = df.column("quality")?.i32()?;
-
-    let mut tasks = vec![];
-
-    for i in 0..df.height() {
-        let fixed_acidity = fixed_acidity_series.get(i).context("Failed to get fixed acidity")?;
-        let volatile_acidity = volatile_acidity_series.get(i).context("Failed to get volatile acidity")?;
-        let citric_acid = citric_acid_series.get(i).context("Failed to get citric acid")?;
-        let residual_sugar = residual_sugar_series.get(i).context("Failed to get residual sugar")?;
-        let chlorides = chlorides_series.get(i).context("Failed to get chlorides")?;
-        let free_sulfur_dioxide = free_sulfur_dioxide_series.get(i).context("Failed to get free sulfur dioxide")? as f64;
-        let total_sulfur_dioxide = total_sulfur_dioxide_series.get(i).context("Failed to get total sulfur dioxide")? as f64;
-        let density = density_series.get(i).context("Failed to get density")?;
-        let ph = ph_series.get(i).context("Failed to get pH")?;
-        let sulphates = sulphates_series.get(i).context("Failed to get sulphates")?;
-        let alcohol = alcohol_series.get(i).context("Failed to get alcohol")?;
-        let quality = quality_series.get(i).context("Failed to get quality")?;
-
-        let pool = pool.clone();
-        let task = tokio::spawn(async move {
-            let result = sqlx::query!(
-                r#"
-                INSERT INTO wine_quality (fixed_acidity, volatile_acidity, citric_acid, residual_sugar, chlorides, free_sulfur_dioxide, total_sulfur_dioxide, density, pH, sulphates, alcohol, quality)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-                "#,
-                fixed_acidity,
-                volatile_acidity,
-                citric_acid,
-                residual_sugar,
-                chlorides,
-                free_sulfur_dioxide,
-                total_sulfur_dioxide,
-                density,
-                ph,
-                sulphates,
-                alcohol,
-                quality
-            )
-            .execute(&pool)
-            .await;
-
-            if let Err(e) = &result {
-                eprintln!("Failed to insert row {}: {:?}", i, e);
+pub async fn store_data(
+    pool: &PgPool,
+    df: &DataFrame,
+    schema: &TableSchema,
+    registry: &TypeRegistry,
+    cipher: Option<&ColumnCipher>,
+    options: InsertOptions<'_>,
+) -> Result<Vec<RejectedRow>> {
+    let guarded;
+    let (df, mut rejects) = match options.on_numeric_overflow {
+        Some(policy) => {
+            let bounds = overflow::column_bounds(pool, options.table).await?;
+            let (fitted, rejects) = overflow::guard(df, schema, &bounds, policy)?;
+            guarded = fitted;
+            (&guarded, rejects)
+        }
+        None => (df, vec![]),
+    };
+    let quarantined: HashSet<usize> = rejects.iter().map(|r| r.row).collect();
+
+    let mut columns = vec![];
+    for spec in &schema.columns {
+        let series = match df.column(&spec.name) {
+            Ok(series) => series,
+            Err(_) if spec.nullable => continue,
+            Err(e) => bail!("Error fetching column {}: {}", spec.name, e),
+        };
+        let encrypted = cipher.is_some_and(|c| c.encrypts(&spec.name));
+        let mapping = if encrypted {
+            if !spec.pg_type.eq_ignore_ascii_case("TEXT") {
+                bail!("Encrypted column {} must be declared with pg_type = \"TEXT\", not {}", spec.name, spec.pg_type);
+            }
+            TypeMapping { pg_type: "TEXT".to_string(), bind: BindStrategy::Text }
+        } else {
+            registry.mapping(&spec.name, series.dtype())?
+        };
+        columns.push((spec, series, mapping, encrypted));
+    }
+
+    let statements = InsertStatements {
+        table: options.table,
+        targets: columns.iter().map(|(spec, _, _, _)| spec.column.as_str()).collect(),
+        casts: columns.iter().map(|(_, _, mapping, _)| mapping.pg_type.as_str()).collect(),
+        batch_rows: rows_per_statement(columns.len(), options.batch_rows)?,
+    };
+
+    let mut converted = vec![];
+    for i in (0..df.height()).filter(|i| !quarantined.contains(i)) {
+        let values = columns
+            .iter()
+            .map(|(spec, series, mapping, encrypted)| {
+                let value = series.get(i).context(format!("Failed to get {}", spec.column))?;
+                let value = mapping.convert(value).context(format!("Failed to convert {}", spec.column))?;
+                match (value, cipher) {
+                    (PgValue::Text(plaintext), Some(cipher)) if *encrypted => Ok(PgValue::Text(cipher.encrypt(&spec.name, &plaintext)?)),
+                    (value, _) => Ok(value),
+                }
+            })
+            .collect::<Result<Vec<_>>>();
+        match values {
+            Ok(values) => converted.push((i, values)),
+            Err(e) => {
+                eprintln!("Failed to convert row {}: {:#}", i, e);
+                rejects.push(RejectedRow { row: i, error: format!("{:#}", e) });
+            }
+        }
+    }
+
+    if let Some(offsets) = options.offsets {
+        if options.partitioning.is_some() || options.resumable.is_some() {
+            bail!("Rows of Kafka topic {} are stored in one transaction with their offsets, which partitioned and resumable writes cannot do", offsets.topic);
+        }
+        rejects.extend(insert_with_offsets(pool, &statements, offsets, &converted).await?);
+        rejects.sort_by_key(|r| r.row);
+        return Ok(rejects);
+    }
+
+    if let Some(partitioning) = options.partitioning {
+        let column = partitioning.column.as_deref().context("Partitioned writes need storage.partitioning.column")?;
+        let key = columns
+            .iter()
+            .position(|(spec, ..)| spec.name == column)
+            .context(format!("Partition column {} is not stored", column))?;
+        let partitions = partition_rows(converted, key);
+        let key = PartitionKey { column: columns[key].0.column.as_str(), cast: columns[key].2.pg_type.as_str(), index: key };
+        let queue = Mutex::new(partitions.into_iter());
+        let workers = (0..options.concurrency.max(1)).map(|_| partition_worker(pool, &statements, partitioning, options.retry, &key, &queue));
+        rejects.extend(try_join_all(workers).await?.into_iter().flatten());
+        rejects.sort_by_key(|r| r.row);
+        return Ok(rejects);
+    }
+
+    if let (Some(resumable), Some(load_id)) = (options.resumable, options.load_id) {
+        rejects.extend(insert_resumable(pool, &statements, resumable, options.retry, load_id, &converted).await?);
+        rejects.sort_by_key(|r| r.row);
+        return Ok(rejects);
+    }
+
+    // Each worker holds one connection and takes the next batch when it is done with the previous one
+    let batches = Mutex::new(converted.chunks(statements.batch_rows));
+    let workers = (0..options.concurrency.max(1).min(converted.len())).map(|_| insert_worker(pool, &statements, &batches));
+    rejects.extend(try_join_all(workers).await?.into_iter().flatten());
+    rejects.sort_by_key(|r| r.row);
+    Ok(rejects)
+}
+
+/// Converted rows, each with its index in the stored DataFrame.
+type ConvertedRow = (usize, Vec<PgValue>);
+
+/// The insert statements of one load.
+struct InsertStatements<'a> {
+    table: &'a str,
+    targets: Vec<&'a str>,
+    casts: Vec<&'a str>,
+    /// Rows per full batch.
+    batch_rows: usize,
+}
+
+impl InsertStatements<'_> {
+    fn sql(&self, rows: usize) -> String {
+        insert_sql(self.table, &self.targets, &self.casts, rows)
+    }
+}
+
+/// Helper function to insert batches on one connection until none are left.
+///
+/// The full-batch statement is prepared once on the connection and every batch is executed through
+/// it, so only the parameters travel per batch. A failing batch is retried row by row through a
+/// prepared single-row statement, to find the failing rows.
+async fn insert_worker(pool: &PgPool, statements: &InsertStatements<'_>, batches: &Mutex<Chunks<'_, ConvertedRow>>) -> Result<Vec<RejectedRow>> {
+    chaos::inject_db_error("acquire a connection for inserts")?;
+    let mut conn = pool.acquire().await.context("Failed to acquire a database connection")?;
+    let (batch_sql, row_sql) = (statements.sql(statements.batch_rows), statements.sql(1));
+    let batch_statement = (&mut *conn).prepare(&batch_sql).await.context("Failed to prepare the insert statement")?;
+    let mut row_statement = None;
+    let mut rejects = vec![];
+
+    loop {
+        let Some(batch) = batches.lock().expect("Batch queue poisoned").next() else { break };
+        let result = if batch.len() == statements.batch_rows {
+            bind_rows(batch_statement.query(), batch).execute(&mut *conn).await
+        } else {
+            // Only the last batch is shorter, so its statement is not worth preparing
+            let sql = statements.sql(batch.len());
+            bind_rows(sqlx::query(&sql), batch).execute(&mut *conn).await
+        };
+        if result.is_ok() {
+            continue;
+        }
+
+        // The statement is atomic, so none of the batch was stored; insert row by row to find the failing rows
+        if row_statement.is_none() {
+            row_statement = Some((&mut *conn).prepare(&row_sql).await.context("Failed to prepare the insert statement")?);
+        }
+        let statement = row_statement.as_ref().expect("Prepared above");
+        for row in batch {
+            if let Err(e) = bind_rows(statement.query(), std::slice::from_ref(row)).execute(&mut *conn).await {
+                eprintln!("Failed to insert row {}: {:?}", row.0, e);
+                rejects.push(RejectedRow { row: row.0, error: e.to_string() });
             }
+        }
+    }
+
+    Ok(rejects)
+}
 
-            result.context("Failed to insert data into the database")
+/// The key column of partitioned writes.
+struct PartitionKey<'a> {
+    column: &'a str,
+    cast: &'a str,
+    /// Position of the key among the values of a converted row.
+    index: usize,
+}
+
+/// Helper function to group converted rows by their key value, in order of first appearance.
+fn partition_rows(rows: Vec<ConvertedRow>, key: usize) -> Vec<Vec<ConvertedRow>> {
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut partitions: Vec<Vec<ConvertedRow>> = vec![];
+    for row in rows {
+        let position = *positions.entry(format!("{:?}", row.1[key])).or_insert_with(|| {
+            partitions.push(vec![]);
+            partitions.len() - 1
         });
+        partitions[position].push(row);
+    }
+    partitions
+}
 
-        tasks.push(task);
+/// Helper function to write partitions, each in its own transaction, until none are left.
+async fn partition_worker(
+    pool: &PgPool,
+    statements: &InsertStatements<'_>,
+    partitioning: &PartitioningConfig,
+    retry: &RetryPolicy,
+    key: &PartitionKey<'_>,
+    partitions: &Mutex<std::vec::IntoIter<Vec<ConvertedRow>>>,
+) -> Result<Vec<RejectedRow>> {
+    let mut rejects = vec![];
+    loop {
+        let Some(rows) = partitions.lock().expect("Partition queue poisoned").next() else { break };
+        let value = &rows[0].1[key.index];
+        let mut retries = 0;
+        loop {
+            match insert_partition(pool, statements, partitioning, key, &rows).await {
+                Ok(partition_rejects) => {
+                    println!("Stored partition {} = {:?}: {} rows, {} rejected", key.column, value, rows.len() - partition_rejects.len(), partition_rejects.len());
+                    rejects.extend(partition_rejects);
+                    break;
+                }
+                Err(e) if retries < partitioning.max_retries => {
+                    retries += 1;
+                    eprintln!("Partition {} = {:?} failed, retrying ({}/{}): {:#}", key.column, value, retries, partitioning.max_retries, e);
+                    tokio::time::sleep(retry.delay(retries)).await;
+                }
+                Err(e) => return Err(e).context(format!("Partition {} = {:?} failed after {} retries", key.column, value, retries)),
+            }
+        }
     }
+    Ok(rejects)
+}
 
-    try_join_all(tasks).await?;
-    Ok(())
+/// Helper function to write one partition in a transaction, replacing its stored rows if configured.
+async fn insert_partition(pool: &PgPool, statements: &InsertStatements<'_>, partitioning: &PartitioningConfig, key: &PartitionKey<'_>, rows: &[ConvertedRow]) -> Result<Vec<RejectedRow>> {
+    chaos::inject_db_error("acquire a connection for inserts")?;
+    let mut tx = pool.begin().await.context("Failed to begin the partition transaction")?;
+    if partitioning.overwrite {
+        // The replaced rows are kept in the history, so the table can still be read as it was before
+        let sql = timetravel::supersede_sql(statements.table, &format!("{} IS NOT DISTINCT FROM $1::{}", key.column, key.cast));
+        rows[0].1[key.index].clone().bind(sqlx::query(&sql)).execute(&mut *tx).await.context("Failed to delete the stored rows of the partition")?;
+    }
+    let rejects = insert_in_transaction(&mut tx, statements, rows).await?;
+    tx.commit().await.context("Failed to commit the partition")?;
+    Ok(rejects)
 }
 
-/// Fetches and prints the first 5 rows from the wine_quality table in the PostgreSQL database.
+/// Helper function to insert rows and record the offsets of the source they end at, in one transaction.
+async fn insert_with_offsets(pool: &PgPool, statements: &InsertStatements<'_>, offsets: &BatchOffsets, rows: &[ConvertedRow]) -> Result<Vec<RejectedRow>> {
+    chaos::inject_db_error("acquire a connection for inserts")?;
+    let mut tx = pool.begin().await.context("Failed to begin the insert transaction")?;
+    let rejects = insert_in_transaction(&mut tx, statements, rows).await?;
+    kafka::store_offsets(&mut tx, offsets).await?;
+    tx.commit().await.context("Failed to commit the rows with their offsets")?;
+    Ok(rejects)
+}
+
+/// Helper function to insert rows inside an open transaction.
+///
+/// A failing batch is rolled back to a savepoint and retried row by row, each row behind a savepoint
+/// of its own, so rejected rows do not abort the transaction.
+async fn insert_in_transaction(conn: &mut PgConnection, statements: &InsertStatements<'_>, rows: &[ConvertedRow]) -> Result<Vec<RejectedRow>> {
+    let row_sql = statements.sql(1);
+    let mut rejects = vec![];
+    for batch in rows.chunks(statements.batch_rows) {
+        sqlx::query("SAVEPOINT batch").execute(&mut *conn).await?;
+        let sql = statements.sql(batch.len());
+        if bind_rows(sqlx::query(&sql), batch).execute(&mut *conn).await.is_ok() {
+            sqlx::query("RELEASE SAVEPOINT batch").execute(&mut *conn).await?;
+            continue;
+        }
+        sqlx::query("ROLLBACK TO SAVEPOINT batch").execute(&mut *conn).await?;
+        for row in batch {
+            sqlx::query("SAVEPOINT row").execute(&mut *conn).await?;
+            if let Err(e) = bind_rows(sqlx::query(&row_sql), std::slice::from_ref(row)).execute(&mut *conn).await {
+                sqlx::query("ROLLBACK TO SAVEPOINT row").execute(&mut *conn).await?;
+                eprintln!("Failed to insert row {}: {:?}", row.0, e);
+                rejects.push(RejectedRow { row: row.0, error: e.to_string() });
+            }
+            sqlx::query("RELEASE SAVEPOINT row").execute(&mut *conn).await?;
+        }
+        sqlx::query("RELEASE SAVEPOINT batch").execute(&mut *conn).await?;
+    }
+    Ok(rejects)
+}
+
+/// Table of the chunks of resumable loads committed so far.
+const PROGRESS_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS load_progress (
+    load_id TEXT NOT NULL,
+    target_table TEXT NOT NULL,
+    chunk INTEGER NOT NULL,
+    row_count BIGINT NOT NULL,
+    rejected_rows BIGINT[] NOT NULL,
+    errors TEXT[] NOT NULL,
+    committed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (load_id, target_table, chunk)
+)";
+
+/// Helper function to commit rows chunk by chunk, skipping the chunks an earlier attempt committed.
+async fn insert_resumable(pool: &PgPool, statements: &InsertStatements<'_>, resumable: &ResumableConfig, retry: &RetryPolicy, load_id: &str, rows: &[ConvertedRow]) -> Result<Vec<RejectedRow>> {
+    sqlx::query(PROGRESS_TABLE_SQL).execute(pool).await.context("Failed to create the load_progress table")?;
+    let committed: Vec<(i32, Vec<i64>, Vec<String>)> =
+        sqlx::query_as("SELECT chunk, rejected_rows, errors FROM load_progress WHERE load_id = $1 AND target_table = $2")
+            .bind(load_id)
+            .bind(statements.table)
+            .fetch_all(pool)
+            .await
+            .context("Failed to read the progress of the load")?;
+
+    // Rows rejected by committed chunks are reported again, so the rejects of the load stay complete
+    let mut rejects = vec![];
+    let mut done = HashSet::new();
+    for (chunk, rejected_rows, errors) in committed {
+        done.insert(chunk as usize);
+        rejects.extend(rejected_rows.into_iter().zip(errors).map(|(row, error)| RejectedRow { row: row as usize, error }));
+    }
+    let chunks: Vec<&[ConvertedRow]> = rows.chunks(resumable.commit_rows.max(1)).collect();
+    if !done.is_empty() {
+        println!("Resuming load {} into {} after {} of {} committed chunks", load_id, statements.table, done.len(), chunks.len());
+    }
+
+    for (chunk, chunk_rows) in chunks.into_iter().enumerate().filter(|(chunk, _)| !done.contains(chunk)) {
+        let mut reconnects = 0;
+        loop {
+            match commit_chunk(pool, statements, load_id, chunk, chunk_rows).await {
+                Ok(chunk_rejects) => {
+                    rejects.extend(chunk_rejects);
+                    break;
+                }
+                Err(e) if reconnects < resumable.max_reconnects => {
+                    reconnects += 1;
+                    eprintln!("Chunk {} of load {} failed, reconnecting ({}/{}): {:#}", chunk, load_id, reconnects, resumable.max_reconnects, e);
+                    tokio::time::sleep(retry.delay(reconnects)).await;
+                }
+                Err(e) => {
+                    return Err(e).context(format!("Chunk {} of load {} failed after {} reconnects; a retry resumes after the last committed chunk", chunk, load_id, reconnects))
+                }
+            }
+        }
+    }
+
+    sqlx::query("DELETE FROM load_progress WHERE load_id = $1 AND target_table = $2")
+        .bind(load_id)
+        .bind(statements.table)
+        .execute(pool)
+        .await
+        .context("Failed to clear the progress of the load")?;
+    Ok(rejects)
+}
+
+/// Helper function to insert one chunk and its progress marker in one transaction.
+async fn commit_chunk(pool: &PgPool, statements: &InsertStatements<'_>, load_id: &str, chunk: usize, rows: &[ConvertedRow]) -> Result<Vec<RejectedRow>> {
+    chaos::inject_db_error("acquire a connection for inserts")?;
+    let mut tx = pool.begin().await.context("Failed to begin the chunk transaction")?;
+    let rejects = insert_in_transaction(&mut tx, statements, rows).await?;
+    sqlx::query("INSERT INTO load_progress (load_id, target_table, chunk, row_count, rejected_rows, errors) VALUES ($1, $2, $3, $4, $5, $6)")
+        .bind(load_id)
+        .bind(statements.table)
+        .bind(chunk as i32)
+        .bind(rows.len() as i64)
+        .bind(rejects.iter().map(|r| r.row as i64).collect::<Vec<_>>())
+        .bind(rejects.iter().map(|r| r.error.clone()).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await
+        .context("Failed to record the progress of the load")?;
+    tx.commit().await.context("Failed to commit the chunk")?;
+    Ok(rejects)
+}
+
+/// Helper function to bind the values of rows, in order, to an insert statement.
+fn bind_rows<'q>(query: Query<'q, Postgres, PgArguments>, rows: &[ConvertedRow]) -> Query<'q, Postgres, PgArguments> {
+    rows.iter().flat_map(|(_, values)| values.iter().cloned()).fold(query, |query, value| value.bind(query))
+}
+
+/// Column of a rejects DataFrame holding the index of the row in the DataFrame it was rejected from.
+pub const SOURCE_ROW_COLUMN: &str = "source_row";
+
+/// Column of a rejects DataFrame holding the reason the row was rejected.
+pub const ERROR_COLUMN: &str = "error";
+
+/// A row that could not be stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedRow {
+    /// Index of the row in the stored DataFrame.
+    pub row: usize,
+    pub error: String,
+}
+
+/// Builds a DataFrame of the rejected rows, with a `source_row` column holding each row's index in the
+/// stored DataFrame and an `error` column holding the reason.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame that was stored.
+/// * `rejects` - The rows rejected by [`store_data`].
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The rejected rows, or an error if a row index is out of bounds.
+pub fn rejects_frame(df: &DataFrame, rejects: &[RejectedRow]) -> Result<DataFrame> {
+    let indices = IdxCa::from_vec("row", rejects.iter().map(|r| r.row as IdxSize).collect());
+    let mut rejected = df.take(&indices).context("Failed to select rejected rows")?;
+    rejected
+        .with_column(Series::new(SOURCE_ROW_COLUMN, rejects.iter().map(|r| r.row as u64).collect::<Vec<_>>()))
+        .context("Failed to append source rows")?;
+    rejected
+        .with_column(Series::new(ERROR_COLUMN, rejects.iter().map(|r| r.error.clone()).collect::<Vec<_>>()))
+        .context("Failed to append rejection reasons")?;
+    Ok(rejected)
+}
+
+/// Selects the rows of a stored DataFrame that were not rejected.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame that was stored.
+/// * `rejects` - The rows rejected by [`store_data`].
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The stored rows, in their original order.
+pub fn accepted_frame(df: &DataFrame, rejects: &[RejectedRow]) -> Result<DataFrame> {
+    let mut keep = vec![true; df.height()];
+    for reject in rejects {
+        if let Some(row) = keep.get_mut(reject.row) {
+            *row = false;
+        }
+    }
+    df.filter(&BooleanChunked::from_slice("accepted", &keep)).context("Failed to select stored rows")
+}
+
+/// PostgreSQL accepts at most this many bind parameters in one statement.
+const MAX_BIND_PARAMETERS: usize = 65_535;
+
+/// Helper function to pick how many rows one insert statement carries, up to `max_rows` and staying below the bind parameter limit.
+fn rows_per_statement(columns: usize, max_rows: usize) -> Result<usize> {
+    if columns > MAX_BIND_PARAMETERS {
+        bail!("Cannot insert {} columns in one statement; PostgreSQL allows {} parameters", columns, MAX_BIND_PARAMETERS);
+    }
+    Ok((MAX_BIND_PARAMETERS / columns.max(1)).min(max_rows).max(1))
+}
+
+/// Helper function to build an insert statement of `rows` rows, casting each parameter to its PostgreSQL type.
+fn insert_sql(table: &str, columns: &[&str], casts: &[&str], rows: usize) -> String {
+    let values: Vec<String> = (0..rows)
+        .map(|row| {
+            let placeholders: Vec<String> = casts
+                .iter()
+                .enumerate()
+                .map(|(i, cast)| format!("${}::{}", row * casts.len() + i + 1, cast))
+                .collect();
+            format!("({})", placeholders.join(", "))
+        })
+        .collect();
+    format!("INSERT INTO {} ({}) VALUES {}", table, columns.join(", "), values.join(", "))
+}
+
+/// Fetches and prints the first 5 rows from a table in the PostgreSQL database.
+///
+/// Every column of the schema is read as text, so tables of any width and column type are printed.
 ///
 /// # Arguments
 ///
 /// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The schema of the table.
+/// * `table` - The table, e.g. `wine_quality`.
 ///
 /// # Returns
 ///
@@ -145,35 +649,211 @@ pub async fn store_data(pool: &PgPool, df: &DataFrame) -> Result<()> {
 /// # Example
 ///
 /// ```
-/// get_first_5_rows(&pool).await.expect("Failed to fetch first 5 rows");
+/// get_first_5_rows(&pool, &config.schema, &config.storage.table).await.expect("Failed to fetch first 5 rows");
 /// ```
-pub async fn get_first_5_rows(pool: &PgPool) -> Result<()> {
-    let rows = sqlx::query("SELECT * FROM wine_quality LIMIT 5")
+pub async fn get_first_5_rows(pool: &PgPool, schema: &TableSchema, table: &str) -> Result<()> {
+    let columns: Vec<String> = schema.columns.iter().map(|c| format!("{}::TEXT", c.column)).collect();
+    let sql = format!("SELECT id, {} FROM {} ORDER BY id LIMIT 5", columns.join(", "), table);
+    let rows = sqlx::query(&sql)
         .fetch_all(pool)
         .await
         .context("Failed to fetch rows from the database")?;
 
     for row in rows {
         let id: i32 = row.try_get("id")?;
-        let fixed_acidity: f64 = row.try_get("fixed_acidity")?;
-        let volatile_acidity: f64 = row.try_get("volatile_acidity")?;
-        let citric_acid: f64 = row.try_get("citric_acid")?;
-        let residual_sugar: f64 = row.try_get("residual_sugar")?;
-        let chlorides: f64 = row.try_get("chlorides")?;
-        let free_sulfur_dioxide: f64 = row.try_get("free_sulfur_dioxide")?;
-        let total_sulfur_dioxide: f64 = row.try_get("total_sulfur_dioxide")?;
-        let density: f64 = row.try_get("density")?;
-        let ph: f64 = row.try_get("pH")?;
-        let sulphates: f64 = row.try_get("sulphates")?;
-        let alcohol: f64 = row.try_get("alcohol")?;
-        let quality: i32 = row.try_get("quality")?;
-
-        println!(
-            "ID: {}, Fixed Acidity: {}, Volatile Acidity: {}, Citric Acid: {}, Residual Sugar: {}, Chlorides: {}, Free Sulfur Dioxide: {}, Total Sulfur Dioxide: {}, Density: {}, pH: {}, Sulphates: {}, Alcohol: {}, Quality: {}",
-            id, fixed_acidity, volatile_acidity, citric_acid, residual_sugar, chlorides, free_sulfur_dioxide, total_sulfur_dioxide, density, ph, sulphates, alcohol, quality
-        );
+        let mut values = vec![format!("ID: {}", id)];
+        for (i, spec) in schema.columns.iter().enumerate() {
+            let value: Option<String> = row.try_get(i + 1)?;
+            values.push(format!("{}: {}", spec.name, value.as_deref().unwrap_or("NULL")));
+        }
+        println!("{}", values.join(", "));
     }
 
     Ok(())
 }
 
+/// Reads the rows of a table matching a condition into a DataFrame.
+///
+/// Columns are named after the DataFrame columns of the schema. Integer, numeric, and boolean columns
+/// are read as Int64, Float64, and Boolean; every other type is read as its text representation.
+/// Encrypted columns stay encrypted.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The schema of the table.
+/// * `table` - The table, e.g. `wine_quality`.
+/// * `filter` - An SQL condition on the table columns, e.g. `quality >= 7`; may refer to `params` as `$1`, `$2`, ...
+/// * `params` - The text parameters bound to the condition.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The matching rows in insertion order, or an error if the query fails.
+///
+/// # Example
+///
+/// ```
+/// let df = fetch_frame(&pool, &config.schema, "wine_quality", "run_id = $1", &[run.id.clone()]).await.expect("Failed to read rows");
+/// ```
+pub async fn fetch_frame(pool: &PgPool, schema: &TableSchema, table: &str, filter: &str, params: &[String]) -> Result<DataFrame> {
+    let columns: Vec<String> = schema.columns.iter().map(|c| format!("{}::TEXT", c.column)).collect();
+    let sql = format!("SELECT {} FROM {} WHERE {} ORDER BY id", columns.join(", "), table, filter);
+    let rows = params
+        .iter()
+        .fold(sqlx::query(&sql), |query, param| query.bind(param))
+        .fetch_all(pool)
+        .await
+        .context(format!("Failed to fetch rows where {}", filter))?;
+
+    frame_from_rows(&rows, schema, 0)
+}
+
+/// Builds a DataFrame from rows whose columns, starting at `offset`, are the schema's columns read as text.
+///
+/// # Arguments
+///
+/// * `rows` - The fetched rows.
+/// * `schema` - The schema describing the text columns, in order.
+/// * `offset` - The index of the first schema column in each row.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The rows, typed as described for [`fetch_frame`], or an error if a value cannot be read.
+pub fn frame_from_rows(rows: &[PgRow], schema: &TableSchema, offset: usize) -> Result<DataFrame> {
+    let mut series = vec![];
+    for (i, spec) in schema.columns.iter().enumerate() {
+        let values = rows.iter().map(|row| row.try_get::<Option<String>, _>(offset + i)).collect::<Result<Vec<_>, _>>()?;
+        let pg_type = spec.pg_type.to_ascii_uppercase();
+        let column = if pg_type.starts_with("BOOL") {
+            Series::new(&spec.name, values.iter().map(|v| v.as_deref().map(|v| v == "true")).collect::<Vec<_>>())
+        } else {
+            let text = Series::new(&spec.name, values);
+            match frame_dtype(&pg_type) {
+                Some(dtype) => text.cast(&dtype).context(format!("Failed to read column {} as {}", spec.name, dtype))?,
+                None => text,
+            }
+        };
+        series.push(column);
+    }
+    DataFrame::new(series).context("Failed to build a DataFrame of the fetched rows")
+}
+
+/// Helper function to pick the DataFrame dtype a PostgreSQL column is read as, if not text.
+fn frame_dtype(pg_type: &str) -> Option<DataType> {
+    if (pg_type.contains("INT") && !pg_type.starts_with("INTERVAL")) || pg_type.contains("SERIAL") {
+        Some(DataType::Int64)
+    } else if ["DECIMAL", "NUMERIC", "DOUBLE", "REAL", "FLOAT"].iter().any(|t| pg_type.starts_with(t)) {
+        Some(DataType::Float64)
+    } else {
+        None
+    }
+}
+
+/// Reads the first rows of the encrypted columns and decrypts them.
+///
+/// The control API serves them at `GET /records/encrypted` to keys allowed to read records.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `schema` - The schema of the table, mapping DataFrame columns to table columns.
+/// * `table` - The table, e.g. `wine_quality`.
+/// * `cipher` - The cipher the columns were encrypted with.
+/// * `limit` - The maximum number of rows to read.
+///
+/// # Returns
+///
+/// * `Result<Vec<Vec<(String, Option<String>)>>>` - Per row, the DataFrame column name and decrypted value of each encrypted column, or an error if a value cannot be decrypted.
+///
+/// # Example
+///
+/// ```
+/// let rows = fetch_decrypted(&pool, &config.schema, &config.storage.table, &cipher, 5).await.expect("Failed to read encrypted columns");
+/// ```
+pub async fn fetch_decrypted(pool: &PgPool, schema: &TableSchema, table: &str, cipher: &ColumnCipher, limit: i64) -> Result<Vec<Vec<(String, Option<String>)>>> {
+    let encrypted: Vec<_> = schema.columns.iter().filter(|c| cipher.encrypts(&c.name)).collect();
+    if encrypted.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let targets: Vec<&str> = encrypted.iter().map(|c| c.column.as_str()).collect();
+    let sql = format!("SELECT {} FROM {} ORDER BY id LIMIT $1", targets.join(", "), table);
+    let rows = sqlx::query(&sql)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch encrypted columns from the database")?;
+
+    rows.iter()
+        .map(|row| {
+            encrypted
+                .iter()
+                .map(|spec| {
+                    let stored: Option<String> = row.try_get(spec.column.as_str())?;
+                    let value = stored.map(|v| cipher.decrypt(&spec.name, &v)).transpose()?;
+                    Ok((spec.name.clone(), value))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_injected_pool_is_shared() {
+        // Lazy pools do not connect until the first query, so no database is needed
+        let pool = PgPoolOptions::new().max_connections(2).connect_lazy("postgres://host.invalid/wine").unwrap();
+        let pools = PoolProvider::Injected(pool);
+
+        let first = pools.get().await.unwrap();
+        let second = pools.get().await.unwrap();
+        assert_eq!(first.options().get_max_connections(), 2);
+        assert_eq!(second.size(), 0);
+    }
+
+    #[test]
+    fn test_rejects_frame() {
+        let df = polars::df!("alcohol" => &[9.4, 9.8, 10.1]).unwrap();
+        let rejects = vec![RejectedRow { row: 2, error: "numeric field overflow".to_string() }];
+
+        let rejected = rejects_frame(&df, &rejects).unwrap();
+
+        assert_eq!(rejected.height(), 1);
+        assert_eq!(rejected.column("source_row").unwrap().u64().unwrap().get(0), Some(2));
+        assert_eq!(rejected.column("alcohol").unwrap().f64().unwrap().get(0), Some(10.1));
+        assert_eq!(rejected.column("error").unwrap().str().unwrap().get(0), Some("numeric field overflow"));
+
+        let accepted = accepted_frame(&df, &rejects).unwrap();
+        assert_eq!(accepted.column("alcohol").unwrap().f64().unwrap().into_iter().collect::<Vec<_>>(), vec![Some(9.4), Some(9.8)]);
+    }
+
+    #[test]
+    fn test_partition_rows_groups_by_key_in_order() {
+        let row = |i: usize, vintage: i64| (i, vec![PgValue::Float8(9.4), PgValue::Int8(vintage)]);
+        let rows = vec![row(0, 2019), row(1, 2021), row(2, 2019), (3, vec![PgValue::Float8(9.8), PgValue::Null(BindStrategy::Int8)])];
+
+        let partitions = partition_rows(rows, 1);
+        let indices: Vec<Vec<usize>> = partitions.iter().map(|p| p.iter().map(|(i, _)| *i).collect()).collect();
+        assert_eq!(indices, vec![vec![0, 2], vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn test_insert_sql_casts_parameters() {
+        let sql = insert_sql("wine_quality", &["alcohol", "quality"], &["DOUBLE PRECISION", "BIGINT"], 1);
+        assert_eq!(sql, "INSERT INTO wine_quality (alcohol, quality) VALUES ($1::DOUBLE PRECISION, $2::BIGINT)");
+
+        let sql = insert_sql("wine_quality", &["alcohol", "quality"], &["DOUBLE PRECISION", "BIGINT"], 2);
+        assert_eq!(sql, "INSERT INTO wine_quality (alcohol, quality) VALUES ($1::DOUBLE PRECISION, $2::BIGINT), ($3::DOUBLE PRECISION, $4::BIGINT)");
+    }
+
+    #[test]
+    fn test_rows_per_statement_respects_parameter_limit() {
+        assert_eq!(rows_per_statement(14, 1_000).unwrap(), 1_000);
+        assert_eq!(rows_per_statement(500, 1_000).unwrap(), 131);
+        assert!(rows_per_statement(1_500, 1_000).unwrap() * 1_500 <= MAX_BIND_PARAMETERS);
+        assert!(rows_per_statement(70_000, 1_000).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! This module runs the pipeline over a large input in chunks.
+//!
+//! Ingestion, transformation, and storage run concurrently and hand chunks to each other through
+//! bounded channels: while chunk N is being inserted, chunk N+1 is being transformed and chunk N+2 is
+//! being read. A full channel holds the sending stage back, so a slow database slows ingestion down
+//! instead of piling up DataFrames in memory. Stages that need the whole dataset at once are not
+//! available in this mode.
+
+use crate::config::PipelineConfig;
+use crate::encryption::ColumnCipher;
+use crate::hooks::{HookEvent, HookPoint, Hooks};
+use crate::run::RunContext;
+use crate::typemap::TypeRegistry;
+use crate::source::Source;
+use crate::{audit, chaos, dataset, evolution, lakehouse, model, rounding, staging, status, storage, transformation};
+use futures::StreamExt;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use sqlx::postgres::PgPool;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// The sending side of a bounded handoff between two stages. It records how long the sending stage
+/// was held back by a full channel.
+struct Handoff {
+    tx: mpsc::Sender<DataFrame>,
+    waited: Duration,
+}
+
+impl Handoff {
+    /// Sends a chunk, waiting while the channel is full. Returns `false` once the receiving stage has stopped.
+    async fn send(&mut self, df: DataFrame) -> bool {
+        let start = Instant::now();
+        let sent = self.tx.send(df).await.is_ok();
+        self.waited += start.elapsed();
+        sent
+    }
+
+}
+
+/// Creates a handoff buffering at most `capacity` chunks.
+fn handoff(capacity: usize) -> (Handoff, mpsc::Receiver<DataFrame>) {
+    let (tx, rx) = mpsc::channel(capacity);
+    (Handoff { tx, waited: Duration::ZERO }, rx)
+}
+
+/// Runs ingestion, transformation, model scoring, and storage chunk by chunk.
+///
+/// Each chunk is transformed and stored independently, so imputed medians and other per-column
+/// statistics are computed per chunk. Hooks around ingestion, transformation, and storage fire once per chunk.
+/// At most `channel_capacity` chunks wait between two stages, bounding memory to roughly
+/// `(2 * channel_capacity + 3) * chunk_rows` rows.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run` - The context of the current run.
+/// * `config` - The pipeline configuration.
+/// * `hooks` - The hooks fired around each chunk.
+/// * `source` - The input, yielding chunks of `streaming.chunk_rows` rows.
+///
+/// # Returns
+///
+/// * `Result<usize>` - The number of rows stored, or an error if the settings are invalid, a whole-dataset stage is enabled, or any stage fails.
+///
+/// # Example
+///
+/// ```
+/// let rows = run_chunked(&pool, &run, &config, &hooks, source::from_config(&config).as_ref()).await.expect("Chunked run failed");
+/// ```
+pub async fn run_chunked(pool: &PgPool, run: &RunContext, config: &PipelineConfig, hooks: &Hooks, source: &dyn Source) -> Result<usize> {
+    let whole_dataset_stages = [
+        ("visualization", config.visualization.enabled),
+        ("expectations", !config.expectations.suite.is_empty()),
+        ("model training", config.model.train),
+        ("pca", config.pca.enabled),
+        ("clustering", config.clustering.enabled),
+        ("analysis", !config.analysis.tests.is_empty()),
+        ("catalog", config.catalog.enabled),
+        ("column stats", config.column_stats.enabled),
+    ];
+    let enabled: Vec<&str> = whole_dataset_stages.iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+    if !enabled.is_empty() {
+        bail!("Chunked mode does not support stages that need the whole dataset: {}", enabled.join(", "));
+    }
+
+    if config.streaming.channel_capacity == 0 {
+        bail!("streaming.channel_capacity must be at least 1");
+    }
+
+    let (mut chunk_tx, mut chunk_rx) = handoff(config.streaming.channel_capacity);
+    let (mut ready_tx, mut ready_rx) = handoff(config.streaming.channel_capacity);
+    status::watch_handoff(&run.id, "transform", &chunk_tx.tx);
+    status::watch_handoff(&run.id, "store", &ready_tx.tx);
+
+    let mut chunks = source.read().await?;
+    let ingest = async move {
+        let mut rows = 0;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chaos::corrupt_rows(chunk?)?;
+            rows += chunk.height();
+            status::add_rows(&run.id, chunk.height());
+            // The receiver is gone when a later stage failed; its error is reported instead
+            if !chunk_tx.send(chunk).await {
+                break;
+            }
+        }
+        Ok::<_, anyhow::Error>((rows, chunk_tx.waited))
+    };
+
+    let transform = async move {
+        while let Some(chunk) = chunk_rx.recv().await {
+            hooks.fire(HookEvent::new(HookPoint::AfterIngest, &run.id).with_rows(chunk.height())).await?;
+
+            let schema = config.schema.clone();
+            let passthrough = config.transform.passthrough.clone();
+            let model_config = config.model.clone();
+            let rounding_config = config.rounding.clone();
+            let max_filled_rate = config.transform.max_filled_rate;
+            let (chunk, fill_warnings) = tokio::task::spawn_blocking(move || -> Result<(DataFrame, Vec<String>)> {
+                let df = transformation::transform_data(chunk.clone(), &passthrough)?;
+                let df = transformation::apply_schema(df, &schema)?;
+                let fill_warnings = transformation::fill_warnings(&chunk, &df, max_filled_rate);
+                let df = model::score_stage(df, &model_config)?;
+                Ok((rounding::round_stage(df, &rounding_config, &schema)?, fill_warnings))
+            })
+            .await
+            .context("Transformation task failed")??;
+            for warning in fill_warnings {
+                run.add_warning("transform", warning);
+            }
+
+            hooks.fire(HookEvent::new(HookPoint::AfterTransform, &run.id).with_rows(chunk.height())).await?;
+            if !ready_tx.send(chunk).await {
+                break;
+            }
+        }
+        Ok::<_, anyhow::Error>(ready_tx.waited)
+    };
+
+    let store = async move {
+        let registry = TypeRegistry::with_overrides(&config.storage.column_types);
+        let cipher = ColumnCipher::from_config(&config.storage.encryption)?;
+        let table = staging::target_table(pool, config).await?;
+        let mut stored = 0;
+        let mut schema = None;
+        let mut chunks_stored = 0;
+        while let Some(chunk) = ready_rx.recv().await {
+            let chunk = audit::add_audit_columns(chunk, run)?;
+            hooks.fire(HookEvent::new(HookPoint::BeforeStore, &run.id).with_rows(chunk.height())).await?;
+            // Chunks share their columns, so the table is reconciled with the first one
+            if schema.is_none() {
+                schema = Some(evolution::evolve(pool, table, &chunk, config, &registry).await?);
+            }
+            let table_schema = schema.as_ref().expect("Schema reconciled above");
+            let load_id = format!("{}/chunk-{}", run.id, chunks_stored);
+            // The position of the source is committed with the rows of its chunk
+            let offsets = source.take_offsets();
+            let options = storage::InsertOptions { table, load_id: Some(&load_id), offsets: offsets.as_ref(), ..storage::InsertOptions::from_config(&config.storage) };
+            let rejects = storage::store_data(pool, &chunk, table_schema, &registry, cipher.as_ref(), options).await?;
+            chunks_stored += 1;
+            if config.iceberg.enabled || config.dataset.enabled {
+                let accepted = storage::accepted_frame(&chunk, &rejects)?;
+                lakehouse::append(&config.iceberg, &config.schema, cipher.as_ref(), run, &accepted).await?;
+                dataset::append(&config.dataset, &config.schema, cipher.as_ref(), run, &accepted).await?;
+            }
+            stored += chunk.height() - rejects.len();
+            if !rejects.is_empty() {
+                run.add_warning("store", format_args!("{} of {} rows of chunk {} were rejected by the database", rejects.len(), chunk.height(), chunks_stored - 1));
+            }
+            hooks
+                .fire(HookEvent::new(HookPoint::AfterStore, &run.id).with_rows(chunk.height() - rejects.len()).with_rejected(rejects.len()))
+                .await?;
+            run.log(format_args!("Stored chunk of {} rows, {} rejected ({} rows so far)", chunk.height(), rejects.len(), stored));
+        }
+        Ok::<_, anyhow::Error>(stored)
+    };
+
+    let ((ingested, ingest_waited), transform_waited, stored) =
+        tokio::try_join!(ingest, transform, store)?;
+    run.log(format_args!("Chunked run complete: {} rows ingested, {} rows stored", ingested, stored));
+    run.log(format_args!(
+        "Backpressure: ingestion waited {:.2?} for transformation, transformation waited {:.2?} for storage",
+        ingest_waited, transform_waited
+    ));
+    Ok(stored)
+}
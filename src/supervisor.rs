@@ -0,0 +1,171 @@
+//! This module supervises the long-lived components of daemon mode: its triggers and endpoints.
+//!
+//! Each component runs in a task of its own. Without supervision, a component that panics or returns
+//! an error stops for good while the process keeps running and `/healthz` keeps answering, so a
+//! daemon whose landing watcher died looks healthy but never loads another file. The [`Supervisor`]
+//! logs the failure, records it, and starts the component again after a backoff that doubles with
+//! each consecutive failure, up to `max_restart_backoff_secs`. `/readyz` reports the daemon not ready
+//! while a component is waiting to restart, and `/metrics` exposes the state and restart count of
+//! every component.
+
+use crate::config::SupervisorConfig;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// State of a supervised component.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ComponentStatus {
+    /// Whether the component is running, rather than waiting to restart or stopped.
+    pub running: bool,
+    /// Number of times the component was restarted after a panic or an error.
+    pub restarts: u64,
+    /// The panic message or error of its last failure.
+    pub last_error: Option<String>,
+}
+
+/// Runs the daemon's components, restarting those that fail.
+pub struct Supervisor {
+    config: SupervisorConfig,
+    components: Mutex<BTreeMap<&'static str, ComponentStatus>>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self {
+            config,
+            components: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Starts a component in a task of its own, and again each time it panics or returns an error.
+    ///
+    /// A component that returns `Ok(())` stopped on purpose, e.g. because the daemon is stopping, and is not restarted.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the component in logs, readiness checks, and metrics.
+    /// * `component` - Creates the future of a fresh instance of the component.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// supervisor.spawn("landing", move || landing::watch(landing_config.clone(), tx.clone()));
+    /// ```
+    pub fn spawn<F, Fut>(self: &Arc<Self>, name: &'static str, component: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut failures = 0;
+            loop {
+                supervisor.update(name, |status| status.running = true);
+                let started = Instant::now();
+                let error = match tokio::spawn(component()).await {
+                    Ok(Ok(())) => {
+                        supervisor.update(name, |status| status.running = false);
+                        return;
+                    }
+                    Ok(Err(e)) => format!("failed: {:#}", e),
+                    Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+                    Err(e) => format!("was cancelled: {}", e),
+                };
+
+                // A component that ran for longer than the longest backoff is no longer failing in a loop
+                let max_backoff = Duration::from_secs(supervisor.config.max_restart_backoff_secs);
+                failures = if started.elapsed() > max_backoff { 1 } else { failures + 1 };
+                let delay = backoff(supervisor.config.restart_backoff_secs, failures).min(max_backoff);
+                eprintln!("Daemon component {} {}; restarting it in {}s", name, error, delay.as_secs());
+                supervisor.update(name, |status| {
+                    status.running = false;
+                    status.restarts += 1;
+                    status.last_error = Some(error);
+                });
+                tokio::time::sleep(delay).await;
+            }
+        });
+    }
+
+    /// Returns the state of every component started so far, by name.
+    pub fn components(&self) -> BTreeMap<&'static str, ComponentStatus> {
+        self.components.lock().unwrap().clone()
+    }
+
+    /// Renders the state of the components in the Prometheus text exposition format.
+    pub fn metrics(&self) -> String {
+        let components = self.components();
+        let mut text = String::from("# HELP pipeline_component_up Whether a daemon component is running.\n# TYPE pipeline_component_up gauge\n");
+        for (name, status) in &components {
+            text.push_str(&format!("pipeline_component_up{{component=\"{}\"}} {}\n", name, status.running as u8));
+        }
+        text.push_str("# HELP pipeline_component_restarts_total Restarts of a daemon component after a panic or an error.\n# TYPE pipeline_component_restarts_total counter\n");
+        for (name, status) in &components {
+            text.push_str(&format!("pipeline_component_restarts_total{{component=\"{}\"}} {}\n", name, status.restarts));
+        }
+        text
+    }
+
+    fn update(&self, name: &'static str, change: impl FnOnce(&mut ComponentStatus)) {
+        change(self.components.lock().unwrap().entry(name).or_default());
+    }
+}
+
+/// Helper function to compute the delay before restarting a component, doubling with each consecutive failure.
+fn backoff(base_secs: u64, failures: u32) -> Duration {
+    Duration::from_secs(base_secs.saturating_mul(1u64 << (failures.max(1) - 1).min(16)))
+}
+
+/// Helper function to read the message of a panic, which is a `&str` or a `String` unless panicked with another payload.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or("(no message)".to_string(), |m| m.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_panicking_component_is_restarted() {
+        let supervisor = Arc::new(Supervisor::new(SupervisorConfig { restart_backoff_secs: 0, max_restart_backoff_secs: 0 }));
+        let starts = Arc::new(AtomicUsize::new(0));
+        let counter = starts.clone();
+        supervisor.spawn("watcher", move || {
+            let counter = counter.clone();
+            async move {
+                match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => panic!("watch failed"),
+                    1 => anyhow::bail!("directory vanished"),
+                    _ => Ok(()),
+                }
+            }
+        });
+        for _ in 0..100 {
+            if starts.load(Ordering::SeqCst) >= 3 && !supervisor.components()["watcher"].running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let status = &supervisor.components()["watcher"];
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_error.as_deref(), Some("failed: directory vanished"));
+        assert!(supervisor.metrics().contains("pipeline_component_restarts_total{component=\"watcher\"} 2"));
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        assert_eq!(backoff(2, 1), Duration::from_secs(2));
+        assert_eq!(backoff(2, 3), Duration::from_secs(8));
+        assert_eq!(backoff(0, 5), Duration::ZERO);
+    }
+}
@@ -0,0 +1,55 @@
+//! This module routes the pipeline's data to the schema of a tenant.
+//!
+//! With `--tenant NAME`, every connection of the pool uses the PostgreSQL schema `tenant_NAME` as its
+//! search path, and the schema is created on first use. Since the pipeline refers to its tables
+//! without a schema, the stored rows, results, catalog, and run history of each tenant are kept apart,
+//! so one deployment serves several wineries. Without a tenant, the database's default search path
+//! (normally `public`) is used as before.
+
+use anyhow::{bail, Result};
+use std::sync::OnceLock;
+
+static TENANT_SCHEMA: OnceLock<String> = OnceLock::new();
+
+/// Selects the tenant of this invocation. It applies to every connection pool created afterwards.
+///
+/// # Arguments
+///
+/// * `tenant` - The tenant name: lowercase letters, digits, and underscores.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if the name is invalid or another tenant was already selected.
+pub fn select(tenant: &str) -> Result<()> {
+    let schema = schema_name(tenant)?;
+    if TENANT_SCHEMA.get_or_init(|| schema.clone()) != &schema {
+        bail!("Tenant already selected; a process serves one tenant");
+    }
+    Ok(())
+}
+
+/// Returns the schema of the selected tenant, if any.
+pub fn schema() -> Option<&'static str> {
+    TENANT_SCHEMA.get().map(String::as_str)
+}
+
+/// Helper function to build the schema name of a tenant, rejecting names that are not plain identifiers.
+fn schema_name(tenant: &str) -> Result<String> {
+    if tenant.is_empty() || tenant.len() > 48 || !tenant.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+        bail!("Invalid tenant {:?}: use 1 to 48 lowercase letters, digits, and underscores", tenant);
+    }
+    Ok(format!("tenant_{}", tenant))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_name() {
+        assert_eq!(schema_name("chateau_2").unwrap(), "tenant_chateau_2");
+        assert!(schema_name("").is_err());
+        assert!(schema_name("Chateau").is_err());
+        assert!(schema_name("a; DROP TABLE wine_quality").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! This module holds the helpers of the tests that run the whole pipeline against the database.
+//!
+//! A test takes a [`Scratch`] guard, which names the tables its runs store in uniquely and writes its
+//! input files, so the test touches neither the tables of real runs nor those of other tests. Dropping
+//! the guard, also when an assertion fails, drops the tables and deletes the files and the records the
+//! runs kept about them.
+
+use crate::config::PipelineConfig;
+use crate::source::SourceConfig;
+use crate::{seed, storage};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use ulid::Ulid;
+
+/// Header of the CSV inputs, naming the columns of the default schema.
+pub const HEADER: &str = "fixed acidity,volatile acidity,citric acid,residual sugar,chlorides,free sulfur dioxide,total sulfur dioxide,density,pH,sulphates,alcohol,quality\n";
+
+/// Wines of the red wine dataset, one CSV line each: three of quality 5, then one of quality 6 and one of quality 7.
+pub const ROWS: [&str; 5] = [
+    "7.4,0.7,0,1.9,0.076,11,34,0.9978,3.51,0.56,9.4,5\n",
+    "7.8,0.88,0,2.6,0.098,25,67,0.9968,3.2,0.68,9.8,5\n",
+    "7.8,0.76,0.04,2.3,0.092,15,54,0.997,3.26,0.65,9.8,5\n",
+    "11.2,0.28,0.56,1.9,0.075,17,60,0.998,3.16,0.58,9.8,6\n",
+    "7.3,0.65,0,1.2,0.065,15,21,0.9946,3.39,0.47,10,7\n",
+];
+
+/// The tables and files of a test, removed when it is dropped.
+#[derive(Default)]
+pub struct Scratch {
+    tables: Vec<String>,
+    paths: Vec<PathBuf>,
+}
+
+impl Scratch {
+    /// Names a table of the test, e.g. `temp_retention_runs_01hwkq8y6zj0d5vx3q9n2t4m7b`.
+    pub fn table(&mut self, prefix: &str) -> String {
+        let table = format!("{}_{}", prefix, Ulid::new().to_string().to_lowercase());
+        self.tables.push(table.clone());
+        table
+    }
+
+    /// Names a file or directory of the test in the temporary directory, e.g. for a report directory.
+    pub fn path(&mut self, prefix: &str, extension: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}{}", prefix, Ulid::new(), extension));
+        self.paths.push(path.clone());
+        path
+    }
+
+    /// Writes a CSV input of the given rows under the header, at a path of its own.
+    pub fn csv(&mut self, prefix: &str, rows: &[&str]) -> Result<PathBuf> {
+        let path = self.path(prefix, ".csv");
+        std::fs::write(&path, format!("{}{}", HEADER, rows.concat()))?;
+        Ok(path)
+    }
+
+    /// Returns the default configuration, reading the CSV file at `path` into `table`.
+    pub fn config(table: &str, path: &Path) -> PipelineConfig {
+        let mut config = PipelineConfig::default();
+        config.storage.table = table.to_string();
+        config.source = SourceConfig::Csv { path: path.display().to_string(), options: Default::default() };
+        config
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let (tables, paths) = (std::mem::take(&mut self.tables), self.paths.clone());
+        // The test's runtime cannot block on the cleanup, so it runs on one of its own
+        let cleaned = std::thread::spawn(move || tokio::runtime::Builder::new_current_thread().enable_all().build()?.block_on(clean_up(&tables, &paths))).join();
+        if let Ok(Err(e)) = cleaned {
+            eprintln!("Failed to drop the scratch tables: {:#}", e);
+        }
+        for path in &self.paths {
+            std::fs::remove_file(path).or_else(|_| std::fs::remove_dir_all(path)).ok();
+        }
+    }
+}
+
+/// Helper function to drop the tables of a test and delete the records of its tables and input files.
+async fn clean_up(tables: &[String], paths: &[PathBuf]) -> Result<()> {
+    dotenv::dotenv().ok();
+    let pool = storage::create_connection_pool().await?;
+    for table in tables {
+        sqlx::query(&format!("DROP TABLE IF EXISTS {} CASCADE", table)).execute(&pool).await?;
+        for (records, column) in [("row_history", "table_name"), ("staged_runs", "staging_table")] {
+            if seed::table_exists(&pool, records).await? {
+                sqlx::query(&format!("DELETE FROM {} WHERE {} = $1", records, column)).bind(table).execute(&pool).await?;
+            }
+        }
+    }
+    if seed::table_exists(&pool, "file_bookmarks").await? {
+        for path in paths {
+            sqlx::query("DELETE FROM file_bookmarks WHERE path = $1").bind(path.display().to_string()).execute(&pool).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+//! This module reads the stored table as it was when a given run completed.
+//!
+//! Every stored row carries the ID of the run that loaded it, so the rows a run added are a batch. Rows
+//! are removed from the table by partition overwrites of later loads; instead of being lost, each
+//! removed row is kept as JSON in the `row_history` table with the time it was superseded. The table as
+//! of a run is then the rows, present or superseded since, of the runs that had completed when it did:
+//! runs count from their last successful attempt, or from their promotion for staged runs. Rows without
+//! a run ID, loaded before the audit columns existed, belong to every state of the table. Rows rotated out
+//! by `[retention]` are not kept, so the table as of a run older than the retention period lacks them.
+
+use crate::schema::TableSchema;
+use crate::{audit, history};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgPool;
+
+/// Table keeping the rows removed from the stored tables.
+pub const HISTORY_TABLE: &str = "row_history";
+
+/// DDL of the history table, created with the other result tables of the database.
+pub const HISTORY_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS row_history (
+    table_name TEXT NOT NULL,
+    row_data JSONB NOT NULL,
+    superseded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+)";
+
+/// Time a run's rows became part of the table, over `pipeline_runs r` joined with [`PROMOTIONS`].
+const LOADED_AT: &str = "COALESCE(s.decided_at, max(r.finished_at))";
+
+/// Join of the promotions of staged runs.
+const PROMOTIONS: &str = "LEFT JOIN staged_runs s ON s.run_id = r.run_id AND s.status = 'PROMOTED'";
+
+/// Builds the statement deleting the rows of a table matching a condition, keeping them in the history table.
+///
+/// # Arguments
+///
+/// * `table` - The table rows are deleted from.
+/// * `filter` - SQL condition selecting the deleted rows, with its own placeholders.
+///
+/// # Returns
+///
+/// * `String` - The statement, which takes the placeholders of the condition.
+///
+/// # Example
+///
+/// ```
+/// let sql = supersede_sql("wine_quality", "region IS NOT DISTINCT FROM $1::TEXT");
+/// ```
+pub fn supersede_sql(table: &str, filter: &str) -> String {
+    format!(
+        "WITH superseded AS (DELETE FROM {0} WHERE {1} RETURNING *) \
+         INSERT INTO {2} (table_name, row_data) SELECT '{0}', to_jsonb(superseded) FROM superseded",
+        table, filter, HISTORY_TABLE
+    )
+}
+
+/// Looks up the time a run's rows became part of the table: its promotion for a promoted staged run, else the end of its last successful attempt.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run_id` - The ID of the run.
+///
+/// # Returns
+///
+/// * `Result<DateTime<Utc>>` - The time, or an error if the run never succeeded.
+///
+/// # Example
+///
+/// ```
+/// let at = load_time(&pool, "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B").await?;
+/// ```
+pub async fn load_time(pool: &PgPool, run_id: &str) -> Result<DateTime<Utc>> {
+    let sql = format!("SELECT {} FROM pipeline_runs r {} WHERE r.run_id = $1 AND r.status = $2 GROUP BY s.decided_at", LOADED_AT, PROMOTIONS);
+    let at: Option<DateTime<Utc>> = sqlx::query_scalar(&sql)
+        .bind(run_id)
+        .bind(history::SUCCEEDED)
+        .fetch_optional(pool)
+        .await
+        .context(format!("Failed to look up run {}", run_id))?;
+    at.context(format!("Run {} has no successful attempt, so the table has no state as of it", run_id))
+}
+
+/// Finds the column of the run ID, which tells the batch of each row.
+///
+/// # Arguments
+///
+/// * `schema` - The schema of the stored table.
+///
+/// # Returns
+///
+/// * `Result<&str>` - The name of the column in the table, or an error if the schema lacks it.
+pub fn run_column(schema: &TableSchema) -> Result<&str> {
+    schema
+        .columns
+        .iter()
+        .find(|c| c.name == audit::RUN_ID_COLUMN)
+        .map(|c| c.column.as_str())
+        .context("Reading the table as of a run requires the run_id column in the schema")
+}
+
+/// Builds a subquery of the rows of a table as of a time, to select from in place of the table.
+///
+/// # Arguments
+///
+/// * `table` - The stored table.
+/// * `run_column` - The column of the table holding the run ID of each row.
+/// * `at` - The placeholder of the time, as returned by [`load_time`], e.g. `$3`.
+///
+/// # Returns
+///
+/// * `String` - The subquery, aliased `snapshot`, with the columns of the table.
+///
+/// # Example
+///
+/// ```
+/// let sql = format!("SELECT count(*) FROM {}", snapshot_sql("wine_quality", "run_id", "$1"));
+/// ```
+pub fn snapshot_sql(table: &str, run_column: &str, at: &str) -> String {
+    format!(
+        "(SELECT * FROM (SELECT * FROM {0} UNION ALL SELECT (jsonb_populate_record(NULL::{0}, row_data)).* FROM {1} WHERE table_name = '{0}' AND superseded_at > {2}) AS versions \
+         WHERE {3} IS NULL OR {3} IN (SELECT r.run_id FROM pipeline_runs r {4} WHERE r.status = '{5}' GROUP BY r.run_id, s.decided_at HAVING {6} <= {2})) AS snapshot",
+        table,
+        HISTORY_TABLE,
+        at,
+        run_column,
+        PROMOTIONS,
+        history::SUCCEEDED,
+        LOADED_AT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_reads_superseded_rows_of_earlier_runs() {
+        let sql = snapshot_sql("wine_quality", "run_id", "$3");
+        assert!(sql.contains("SELECT * FROM wine_quality UNION ALL SELECT (jsonb_populate_record(NULL::wine_quality, row_data)).* FROM row_history"));
+        assert!(sql.contains("table_name = 'wine_quality' AND superseded_at > $3"));
+        assert!(sql.contains("HAVING COALESCE(s.decided_at, max(r.finished_at)) <= $3)) AS snapshot"));
+
+        let sql = supersede_sql("wine_quality", "region = $1");
+        assert!(sql.starts_with("WITH superseded AS (DELETE FROM wine_quality WHERE region = $1 RETURNING *) INSERT INTO row_history"));
+    }
+
+    #[tokio::test]
+    async fn test_table_as_of_earlier_run_has_its_overwritten_rows() -> Result<()> {
+        use crate::hooks::Hooks;
+        use crate::testing::{Scratch, ROWS};
+        use crate::{pipeline, storage};
+
+        dotenv::dotenv().ok();
+        let pool = storage::create_connection_pool().await?;
+        let mut scratch = Scratch::default();
+        let table = scratch.table("temp_timetravel_runs");
+
+        // The second run replaces the rows of quality 5 the first one stored
+        for rows in [&[ROWS[0], ROWS[1], ROWS[3]][..], &ROWS[2..3]] {
+            let mut config = Scratch::config(&table, &scratch.csv("temp_timetravel", rows)?);
+            config.storage.partitioning.column = Some("quality".to_string());
+            config.storage.partitioning.overwrite = true;
+            pipeline::run(&config, &Hooks::from_commands(&[])).await?;
+        }
+
+        let first_run: String = sqlx::query_scalar(&format!("SELECT run_id FROM {} WHERE quality = 6", table)).fetch_one(&pool).await?;
+        let second_run: String = sqlx::query_scalar(&format!("SELECT run_id FROM {} WHERE quality = 5", table)).fetch_one(&pool).await?;
+        let mut stored = vec![];
+        for run_id in [&first_run, &second_run] {
+            let at = load_time(&pool, run_id).await?;
+            let sql = format!("SELECT run_id, quality, alcohol::TEXT FROM {} ORDER BY quality, alcohol", snapshot_sql(&table, "run_id", "$1"));
+            let rows: Vec<(String, i32, String)> = sqlx::query_as(&sql).bind(at).fetch_all(&pool).await?;
+            stored.push(rows.into_iter().map(|(run, quality, alcohol)| (run == first_run, quality, alcohol)).collect::<Vec<_>>());
+        }
+
+        let row = |first: bool, quality: i32, alcohol: &str| (first, quality, alcohol.to_string());
+        assert_eq!(stored[0], vec![row(true, 5, "9.4"), row(true, 5, "9.8"), row(true, 6, "9.8")]);
+        assert_eq!(stored[1], vec![row(false, 5, "9.8"), row(true, 6, "9.8")]);
+        Ok(())
+    }
+}
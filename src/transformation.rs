@@ -1,8 +1,11 @@
 //! This module handles the transformation of data within DataFrames.
 //!
-//! It provides functions for cleaning, normalizing, and validating data.
+//! It provides functions for cleaning, normalizing, and validating data. Columns listed in
+//! `[transform] passthrough`, such as `quality` or identifiers, are restored to their input values after
+//! every stage, so no stage can clean or scale them whether or not it skips them itself.
 
-use anyhow::{Context, Result};
+use crate::schema::{Imputation, TableSchema};
+use anyhow::{bail, Context, Result};
 use polars::prelude::*;
 
 /// Transforms the input DataFrame by cleaning, normalizing, and validating the data.
@@ -10,6 +13,7 @@ use polars::prelude::*;
 /// # Arguments
 ///
 /// * `df` - A DataFrame containing the data to be transformed.
+/// * `passthrough` - Columns kept exactly as they are in the input.
 ///
 /// # Returns
 ///
@@ -24,15 +28,190 @@ use polars::prelude::*;
 ///     // other columns...
 /// ]).unwrap();
 ///
-/// let transformed_df = transform_data(df).expect("Data transformation failed");
+/// let transformed_df = transform_data(df, &config.transform.passthrough).expect("Data transformation failed");
 /// ```
-pub fn transform_data(df: DataFrame) -> Result<DataFrame> {
-    let df = clean_data(df)?;
-    let df = normalize_data(df)?;
-    let df = validate_data(df)?;
+pub fn transform_data(df: DataFrame, passthrough: &[String]) -> Result<DataFrame> {
+    transform_stages(df, passthrough, |_, _| Ok(()))
+}
+
+/// Transforms the input DataFrame like [`transform_data`], handing each intermediate result to `on_stage`.
+///
+/// `on_stage` receives `cleaned` after cleaning and `normalized` after normalization.
+///
+/// # Arguments
+///
+/// * `df` - A DataFrame containing the data to be transformed.
+/// * `passthrough` - Columns kept exactly as they are in the input.
+/// * `on_stage` - Called with the stage name and its output, e.g. to persist intermediates.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - A result containing the transformed DataFrame if successful, or an error if the transformation or `on_stage` fails.
+///
+/// # Example
+///
+/// ```
+/// let mut intermediates = vec![];
+/// let transformed_df = transform_stages(df, &config.transform.passthrough, |name, df| {
+///     intermediates.push((name, df.clone()));
+///     Ok(())
+/// })
+/// .expect("Data transformation failed");
+/// ```
+pub fn transform_stages(df: DataFrame, passthrough: &[String], mut on_stage: impl FnMut(&'static str, &DataFrame) -> Result<()>) -> Result<DataFrame> {
+    let df = pass_through(df, passthrough, "cleaning", clean_data)?;
+    on_stage("cleaned", &df)?;
+    let df = pass_through(df, passthrough, "normalization", normalize_data)?;
+    on_stage("normalized", &df)?;
+    let df = pass_through(df, passthrough, "validation", validate_data)?;
+    Ok(df)
+}
+
+/// The column medians cleaning fills missing values with.
+///
+/// Computed once over the whole input, they let partitions of it be cleaned separately with the same
+/// result as cleaning it at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CleaningMedians {
+    fixed_acidity: f64,
+    volatile_acidity: f64,
+    // Repeat for other columns...
+}
+
+impl CleaningMedians {
+    /// Computes the medians of a DataFrame.
+    pub fn compute(df: &DataFrame) -> Result<Self> {
+        Ok(Self {
+            fixed_acidity: median_value(df, "fixed acidity")?,
+            volatile_acidity: median_value(df, "volatile acidity")?,
+        })
+    }
+}
+
+/// Transforms a partition of the input like [`transform_data`], cleaning it with medians computed over the whole input.
+///
+/// # Arguments
+///
+/// * `df` - A partition of the input.
+/// * `medians` - The medians of the whole input.
+/// * `passthrough` - Columns kept exactly as they are in the input.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The transformed partition, or an error if the transformation fails.
+pub fn transform_partition(df: DataFrame, medians: &CleaningMedians, passthrough: &[String]) -> Result<DataFrame> {
+    let df = pass_through(df, passthrough, "cleaning", |df| clean_with(df, medians))?;
+    let df = pass_through(df, passthrough, "normalization", normalize_data)?;
+    pass_through(df, passthrough, "validation", validate_data)
+}
+
+/// Helper function to run a stage and restore the passthrough columns to their values before it.
+///
+/// Columns the stage dropped are added back; a stage that changed the number of rows cannot have its
+/// passthrough columns restored and fails.
+fn pass_through(df: DataFrame, passthrough: &[String], stage: &str, transform: impl FnOnce(DataFrame) -> Result<DataFrame>) -> Result<DataFrame> {
+    let kept: Vec<Series> = passthrough.iter().filter_map(|name| df.column(name).ok().cloned()).collect();
+    let mut df = transform(df)?;
+    for series in kept {
+        if series.len() != df.height() {
+            bail!("The {} stage changed the number of rows from {} to {}, so passthrough column {} cannot be restored", stage, series.len(), df.height(), series.name());
+        }
+        df.with_column(series).context(format!("Error restoring passthrough column after {}", stage))?;
+    }
+    Ok(df)
+}
+
+/// Applies the null handling declared in the schema.
+///
+/// Missing values are filled for every column with an imputation. Afterwards, a column that is not
+/// nullable must not contain missing values. Columns absent from the DataFrame are left to storage,
+/// since some are only added by later stages.
+///
+/// # Arguments
+///
+/// * `df` - A DataFrame containing the transformed data.
+/// * `schema` - The schema of the stored table.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - The DataFrame with imputed values, or an error if a non-nullable column still has missing values.
+///
+/// # Example
+///
+/// ```
+/// let df = apply_schema(transformed_df, &config.schema).expect("Schema validation failed");
+/// ```
+pub fn apply_schema(mut df: DataFrame, schema: &TableSchema) -> Result<DataFrame> {
+    for spec in &schema.columns {
+        let Ok(series) = df.column(&spec.name) else { continue };
+
+        if let (Some(impute), true) = (spec.impute, series.null_count() > 0) {
+            let dtype = series.dtype().clone();
+            let values = series.cast(&DataType::Float64).context(format!("Error converting {} column to f64", spec.name))?;
+            let values = values.f64()?;
+            let fill = match impute {
+                Imputation::Mean => values.mean(),
+                Imputation::Median => values.median(),
+                Imputation::Zero => Some(0.0),
+                Imputation::Constant(value) => Some(value),
+            }
+            .context(format!("Error calculating the imputed value for {} column", spec.name))?;
+            let fill = if dtype.is_integer() { fill.round() } else { fill };
+
+            let filled = values
+                .fill_null_with_values(fill)?
+                .into_series()
+                .cast(&dtype)
+                .context(format!("Error converting imputed {} column back to {}", spec.name, dtype))?;
+            df.with_column(filled).context(format!("Error replacing {} column", spec.name))?;
+        }
+
+        let nulls = df.column(&spec.name)?.null_count();
+        if nulls > 0 && !spec.nullable {
+            bail!("Column {} is not nullable but has {} missing values; declare it nullable or add an imputation", spec.name, nulls);
+        }
+    }
+
     Ok(df)
 }
 
+/// Describes the columns of which cleaning and imputation filled in more than `max_rate` of the values.
+///
+/// # Arguments
+///
+/// * `before` - The DataFrame before transformation.
+/// * `after` - The DataFrame after transformation and [`apply_schema`], with the same rows.
+/// * `max_rate` - The share of a column's values that may be filled in without a warning.
+///
+/// # Returns
+///
+/// * `Vec<String>` - One description per column above the threshold.
+///
+/// # Example
+///
+/// ```
+/// for warning in fill_warnings(&df, &transformed_df, config.transform.max_filled_rate) {
+///     run.add_warning("transform", warning);
+/// }
+/// ```
+pub fn fill_warnings(before: &DataFrame, after: &DataFrame, max_rate: f64) -> Vec<String> {
+    let height = before.height();
+    if height == 0 {
+        return vec![];
+    }
+    before
+        .get_columns()
+        .iter()
+        .filter_map(|series| {
+            let filled = series.null_count().saturating_sub(after.column(series.name()).ok()?.null_count());
+            let rate = filled as f64 / height as f64;
+            (rate > max_rate).then(|| {
+                format!("Filled in {} of {} values of column {} ({:.1}%, above {:.1}%)", filled, height, series.name(), rate * 100.0, max_rate * 100.0)
+            })
+        })
+        .collect()
+}
+
 /// Cleans the data by replacing missing values with the median value of each column.
 ///
 /// # Arguments
@@ -43,14 +222,16 @@ pub fn transform_data(df: DataFrame) -> Result<DataFrame> {
 ///
 /// * `Result<DataFrame>` - A result containing the cleaned DataFrame if successful, or an error if the cleaning fails.
 fn clean_data(df: DataFrame) -> Result<DataFrame> {
-    let median_fixed_acidity = median_value(&df, "fixed acidity")?;
-    let median_volatile_acidity = median_value(&df, "volatile acidity")?;
-    // Repeat for other columns...
+    let medians = CleaningMedians::compute(&df)?;
+    clean_with(df, &medians)
+}
 
+/// Helper function to replace missing values with the given medians.
+fn clean_with(df: DataFrame, medians: &CleaningMedians) -> Result<DataFrame> {
     let df = df
         .lazy()
-        .with_column(col("fixed acidity").fill_null(lit(median_fixed_acidity)))
-        .with_column(col("volatile acidity").fill_null(lit(median_volatile_acidity)))
+        .with_column(col("fixed acidity").fill_null(lit(medians.fixed_acidity)))
+        .with_column(col("volatile acidity").fill_null(lit(medians.volatile_acidity)))
         // Repeat for other columns...
         .collect()
         .context("Error collecting DataFrame after cleaning")?;
@@ -125,12 +306,13 @@ mod tests {
     #[test]
     fn test_transform_data() {
         let df = create_test_dataframe();
-        let result = transform_data(df);
+        let width = df.width();
+        let result = transform_data(df, &[]);
         assert!(result.is_ok());
 
         let transformed_df = result.unwrap();
         assert_eq!(transformed_df.height(), 3); // Should remain 3 rows
-        assert_eq!(transformed_df.width(), 12); // 12 columns after transformation
+        assert_eq!(transformed_df.width(), width); // Transformation keeps every column
     }
 
     #[test]
@@ -149,5 +331,56 @@ mod tests {
 
         // Add more assertions for other columns if needed
     }
-}
 
+    #[test]
+    fn test_passthrough_columns_keep_their_values() {
+        let df = df!(
+            "fixed acidity" => &vec![Some(7.4), None, Some(7.5)],
+            "volatile acidity" => &vec![Some(0.7), None, Some(0.76)]
+        )
+        .unwrap();
+
+        let transformed_df = transform_data(df.clone(), &["fixed acidity".to_string(), "sample_id".to_string()]).unwrap();
+        assert!(transformed_df.column("fixed acidity").unwrap().equals_missing(df.column("fixed acidity").unwrap()));
+        assert_eq!(transformed_df.column("volatile acidity").unwrap().null_count(), 0);
+
+        let dropped = pass_through(df.clone(), &["volatile acidity".to_string()], "cleaning", |df| Ok(df.drop("volatile acidity")?));
+        assert_eq!(dropped.unwrap().get_column_names(), vec!["fixed acidity", "volatile acidity"]);
+        assert!(pass_through(df, &["volatile acidity".to_string()], "validation", |df| Ok(df.head(Some(1)))).is_err());
+    }
+
+    #[test]
+    fn test_apply_schema() {
+        let df = df!(
+            "free sulfur dioxide" => &vec![Some(10i64), None, Some(13)],
+            "residual sugar" => &vec![Some(1.9), None, Some(2.3)]
+        )
+        .unwrap();
+        let mut schema = TableSchema::default();
+        schema.columns.retain(|c| c.name == "free sulfur dioxide" || c.name == "residual sugar");
+        // Ordered as in the default schema
+        schema.columns[1].nullable = true;
+
+        // Not nullable and no imputation
+        assert!(apply_schema(df.clone(), &schema).is_err());
+
+        schema.columns[0].impute = Some(Imputation::Mean);
+        let applied = apply_schema(df, &schema).expect("Applying the schema failed");
+        assert_eq!(applied.column("free sulfur dioxide").unwrap().null_count(), 1);
+        let sugar: Vec<Option<f64>> = applied.column("residual sugar").unwrap().f64().unwrap().into_iter().collect();
+        assert!((sugar[1].unwrap() - 2.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fill_warnings_report_columns_above_threshold() {
+        let df = df!(
+            "fixed acidity" => &vec![Some(7.4), None, Some(7.5), Some(7.8)],
+            "volatile acidity" => &vec![Some(0.7), Some(0.88), Some(0.76), Some(0.28)]
+        )
+        .unwrap();
+
+        let transformed_df = transform_data(df.clone(), &[]).unwrap();
+        assert_eq!(fill_warnings(&df, &transformed_df, 0.05), vec!["Filled in 1 of 4 values of column fixed acidity (25.0%, above 5.0%)"]);
+        assert!(fill_warnings(&df, &transformed_df, 0.25).is_empty());
+    }
+}
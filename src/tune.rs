@@ -0,0 +1,186 @@
+//! This module implements `pipeline tune`.
+//!
+//! The best insert batch size and concurrency depend on the database server, the network between it
+//! and the pipeline, and the width of the table, so they are measured rather than guessed. The tuner
+//! creates a scratch copy of the configured table, loads synthetic rows shaped like the schema with
+//! every combination of candidate settings, and recommends the fastest one. With `--write`, the
+//! recommendation is written into the `[storage]` section of the configuration file, keeping the rest
+//! of the file, comments included, as it was.
+
+use crate::config::PipelineConfig;
+use crate::schema::TableSchema;
+use crate::storage::{self, InsertOptions};
+use crate::typemap::TypeRegistry;
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use std::path::Path;
+use std::time::Instant;
+use toml_edit::DocumentMut;
+
+/// Scratch table the trials load; dropped when tuning ends.
+const TUNE_TABLE: &str = "wine_quality_tune";
+
+/// Candidate values of `storage.batch_rows`.
+const BATCH_ROWS: [usize; 6] = [100, 250, 500, 1_000, 2_500, 5_000];
+
+/// Outcome of loading the synthetic rows with one combination of settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    pub batch_rows: usize,
+    pub concurrency: usize,
+    pub rows_per_second: f64,
+}
+
+/// Benchmarks the insert settings against the configured database and prints the recommendation.
+///
+/// # Arguments
+///
+/// * `config` - The pipeline configuration; its schema and type mappings shape the synthetic rows.
+/// * `config_path` - The configuration file the recommendation is written to.
+/// * `rows` - Number of synthetic rows loaded by each trial.
+/// * `write` - Whether to write the recommendation into the configuration file.
+///
+/// # Returns
+///
+/// * `Result<Trial>` - The fastest trial, or an error if a trial fails.
+///
+/// # Example
+///
+/// ```
+/// let best = run_tune(&config, "pipeline.toml", 20_000, true).await?;
+/// ```
+pub async fn run_tune(config: &PipelineConfig, config_path: &str, rows: usize, write: bool) -> Result<Trial> {
+    let df = synthetic_frame(&config.schema, rows)?;
+    let registry = TypeRegistry::with_overrides(&config.storage.column_types);
+    let pool = storage::create_connection_pool().await?;
+
+    sqlx::query(&format!("DROP TABLE IF EXISTS {}", TUNE_TABLE)).execute(&pool).await?;
+    sqlx::query(&config.schema.create_table_sql(TUNE_TABLE))
+        .execute(&pool)
+        .await
+        .context(format!("Failed to create the {} table", TUNE_TABLE))?;
+
+    let mut trials = vec![];
+    for batch_rows in BATCH_ROWS {
+        for concurrency in 1..=storage::POOL_SIZE {
+            sqlx::query(&format!("TRUNCATE {}", TUNE_TABLE)).execute(&pool).await?;
+
+            let options = InsertOptions { table: TUNE_TABLE, batch_rows, concurrency, partitioning: None, resumable: None, retry: &config.storage.retry, load_id: None, offsets: None, on_numeric_overflow: None };
+            let started = Instant::now();
+            let rejects = storage::store_data(&pool, &df, &config.schema, &registry, None, options).await?;
+            let elapsed = started.elapsed().as_secs_f64();
+            if let Some(reject) = rejects.first() {
+                bail!("The database rejected {} synthetic rows, e.g.: {}", rejects.len(), reject.error);
+            }
+
+            let trial = Trial { batch_rows, concurrency, rows_per_second: rows as f64 / elapsed.max(f64::EPSILON) };
+            println!("batch_rows = {:>5}, concurrency = {}: {:>10.0} rows/s", trial.batch_rows, trial.concurrency, trial.rows_per_second);
+            trials.push(trial);
+        }
+    }
+
+    sqlx::query(&format!("DROP TABLE IF EXISTS {}", TUNE_TABLE)).execute(&pool).await?;
+
+    let best = recommend(&trials).context("No trial was run")?;
+    println!(
+        "Recommended: batch_rows = {}, concurrency = {} ({:.0} rows/s)",
+        best.batch_rows, best.concurrency, best.rows_per_second
+    );
+    if write {
+        write_settings(config_path, &best)?;
+        println!("Wrote the recommended settings to {}", config_path);
+    }
+    Ok(best)
+}
+
+/// Helper function to pick the fastest trial; among trials within 5% of it, the one with the fewest
+/// connections and then the smallest batches, as its advantage is within the noise of a measurement.
+fn recommend(trials: &[Trial]) -> Option<Trial> {
+    let fastest = trials.iter().map(|t| t.rows_per_second).fold(f64::NAN, f64::max);
+    trials
+        .iter()
+        .filter(|t| t.rows_per_second >= fastest * 0.95)
+        .min_by_key(|t| (t.concurrency, t.batch_rows))
+        .cloned()
+}
+
+/// Helper function to build synthetic rows for the required columns of the schema.
+///
+/// Numeric values stay below 1, so they fit every `DECIMAL(p, s)` declaration.
+fn synthetic_frame(schema: &TableSchema, rows: usize) -> Result<DataFrame> {
+    let mut columns = vec![];
+    for spec in schema.columns.iter().filter(|c| !c.nullable) {
+        let pg_type = spec.pg_type.to_ascii_uppercase();
+        let series = if pg_type.contains("INT") {
+            Series::new(&spec.name, (0..rows).map(|i| (i % 100) as i64).collect::<Vec<_>>())
+        } else if ["DECIMAL", "NUMERIC", "DOUBLE", "REAL", "FLOAT"].iter().any(|t| pg_type.starts_with(t)) {
+            Series::new(&spec.name, (0..rows).map(|i| (i % 997) as f64 / 1000.0).collect::<Vec<_>>())
+        } else if pg_type.starts_with("TEXT") || pg_type.starts_with("VARCHAR") {
+            Series::new(&spec.name, (0..rows).map(|i| format!("value {}", i % 100)).collect::<Vec<_>>())
+        } else if pg_type.starts_with("BOOL") {
+            Series::new(&spec.name, (0..rows).map(|i| i % 2 == 0).collect::<Vec<_>>())
+        } else {
+            bail!("Cannot generate synthetic values for column {} of type {}", spec.name, spec.pg_type);
+        };
+        columns.push(series);
+    }
+    DataFrame::new(columns).context("Failed to build the synthetic rows")
+}
+
+/// Helper function to write the settings of a trial into the `[storage]` section of a configuration file.
+fn write_settings(path: &str, trial: &Trial) -> Result<()> {
+    let contents = if Path::new(path).exists() {
+        std::fs::read_to_string(path).context(format!("Failed to read configuration file {}", path))?
+    } else {
+        String::new()
+    };
+    let mut document: DocumentMut = contents.parse().context(format!("Failed to parse configuration file {}", path))?;
+
+    if !document.contains_key("storage") {
+        document["storage"] = toml_edit::table();
+    }
+    // A file with only `[storage.column_types]` has an implicit `[storage]` table, which needs a header now
+    if let Some(storage) = document["storage"].as_table_mut() {
+        storage.set_implicit(false);
+    }
+    document["storage"]["batch_rows"] = toml_edit::value(trial.batch_rows as i64);
+    document["storage"]["concurrency"] = toml_edit::value(trial.concurrency as i64);
+
+    std::fs::write(path, document.to_string()).context(format!("Failed to write configuration file {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommend_prefers_fewer_connections_within_noise() {
+        let trials = vec![
+            Trial { batch_rows: 500, concurrency: 1, rows_per_second: 9_000.0 },
+            Trial { batch_rows: 1_000, concurrency: 2, rows_per_second: 19_500.0 },
+            Trial { batch_rows: 1_000, concurrency: 4, rows_per_second: 20_000.0 },
+        ];
+
+        let best = recommend(&trials).unwrap();
+
+        assert_eq!((best.batch_rows, best.concurrency), (1_000, 2));
+        assert!(recommend(&[]).is_none());
+    }
+
+    #[test]
+    fn test_write_settings_keeps_the_rest_of_the_file() {
+        let path = "temp_tune_test.toml";
+        std::fs::write(path, "# Nightly load\n[storage]\nbatch_rows = 1000 # tuned\n\n[model]\ntrain = true\n").unwrap();
+
+        write_settings(path, &Trial { batch_rows: 2_500, concurrency: 3, rows_per_second: 1.0 }).unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).ok();
+
+        assert!(written.starts_with("# Nightly load\n"));
+        assert!(written.contains("batch_rows = 2500"));
+        assert!(written.contains("concurrency = 3"));
+        assert!(written.contains("[model]\ntrain = true"));
+        let config: PipelineConfig = toml::from_str(&written).unwrap();
+        assert_eq!((config.storage.batch_rows, config.storage.concurrency), (2_500, 3));
+    }
+}
@@ -0,0 +1,262 @@
+//! This module maps Polars data types to PostgreSQL types and bind strategies.
+//!
+//! The registry decides, per DataFrame column, which PostgreSQL type a value is cast to and which Rust
+//! type it is bound as. Defaults cover the numeric, string, boolean, and temporal dtypes; the
+//! `[storage.column_types]` section of the configuration overrides them per column.
+
+use crate::audit;
+use anyhow::{anyhow, bail, Context, Result};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+use serde::Deserialize;
+use sqlx::postgres::{PgArguments, Postgres};
+use sqlx::query::Query;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The Rust type a value is bound as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BindStrategy {
+    Float4,
+    Float8,
+    Int2,
+    Int4,
+    Int8,
+    /// Bound as an exact decimal, so `NUMERIC` columns receive the value as written rather than its binary approximation.
+    Numeric,
+    Text,
+    Bool,
+    Date,
+    Timestamp,
+}
+
+/// How a column is written to PostgreSQL.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TypeMapping {
+    /// The PostgreSQL type the bound parameter is cast to, e.g. `DOUBLE PRECISION` or `NUMERIC(4, 2)`.
+    pub pg_type: String,
+    pub bind: BindStrategy,
+}
+
+impl TypeMapping {
+    fn new(pg_type: &str, bind: BindStrategy) -> Self {
+        Self {
+            pg_type: pg_type.to_string(),
+            bind,
+        }
+    }
+
+    /// Converts a Polars value into an owned value ready to be bound.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value read from the DataFrame.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<PgValue>` - The converted value, or an error if the value cannot be represented with the bind strategy.
+    pub fn convert(&self, value: AnyValue) -> Result<PgValue> {
+        if matches!(value, AnyValue::Null) {
+            return Ok(PgValue::Null(self.bind));
+        }
+
+        let converted = match self.bind {
+            BindStrategy::Float4 => value.extract::<f64>().map(|v| PgValue::Float4(v as f32)),
+            // Through its decimal form, so a downcast f32 is stored as written rather than widened
+            BindStrategy::Float8 => match value {
+                AnyValue::Float32(v) => v.to_string().parse().ok(),
+                _ => value.extract::<f64>(),
+            }
+            .map(PgValue::Float8),
+            BindStrategy::Int2 => integral(&value).and_then(|v| i16::try_from(v).ok()).map(PgValue::Int2),
+            BindStrategy::Int4 => integral(&value).and_then(|v| i32::try_from(v).ok()).map(PgValue::Int4),
+            BindStrategy::Int8 => integral(&value).map(PgValue::Int8),
+            BindStrategy::Numeric => match &value {
+                AnyValue::String(s) => BigDecimal::from_str(s).ok(),
+                _ => value.extract::<f64>().and_then(|v| BigDecimal::from_str(&v.to_string()).ok()),
+            }
+            .map(PgValue::Numeric),
+            BindStrategy::Text => Some(PgValue::Text(match &value {
+                AnyValue::String(s) => s.to_string(),
+                other => other.to_string(),
+            })),
+            BindStrategy::Bool => match value {
+                AnyValue::Boolean(b) => Some(PgValue::Bool(b)),
+                _ => None,
+            },
+            BindStrategy::Date => match value {
+                AnyValue::Date(days) => NaiveDate::from_num_days_from_ce_opt(days + UNIX_EPOCH_DAYS_FROM_CE).map(PgValue::Date),
+                AnyValue::Datetime(v, unit, _) => datetime(v, unit).map(|dt| PgValue::Date(dt.date())),
+                _ => None,
+            },
+            BindStrategy::Timestamp => match value {
+                AnyValue::Datetime(v, unit, _) => datetime(v, unit).map(PgValue::Timestamp),
+                AnyValue::Date(days) => NaiveDate::from_num_days_from_ce_opt(days + UNIX_EPOCH_DAYS_FROM_CE)
+                    .and_then(|d| d.and_hms_opt(0, 0, 0))
+                    .map(PgValue::Timestamp),
+                _ => None,
+            },
+        };
+
+        converted.ok_or_else(|| anyhow!("Cannot bind {} as {:?}", value, self.bind))
+    }
+}
+
+/// Days between 0001-01-01 and 1970-01-01, the epoch of Polars dates.
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+/// Helper function to read an integer, accepting floats only when they have no fractional part.
+fn integral(value: &AnyValue) -> Option<i64> {
+    if matches!(value, AnyValue::Float32(_) | AnyValue::Float64(_)) {
+        return value.extract::<f64>().filter(|v| v.fract() == 0.0).map(|v| v as i64);
+    }
+    value.extract::<i64>()
+}
+
+fn datetime(value: i64, unit: TimeUnit) -> Option<NaiveDateTime> {
+    let datetime = match unit {
+        TimeUnit::Nanoseconds => Some(DateTime::from_timestamp_nanos(value)),
+        TimeUnit::Microseconds => DateTime::from_timestamp_micros(value),
+        TimeUnit::Milliseconds => DateTime::from_timestamp_millis(value),
+    };
+    datetime.map(|dt| dt.naive_utc())
+}
+
+/// An owned value to bind to a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PgValue {
+    /// A null, carrying the strategy so the parameter keeps its type.
+    Null(BindStrategy),
+    Float4(f32),
+    Float8(f64),
+    Int2(i16),
+    Int4(i32),
+    Int8(i64),
+    Numeric(BigDecimal),
+    Text(String),
+    Bool(bool),
+    Date(NaiveDate),
+    Timestamp(NaiveDateTime),
+}
+
+impl PgValue {
+    /// Binds the value as the next parameter of a query.
+    pub fn bind<'q>(self, query: Query<'q, Postgres, PgArguments>) -> Query<'q, Postgres, PgArguments> {
+        match self {
+            PgValue::Null(strategy) => match strategy {
+                BindStrategy::Float4 => query.bind(None::<f32>),
+                BindStrategy::Float8 => query.bind(None::<f64>),
+                BindStrategy::Int2 => query.bind(None::<i16>),
+                BindStrategy::Int4 => query.bind(None::<i32>),
+                BindStrategy::Int8 => query.bind(None::<i64>),
+                BindStrategy::Numeric => query.bind(None::<BigDecimal>),
+                BindStrategy::Text => query.bind(None::<String>),
+                BindStrategy::Bool => query.bind(None::<bool>),
+                BindStrategy::Date => query.bind(None::<NaiveDate>),
+                BindStrategy::Timestamp => query.bind(None::<NaiveDateTime>),
+            },
+            PgValue::Float4(v) => query.bind(v),
+            PgValue::Float8(v) => query.bind(v),
+            PgValue::Int2(v) => query.bind(v),
+            PgValue::Int4(v) => query.bind(v),
+            PgValue::Int8(v) => query.bind(v),
+            PgValue::Numeric(v) => query.bind(v),
+            PgValue::Text(v) => query.bind(v),
+            PgValue::Bool(v) => query.bind(v),
+            PgValue::Date(v) => query.bind(v),
+            PgValue::Timestamp(v) => query.bind(v),
+        }
+    }
+}
+
+/// Resolves the type mapping of each column: a per-column override if configured, otherwise the default for its dtype.
+#[derive(Debug, Clone, Default)]
+pub struct TypeRegistry {
+    overrides: HashMap<String, TypeMapping>,
+}
+
+impl TypeRegistry {
+    /// Creates a registry with per-column overrides, keyed by DataFrame column name.
+    pub fn with_overrides(overrides: &HashMap<String, TypeMapping>) -> Self {
+        Self { overrides: overrides.clone() }
+    }
+
+    /// Returns the mapping for a column.
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The DataFrame column name.
+    /// * `dtype` - The Polars data type of the column.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<TypeMapping>` - The mapping, or an error if the dtype has no default and no override is configured.
+    pub fn mapping(&self, column: &str, dtype: &DataType) -> Result<TypeMapping> {
+        if let Some(mapping) = self.overrides.get(column) {
+            return Ok(mapping.clone());
+        }
+        // Labels are serialized as a JSON string, but stored as JSONB so they can be queried
+        if column == audit::LABELS_COLUMN {
+            return Ok(TypeMapping::new("JSONB", BindStrategy::Text));
+        }
+        default_mapping(dtype).context(format!(
+            "No PostgreSQL type mapping for column {} of type {}; add one under [storage.column_types]",
+            column, dtype
+        ))
+    }
+}
+
+/// Returns the default mapping of a Polars data type.
+pub fn default_mapping(dtype: &DataType) -> Result<TypeMapping> {
+    let mapping = match dtype {
+        DataType::Float32 => TypeMapping::new("REAL", BindStrategy::Float4),
+        DataType::Float64 => TypeMapping::new("DOUBLE PRECISION", BindStrategy::Float8),
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 => TypeMapping::new("SMALLINT", BindStrategy::Int2),
+        DataType::Int32 | DataType::UInt16 => TypeMapping::new("INTEGER", BindStrategy::Int4),
+        DataType::Int64 | DataType::UInt32 | DataType::UInt64 => TypeMapping::new("BIGINT", BindStrategy::Int8),
+        DataType::Boolean => TypeMapping::new("BOOLEAN", BindStrategy::Bool),
+        DataType::String => TypeMapping::new("TEXT", BindStrategy::Text),
+        DataType::Date => TypeMapping::new("DATE", BindStrategy::Date),
+        DataType::Datetime(_, _) => TypeMapping::new("TIMESTAMP", BindStrategy::Timestamp),
+        other => bail!("Unsupported data type {}", other),
+    };
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_prefers_overrides() {
+        let overrides = HashMap::from([("alcohol".to_string(), TypeMapping::new("NUMERIC(4, 1)", BindStrategy::Numeric))]);
+        let registry = TypeRegistry::with_overrides(&overrides);
+
+        assert_eq!(registry.mapping("alcohol", &DataType::Float64).unwrap().bind, BindStrategy::Numeric);
+        assert_eq!(registry.mapping("pH", &DataType::Float64).unwrap().pg_type, "DOUBLE PRECISION");
+        assert_eq!(registry.mapping("quality", &DataType::Int64).unwrap().bind, BindStrategy::Int8);
+        assert!(registry.mapping("raw", &DataType::Binary).is_err());
+        assert_eq!(registry.mapping("labels", &DataType::String).unwrap().pg_type, "JSONB");
+    }
+
+    #[test]
+    fn test_convert_values() {
+        let int4 = TypeMapping::new("INTEGER", BindStrategy::Int4);
+        assert_eq!(int4.convert(AnyValue::Float64(34.0)).unwrap(), PgValue::Int4(34));
+        assert!(int4.convert(AnyValue::Float64(34.5)).is_err());
+        assert!(int4.convert(AnyValue::Int64(i64::MAX)).is_err());
+        assert_eq!(int4.convert(AnyValue::Null).unwrap(), PgValue::Null(BindStrategy::Int4));
+
+        let numeric = TypeMapping::new("NUMERIC(4, 2)", BindStrategy::Numeric);
+        assert_eq!(numeric.convert(AnyValue::Float64(7.4)).unwrap(), PgValue::Numeric(BigDecimal::from_str("7.4").unwrap()));
+
+        let float8 = TypeMapping::new("DOUBLE PRECISION", BindStrategy::Float8);
+        assert_eq!(float8.convert(AnyValue::Float32(7.4)).unwrap(), PgValue::Float8(7.4));
+
+        let date = TypeMapping::new("DATE", BindStrategy::Date);
+        assert_eq!(date.convert(AnyValue::Date(0)).unwrap(), PgValue::Date(NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()));
+    }
+}
@@ -0,0 +1,211 @@
+//! This module serves the web UI of daemon mode, a page showing the history of runs.
+//!
+//! With `[daemon.api] ui = true`, the control API serves a page at `/ui` listing the latest attempts
+//! of runs with their outcome, row count, and warnings, so checking last night's load takes a browser
+//! rather than database access. Selecting a run shows its quality report, the expectation results and
+//! column statistics it recorded, and a sample of the rows the database rejected, read from its
+//! `rejects` artifact. The page, its script, and its stylesheet are compiled into the binary. The page
+//! asks for an API key once per browser session and reads the endpoints below with it: the run history
+//! and reports require `read_status`, and the rejected rows, which hold data, require `read_records`.
+
+use crate::api::{authorize, ApiState, Permission};
+use crate::artifacts::ArtifactStore;
+use crate::encryption::ColumnCipher;
+use anyhow::{Context, Result};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+
+const INDEX_HTML: &str = include_str!("../ui/index.html");
+const APP_JS: &str = include_str!("../ui/app.js");
+const STYLE_CSS: &str = include_str!("../ui/style.css");
+
+/// Number of runs and rejected rows returned when the request sets no limit.
+const DEFAULT_LIMIT: i64 = 50;
+
+/// An attempt of a run, as recorded in `pipeline_runs`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunAttempt {
+    pub run_id: String,
+    pub attempt: i32,
+    pub status: String,
+    pub error: Option<String>,
+    pub row_count: Option<i64>,
+    pub started_at: String,
+    pub finished_at: String,
+    /// The warnings of the attempt, each with its `stage` and `message`.
+    pub warnings: serde_json::Value,
+}
+
+/// The quality report of a run: its expectation results and column statistics.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    pub run_id: String,
+    pub expectations: Vec<ExpectationOutcome>,
+    pub columns: Vec<ColumnSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct ExpectationOutcome {
+    pub expectation: String,
+    pub severity: String,
+    pub passed: bool,
+    pub observed: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct ColumnSummary {
+    pub column: String,
+    pub rows: i64,
+    pub null_rate: f64,
+    pub mean: Option<f64>,
+    pub std: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// The first rejected rows of a run, with the number rejected in all.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RejectSample {
+    pub total: usize,
+    pub rows: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Limit {
+    limit: Option<i64>,
+}
+
+/// Builds the routes of the page and of the endpoints it reads, merged into the control API.
+pub fn router() -> Router<ApiState> {
+    Router::new()
+        .route("/ui", get(|| async { Html(INDEX_HTML) }))
+        .route("/ui/app.js", get(|| async { ([(header::CONTENT_TYPE, "text/javascript; charset=utf-8")], APP_JS) }))
+        .route("/ui/style.css", get(|| async { ([(header::CONTENT_TYPE, "text/css; charset=utf-8")], STYLE_CSS) }))
+        .route("/runs/history", get(run_history))
+        .route("/runs/:run_id/report", get(run_report))
+        .route("/runs/:run_id/rejects", get(run_rejects))
+}
+
+/// Reads the latest attempts of runs, newest first.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `limit` - The maximum number of attempts returned.
+///
+/// # Returns
+///
+/// * `Result<Vec<RunAttempt>>` - The attempts, or an error if the query fails.
+///
+/// # Example
+///
+/// ```
+/// let runs = fetch_run_history(&pool, 50).await?;
+/// ```
+pub async fn fetch_run_history(pool: &PgPool, limit: i64) -> Result<Vec<RunAttempt>> {
+    let rows: Vec<(String, i32, String, Option<String>, Option<i64>, String, String, String)> = sqlx::query_as(
+        "SELECT run_id, attempt, status, error, row_count, started_at::TEXT, finished_at::TEXT, warnings::TEXT FROM pipeline_runs ORDER BY started_at DESC, attempt DESC LIMIT $1",
+    )
+    .bind(limit.max(0))
+    .fetch_all(pool)
+    .await
+    .context("Failed to read the run history")?;
+    rows.into_iter()
+        .map(|(run_id, attempt, status, error, row_count, started_at, finished_at, warnings)| {
+            let warnings = serde_json::from_str(&warnings).context(format!("The warnings of run {} are not valid JSON", run_id))?;
+            Ok(RunAttempt { run_id, attempt, status, error, row_count, started_at, finished_at, warnings })
+        })
+        .collect()
+}
+
+/// Reads the quality report of a run.
+///
+/// # Arguments
+///
+/// * `pool` - A reference to the PostgreSQL connection pool.
+/// * `run_id` - The ID of the run.
+///
+/// # Returns
+///
+/// * `Result<RunReport>` - The report, empty if the run recorded no results, or an error if a query fails.
+pub async fn fetch_run_report(pool: &PgPool, run_id: &str) -> Result<RunReport> {
+    let expectations = sqlx::query_as("SELECT expectation, severity, passed, observed FROM expectation_results WHERE run_id = $1 ORDER BY id")
+        .bind(run_id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to read the expectation results")?;
+    let columns = sqlx::query_as("SELECT column_name AS \"column\", row_count AS rows, null_rate, mean, std, min, max FROM column_stats WHERE run_id = $1 ORDER BY id")
+        .bind(run_id)
+        .fetch_all(pool)
+        .await
+        .context("Failed to read the column statistics")?;
+    Ok(RunReport { run_id: run_id.to_string(), expectations, columns })
+}
+
+/// Helper function to render the first rows of a frame as JSON objects keyed by column.
+fn sample_rows(df: &DataFrame, limit: usize) -> Result<RejectSample> {
+    let mut head = df.head(Some(limit));
+    let mut buffer = Vec::new();
+    JsonWriter::new(&mut buffer).with_json_format(JsonFormat::Json).finish(&mut head).context("Failed to encode the rejected rows")?;
+    let rows = serde_json::from_slice(&buffer).context("Failed to encode the rejected rows")?;
+    Ok(RejectSample { total: df.height(), rows })
+}
+
+/// Helper function to log an error and answer with a status.
+fn failure(status: StatusCode) -> impl FnOnce(anyhow::Error) -> StatusCode {
+    move |e| {
+        eprintln!("{:#}", e);
+        status
+    }
+}
+
+async fn run_history(State(state): State<ApiState>, Query(limit): Query<Limit>, headers: HeaderMap) -> Result<Json<Vec<RunAttempt>>, StatusCode> {
+    authorize(&state.keys, &headers, Permission::ReadStatus)?;
+    let runs = fetch_run_history(&state.pool, limit.limit.unwrap_or(DEFAULT_LIMIT)).await.map_err(failure(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(Json(runs))
+}
+
+async fn run_report(State(state): State<ApiState>, Path(run_id): Path<String>, headers: HeaderMap) -> Result<Json<RunReport>, StatusCode> {
+    authorize(&state.keys, &headers, Permission::ReadStatus)?;
+    let report = fetch_run_report(&state.pool, &run_id).await.map_err(failure(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(Json(report))
+}
+
+async fn run_rejects(State(state): State<ApiState>, Path(run_id): Path<String>, Query(limit): Query<Limit>, headers: HeaderMap) -> Result<Json<RejectSample>, StatusCode> {
+    authorize(&state.keys, &headers, Permission::ReadRecords)?;
+    // Without persisted rejects there is nothing to read, as for a run that rejected no rows
+    let store = ArtifactStore::from_config(&state.config.artifacts).map_err(failure(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let Some(store) = store.filter(|s| s.wants("rejects")) else { return Err(StatusCode::NOT_FOUND) };
+    let rejected = store.get(&run_id, "rejects").await.map_err(|e| match e.downcast_ref::<object_store::Error>() {
+        Some(object_store::Error::NotFound { .. }) => StatusCode::NOT_FOUND,
+        _ => failure(StatusCode::INTERNAL_SERVER_ERROR)(e),
+    })?;
+    // Callers entitled to read records read the encrypted columns in plaintext
+    let cipher = ColumnCipher::from_config(&state.config.storage.encryption).map_err(failure(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let rejected = match &cipher {
+        Some(cipher) => cipher.decrypt_frame(&rejected).map_err(failure(StatusCode::INTERNAL_SERVER_ERROR))?,
+        None => rejected,
+    };
+    let sample = sample_rows(&rejected, limit.limit.unwrap_or(DEFAULT_LIMIT).max(0) as usize).map_err(failure(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(Json(sample))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rows_are_json_objects() {
+        let df = polars::df!("alcohol" => &[9.4, 15.2, 10.0], "error" => &["too strong", "too strong", "out of range"]).unwrap();
+        let sample = sample_rows(&df, 2).unwrap();
+        assert_eq!(sample.total, 3);
+        assert_eq!(sample.rows, vec![serde_json::json!({"alcohol": 9.4, "error": "too strong"}), serde_json::json!({"alcohol": 15.2, "error": "too strong"})]);
+        assert!(INDEX_HTML.contains("/ui/app.js") && APP_JS.contains("/runs/history"));
+    }
+}
@@ -0,0 +1,402 @@
+//! This module validates a parsed configuration before any stage runs.
+//!
+//! Parsing already rejects unknown keys, wrong types, and malformed values, reporting the line and
+//! column of the offending TOML. This module adds the checks parsing cannot do: every column named in
+//! a rule or stage setting must be a column of the schema (or one a stage derives), and numeric
+//! settings must be in range. Each issue names the key it was found at, e.g.
+//! `expectations.suite[1].column`, and suggests the closest known column for likely typos.
+
+use crate::analysis::HypothesisTest;
+use crate::config::PipelineConfig;
+use crate::dataset;
+use crate::expectations::Expectation;
+use crate::kafka::RecordFormat;
+use crate::locale;
+use crate::source::SourceConfig;
+use crate::staging;
+use crate::storage;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+/// A problem found in the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    /// The key the problem was found at.
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Checks the column references and numeric settings of a configuration.
+///
+/// # Arguments
+///
+/// * `config` - The parsed configuration.
+///
+/// # Returns
+///
+/// * `Vec<ConfigIssue>` - The problems found; empty if the configuration is valid.
+pub fn validate(config: &PipelineConfig) -> Vec<ConfigIssue> {
+    let mut issues = vec![];
+    let mut known: BTreeSet<String> = BTreeSet::new();
+    let mut targets: BTreeSet<&str> = BTreeSet::new();
+
+    for (i, column) in config.schema.columns.iter().enumerate() {
+        if !known.insert(column.name.clone()) {
+            issues.push(issue(format!("schema.columns[{}].name", i), format!("column {} is declared twice", column.name)));
+        }
+        if !targets.insert(&column.column) {
+            issues.push(issue(
+                format!("schema.columns[{}].column", i),
+                format!("{} is mapped to table column {}, which another column already uses", column.name, column.column),
+            ));
+        }
+    }
+    if config.pca.enabled {
+        known.extend((1..=config.pca.components).map(|i| format!("pc{}", i)));
+    }
+
+    let mut check = |location: String, column: &str| {
+        if !known.contains(column) {
+            issues.push(unknown_column(location, column, &known));
+        }
+    };
+
+    for (i, rule) in config.expectations.suite.iter().enumerate() {
+        match &rule.expectation {
+            Expectation::ColumnMeanBetween { column, .. }
+            | Expectation::ColumnValuesBetween { column, .. }
+            | Expectation::ColumnValuesInSet { column, .. }
+            | Expectation::ColumnValuesInTable { column, .. }
+            | Expectation::ColumnValuesNotNull { column } => check(format!("expectations.suite[{}].column", i), column),
+            Expectation::RowCountBetween { .. } => {}
+        }
+    }
+    for (i, test) in config.analysis.tests.iter().enumerate() {
+        let (HypothesisTest::TTest { column, group_by, .. } | HypothesisTest::Anova { column, group_by } | HypothesisTest::ChiSquare { column, group_by }) = test;
+        check(format!("analysis.tests[{}].column", i), column);
+        check(format!("analysis.tests[{}].group_by", i), group_by);
+    }
+    if let Some(source) = config.source.object_source(None) {
+        if source.bucket.is_empty() || source.key.is_empty() {
+            issues.push(issue("source", format!("an {} source needs a bucket (or container) and a key", source.provider.feature())));
+        }
+    }
+    if let SourceConfig::Csv { options, .. } = &config.source {
+        if options.sniff && options.sniff_bytes == 0 {
+            issues.push(issue("source.options.sniff_bytes", "must be at least 1".to_string()));
+        }
+        for name in options.dtypes.keys().filter(|name| !options.columns.is_empty() && !options.columns.contains(name)) {
+            issues.push(issue(format!("source.options.dtypes.{}", name), format!("{} is not one of source.options.columns", name)));
+        }
+        for (i, name) in options.select.iter().enumerate().filter(|(_, name)| !options.columns.is_empty() && !options.columns.contains(name)) {
+            issues.push(issue(format!("source.options.select[{}]", i), format!("{} is not one of source.options.columns", name)));
+        }
+        if !options.select.is_empty() {
+            for column in config.schema.input_columns().filter(|c| !c.nullable && !options.select.contains(&c.name)) {
+                issues.push(issue("source.options.select", format!("leaves out {}, which the schema declares NOT NULL", column.name)));
+            }
+        }
+    }
+    if config.deduplication.incremental {
+        match &config.source {
+            SourceConfig::Csv { options, .. } if options.skip_data_rows > 0 => {
+                issues.push(issue("source.options.skip_data_rows", "would skip the first rows appended since the last run with deduplication.incremental".to_string()));
+            }
+            SourceConfig::Csv { .. } => {}
+            _ => issues.push(issue("deduplication.incremental", "reads only the rows appended to CSV files; use a csv source".to_string())),
+        }
+        if config.deduplication.skip_processed_files {
+            issues.push(issue("deduplication.incremental", "cannot be combined with skip_processed_files, which reads or leaves out whole files".to_string()));
+        }
+    }
+    if let SourceConfig::Postgres { query, .. } = &config.source {
+        if query.trim().is_empty() {
+            issues.push(issue("source.query", "must not be empty".to_string()));
+        }
+    }
+    if let SourceConfig::Kafka { brokers, topic, format, columns, .. } = &config.source {
+        if brokers.is_empty() || topic.is_empty() {
+            issues.push(issue("source", "a kafka source needs brokers and a topic".to_string()));
+        }
+        if *format == RecordFormat::Csv && columns.is_empty() {
+            issues.push(issue("source.columns", "must name the fields of CSV records, which carry no header".to_string()));
+        }
+        if !config.streaming.enabled && config.row_limit.is_none() {
+            issues.push(issue("source", "a kafka topic never runs out of records; enable [streaming] or set a row limit".to_string()));
+        }
+        if config.sampling.rows.is_some() {
+            issues.push(issue("sampling.rows", "draws from the whole input, but a kafka topic never runs out of records; sample a fraction".to_string()));
+        }
+    }
+    if !(0.0..=1.0).contains(&config.transform.max_filled_rate) {
+        issues.push(issue("transform.max_filled_rate", format!("must be between 0 and 1, got {}", config.transform.max_filled_rate)));
+    }
+    for (i, column) in config.transform.passthrough.iter().enumerate() {
+        check(format!("transform.passthrough[{}]", i), column);
+    }
+    for (i, column) in config.visualization.columns.iter().enumerate() {
+        check(format!("visualization.columns[{}]", i), column);
+    }
+    if config.model.train {
+        check("model.target".to_string(), &config.model.target);
+    }
+    for (i, column) in config.model.features.iter().enumerate() {
+        check(format!("model.features[{}]", i), column);
+    }
+    for (section, features, exclude) in [
+        ("pca", &config.pca.features, &config.pca.exclude),
+        ("clustering", &config.clustering.features, &config.clustering.exclude),
+    ] {
+        for (i, column) in features.iter().enumerate() {
+            check(format!("{}.features[{}]", section, i), column);
+        }
+        for (i, column) in exclude.iter().enumerate() {
+            check(format!("{}.exclude[{}]", section, i), column);
+        }
+    }
+    for column in config.rounding.decimals.keys() {
+        check(format!("rounding.decimals.\"{}\"", column), column);
+    }
+    for header in config.column_mapping.rename.keys() {
+        check(format!("column_mapping.rename.\"{}\"", header), header);
+    }
+    for (header, name) in &config.headers.rename {
+        check(format!("headers.rename.\"{}\"", header), name);
+    }
+    for column in config.storage.column_types.keys() {
+        check(format!("storage.column_types.\"{}\"", column), column);
+    }
+    for (i, column) in config.storage.encryption.columns.iter().enumerate() {
+        check(format!("storage.encryption.columns[{}]", i), column);
+    }
+    for (i, column) in config.dataset.partition_by.iter().enumerate() {
+        if column != dataset::LOAD_DATE_PARTITION {
+            check(format!("dataset.partition_by[{}]", i), column);
+        }
+    }
+    if let Some(column) = &config.storage.partitioning.column {
+        check("storage.partitioning.column".to_string(), column);
+        if config.storage.resumable.enabled {
+            issues.push(issue("storage.resumable.enabled", "cannot be combined with storage.partitioning, whose partitions already commit on their own".to_string()));
+        }
+    }
+    if config.storage.resumable.enabled && config.storage.resumable.commit_rows == 0 {
+        issues.push(issue("storage.resumable.commit_rows", "must be at least 1".to_string()));
+    }
+
+    if config.model.train && !(config.model.test_fraction > 0.0 && config.model.test_fraction < 1.0) {
+        issues.push(issue("model.test_fraction", format!("must be between 0 and 1 (exclusive), got {}", config.model.test_fraction)));
+    }
+    if let Some(fraction) = config.expectations.sample_fraction.filter(|f| !(*f > 0.0 && *f <= 1.0)) {
+        issues.push(issue("expectations.sample_fraction", format!("must be greater than 0 and at most 1, got {}", fraction)));
+    }
+    if config.reference_cache.enabled && config.reference_cache.ttl_secs == 0 {
+        issues.push(issue("reference_cache.ttl_secs", "must be at least 1".to_string()));
+    }
+    if let Some(locale) = config.reports.locale.as_deref().filter(|locale| locale::NumberFormat::for_locale(locale).is_none()) {
+        issues.push(issue("reports.locale", format!("unknown locale \"{}\"; use a language code such as en, de, fr, or pt-BR", locale)));
+    }
+    for column in config.reports.units.keys().filter(|column| !config.schema.columns.iter().any(|c| &c.column == *column)) {
+        issues.push(issue(format!("reports.units.{}", column), "is not a column of the stored table".to_string()));
+    }
+    if !(0.0..).contains(&config.performance.regression_threshold) {
+        issues.push(issue("performance.regression_threshold", format!("must be at least 0, got {}", config.performance.regression_threshold)));
+    }
+    for (location, rate) in [
+        ("chaos.db_error_rate", config.chaos.db_error_rate),
+        ("chaos.slow_read_rate", config.chaos.slow_read_rate),
+        ("chaos.malformed_row_rate", config.chaos.malformed_row_rate),
+    ] {
+        if !(0.0..=1.0).contains(&rate) {
+            issues.push(issue(location, format!("must be between 0 and 1, got {}", rate)));
+        }
+    }
+    for (location, enabled) in [("iceberg.enabled", config.iceberg.enabled), ("dataset.enabled", config.dataset.enabled)] {
+        if enabled && config.staging.enabled {
+            issues.push(issue(location, "cannot be combined with staging.enabled; staged rows are not final when stored".to_string()));
+        }
+    }
+    for (location, compression) in [("artifacts.compression", &config.artifacts.compression), ("dataset.compression", &config.dataset.compression), ("retention.compression", &config.retention.compression)] {
+        if let Err(e) = compression.parquet() {
+            issues.push(issue(location, format!("{:#}", e)));
+        }
+    }
+    if config.dataset.enabled && config.dataset.path.trim().is_empty() {
+        issues.push(issue("dataset.path", "must not be empty".to_string()));
+    }
+    for (i, column) in config.dataset.partition_by.iter().enumerate() {
+        if config.storage.encryption.columns.contains(column) {
+            issues.push(issue(format!("dataset.partition_by[{}]", i), format!("{} is encrypted, and its ciphertexts would name the partitions", column)));
+        }
+        if config.dataset.partition_by[..i].contains(column) {
+            issues.push(issue(format!("dataset.partition_by[{}]", i), format!("{} is listed twice", column)));
+        }
+    }
+    for (location, value) in [("iceberg.catalog_uri", &config.iceberg.catalog_uri), ("iceberg.namespace", &config.iceberg.namespace), ("iceberg.table", &config.iceberg.table)] {
+        if config.iceberg.enabled && value.trim().is_empty() {
+            issues.push(issue(location, "must not be empty".to_string()));
+        }
+    }
+    if config.spill.memory_budget_mb == Some(0) {
+        issues.push(issue("spill.memory_budget_mb", "must be at least 1".to_string()));
+    }
+    if config.pca.enabled && config.pca.components == 0 {
+        issues.push(issue("pca.components", "must be at least 1".to_string()));
+    }
+    if config.clustering.enabled && config.clustering.k == 0 {
+        issues.push(issue("clustering.k", "must be at least 1".to_string()));
+    }
+    if config.storage.batch_rows == 0 {
+        issues.push(issue("storage.batch_rows", "must be at least 1".to_string()));
+    }
+    if !(1..=storage::POOL_SIZE).contains(&config.storage.concurrency) {
+        issues.push(issue("storage.concurrency", format!("must be between 1 and {}, got {}", storage::POOL_SIZE, config.storage.concurrency)));
+    }
+    if !is_table_name(&config.storage.table) {
+        issues.push(issue("storage.table", format!("must be a table name of letters, digits, and underscores, got {:?}", config.storage.table)));
+    }
+    if config.storage.table != staging::PRODUCTION_TABLE {
+        for (location, enabled) in [("staging.enabled", config.staging.enabled), ("aggregates.enabled", config.aggregates.enabled)] {
+            if enabled {
+                issues.push(issue(location, format!("reads the {} table, but storage.table stores the rows in {}", staging::PRODUCTION_TABLE, config.storage.table)));
+            }
+        }
+    }
+    if config.resources.monitor && config.resources.interval_ms == 0 {
+        issues.push(issue("resources.interval_ms", "must be at least 1".to_string()));
+    }
+    if let Some(max_rss_mb) = config.resources.max_rss_mb {
+        if !config.resources.monitor {
+            issues.push(issue("resources.max_rss_mb", "needs resources.monitor, which samples the memory it is checked against".to_string()));
+        }
+        if max_rss_mb == 0 {
+            issues.push(issue("resources.max_rss_mb", "must be at least 1".to_string()));
+        }
+    }
+    if config.row_limit == Some(0) {
+        issues.push(issue("row_limit", "must be at least 1".to_string()));
+    }
+    if let Some(fraction) = config.sampling.fraction {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            issues.push(issue("sampling.fraction", format!("must be greater than 0 and at most 1, got {}", fraction)));
+        }
+        if config.sampling.rows.is_some() {
+            issues.push(issue("sampling", "takes a fraction or a number of rows, not both".to_string()));
+        }
+    }
+    if config.sampling.rows == Some(0) {
+        issues.push(issue("sampling.rows", "must be at least 1".to_string()));
+    }
+    if config.streaming.enabled && config.streaming.chunk_rows == 0 {
+        issues.push(issue("streaming.chunk_rows", "must be at least 1".to_string()));
+    }
+    for (location, retry) in [("retry.ingestion", &config.retry.ingestion), ("storage.retry", &config.storage.retry)] {
+        if retry.multiplier < 1.0 {
+            issues.push(issue(format!("{}.multiplier", location), format!("must be at least 1, got {}", retry.multiplier)));
+        }
+        if !(0.0..=1.0).contains(&retry.jitter) {
+            issues.push(issue(format!("{}.jitter", location), format!("must be between 0 and 1, got {}", retry.jitter)));
+        }
+        if retry.max_delay_ms < retry.initial_delay_ms {
+            issues.push(issue(format!("{}.max_delay_ms", location), format!("must be at least initial_delay_ms ({})", retry.initial_delay_ms)));
+        }
+        if retry.max_attempts == 0 {
+            issues.push(issue(format!("{}.max_attempts", location), "must be at least 1".to_string()));
+        }
+    }
+    let supervisor = &config.daemon.supervisor;
+    if supervisor.max_restart_backoff_secs < supervisor.restart_backoff_secs {
+        issues.push(issue("daemon.supervisor.max_restart_backoff_secs", format!("must be at least restart_backoff_secs ({})", supervisor.restart_backoff_secs)));
+    }
+    let landing = &config.daemon.landing;
+    if let Some(dir) = &landing.dir {
+        if config.source.path().is_none() {
+            issues.push(issue("daemon.landing.dir", "landed files are read like the source, which must read a local file".to_string()));
+        }
+        for (location, target) in [("daemon.landing.archive_dir", &landing.archive_dir), ("daemon.landing.failed_dir", &landing.failed_dir)] {
+            if Path::new(target) == Path::new(dir) {
+                issues.push(issue(location, "must differ from the landing directory, or files are loaded again".to_string()));
+            }
+        }
+        if let Err(e) = glob::Pattern::new(&landing.pattern) {
+            issues.push(issue("daemon.landing.pattern", format!("is not a valid glob pattern: {}", e)));
+        }
+    }
+
+    issues
+}
+
+/// Helper function to check that a name is a plain, unquoted PostgreSQL table name.
+fn is_table_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn issue(location: impl Into<String>, message: String) -> ConfigIssue {
+    ConfigIssue { location: location.into(), message }
+}
+
+fn unknown_column(location: String, column: &str, known: &BTreeSet<String>) -> ConfigIssue {
+    let closest = known
+        .iter()
+        .map(|candidate| (edit_distance(column, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2.max(column.len() / 4))
+        .min();
+    let message = match closest {
+        Some((_, candidate)) => format!("unknown column \"{}\" (did you mean \"{}\"?)", column, candidate),
+        None => format!("unknown column \"{}\"; declare it under [[schema.columns]]", column),
+    };
+    issue(location, message)
+}
+
+/// Helper function to compute the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_reports_typoed_columns() {
+        let config: PipelineConfig = toml::from_str(
+            r#"
+            [[expectations.suite]]
+            expect = "column_values_not_null"
+            column = "alcohl"
+
+            [[expectations.suite]]
+            expect = "column_values_not_null"
+            column = "quality"
+
+            [rounding]
+            decimals = { "tasting notes" = 1 }
+            "#,
+        )
+        .unwrap();
+
+        let issues = validate(&config);
+
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].to_string(), "expectations.suite[0].column: unknown column \"alcohl\" (did you mean \"alcohol\"?)");
+        assert_eq!(issues[1].location, "rounding.decimals.\"tasting notes\"");
+        assert!(validate(&PipelineConfig::default()).is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+//! This module renders distribution charts of DataFrame columns.
+//!
+//! It provides functions for drawing histograms and box plots to PNG or SVG files, so value drift
+//! between the raw and transformed data is visible at a glance.
+
+use crate::config::{ChartFormat, VisualizationConfig};
+use crate::run::RunContext;
+use anyhow::{Context, Result};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use polars::prelude::*;
+use std::path::{Path, PathBuf};
+
+const CHART_SIZE: (u32, u32) = (800, 600);
+
+/// Renders the configured distribution charts for one pipeline stage, if visualization is enabled.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to chart.
+/// * `config` - The visualization settings.
+/// * `run` - The context of the current run, used to pick the output directory.
+/// * `stage` - A label for the stage, such as `before` or `after`, included in the file names.
+///
+/// # Returns
+///
+/// * `Result<()>` - A result indicating success or failure of rendering.
+///
+/// # Example
+///
+/// ```
+/// render_stage(&df, &config.visualization, &run, "before").expect("Failed to render charts");
+/// ```
+pub fn render_stage(df: &DataFrame, config: &VisualizationConfig, run: &RunContext, stage: &str) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let out_dir = run.artifact_dir(&config.output_dir);
+    let written = render_distributions(df, config, &out_dir, stage)?;
+    println!("Rendered {} {} charts to {}", written.len(), stage, out_dir.display());
+    Ok(())
+}
+
+/// Renders a histogram and a box plot for each configured column.
+///
+/// # Arguments
+///
+/// * `df` - A reference to the DataFrame to chart.
+/// * `config` - The visualization settings.
+/// * `out_dir` - The directory the charts are written to. It is created if missing.
+/// * `stage` - A label for the stage, included in the file names.
+///
+/// # Returns
+///
+/// * `Result<Vec<PathBuf>>` - The paths of the written chart files, or an error if a column is missing or rendering fails.
+pub fn render_distributions(df: &DataFrame, config: &VisualizationConfig, out_dir: &Path, stage: &str) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(out_dir).context(format!("Failed to create chart directory {}", out_dir.display()))?;
+
+    let mut written = vec![];
+    for column in &config.columns {
+        let values = column_values(df, column)?;
+        if values.is_empty() {
+            println!("Skipping charts for {}: column has no values", column);
+            continue;
+        }
+
+        let stem = format!("{}_{}", column.replace(' ', "_").to_lowercase(), stage);
+        let extension = config.format.extension();
+
+        let histogram_path = out_dir.join(format!("{}_histogram.{}", stem, extension));
+        let title = format!("{} ({})", column, stage);
+        match config.format {
+            ChartFormat::Png => draw_histogram(BitMapBackend::new(&histogram_path, CHART_SIZE).into_drawing_area(), &values, &title, config.bins),
+            ChartFormat::Svg => draw_histogram(SVGBackend::new(&histogram_path, CHART_SIZE).into_drawing_area(), &values, &title, config.bins),
+        }
+        .context(format!("Failed to render histogram for {}", column))?;
+        written.push(histogram_path);
+
+        let box_plot_path = out_dir.join(format!("{}_boxplot.{}", stem, extension));
+        match config.format {
+            ChartFormat::Png => draw_box_plot(BitMapBackend::new(&box_plot_path, CHART_SIZE).into_drawing_area(), &values, column, &title),
+            ChartFormat::Svg => draw_box_plot(SVGBackend::new(&box_plot_path, CHART_SIZE).into_drawing_area(), &values, column, &title),
+        }
+        .context(format!("Failed to render box plot for {}", column))?;
+        written.push(box_plot_path);
+    }
+
+    Ok(written)
+}
+
+/// Helper function to collect the non-null values of a column as f64.
+fn column_values(df: &DataFrame, column: &str) -> Result<Vec<f64>> {
+    let series = df
+        .column(column)
+        .context(format!("Error fetching column {}", column))?
+        .cast(&DataType::Float64)
+        .context(format!("Error converting {} column to f64", column))?;
+    let values = series.f64()?.into_iter().flatten().collect();
+    Ok(values)
+}
+
+/// Counts how many values fall into each of `bins` equal-width bins between `min` and `max`.
+fn bin_counts(values: &[f64], min: f64, max: f64, bins: usize) -> Vec<u32> {
+    let bins = bins.max(1);
+    let mut counts = vec![0u32; bins];
+    let width = (max - min) / bins as f64;
+
+    for &value in values {
+        let index = if width > 0.0 { ((value - min) / width) as usize } else { 0 };
+        counts[index.min(bins - 1)] += 1;
+    }
+
+    counts
+}
+
+/// Helper function to find the smallest and largest value.
+fn min_max(values: &[f64]) -> (f64, f64) {
+    values
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)))
+}
+
+fn draw_histogram<DB: DrawingBackend>(root: DrawingArea<DB, Shift>, values: &[f64], title: &str, bins: usize) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let (min, mut max) = min_max(values);
+    if max <= min {
+        max = min + 1.0;
+    }
+    let counts = bin_counts(values, min, max, bins);
+    let max_count = counts.iter().copied().max().unwrap_or(0);
+    let width = (max - min) / counts.len() as f64;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(35)
+        .y_label_area_size(45)
+        .build_cartesian_2d(min..max, 0u32..max_count + 1)?;
+    chart.configure_mesh().y_desc("count").draw()?;
+
+    chart.draw_series(counts.iter().enumerate().map(|(i, &count)| {
+        let x0 = min + width * i as f64;
+        Rectangle::new([(x0, 0), (x0 + width, count)], BLUE.mix(0.6).filled())
+    }))?;
+
+    root.present()?;
+    Ok(())
+}
+
+fn draw_box_plot<DB: DrawingBackend>(root: DrawingArea<DB, Shift>, values: &[f64], column: &str, title: &str) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let (min, max) = min_max(values);
+    let padding = ((max - min) * 0.05).max(0.01);
+    let y_range = (min - padding) as f32..(max + padding) as f32;
+    let labels = vec![column.to_string()];
+    let quartiles = Quartiles::new(values);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(35)
+        .y_label_area_size(45)
+        .build_cartesian_2d(labels[..].into_segmented(), y_range)?;
+    chart.configure_mesh().draw()?;
+
+    chart.draw_series(std::iter::once(Boxplot::new_vertical(SegmentValue::CenterOf(&labels[0]), &quartiles)))?;
+
+    root.present()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bin_counts() {
+        let counts = bin_counts(&[0.0, 0.1, 0.5, 0.9, 1.0], 0.0, 1.0, 2);
+        assert_eq!(counts, vec![2, 3]); // the maximum lands in the last bin
+    }
+
+    #[test]
+    fn test_bin_counts_constant_column() {
+        let counts = bin_counts(&[5.0, 5.0, 5.0], 5.0, 5.0, 4);
+        assert_eq!(counts, vec![3, 0, 0, 0]);
+    }
+}
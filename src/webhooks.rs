@@ -0,0 +1,132 @@
+//! This module posts run lifecycle events to HTTP webhooks.
+//!
+//! Each `[[webhooks]]` entry names a URL and the hook points it subscribes to. When one of them fires,
+//! the event is posted as JSON with the run ID, labels, row count, error or failed expectations, and
+//! links to the run's reports, so dashboards and downstream jobs react to loads without polling.
+//! Webhooks are registered as hook callbacks; a failing delivery is reported and, unless the webhook
+//! is `required`, does not fail the run.
+
+use crate::hooks::{HookEvent, HookPoint, Hooks};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time after which a delivery counts as failed.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A webhook receiving run events.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Hook points posted to the webhook, e.g. `on_run_start`, `on_success`, `on_failure`, `on_recovery`.
+    pub events: Vec<HookPoint>,
+    /// Environment variable holding a bearer token sent with each request.
+    #[serde(default)]
+    pub token_env: Option<String>,
+    /// Whether a failed delivery fails the run. Otherwise the failure is only reported.
+    #[serde(default)]
+    pub required: bool,
+    /// Links included in the payload, keyed by name; `{run_id}` is replaced by the ID of the run.
+    #[serde(default)]
+    pub links: BTreeMap<String, String>,
+}
+
+/// The JSON body posted to a webhook.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WebhookPayload {
+    pub event: &'static str,
+    pub run_id: String,
+    pub labels: BTreeMap<String, String>,
+    pub rows: Option<usize>,
+    pub error: Option<String>,
+    pub links: BTreeMap<String, String>,
+    /// RFC 3339 time of sending.
+    pub sent_at: String,
+}
+
+/// Registers a hook callback posting to each webhook for the hook points it subscribes to.
+///
+/// # Arguments
+///
+/// * `hooks` - The hooks of the pipeline.
+/// * `webhooks` - The configured webhooks.
+/// * `labels` - The labels of the runs, included in every payload.
+///
+/// # Example
+///
+/// ```
+/// register(&mut hooks, &config.webhooks, &config.labels);
+/// ```
+pub fn register(hooks: &mut Hooks, webhooks: &[WebhookConfig], labels: &BTreeMap<String, String>) {
+    let client = reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build().unwrap_or_default();
+    for webhook in webhooks {
+        let webhook = Arc::new(webhook.clone());
+        for point in &webhook.events {
+            let (webhook, client, labels) = (webhook.clone(), client.clone(), labels.clone());
+            hooks.register(*point, move |event| {
+                let (webhook, client) = (webhook.clone(), client.clone());
+                let payload = payload(&event, &labels, &webhook.links);
+                async move {
+                    match deliver(&client, &webhook, &payload).await {
+                        Err(e) if !webhook.required => {
+                            eprintln!("[run {}] {:#}", payload.run_id, e);
+                            Ok(())
+                        }
+                        result => result,
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Helper function to build the payload of an event.
+fn payload(event: &HookEvent, labels: &BTreeMap<String, String>, links: &BTreeMap<String, String>) -> WebhookPayload {
+    WebhookPayload {
+        event: event.point.as_str(),
+        run_id: event.run_id.clone(),
+        labels: labels.clone(),
+        rows: event.rows,
+        error: event.error.clone(),
+        links: links.iter().map(|(name, link)| (name.clone(), link.replace("{run_id}", &event.run_id))).collect(),
+        sent_at: Utc::now().to_rfc3339(),
+    }
+}
+
+/// Helper function to post a payload to a webhook.
+async fn deliver(client: &reqwest::Client, webhook: &WebhookConfig, payload: &WebhookPayload) -> Result<()> {
+    let mut request = client.post(&webhook.url).json(payload);
+    if let Some(token) = webhook.token_env.as_deref().and_then(|name| std::env::var(name).ok()) {
+        request = request.bearer_auth(token);
+    }
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context(format!("Failed to post {} to webhook {}", payload.event, webhook.url))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_fills_in_links() {
+        let event = HookEvent::new(HookPoint::OnSuccess, "01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B").with_rows(1599);
+        let labels = BTreeMap::from([("vintage".to_string(), "2023".to_string())]);
+        let links = BTreeMap::from([("report".to_string(), "https://reports.example.com/runs/{run_id}".to_string())]);
+
+        let payload = payload(&event, &labels, &links);
+        let json = serde_json::to_value(&payload).unwrap();
+
+        assert_eq!(json["event"], "on_success");
+        assert_eq!(json["rows"], 1599);
+        assert_eq!(json["labels"]["vintage"], "2023");
+        assert_eq!(json["links"]["report"], "https://reports.example.com/runs/01HWKQ8Y6ZJ0D5VX3Q9N2T4M7B");
+    }
+}
@@ -0,0 +1,138 @@
+// Run history page of the pipeline daemon, reading the control API with the key kept for the browser session.
+"use strict";
+
+const KEY_ITEM = "pipeline-api-key";
+
+function apiKey(ask) {
+  let key = sessionStorage.getItem(KEY_ITEM);
+  if (!key || ask) {
+    key = window.prompt("API key (needs read_status, and read_records for rejected rows)") || "";
+    sessionStorage.setItem(KEY_ITEM, key);
+  }
+  return key;
+}
+
+async function get(path) {
+  const response = await fetch(path, { headers: { Authorization: "Bearer " + apiKey(false) } });
+  if (response.status === 401) {
+    throw new Error("The API key was not accepted; use \"Change API key\".");
+  }
+  if (response.status === 403) {
+    throw new Error("The API key lacks the permission to read " + path + ".");
+  }
+  if (!response.ok) {
+    const error = new Error("Reading " + path + " failed with status " + response.status + ".");
+    error.status = response.status;
+    throw error;
+  }
+  return response.json();
+}
+
+function cell(row, value, className) {
+  const td = document.createElement("td");
+  td.textContent = value === null || value === undefined ? "" : String(value);
+  if (className) {
+    td.className = className;
+  }
+  row.appendChild(td);
+}
+
+function number(value, digits) {
+  return value === null || value === undefined ? "" : Number(value).toFixed(digits);
+}
+
+function fill(table, rows, render) {
+  const body = document.querySelector(table + " tbody");
+  body.replaceChildren();
+  for (const row of rows) {
+    const tr = document.createElement("tr");
+    render(tr, row);
+    body.appendChild(tr);
+  }
+}
+
+function showMessage(text) {
+  const message = document.getElementById("message");
+  message.textContent = text;
+  message.hidden = !text;
+}
+
+async function loadRuns() {
+  try {
+    const runs = await get("/runs/history?limit=50");
+    showMessage(runs.length ? "" : "No runs recorded yet.");
+    fill("#runs", runs, (tr, run) => {
+      cell(tr, run.run_id);
+      cell(tr, run.attempt);
+      cell(tr, run.status, run.status);
+      cell(tr, run.row_count);
+      cell(tr, run.started_at);
+      cell(tr, run.finished_at);
+      cell(tr, run.warnings.map((w) => "[" + w.stage + "] " + w.message).join("\n"), run.warnings.length ? "warning" : "");
+      tr.title = run.error || "";
+      tr.addEventListener("click", () => {
+        document.querySelectorAll("#runs tr.selected").forEach((r) => r.classList.remove("selected"));
+        tr.classList.add("selected");
+        loadRun(run);
+      });
+    });
+  } catch (error) {
+    showMessage(error.message);
+  }
+}
+
+async function loadRun(run) {
+  document.getElementById("run").hidden = false;
+  document.getElementById("run-id").textContent = run.run_id;
+  const runError = document.getElementById("run-error");
+  runError.textContent = run.error || "";
+  runError.hidden = !run.error;
+
+  try {
+    const report = await get("/runs/" + encodeURIComponent(run.run_id) + "/report");
+    fill("#expectations", report.expectations, (tr, e) => {
+      cell(tr, e.expectation);
+      cell(tr, e.severity);
+      cell(tr, e.passed ? "passed" : "failed", e.passed ? "passed" : "failed");
+      cell(tr, e.observed);
+    });
+    fill("#columns", report.columns, (tr, c) => {
+      cell(tr, c.column);
+      cell(tr, c.rows);
+      cell(tr, number(c.null_rate * 100, 1) + "%");
+      cell(tr, number(c.mean, 3));
+      cell(tr, number(c.std, 3));
+      cell(tr, number(c.min, 3));
+      cell(tr, number(c.max, 3));
+    });
+  } catch (error) {
+    showMessage(error.message);
+  }
+
+  const note = document.getElementById("rejects-note");
+  const head = document.querySelector("#rejects thead");
+  head.replaceChildren();
+  fill("#rejects", [], () => {});
+  try {
+    const rejects = await get("/runs/" + encodeURIComponent(run.run_id) + "/rejects?limit=20");
+    const columns = rejects.rows.length ? Object.keys(rejects.rows[0]) : [];
+    const tr = document.createElement("tr");
+    for (const column of columns) {
+      const th = document.createElement("th");
+      th.textContent = column;
+      tr.appendChild(th);
+    }
+    head.appendChild(tr);
+    fill("#rejects", rejects.rows, (tr, row) => columns.forEach((column) => cell(tr, row[column])));
+    note.textContent = rejects.total ? "Showing " + rejects.rows.length + " of " + rejects.total + " rejected rows." : "The database rejected no rows.";
+  } catch (error) {
+    note.textContent = error.status === 404 ? "The run kept no rejected rows: none were rejected, or the rejects artifact is not persisted." : error.message;
+  }
+  note.hidden = false;
+}
+
+document.getElementById("change-key").addEventListener("click", () => {
+  apiKey(true);
+  loadRuns();
+});
+loadRuns();